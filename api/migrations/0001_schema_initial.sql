@@ -0,0 +1,38 @@
+-- Schéma initial, celui des bases créées avant les migrations
+
+CREATE TABLE IF NOT EXISTS user
+(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    password TEXT NOT NULL,
+    api_key TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS user_username
+    on user (username);
+
+CREATE TABLE IF NOT EXISTS room
+(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS message
+(
+    date INTEGER NOT NULL,
+    room_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    text TEXT NOT NULL,
+
+    FOREIGN KEY(user_id, room_id) REFERENCES user_room(user_id, room_id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS user_room
+(
+    user_id INTEGER NOT NULL,
+    room_id INTEGER NOT NULL,
+
+    PRIMARY KEY(user_id, room_id),
+    FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE,
+    FOREIGN KEY(room_id) REFERENCES room(id) ON DELETE CASCADE
+);
@@ -0,0 +1,126 @@
+-- Identifiant des messages (l'ancien rowid), fils de discussion, messages éphémères,
+-- réactions, pièces jointes, mentions, épingles, messages programmés et recherche
+--
+-- La table des messages est reconstruite, le renommage met à jour sa référence vers elle-même.
+
+CREATE TABLE message_migration
+(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    date INTEGER NOT NULL,
+    room_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    parent_id INTEGER,
+    expires_at INTEGER,
+
+    FOREIGN KEY(user_id, room_id) REFERENCES user_room(user_id, room_id) ON DELETE CASCADE,
+    FOREIGN KEY(parent_id) REFERENCES message_migration(id) ON DELETE CASCADE
+);
+
+INSERT INTO message_migration (id, date, room_id, user_id, text)
+    SELECT rowid, date, room_id, user_id, text FROM message ORDER BY rowid;
+
+DROP TABLE message;
+
+ALTER TABLE message_migration RENAME TO message;
+
+CREATE INDEX message_parent_id
+    on message (parent_id);
+
+CREATE INDEX message_expires_at
+    on message (expires_at);
+
+CREATE TABLE reaction
+(
+    message_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    emoji TEXT NOT NULL,
+
+    PRIMARY KEY(message_id, user_id, emoji),
+    FOREIGN KEY(message_id) REFERENCES message(id) ON DELETE CASCADE,
+    FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE
+);
+
+CREATE TABLE attachment
+(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    date INTEGER NOT NULL,
+    room_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    message_id INTEGER,
+    name TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size INTEGER NOT NULL,
+
+    FOREIGN KEY(user_id, room_id) REFERENCES user_room(user_id, room_id) ON DELETE CASCADE,
+    FOREIGN KEY(message_id) REFERENCES message(id) ON DELETE CASCADE
+);
+
+CREATE INDEX attachment_message_id
+    on attachment (message_id);
+
+CREATE TABLE mention
+(
+    message_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+
+    PRIMARY KEY(message_id, user_id),
+    FOREIGN KEY(message_id) REFERENCES message(id) ON DELETE CASCADE,
+    FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE
+);
+
+CREATE INDEX mention_user_id
+    on mention (user_id, message_id);
+
+CREATE TABLE pin
+(
+    message_id INTEGER NOT NULL PRIMARY KEY,
+    room_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    date INTEGER NOT NULL,
+
+    FOREIGN KEY(message_id) REFERENCES message(id) ON DELETE CASCADE,
+    FOREIGN KEY(room_id) REFERENCES room(id) ON DELETE CASCADE,
+    FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE
+);
+
+CREATE INDEX pin_room_id
+    on pin (room_id);
+
+CREATE TABLE scheduled_message
+(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    send_at INTEGER NOT NULL,
+    room_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    parent_id INTEGER,
+
+    FOREIGN KEY(user_id, room_id) REFERENCES user_room(user_id, room_id) ON DELETE CASCADE,
+    FOREIGN KEY(parent_id) REFERENCES message(id) ON DELETE CASCADE
+);
+
+CREATE INDEX scheduled_message_send_at
+    on scheduled_message (send_at);
+
+-- L'index de recherche est tenu à jour par des triggers
+CREATE VIRTUAL TABLE message_fts
+    USING fts5(text, content = 'message', content_rowid = 'id');
+
+CREATE TRIGGER message_fts_insert AFTER INSERT ON message
+BEGIN
+    INSERT INTO message_fts (rowid, text) VALUES (new.id, new.text);
+END;
+
+CREATE TRIGGER message_fts_delete AFTER DELETE ON message
+BEGIN
+    INSERT INTO message_fts (message_fts, rowid, text) VALUES ('delete', old.id, old.text);
+END;
+
+CREATE TRIGGER message_fts_update AFTER UPDATE OF text ON message
+BEGIN
+    INSERT INTO message_fts (message_fts, rowid, text) VALUES ('delete', old.id, old.text);
+    INSERT INTO message_fts (rowid, text) VALUES (new.id, new.text);
+END;
+
+INSERT INTO message_fts (message_fts) VALUES ('rebuild');
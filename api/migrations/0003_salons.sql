@@ -0,0 +1,44 @@
+-- Sujet, type et durée de conservation des salons, dernier message lu et rôle des membres,
+-- bannissements et conversations privées
+
+ALTER TABLE room ADD COLUMN topic TEXT NOT NULL DEFAULT '';
+
+ALTER TABLE room ADD COLUMN kind TEXT NOT NULL DEFAULT 'group';
+
+ALTER TABLE room ADD COLUMN retention INTEGER;
+
+ALTER TABLE user_room ADD COLUMN last_read INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE user_room ADD COLUMN role TEXT NOT NULL DEFAULT 'member';
+
+-- Les messages déjà envoyés sont considérés comme lus
+UPDATE user_room SET last_read = COALESCE(
+    (SELECT MAX(message.id) FROM message WHERE message.room_id = user_room.room_id), 0
+);
+
+-- Le plus ancien membre de chaque salon en devient propriétaire
+UPDATE user_room SET role = 'owner'
+    WHERE rowid IN (SELECT MIN(rowid) FROM user_room GROUP BY room_id);
+
+CREATE TABLE room_ban
+(
+    user_id INTEGER NOT NULL,
+    room_id INTEGER NOT NULL,
+
+    PRIMARY KEY(user_id, room_id),
+    FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE,
+    FOREIGN KEY(room_id) REFERENCES room(id) ON DELETE CASCADE
+);
+
+CREATE TABLE room_direct
+(
+    user_id_1 INTEGER NOT NULL,
+    user_id_2 INTEGER NOT NULL,
+    room_id INTEGER NOT NULL,
+
+    PRIMARY KEY(user_id_1, user_id_2),
+    CHECK(user_id_1 < user_id_2),
+    FOREIGN KEY(user_id_1) REFERENCES user(id) ON DELETE CASCADE,
+    FOREIGN KEY(user_id_2) REFERENCES user(id) ON DELETE CASCADE,
+    FOREIGN KEY(room_id) REFERENCES room(id) ON DELETE CASCADE
+);
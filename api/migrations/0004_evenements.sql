@@ -0,0 +1,16 @@
+-- Journal des événements de chaque utilisateur et dernière connexion
+
+ALTER TABLE user ADD COLUMN last_seen INTEGER;
+
+CREATE TABLE event
+(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    date INTEGER NOT NULL,
+    data TEXT NOT NULL,
+
+    FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE
+);
+
+CREATE INDEX event_user_id
+    on event (user_id, id);
@@ -0,0 +1,15 @@
+-- Sessions des utilisateurs, qui remplacent l'api_key renouvelée à chaque requête
+
+CREATE TABLE session
+(
+    token TEXT NOT NULL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+
+    FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE
+);
+
+CREATE INDEX session_user_id
+    on session (user_id);
+
+ALTER TABLE user DROP COLUMN api_key;
@@ -0,0 +1,26 @@
+-- Une session par appareil : identifiant, appareil (en-tête User-Agent), ouverture et dernière utilisation
+--
+-- La table des sessions est reconstruite pour avoir un identifiant, les sessions ouvertes sont gardées.
+
+CREATE TABLE session_migration
+(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    token TEXT NOT NULL UNIQUE,
+    user_id INTEGER NOT NULL,
+    user_agent TEXT NOT NULL DEFAULT '',
+    created_at INTEGER NOT NULL,
+    last_used INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+
+    FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE
+);
+
+INSERT INTO session_migration (token, user_id, created_at, last_used, expires_at)
+    SELECT token, user_id, strftime('%s', 'now'), strftime('%s', 'now'), expires_at FROM session;
+
+DROP TABLE session;
+
+ALTER TABLE session_migration RENAME TO session;
+
+CREATE INDEX session_user_id
+    on session (user_id);
@@ -0,0 +1,14 @@
+-- Jetons d'accès de courte durée, rafraîchis avec un jeton de rafraîchissement
+--
+-- `token` devient le jeton d'accès, qui expire à `access_expires_at`. `expires_at` reste
+-- l'expiration de la session. Les sessions déjà ouvertes n'ont pas de jeton de rafraîchissement :
+-- leur jeton d'accès reste valide jusqu'à l'expiration de la session.
+
+ALTER TABLE session ADD COLUMN refresh_token TEXT;
+
+ALTER TABLE session ADD COLUMN access_expires_at INTEGER NOT NULL DEFAULT 0;
+
+UPDATE session SET access_expires_at = expires_at;
+
+CREATE UNIQUE INDEX session_refresh_token
+    on session (refresh_token);
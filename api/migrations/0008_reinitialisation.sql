@@ -0,0 +1,10 @@
+-- Jetons à usage unique pour réinitialiser le mot de passe d'un utilisateur, créés par un administrateur
+
+CREATE TABLE password_reset
+(
+    token TEXT NOT NULL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+
+    FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+-- Index des messages d'un salon par date, pour supprimer ceux plus anciens que sa durée de conservation
+
+CREATE INDEX message_room_id_date
+    on message (room_id, date);
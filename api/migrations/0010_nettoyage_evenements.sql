@@ -0,0 +1,4 @@
+-- Index des événements par date, pour supprimer régulièrement ceux plus vieux que la période de rétention
+
+CREATE INDEX event_date
+    on event (date);
@@ -0,0 +1,21 @@
+-- Empreintes (SHA-256) des jetons de session, de rafraîchissement et de réinitialisation
+-- à la place des jetons
+--
+-- Les jetons déjà donnés ne peuvent pas être convertis : les sessions ouvertes sont fermées
+-- et les jetons de réinitialisation supprimés.
+
+DELETE FROM session;
+
+ALTER TABLE session RENAME COLUMN token TO token_hash;
+
+ALTER TABLE session RENAME COLUMN refresh_token TO refresh_token_hash;
+
+DELETE FROM password_reset;
+
+ALTER TABLE password_reset RENAME COLUMN token TO token_hash;
+
+CREATE INDEX password_reset_user_id
+    on password_reset (user_id);
+
+CREATE INDEX password_reset_expires_at
+    on password_reset (expires_at);
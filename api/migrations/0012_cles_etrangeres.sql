@@ -0,0 +1,90 @@
+-- Les messages et les pièces jointes référencent leur salon et leur auteur plutôt que
+-- son appartenance au salon : avec les clés étrangères vérifiées, ils restent quand il le quitte
+-- et sont supprimés avec le salon. Les messages programmés d'un membre qui quitte le salon
+-- sont toujours supprimés.
+--
+-- Les tables sont reconstruites (sans vérifier les clés étrangères, voir `Database::migre`),
+-- avec leurs index et les triggers de la recherche.
+
+CREATE TABLE message_migration
+(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    date INTEGER NOT NULL,
+    room_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    parent_id INTEGER,
+    expires_at INTEGER,
+
+    FOREIGN KEY(room_id) REFERENCES room(id) ON DELETE CASCADE,
+    FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE,
+    FOREIGN KEY(parent_id) REFERENCES message_migration(id) ON DELETE CASCADE
+);
+
+INSERT INTO message_migration (id, date, room_id, user_id, text, parent_id, expires_at)
+    SELECT id, date, room_id, user_id, text, parent_id, expires_at FROM message ORDER BY id;
+
+-- Les identifiants supprimés ne sont pas réutilisés
+DELETE FROM sqlite_sequence WHERE name = 'message_migration';
+UPDATE sqlite_sequence SET name = 'message_migration' WHERE name = 'message';
+
+DROP TABLE message;
+
+ALTER TABLE message_migration RENAME TO message;
+
+CREATE INDEX message_parent_id
+    on message (parent_id);
+
+CREATE INDEX message_expires_at
+    on message (expires_at);
+
+CREATE INDEX message_room_id_date
+    on message (room_id, date);
+
+CREATE TRIGGER message_fts_insert AFTER INSERT ON message
+BEGIN
+    INSERT INTO message_fts (rowid, text) VALUES (new.id, new.text);
+END;
+
+CREATE TRIGGER message_fts_delete AFTER DELETE ON message
+BEGIN
+    INSERT INTO message_fts (message_fts, rowid, text) VALUES ('delete', old.id, old.text);
+END;
+
+CREATE TRIGGER message_fts_update AFTER UPDATE OF text ON message
+BEGIN
+    INSERT INTO message_fts (message_fts, rowid, text) VALUES ('delete', old.id, old.text);
+    INSERT INTO message_fts (rowid, text) VALUES (new.id, new.text);
+END;
+
+INSERT INTO message_fts (message_fts) VALUES ('rebuild');
+
+CREATE TABLE attachment_migration
+(
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    date INTEGER NOT NULL,
+    room_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    message_id INTEGER,
+    name TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size INTEGER NOT NULL,
+
+    FOREIGN KEY(room_id) REFERENCES room(id) ON DELETE CASCADE,
+    FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE,
+    FOREIGN KEY(message_id) REFERENCES message(id) ON DELETE CASCADE
+);
+
+INSERT INTO attachment_migration (id, date, room_id, user_id, message_id, name, content_type, size)
+    SELECT id, date, room_id, user_id, message_id, name, content_type, size FROM attachment ORDER BY id;
+
+-- Les identifiants supprimés ne sont pas réutilisés
+DELETE FROM sqlite_sequence WHERE name = 'attachment_migration';
+UPDATE sqlite_sequence SET name = 'attachment_migration' WHERE name = 'attachment';
+
+DROP TABLE attachment;
+
+ALTER TABLE attachment_migration RENAME TO attachment;
+
+CREATE INDEX attachment_message_id
+    on attachment (message_id);
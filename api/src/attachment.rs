@@ -0,0 +1,301 @@
+//! Gestion des pièces jointes des messages
+//!
+//! Ce module implémente des méthodes pour enregistrer les fichiers envoyés dans un salon
+//! (sur le disque local, dans le dossier `ATTACHMENTS_DIR`), les lier à un message
+//! et les récupérer seulement pour les membres du salon.
+
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::Utc;
+use lib::{Attachment, Message};
+use rocket::fs::{NamedFile, TempFile};
+use rocket::http::{ContentType, Header};
+use rusqlite::{Result, Row};
+
+use crate::database::Database;
+use crate::erreur::Erreur;
+
+/// Délai en secondes après lequel une pièce jointe liée à aucun message est supprimée (un jour)
+pub const DELAI_PIECE_JOINTE_NON_LIEE: i64 = 24 * 3600;
+
+/// Pièce jointe envoyée dans un salon, avant d'être liée à un message
+#[derive(Debug, FromForm)]
+pub struct FormAttachment<'r> {
+    pub file: TempFile<'r>,
+}
+
+/// Fichier d'une pièce jointe, affiché dans le navigateur si c'est une image (voir `is_image`)
+///
+/// Le navigateur ne devine pas un autre type que celui donné, et n'exécute pas de script
+/// du fichier s'il est ouvert directement.
+#[derive(Responder)]
+pub struct Fichier {
+    fichier: NamedFile,
+    content_type: ContentType,
+    disposition: Header<'static>,
+    nosniff: Header<'static>,
+    csp: Header<'static>,
+}
+
+impl Fichier {
+    /// Ouvre le fichier d'une pièce jointe
+    pub async fn ouvre(attachment: &Attachment, chemin: PathBuf) -> std::io::Result<Fichier> {
+        let disposition = match attachment.is_image() {
+            true => String::from("inline"),
+            false => format!(
+                "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+                nom_ascii(&attachment.name),
+                encode_nom(&attachment.name)
+            ),
+        };
+
+        Ok(Fichier {
+            fichier: NamedFile::open(chemin).await?,
+            content_type: ContentType::parse_flexible(attachment.content_type.as_str())
+                .unwrap_or(ContentType::Binary),
+            disposition: Header::new("Content-Disposition", disposition),
+            nosniff: Header::new("X-Content-Type-Options", "nosniff"),
+            csp: Header::new("Content-Security-Policy", "sandbox"),
+        })
+    }
+}
+
+impl FormAttachment<'_> {
+    /// Pièce jointe décrite par le fichier envoyé (sans identifiant, voir `ajout_piece_jointe`)
+    pub fn piece_jointe(&self) -> Attachment {
+        let content_type = self
+            .file
+            .content_type()
+            .cloned()
+            .unwrap_or(ContentType::Binary);
+        let name = match (self.file.name(), content_type.extension()) {
+            (Some(name), Some(extension)) => format!("{name}.{extension}"),
+            (Some(name), None) => name.to_string(),
+            (None, _) => String::from("fichier"),
+        };
+        Attachment {
+            id: 0,
+            name,
+            content_type: content_type.to_string(),
+            size: self.file.len() as i64,
+        }
+    }
+}
+
+impl Database {
+    /// Ajoute une pièce jointe (voir `FormAttachment::piece_jointe`) envoyée dans un salon
+    /// dont l'utilisateur fait partie
+    ///
+    /// Le fichier doit ensuite être enregistré dans `chemin_piece_jointe`.
+    pub fn ajout_piece_jointe(
+        &self,
+        room_id: i64,
+        user_id: i64,
+        mut attachment: Attachment,
+    ) -> Result<Attachment, Erreur> {
+        match self.est_dans_room(user_id, room_id) {
+            Ok(true) => {}
+            _ => return Err(Erreur::PasMembre(String::from("Tu n'es pas dans ce salon."))),
+        }
+
+        self.connection
+            .execute(
+                "INSERT INTO attachment (date, room_id, user_id, name, content_type, size) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                (
+                    Utc::now().timestamp(),
+                    room_id,
+                    user_id,
+                    attachment.name.as_str(),
+                    attachment.content_type.as_str(),
+                    attachment.size,
+                ),
+            )?;
+        attachment.id = self.connection.last_insert_rowid();
+
+        Ok(attachment)
+    }
+
+    /// Retire une pièce jointe dont le fichier n'a pas pu être enregistré
+    pub fn supprime_piece_jointe(&self, attachment_id: i64) -> Result<usize> {
+        self.connection
+            .execute("DELETE FROM attachment WHERE id = ?1", [attachment_id])
+    }
+
+    /// Vérifie que les pièces jointes ont été envoyées par l'utilisateur dans le salon
+    /// et qu'elles ne sont pas déjà liées à un message
+    pub fn verification_pieces_jointes(
+        &self,
+        attachment_ids: &[i64],
+        user_id: i64,
+        room_id: i64,
+    ) -> Result<(), Erreur> {
+        for attachment_id in attachment_ids {
+            let disponible: bool = self
+                .connection
+                .query_row(
+                    "SELECT EXISTS(SELECT 1 FROM attachment WHERE id = ?1 AND user_id = ?2 AND room_id = ?3 AND message_id IS NULL)",
+                    (attachment_id, user_id, room_id),
+                    |row| row.get(0),
+                )?;
+            if !disponible {
+                return Err(Erreur::Invalide(format!(
+                    "La pièce jointe {} n'est pas disponible.",
+                    attachment_id
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lie des pièces jointes (voir `verification_pieces_jointes`) à un message
+    pub fn lie_pieces_jointes(&self, message: &mut Message, attachment_ids: &[i64]) -> Result<()> {
+        for attachment_id in attachment_ids {
+            self.connection.execute(
+                "UPDATE attachment SET message_id = ?1 WHERE id = ?2",
+                (message.id, attachment_id),
+            )?;
+        }
+
+        self.ajoute_pieces_jointes(message)
+    }
+
+    /// Remplit les pièces jointes d'un message, dans l'ordre où elles ont été envoyées
+    pub fn ajoute_pieces_jointes(&self, message: &mut Message) -> Result<()> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, name, content_type, size FROM attachment WHERE message_id = ?1 ORDER BY id",
+        )?;
+        let rows = stmt.query_map([message.id], map_attachment)?;
+
+        let mut attachments = Vec::new();
+        for attachment in rows {
+            attachments.push(attachment?);
+        }
+
+        message.attachments = attachments;
+        Ok(())
+    }
+
+    /// Supprime les pièces jointes d'un message et de ses réponses
+    ///
+    /// Retourne leurs identifiants : leurs fichiers sont supprimés une fois la transaction
+    /// validée (voir `supprime_fichiers`).
+    pub fn supprime_pieces_jointes(&self, message_id: i64) -> Result<Vec<i64>> {
+        self.connection
+            .prepare(
+                "DELETE FROM attachment WHERE message_id IN (SELECT id FROM message WHERE id = ?1 OR parent_id = ?1) RETURNING id",
+            )?
+            .query_map([message_id], |row| row.get(0))?
+            .collect()
+    }
+
+    /// Supprime les pièces jointes d'un salon, liées à un message ou non
+    ///
+    /// Retourne leurs identifiants, comme `supprime_pieces_jointes`.
+    pub fn supprime_pieces_jointes_room(&self, room_id: i64) -> Result<Vec<i64>> {
+        self.connection
+            .prepare("DELETE FROM attachment WHERE room_id = ?1 RETURNING id")?
+            .query_map([room_id], |row| row.get(0))?
+            .collect()
+    }
+
+    /// Supprime les pièces jointes liées à aucun message depuis `DELAI_PIECE_JOINTE_NON_LIEE`,
+    /// et leurs fichiers
+    pub fn nettoie_pieces_jointes_non_liees(&self) -> Result<usize> {
+        let attachment_ids = self
+            .connection
+            .prepare("DELETE FROM attachment WHERE message_id IS NULL AND date < ?1 RETURNING id")?
+            .query_map(
+                [Utc::now().timestamp() - DELAI_PIECE_JOINTE_NON_LIEE],
+                |row| row.get(0),
+            )?
+            .collect::<Result<Vec<i64>>>()?;
+        self.supprime_fichiers(&attachment_ids);
+
+        Ok(attachment_ids.len())
+    }
+
+    /// Supprime les fichiers de pièces jointes déjà retirées de la base de donnée
+    ///
+    /// Un fichier qui n'a pas pu être supprimé est laissé sur le disque.
+    pub fn supprime_fichiers(&self, attachment_ids: &[i64]) {
+        for attachment_id in attachment_ids {
+            let _ = fs::remove_file(self.chemin_piece_jointe(*attachment_id));
+        }
+    }
+
+    /// Récupère une pièce jointe
+    ///
+    /// Seuls les membres du salon y ont accès (seulement l'auteur tant qu'elle n'est liée à aucun message).
+    pub fn recupere_piece_jointe(
+        &self,
+        attachment_id: i64,
+        user_id: i64,
+    ) -> Result<Attachment, Erreur> {
+        self.connection
+            .query_row(
+                "
+                SELECT id, name, content_type, size FROM attachment
+                WHERE id = ?1 AND (message_id IS NOT NULL OR user_id = ?2)
+                    AND EXISTS(SELECT 1 FROM user_room WHERE user_id = ?2 AND room_id = attachment.room_id)
+                ",
+                (attachment_id, user_id),
+                map_attachment,
+            )
+            .map_err(|_| Erreur::Interdit(String::from("Tu n'as pas accès à cette pièce jointe.")))
+    }
+
+    /// Emplacement du fichier d'une pièce jointe
+    pub fn chemin_piece_jointe(&self, attachment_id: i64) -> PathBuf {
+        self.dossier_pieces_jointes()
+            .join(attachment_id.to_string())
+    }
+}
+
+/// Nom d'un fichier pour le paramètre `filename` de `Content-Disposition`, pour les navigateurs
+/// qui ne lisent pas `filename*` : les caractères hors ASCII, les guillemets et les barres obliques
+/// inversées sont remplacés
+fn nom_ascii(nom: &str) -> String {
+    nom.chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii_graphic() || c == ' ' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Nom d'un fichier pour le paramètre `filename*` de `Content-Disposition` (RFC 5987),
+/// encodé en UTF-8 puis en pourcentage
+fn encode_nom(nom: &str) -> String {
+    nom.bytes()
+        .map(|octet| match octet {
+            b'a'..=b'z'
+            | b'A'..=b'Z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'#'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~' => (octet as char).to_string(),
+            _ => format!("%{:02X}", octet),
+        })
+        .collect()
+}
+
+fn map_attachment(row: &Row) -> Result<Attachment> {
+    Ok(Attachment {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        content_type: row.get(2)?,
+        size: row.get(3)?,
+    })
+}
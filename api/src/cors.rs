@@ -1,14 +1,19 @@
 //! Middleware pour gérer les en-têtes CORS dans Rocket.rs
 //!
 //! Ce middleware `CORS` ajoute les en-têtes CORS nécessaires aux réponses pour permettre
-//! le partage de ressources entre différentes origines.
+//! le partage de ressources avec le front, servi depuis une autre origine.
+//! Seules les origines du front (`FRONT_ORIGINS`) sont autorisées : elles sont renvoyées
+//! (plutôt que `*`) pour que le navigateur accepte les requêtes avec le cookie de session.
 //! Source: https://stackoverflow.com/questions/62412361/how-to-set-up-cors-or-options-for-rocket-rs
 
 use rocket::fairing::{Fairing, Info, Kind};
 use rocket::http::Header;
 use rocket::{Request, Response};
 
-pub struct CORS;
+/// Origines autorisées à utiliser l'API depuis un navigateur, par exemple `http://localhost:8080`
+pub struct CORS {
+    pub origines: Vec<String>,
+}
 
 #[rocket::async_trait]
 impl Fairing for CORS {
@@ -19,13 +24,29 @@ impl Fairing for CORS {
         }
     }
 
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        response.set_header(Header::new("Vary", "Origin"));
+        let origin = match request.headers().get_one("Origin") {
+            Some(origin) if self.origines.iter().any(|autorisee| autorisee == origin) => origin,
+            _ => return,
+        };
+
+        response.set_header(Header::new(
+            "Access-Control-Allow-Origin",
+            origin.to_string(),
+        ));
         response.set_header(Header::new(
             "Access-Control-Allow-Methods",
-            "POST, GET, PATCH, OPTIONS",
+            "POST, GET, PATCH, DELETE, OPTIONS",
+        ));
+        response.set_header(Header::new(
+            "Access-Control-Allow-Headers",
+            "Authorization, Content-Type, Last-Event-ID",
         ));
-        response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
         response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
     }
 }
+
+/// Répond aux requêtes préliminaires (preflight) des navigateurs pour PATCH et DELETE
+#[options("/<_..>")]
+pub fn preflight() {}
@@ -1,86 +1,160 @@
 //! Module gérant la connexion à la base de données SQLite
 //!
-//! Ce module fournit une structure `Database` pour gérer la connexion à une base de données SQLite,
-//! ainsi que des méthodes pour créer des tables dans cette base de données.
+//! Ce module fournit un pool de connexions (`Pool`), partagé par les routes et les tâches de fond,
+//! et une structure `Database` pour utiliser une de ses connexions.
+//! Son schéma est créé et mis à jour par les migrations (voir `migre`).
 
 use dotenv::dotenv;
+use r2d2::{ManageConnection, PooledConnection};
+use rocket::tokio::task::spawn_blocking;
 use rusqlite::{Connection, Result};
 use std::env;
-use std::sync::Once;
+use std::panic::resume_unwind;
+use std::path::PathBuf;
+use std::time::Duration;
 
-/// Gère la connection de la base de donnée SQLite
-pub struct Database {
-    _private: (),
-    pub connection: Connection,
-}
+use crate::erreur::Erreur;
+use crate::retention::INTERVALLE_PURGE;
+use crate::session::DureesSession;
 
-static mut DATABASE_URL: String = String::new();
-static INIT: Once = Once::new();
+/// Durée pendant laquelle une connexion attend qu'une autre libère la base de donnée
+const DELAI_VERROU: Duration = Duration::from_secs(5);
+
+/// Emplacements de la base de donnée et des fichiers des pièces jointes, durées des sessions,
+/// intervalle entre deux purges des données expirées et origines du front (voir `CORS`)
+#[derive(Debug, Clone)]
+pub struct Configuration {
+    pub database_url: String,
+    pub attachments_dir: PathBuf,
+    pub durees_session: DureesSession,
+    pub intervalle_purge: Duration,
+    pub origines_front: Vec<String>,
+}
 
-fn emplacement_base_de_donnee(is_unit_test: bool) -> &'static String {
-    INIT.call_once(|| {
+impl Configuration {
+    /// Lit la configuration du fichier `.env` (`DATABASE_URL`, `ATTACHMENTS_DIR`,
+    /// les durées des sessions, voir `DureesSession::depuis_env`, `PURGE_INTERVAL_MINUTES`
+    /// et `FRONT_ORIGINS`, séparées par des virgules)
+    pub fn depuis_env() -> Configuration {
         dotenv().unwrap();
-        let path = match is_unit_test {
-            false => "DATABASE_URL",
-            true => "DATABASE_URL_TEST",
-        };
-        let url = env::var(path).unwrap();
-        unsafe {
-            DATABASE_URL = url;
+        Configuration {
+            database_url: env::var("DATABASE_URL").unwrap(),
+            attachments_dir: PathBuf::from(env::var("ATTACHMENTS_DIR").unwrap()),
+            durees_session: DureesSession::depuis_env(),
+            intervalle_purge: env::var("PURGE_INTERVAL_MINUTES")
+                .ok()
+                .and_then(|valeur| valeur.parse::<u64>().ok())
+                .map_or(INTERVALLE_PURGE, |minutes| {
+                    Duration::from_secs(minutes * 60)
+                }),
+            origines_front: env::var("FRONT_ORIGINS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|origine| !origine.is_empty())
+                .map(String::from)
+                .collect(),
         }
-    });
-    unsafe { &DATABASE_URL }
+    }
 }
 
-impl Database {
-    pub fn new(is_unit_test: bool) -> Result<Database> {
+/// Ouvre les connexions du pool, en mode WAL pour que les lectures n'attendent pas les écritures
+pub struct GestionnaireConnexions {
+    chemin: String,
+}
+
+impl ManageConnection for GestionnaireConnexions {
+    type Connection = Connection;
+    type Error = rusqlite::Error;
+
+    fn connect(&self) -> Result<Connection> {
+        let connection = Connection::open(self.chemin.as_str())?;
+        connection.busy_timeout(DELAI_VERROU)?;
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        Ok(connection)
+    }
+
+    fn is_valid(&self, connection: &mut Connection) -> Result<()> {
+        connection.execute_batch("")
+    }
+
+    fn has_broken(&self, _connection: &mut Connection) -> bool {
+        false
+    }
+}
+
+/// Pool de connexions à la base de donnée, géré par Rocket (`State<Pool>`)
+#[derive(Clone)]
+pub struct Pool {
+    connexions: r2d2::Pool<GestionnaireConnexions>,
+    dossier_pieces_jointes: PathBuf,
+    durees_session: DureesSession,
+}
+
+impl Pool {
+    pub fn new(configuration: &Configuration) -> Result<Pool, r2d2::Error> {
+        let gestionnaire = GestionnaireConnexions {
+            chemin: configuration.database_url.to_string(),
+        };
+        Ok(Pool {
+            connexions: r2d2::Pool::new(gestionnaire)?,
+            dossier_pieces_jointes: configuration.attachments_dir.clone(),
+            durees_session: configuration.durees_session,
+        })
+    }
+
+    /// Prend une connexion du pool (elle y retourne quand la `Database` est libérée)
+    pub fn connection(&self) -> Result<Database, r2d2::Error> {
         Ok(Database {
             _private: (),
-            connection: Connection::open(emplacement_base_de_donnee(is_unit_test))?,
+            connection: self.connexions.get()?,
+            dossier_pieces_jointes: self.dossier_pieces_jointes.clone(),
+            durees_session: self.durees_session,
+        })
+    }
+
+    /// Exécute des requêtes sur une connexion du pool, hors des threads asynchrones de Rocket
+    ///
+    /// Une panique des requêtes est propagée à l'appelant. Si aucune connexion n'est libérée
+    /// à temps, les requêtes ne sont pas exécutées et une `Erreur::Interne` est retournée.
+    pub async fn execute<F, T, E>(&self, requetes: F) -> Result<T, E>
+    where
+        F: FnOnce(&Database) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: From<Erreur> + Send + 'static,
+    {
+        let pool = self.clone();
+        let resultat = spawn_blocking(move || match pool.connection() {
+            Ok(connection_bd) => requetes(&connection_bd),
+            Err(_) => Err(E::from(Erreur::Interne(String::from(
+                "Erreur interne de la base de donnée.",
+            )))),
         })
+        .await;
+        match resultat {
+            Ok(resultat) => resultat,
+            Err(e) => resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+/// Gère une connexion de la base de donnée SQLite, prise du `Pool`
+pub struct Database {
+    _private: (),
+    pub connection: PooledConnection<GestionnaireConnexions>,
+    dossier_pieces_jointes: PathBuf,
+    durees_session: DureesSession,
+}
+
+impl Database {
+    /// Dossier où sont enregistrés les fichiers des pièces jointes
+    pub fn dossier_pieces_jointes(&self) -> PathBuf {
+        self.dossier_pieces_jointes.clone()
     }
 
-    /// Crée les tables de la base de donnée
-    pub fn cree_tables(&self) -> Result<()> {
-        self.connection.execute_batch(
-            " 
-            CREATE TABLE IF NOT EXISTS user
-            (
-                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
-                username TEXT NOT NULL,
-                password TEXT NOT NULL,
-                api_key TEXT NOT NULL
-            );
-    
-            CREATE UNIQUE INDEX IF NOT EXISTS user_username
-                on user (username);
-    
-            CREATE TABLE IF NOT EXISTS room
-            (
-                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
-                name TEXT NOT NULL
-            );
-    
-            CREATE TABLE IF NOT EXISTS message
-            (
-                date INTEGER NOT NULL,
-                room_id INTEGER NOT NULL,
-                user_id INTEGER NOT NULL,
-                text TEXT NOT NULL,
-    
-                FOREIGN KEY(user_id, room_id) REFERENCES user_room(user_id, room_id) ON DELETE CASCADE
-            );
-    
-            CREATE TABLE IF NOT EXISTS user_room
-            (
-                user_id INTEGER NOT NULL,
-                room_id INTEGER NOT NULL,
-    
-                PRIMARY KEY(user_id, room_id),
-                FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE,
-                FOREIGN KEY(room_id) REFERENCES room(id) ON DELETE CASCADE
-            );
-            ",
-        )
+    /// Durées de validité des sessions
+    pub fn durees_session(&self) -> DureesSession {
+        self.durees_session
     }
 }
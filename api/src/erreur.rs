@@ -0,0 +1,79 @@
+//! Erreurs de l'API
+//!
+//! Ce module définit `Erreur`, le type d'erreur des méthodes de `Database` et des routes.
+//! Chaque erreur a un code (`lib::ErrorCode`) qui détermine le statut de sa réponse,
+//! `{ "code": ..., "reason": ... }`, que le front décode avec `lib::ApiError`.
+
+use lib::{ApiError, ErrorCode};
+
+/// Erreur de l'API, avec la raison à afficher à l'utilisateur
+#[derive(Debug, Clone, PartialEq)]
+pub enum Erreur {
+    /// Requête invalide (400)
+    Invalide(String),
+    /// Mauvais identifiant, mot de passe ou jeton de session (401)
+    NonAutorise(String),
+    /// Action interdite à l'utilisateur (403)
+    Interdit(String),
+    /// Ressource introuvable (404)
+    Introuvable(String),
+    /// Ressource qui existe déjà (409)
+    Conflit(String),
+    /// Erreur de la base de donnée ou du serveur (500)
+    Interne(String),
+    /// Nom d'utilisateur déjà pris (409)
+    NomPris(String),
+    /// L'utilisateur ne fait pas partie du salon (403)
+    PasMembre(String),
+    /// Message de quelqu'un d'autre (403)
+    PasAuteur(String),
+    /// Jeton d'accès expiré d'une session encore valide (401)
+    JetonExpire(String),
+}
+
+impl Erreur {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Erreur::Invalide(_) => ErrorCode::BadRequest,
+            Erreur::NonAutorise(_) => ErrorCode::Unauthorized,
+            Erreur::Interdit(_) => ErrorCode::Forbidden,
+            Erreur::Introuvable(_) => ErrorCode::NotFound,
+            Erreur::Conflit(_) => ErrorCode::Conflict,
+            Erreur::Interne(_) => ErrorCode::Internal,
+            Erreur::NomPris(_) => ErrorCode::UsernameTaken,
+            Erreur::PasMembre(_) => ErrorCode::NotRoomMember,
+            Erreur::PasAuteur(_) => ErrorCode::NotAuthor,
+            Erreur::JetonExpire(_) => ErrorCode::TokenExpired,
+        }
+    }
+
+    pub fn raison(&self) -> &str {
+        match self {
+            Erreur::Invalide(raison)
+            | Erreur::NonAutorise(raison)
+            | Erreur::Interdit(raison)
+            | Erreur::Introuvable(raison)
+            | Erreur::Conflit(raison)
+            | Erreur::Interne(raison)
+            | Erreur::NomPris(raison)
+            | Erreur::PasMembre(raison)
+            | Erreur::PasAuteur(raison)
+            | Erreur::JetonExpire(raison) => raison.as_str(),
+        }
+    }
+
+    /// Réponse JSON de l'erreur
+    pub fn serialize(&self) -> String {
+        ApiError {
+            code: self.code(),
+            reason: self.raison().to_string(),
+        }
+        .serialize()
+    }
+}
+
+impl From<rusqlite::Error> for Erreur {
+    fn from(_: rusqlite::Error) -> Erreur {
+        Erreur::Interne(String::from("Erreur interne de la base de donnée."))
+    }
+}
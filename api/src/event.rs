@@ -0,0 +1,120 @@
+//! Journal des événements envoyés dans les Event Streams
+//!
+//! Ce module conserve chaque événement envoyé à un utilisateur avec un identifiant croissant,
+//! ce qui permet à un client qui se reconnecte (en-tête `Last-Event-ID` ou paramètre `since`)
+//! de ne recevoir que les événements qu'il a manqués.
+
+use chrono::{Duration, Utc};
+use rocket::request::{FromRequest, Outcome, Request};
+use rusqlite::{Result, Transaction, TransactionBehavior};
+
+use crate::database::Database;
+
+/// Nombre de jours pendant lesquels les événements sont conservés
+pub const EVENEMENTS_RETENTION_JOURS: i64 = 7;
+
+/// Identifiant du dernier événement reçu par le client (en-tête `Last-Event-ID`)
+pub struct LastEventId(pub Option<i64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(LastEventId(
+            request
+                .headers()
+                .get_one("Last-Event-ID")
+                .and_then(|id| id.parse::<i64>().ok()),
+        ))
+    }
+}
+
+/// Événement journalisé, prêt à être envoyé aux Event Streams de ses destinataires
+pub struct Diffusion {
+    pub data: String,
+    /// (user_id, identifiant de l'événement) de chaque destinataire
+    pub destinataires: Vec<(i64, i64)>,
+}
+
+impl Database {
+    /// Enregistre un événement dans le journal de chaque utilisateur
+    ///
+    /// L'événement est enregistré pour tous ses destinataires, ou pour aucun.
+    pub fn journalise_evenement(&self, users: &[i64], data: String) -> Result<Diffusion> {
+        let transaction =
+            Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)?;
+        let destinataires = users
+            .iter()
+            .map(|user_id| Ok((*user_id, self.ajout_evenement(*user_id, data.as_str())?)))
+            .collect::<Result<Vec<(i64, i64)>>>()?;
+        transaction.commit()?;
+
+        Ok(Diffusion {
+            data,
+            destinataires,
+        })
+    }
+
+    /// Enregistre un événement destiné à un utilisateur et retourne son identifiant
+    pub fn ajout_evenement(&self, user_id: i64, data: &str) -> Result<i64> {
+        self.connection.execute(
+            "INSERT INTO event (user_id, date, data) VALUES (?1, ?2, ?3)",
+            (user_id, Utc::now().timestamp(), data),
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// Récupère les événements d'un utilisateur qui suivent l'événement `since`
+    ///
+    /// Retourne `None` si des événements qui suivent `since` ont déjà été supprimés du journal.
+    pub fn recupere_evenements(
+        &self,
+        user_id: i64,
+        since: i64,
+    ) -> Result<Option<Vec<(i64, String)>>> {
+        let premier: i64 = self.connection.query_row(
+            "
+            SELECT COALESCE(
+                (SELECT MIN(id) FROM event),
+                (SELECT seq + 1 FROM sqlite_sequence WHERE name = 'event'),
+                1
+            )
+            ",
+            [],
+            |row| row.get(0),
+        )?;
+        if since + 1 < premier {
+            return Ok(None);
+        }
+
+        let mut stmt = self
+            .connection
+            .prepare("SELECT id, data FROM event WHERE user_id = ?1 AND id > ?2 ORDER BY id")?;
+        let rows = stmt.query_map((user_id, since), |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut events = Vec::new();
+        for event in rows {
+            events.push(event?);
+        }
+
+        Ok(Some(events))
+    }
+
+    /// Récupère l'identifiant du dernier événement d'un utilisateur (0 s'il n'en a pas)
+    pub fn dernier_evenement(&self, user_id: i64) -> Result<i64> {
+        self.connection.query_row(
+            "SELECT COALESCE(MAX(id), 0) FROM event WHERE user_id = ?1",
+            [user_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Supprime les événements plus vieux que la période de rétention
+    pub fn nettoie_evenements(&self) -> Result<usize> {
+        self.connection.execute(
+            "DELETE FROM event WHERE date < ?1",
+            [(Utc::now() - Duration::days(EVENEMENTS_RETENTION_JOURS)).timestamp()],
+        )
+    }
+}
@@ -10,29 +10,77 @@ mod test_event_source;
 #[cfg(test)]
 mod tests;
 
+mod attachment;
 mod auth;
 mod cors;
 mod database;
 mod date_time_sql;
+mod erreur;
+mod event;
+mod mention;
 mod message;
+mod migration;
+mod mot_de_passe;
+mod pin;
+mod presence;
+mod reaction;
+mod retention;
 mod room;
+mod scheduled;
+mod search;
+mod session;
+mod typing;
 mod user;
 
-use database::Database;
-use message::FormMessage;
+use attachment::{Fichier, FormAttachment};
+use database::{Configuration, Pool};
+use erreur::Erreur;
+use event::{Diffusion, LastEventId};
+use lib::{
+    ApiResponse, CreatedAttachment, CreatedMessage, CreatedRoom,
+    CreatedScheduled, DeletedMessage, EditedScheduled, ErrorCode, FormAddRoom, FormAddUser,
+    FormAddUserRoom, FormChangePassword, FormDirectRoom, FormEditMessage, FormEditRoom,
+    FormEditScheduled, FormMember, FormMessage, FormReaction, FormRead, FormRefresh,
+    FormResetPassword, FormRole, FormSearch, Message, MessagePage, Pins, Replies,
+    ScheduledMessages, SearchResults, Sessions, Typing, UserProfile,
+};
+use migration::commande_migration;
+use mot_de_passe::commande_reinitialisation;
+use presence::{nombre_connexions, utilisateurs_connectes, Connexion};
+use retention::purge_donnees_expirees;
+use rocket::fairing::AdHoc;
 use rocket::form::Form;
 use rocket::fs::{relative, FileServer};
+use rocket::http::CookieJar;
 use rocket::response::stream::{Event, EventStream};
 use rocket::response::Responder;
 use rocket::tokio::select;
 use rocket::tokio::sync::broadcast::{channel, error::RecvError, Sender};
 use rocket::tokio::sync::RwLock;
 use rocket::{Build, Rocket, Shutdown, State};
-use room::{FormAddRoom, FormAddUserRoom};
+use scheduled::distribue_messages_programmes;
+use session::{Authentifie, AuthentifieNavigateur, UserAgent};
 use std::collections::HashMap;
-use user::FormAddUser;
+use std::sync::Arc;
+use typing::{Ecritures, DUREE_ECRITURE};
 
-type EventStreams = RwLock<HashMap<i64, Sender<String>>>;
+/// Événements (identifiant, données) en attente d'envoi pour chaque utilisateur
+///
+/// Les événements éphémères (non journalisés) ont l'identifiant `EVENEMENT_EPHEMERE`.
+type EventStreams = Arc<RwLock<HashMap<i64, Sender<(i64, String)>>>>;
+
+/// Identifiant des événements qui ne sont pas journalisés (voir `diffuse_ephemere`)
+const EVENEMENT_EPHEMERE: i64 = 0;
+
+/// Identifiant des messages qui ferment l'Event Stream d'une session (donnée : id de la session)
+///
+/// Ces messages ne sont pas envoyés au client (voir `session::ferme_event_stream`).
+const SESSION_FERMEE: i64 = -1;
+
+/// Nombre de messages par salon envoyés à l'ouverture de l'Event Stream et par page d'historique
+const MESSAGES_PAR_PAGE: i64 = 50;
+/// Nombre maximal de messages qu'une page d'historique peut contenir
+const MESSAGES_PAR_PAGE_MAX: i64 = 100;
 
 #[derive(Debug, Responder)]
 enum ReponseJson {
@@ -46,251 +94,1448 @@ enum ReponseJson {
     BadRequest(String),
     #[response(status = 401, content_type = "json")]
     Unauthorized(String),
+    #[response(status = 403, content_type = "json")]
+    Forbidden(String),
+    #[response(status = 404, content_type = "json")]
+    NotFound(String),
+    #[response(status = 409, content_type = "json")]
+    Conflict(String),
+    #[response(status = 500, content_type = "json")]
+    InternalServerError(String),
+}
+
+/// Réponse d'une erreur, avec le statut correspondant à son code
+impl From<Erreur> for ReponseJson {
+    fn from(erreur: Erreur) -> ReponseJson {
+        let data = erreur.serialize();
+        match erreur.code() {
+            ErrorCode::BadRequest => ReponseJson::BadRequest(data),
+            ErrorCode::Unauthorized | ErrorCode::TokenExpired => ReponseJson::Unauthorized(data),
+            ErrorCode::Forbidden | ErrorCode::NotRoomMember | ErrorCode::NotAuthor => {
+                ReponseJson::Forbidden(data)
+            }
+            ErrorCode::NotFound => ReponseJson::NotFound(data),
+            ErrorCode::Conflict | ErrorCode::UsernameTaken => ReponseJson::Conflict(data),
+            ErrorCode::Internal => ReponseJson::InternalServerError(data),
+        }
+    }
 }
 
-/// Crée un utilisateur
+/// Crée un utilisateur et lui ouvre une session
 #[post("/user", data = "<form>")]
-fn post_user(form: Form<FormAddUser>) -> ReponseJson {
-    let connection_bd = connection_bd();
-    match connection_bd.ajout_user(form.into_inner()) {
-        Ok(user) => ReponseJson::Created(format!(
-            "{{ \"user_id\": {}, \"api_key\": \"{}\" }}",
-            user.user_id, user.api_key
-        )),
-        Err(_) => {
-            ReponseJson::Unauthorized(String::from("{ \"reason\": \"Identifiant déjà pris\" }"))
+async fn post_user(
+    form: Form<FormAddUser>,
+    user_agent: UserAgent,
+    cookies: &CookieJar<'_>,
+    pool: &State<Pool>,
+) -> ReponseJson {
+    let form = form.into_inner();
+
+    match pool
+        .execute(move |connection_bd| connection_bd.ajout_user(form, user_agent.0.as_str()))
+        .await
+    {
+        Ok(session) => {
+            session::ajoute_cookie(cookies, &session);
+            ReponseJson::Created(session.to_json())
         }
+        Err(e) => ReponseJson::from(e),
     }
 }
 
 /// Récupère le nom d'un utilisateur
 #[get("/user/<user_id>")]
-fn get_user(user_id: i64) -> ReponseJson {
-    let connection_bd = connection_bd();
-    match connection_bd.user_select_id(user_id) {
-        Ok(user) => ReponseJson::Ok(format!(
-            "{{ \"user_id\": {}, \"username\": \"{}\" }}",
-            user.id, user.username
-        )),
-        Err(_) => ReponseJson::BadRequest(String::from("{ \"reason\": \"Mauvais id\" }")),
-    }
+async fn get_user(user_id: i64, pool: &State<Pool>) -> ReponseJson {
+    pool.execute(move |connection_bd| -> Result<ReponseJson, Erreur> {
+        match connection_bd.user_select_id(user_id) {
+            Ok(user) => Ok(ReponseJson::Ok(
+                UserProfile {
+                    user_id: user.id,
+                    username: user.username,
+                }
+                .to_json(),
+            )),
+            Err(Erreur::Introuvable(_)) => Err(Erreur::Introuvable(String::from("Mauvais id"))),
+            Err(e) => Err(e),
+        }
+    })
+    .await
+    .unwrap_or_else(ReponseJson::from)
 }
 
-/// Connecte l'utilisateur (crée une api_key)
+/// Connecte l'utilisateur (ouvre une session)
 #[post("/login", data = "<form>")]
-fn post_login(form: Form<FormAddUser>) -> ReponseJson {
-    let connection_bd = connection_bd();
+async fn post_login(
+    form: Form<FormAddUser>,
+    user_agent: UserAgent,
+    cookies: &CookieJar<'_>,
+    pool: &State<Pool>,
+) -> ReponseJson {
     let form = form.into_inner();
 
-    match connection_bd.connecter_utilisateur(form.username.as_str(), form.password.as_str()) {
-        Ok(auth) => ReponseJson::Accepted(format!(
-            "{{ \"user_id\": {}, \"api_key\": \"{}\" }}",
-            auth.user_id, auth.api_key
-        )),
-        Err(_) => {
-            ReponseJson::Unauthorized(String::from("{ \"reason\": \"Mauvais identifiant ou mot de passe\" }"))
+    let session = pool
+        .execute(move |connection_bd| {
+            connection_bd.connecter_utilisateur(
+                form.username.as_str(),
+                form.password.as_str(),
+                user_agent.0.as_str(),
+            )
+        })
+        .await;
+    match session {
+        Ok(session) => {
+            session::ajoute_cookie(cookies, &session);
+            ReponseJson::Accepted(session.to_json())
         }
+        Err(e) => ReponseJson::from(e),
     }
 }
 
-/// Crée un salon
-#[post("/room", data = "<form>")]
-async fn post_room(form: Form<FormAddRoom>, convs: &State<EventStreams>) -> ReponseJson {
-    let connection_bd = connection_bd();
+/// Échange le jeton de rafraîchissement contre de nouveaux jetons de session
+#[post("/refresh", data = "<form>")]
+async fn post_refresh(
+    form: Form<FormRefresh>,
+    cookies: &CookieJar<'_>,
+    pool: &State<Pool>,
+) -> ReponseJson {
+    let form = form.into_inner();
+
+    match pool
+        .execute(move |connection_bd| connection_bd.rafraichit_session(form.refresh_token.as_str()))
+        .await
+    {
+        Ok(session) => {
+            session::ajoute_cookie(cookies, &session);
+            ReponseJson::Ok(session.to_json())
+        }
+        Err(e) => ReponseJson::from(e),
+    }
+}
+
+/// Change le mot de passe de l'utilisateur et ferme ses autres sessions
+#[post("/password", data = "<form>")]
+async fn post_password(
+    form: Form<FormChangePassword>,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let session = match user.session() {
+        Ok(session) => session,
+        Err(e) => return ReponseJson::from(e),
+    };
+    let form = form.into_inner();
+
+    match pool
+        .execute(move |connection_bd| connection_bd.change_mot_de_passe(session, &form))
+        .await
+    {
+        Ok(fermees) => {
+            for session in fermees {
+                session::ferme_event_stream(event_streams, session).await;
+            }
+            ReponseJson::Ok(String::from("{}"))
+        }
+        Err(e) => ReponseJson::from(e),
+    }
+}
+
+/// Choisit un nouveau mot de passe avec un jeton de réinitialisation et ferme toutes les sessions
+#[post("/password/reset", data = "<form>")]
+async fn post_password_reset(
+    form: Form<FormResetPassword>,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
     let form = form.into_inner();
-    let user_id = form.user_id;
-
-    let user = match connection_bd.verification_api_key_de_utilisateur(form.user_id, form.api_key.as_str()) {
-        Ok(user) => user,
-        Err(_) => {
-            return ReponseJson::Unauthorized(String::from(
-                "{ \"reason\": \"Mauvais id ou api key\" }",
-            ));
+
+    match pool
+        .execute(move |connection_bd| connection_bd.reinitialise_mot_de_passe(&form))
+        .await
+    {
+        Ok(fermees) => {
+            for session in fermees {
+                session::ferme_event_stream(event_streams, session).await;
+            }
+            ReponseJson::Ok(String::from("{}"))
         }
+        Err(e) => ReponseJson::from(e),
+    }
+}
+
+/// Récupère les sessions ouvertes de l'utilisateur (une par appareil)
+#[get("/sessions")]
+async fn get_sessions(user: Authentifie, pool: &State<Pool>) -> ReponseJson {
+    let session = match user.session() {
+        Ok(session) => session,
+        Err(e) => return ReponseJson::from(e),
     };
 
-    let room = connection_bd.ajout_room(form).unwrap();
+    pool.execute(move |connection_bd| -> Result<ReponseJson, Erreur> {
+        let sessions = connection_bd.recupere_sessions(session.user_id, session.id)?;
+
+        Ok(ReponseJson::Ok(Sessions { sessions }.to_json()))
+    })
+    .await
+    .unwrap_or_else(ReponseJson::from)
+}
+
+/// Ferme une session de l'utilisateur et l'Event Stream de son appareil
+#[delete("/sessions/<session_id>")]
+async fn delete_session(
+    session_id: i64,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
 
-    let lock = convs.read().await;
-    if let Some(event_stream) = lock.get(&user_id) {
-        event_stream.send(room.serialize()).unwrap();
+    match pool
+        .execute(move |connection_bd| connection_bd.ferme_session(session_id, user_id))
+        .await
+    {
+        Ok(session) => {
+            session::ferme_event_stream(event_streams, session).await;
+            ReponseJson::Ok(String::from("{}"))
+        }
+        Err(e) => ReponseJson::from(e),
     }
+}
+
+/// Crée un salon
+#[post("/room", data = "<form>")]
+async fn post_room(
+    form: Form<FormAddRoom>,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    let form = form.into_inner();
+
+    let (reponse, diffusions) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let room = connection_bd.ajout_room(user_id, form)?;
+
+            Ok((
+                ReponseJson::Created(CreatedRoom { room_id: room.id }.to_json()),
+                vec![connection_bd.journalise_evenement(&[user_id], room.serialize())?],
+            ))
+        })
+        .await
+        .unwrap_or_else(|e| (ReponseJson::from(e), Vec::new()));
+    diffuse_evenements(event_streams, diffusions).await;
+
+    reponse
+}
+
+/// Ouvre la conversation privée avec un autre utilisateur
+///
+/// Elle n'est créée (et envoyée aux deux utilisateurs) que si elle n'existe pas déjà.
+#[post("/dm", data = "<form>")]
+async fn post_dm(
+    form: Form<FormDirectRoom>,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    let form = form.into_inner();
+
+    let resultat = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let (room, other_user_id) = connection_bd.ajout_room_directe(user_id, &form)?;
+            let nouvelle = match other_user_id {
+                Some(other_user_id) => Some((
+                    other_user_id,
+                    connection_bd.journalise_evenement(&[user_id, other_user_id], room.serialize())?,
+                )),
+                None => None,
+            };
+            Ok((room.id, nouvelle))
+        })
+        .await;
+    let (room_id, nouvelle) = match resultat {
+        Ok(resultat) => resultat,
+        Err(e) => return ReponseJson::from(e),
+    };
+    // La conversation existe déjà : elle est retournée sans être diffusée
+    let (other_user_id, diffusion) = match nouvelle {
+        Some(nouvelle) => nouvelle,
+        None => return ReponseJson::Ok(CreatedRoom { room_id }.to_json()),
+    };
+    diffuse_evenement(event_streams, diffusion).await;
+
+    let membres = [user_id, other_user_id];
+    let connectes = utilisateurs_connectes(event_streams, &membres).await;
+    // La conversation est créée même si les présences n'ont pas pu être journalisées
+    let diffusions = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let diffusions = connection_bd
+                .recupere_presences(&membres, &connectes)?
+                .into_iter()
+                .map(|presence| {
+                    let destinataire = match presence.user_id == user_id {
+                        true => other_user_id,
+                        false => user_id,
+                    };
+                    connection_bd.journalise_evenement(&[destinataire], presence.serialize())
+                })
+                .collect::<rusqlite::Result<Vec<Diffusion>>>()?;
+            Ok(diffusions)
+        })
+        .await
+        .unwrap_or_default();
+    diffuse_evenements(event_streams, diffusions).await;
 
-    ReponseJson::Created(format!("{{ \"api_key\": \"{}\" }}", user))
+    ReponseJson::Created(CreatedRoom { room_id }.to_json())
 }
 
 #[derive(Responder)]
 enum Reponse<T> {
     #[response(status = 200)]
     Ok(T),
-    #[response(status = 401, content_type = "json")]
-    Unauthorized(String),
+    Erreur(ReponseJson),
 }
 
 /// Crée l'Event Stream
-#[get("/events/<user_id>?<api_key>")]
+///
+/// Si le client donne l'identifiant du dernier événement reçu (en-tête `Last-Event-ID` ou `since`),
+/// seulement les événements manqués sont envoyés, sinon les salons, les messages récents,
+/// le dernier message lu par chaque membre des salons et la présence des contacts le sont.
+#[get("/events?<since>")]
 async fn get_events(
-    user_id: i64,
-    api_key: String,
+    since: Option<i64>,
+    last_event_id: LastEventId,
+    user: AuthentifieNavigateur,
+    pool: &State<Pool>,
     event_streams: &State<EventStreams>,
     mut end: Shutdown,
 ) -> Reponse<EventStream![]> {
-    let connection_bd = connection_bd();
-
-    let bd_user = match connection_bd.user_select_id(user_id) {
-        Ok(bd_user) => bd_user,
-        Err(_) => return Reponse::Unauthorized(String::from("Mauvais id ou api key")),
+    let session = match user.session() {
+        Ok(session) => session,
+        Err(e) => return Reponse::Erreur(ReponseJson::from(e)),
     };
-    let bd_api_key = bd_user.api_key.as_str();
-
-    if bd_api_key.eq("") || !bd_api_key.eq(api_key.as_str()) {
-        return Reponse::Unauthorized(String::from("Mauvais id ou api key"));
-    }
+    let user_id = session.user_id;
+    let pool = pool.inner().clone();
 
-    let mut event_receiver = match {
+    let event_receiver = match {
         let lock = event_streams.read().await;
         lock.get(&user_id)
             .map(|event_sender| event_sender.subscribe())
     } {
         Some(event_receiver) => event_receiver,
         None => {
-            let event_sender = Some(channel::<String>(1024).0).unwrap();
+            let event_sender = Some(channel::<(i64, String)>(1024).0).unwrap();
             let mut lock = event_streams.write().await;
             lock.insert(user_id, event_sender);
 
             lock.get(&user_id).unwrap().subscribe()
         }
     };
+    let connexion = Connexion {
+        user_id,
+        event_streams: event_streams.inner().clone(),
+        pool: pool.clone(),
+    };
+    if nombre_connexions(event_streams, user_id).await == 1 {
+        presence::connexion(&pool, event_streams, user_id).await;
+    }
 
-    let messages = connection_bd.recupere_messages(user_id).unwrap();
-    let rooms = connection_bd.recupere_rooms(user_id).unwrap();
+    let since = last_event_id.0.or(since);
+    let manques = match since {
+        Some(since) => {
+            pool.execute(move |connection_bd| -> Result<_, Erreur> {
+                Ok(connection_bd.recupere_evenements(user_id, since)?)
+            })
+            .await
+        }
+        None => Ok(None),
+    };
+    let resultat = match (manques, since) {
+        (Err(e), _) => Err(e),
+        (Ok(Some(evenements)), Some(since)) => {
+            let dernier = evenements.last().map_or(since, |(id, _)| *id);
+            Ok((evenements, dernier))
+        }
+        _ => match pool
+            .execute(move |connection_bd| -> Result<_, Erreur> {
+                Ok(connection_bd.recupere_contacts(user_id)?)
+            })
+            .await
+        {
+            Err(e) => Err(e),
+            Ok(contacts) => {
+                let connectes = utilisateurs_connectes(event_streams, &contacts).await;
+                pool.execute(move |connection_bd| -> Result<_, Erreur> {
+                    let dernier = connection_bd.dernier_evenement(user_id)?;
+                    let rooms = connection_bd.recupere_rooms(user_id)?;
+                    let messages =
+                        connection_bd.recupere_messages_recents(user_id, MESSAGES_PAR_PAGE)?;
+                    let lectures = connection_bd.recupere_lectures(user_id, None)?;
+                    let presences = connection_bd.recupere_presences(&contacts, &connectes)?;
+
+                    let evenements: Vec<(i64, String)> = rooms
+                        .iter()
+                        .map(|room| room.serialize())
+                        .chain(messages.iter().map(|message| message.serialize()))
+                        .chain(lectures.iter().map(|lecture| lecture.serialize()))
+                        .chain(presences.iter().map(|presence| presence.serialize()))
+                        .map(|data| (dernier, data))
+                        .collect();
+                    Ok((evenements, dernier))
+                })
+                .await
+            }
+        },
+    };
+    let (evenements, mut dernier) = match resultat {
+        Ok(resultat) => resultat,
+        Err(e) => return Reponse::Erreur(e.into()),
+    };
 
     Reponse::Ok(EventStream! {
-        for room in rooms {
-            yield Event::data(room.serialize());
-        };
-        for message in messages {
-            yield Event::data(message.serialize());
+        // Le récepteur doit être libéré avant la garde pour que la déconnexion soit constatée
+        let _connexion = connexion;
+        let mut event_receiver = event_receiver;
+        for (id, data) in evenements {
+            yield Event::data(data).id(id.to_string());
         };
         loop {
-            yield Event::data(select! {
+            // Un événement journalisé peut être diffusé avant un autre journalisé plus tôt,
+            // ou manqué par un récepteur en retard : ils sont relus du journal, dans l'ordre
+            let id = select! {
                 message = event_receiver.recv() => match message {
-                    Ok(message) => message,
-                    Err(RecvError::Closed) => {
-                        connection_bd.logout(user_id).unwrap();
-                        break;
-                    },
-                    Err(RecvError::Lagged(_)) => continue,
+                    Ok((id, data)) if id == SESSION_FERMEE => {
+                        if data == session.id.to_string() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Ok((id, data)) if id == EVENEMENT_EPHEMERE => {
+                        yield Event::data(data);
+                        continue;
+                    }
+                    Ok((id, _)) => Some(id),
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => None,
                 },
                 _ = &mut end => break,
-            });
+            };
+            if id.is_some_and(|id| id <= dernier) {
+                continue;
+            }
+
+            let depuis = dernier;
+            let manques = pool
+                .execute(move |connection_bd| -> Result<_, Erreur> {
+                    Ok(connection_bd.recupere_evenements(user_id, depuis)?)
+                })
+                .await;
+            // Sans les événements manqués, le client reprend avec `Last-Event-ID`
+            let evenements = match manques {
+                Ok(Some(evenements)) => evenements,
+                _ => break,
+            };
+            for (id, data) in evenements {
+                dernier = id;
+                yield Event::data(data).id(id.to_string());
+            }
         }
     })
 }
 
-/// Envoie un message
+/// Envoie un message, ou le programme si `send_at` est donné
 #[post("/message", data = "<form>")]
-async fn post_message(form: Form<FormMessage>, event_streams: &State<EventStreams>) -> ReponseJson {
-    let connection_bd = connection_bd();
+async fn post_message(
+    form: Form<FormMessage>,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
     let form = form.into_inner();
-    let room_id = form.room_id;
-
-    let user = match connection_bd.verification_api_key_de_utilisateur(form.user_id, form.api_key.as_str()) {
-        Ok(user) => user,
-        Err(_) => {
-            return ReponseJson::Unauthorized(String::from(
-                "{ \"reason\": \"Mauvais id ou api key\" }",
-            ));
-        }
+
+    // La réponse est déjà connue si le message est refusé ou programmé
+    let verification = pool
+        .execute(move |connection_bd| {
+            if let Err(e) = connection_bd.verification_message(user_id, &form) {
+                return Err(ReponseJson::from(e));
+            }
+
+            if let Some(send_at) = form.send_at {
+                return Err(match connection_bd.ajout_message_programme(user_id, &form, send_at) {
+                    Ok(programme) => ReponseJson::Accepted(CreatedScheduled { scheduled_id: programme.id }.to_json()),
+                    Err(e) => ReponseJson::from(e),
+                });
+            }
+
+            Ok(form)
+        })
+        .await;
+    let form = match verification {
+        Ok(verification) => verification,
+        Err(reponse) => return reponse,
+    };
+
+    match publie_message(pool, event_streams, user_id, form).await {
+        Ok(message) => ReponseJson::Created(CreatedMessage { message_id: message.id }.to_json()),
+        Err(e) => ReponseJson::from(e),
+    }
+}
+
+/// Enregistre un message (déjà vérifié, voir `verification_message`) et le diffuse
+/// aux membres du salon, avec le nouvel état de son fil de discussion et les mentions
+async fn publie_message(
+    pool: &Pool,
+    event_streams: &EventStreams,
+    user_id: i64,
+    form: FormMessage,
+) -> Result<Message, Erreur> {
+    let (message, diffusions) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let room_id = form.room_id;
+            let (message, mentionnes) = connection_bd.ajout_message(user_id, form)?;
+            let users = connection_bd.select_users_room(room_id)?;
+
+            let mut diffusions = vec![connection_bd.journalise_evenement(&users, message.serialize())?];
+            if let Some(parent_id) = message.parent_id {
+                let thread = connection_bd.etat_fil(parent_id, room_id)?;
+                diffusions.push(connection_bd.journalise_evenement(&users, thread.serialize())?);
+            }
+            diffusions.push(connection_bd.journalise_evenement(&mentionnes, message.serialize_mention())?);
+            Ok((message, diffusions))
+        })
+        .await?;
+    diffuse_evenements(event_streams, diffusions).await;
+
+    Ok(message)
+}
+
+/// Récupère les messages programmés de l'utilisateur
+#[get("/scheduled")]
+async fn get_scheduled(user: Authentifie, pool: &State<Pool>) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    pool.execute(move |connection_bd| -> Result<ReponseJson, Erreur> {
+        let programmes = connection_bd.recupere_messages_programmes(user_id)?;
+
+        Ok(ReponseJson::Ok(
+            ScheduledMessages {
+                scheduled: programmes,
+            }
+            .to_json(),
+        ))
+    })
+    .await
+    .unwrap_or_else(ReponseJson::from)
+}
+
+/// Modifie le texte et (ou) la date d'envoi d'un message programmé
+#[patch("/scheduled/<scheduled_id>", data = "<form>")]
+async fn patch_scheduled(
+    scheduled_id: i64,
+    form: Form<FormEditScheduled>,
+    user: Authentifie,
+    pool: &State<Pool>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    let form = form.into_inner();
+
+    pool.execute(move |connection_bd| -> Result<ReponseJson, Erreur> {
+        let programme = connection_bd.modifie_message_programme(scheduled_id, user_id, &form)?;
+
+        Ok(ReponseJson::Ok(
+            EditedScheduled {
+                scheduled: programme,
+            }
+            .to_json(),
+        ))
+    })
+    .await
+    .unwrap_or_else(ReponseJson::from)
+}
+
+/// Annule un message programmé
+#[delete("/scheduled/<scheduled_id>")]
+async fn delete_scheduled(
+    scheduled_id: i64,
+    user: Authentifie,
+    pool: &State<Pool>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+
+    pool.execute(move |connection_bd| -> Result<ReponseJson, Erreur> {
+        connection_bd.supprime_message_programme(scheduled_id, user_id)?;
+
+        Ok(ReponseJson::Ok(String::from("{}")))
+    })
+    .await
+    .unwrap_or_else(ReponseJson::from)
+}
+
+/// Envoie une pièce jointe dans un salon (à lier ensuite à un message avec `attachment_ids`)
+#[post("/room/<room_id>/attachment", data = "<form>")]
+async fn post_attachment(
+    room_id: i64,
+    form: Form<FormAttachment<'_>>,
+    user: Authentifie,
+    pool: &State<Pool>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    let mut form = form.into_inner();
+    let piece_jointe = form.piece_jointe();
+
+    let resultat = pool
+        .execute(move |connection_bd| {
+            match connection_bd.ajout_piece_jointe(room_id, user_id, piece_jointe) {
+                Ok(attachment) => {
+                    let chemin = connection_bd.chemin_piece_jointe(attachment.id);
+                    Ok((attachment, chemin))
+                }
+                Err(e) => Err(ReponseJson::from(e)),
+            }
+        })
+        .await;
+    let (attachment, chemin) = match resultat {
+        Ok(resultat) => resultat,
+        Err(reponse) => return reponse,
+    };
+
+    if form.file.move_copy_to(chemin).await.is_err() {
+        let attachment_id = attachment.id;
+        let _ = pool
+            .execute(move |connection_bd| -> Result<_, Erreur> {
+                Ok(connection_bd.supprime_piece_jointe(attachment_id)?)
+            })
+            .await;
+        return ReponseJson::from(Erreur::Interne(String::from(
+            "Le fichier n'a pas pu être enregistré.",
+        )));
+    }
+
+    ReponseJson::Created(CreatedAttachment { attachment }.to_json())
+}
+
+#[derive(Responder)]
+enum ReponseFichier {
+    #[response(status = 200)]
+    Ok(Box<Fichier>),
+    Erreur(ReponseJson),
+}
+
+/// Télécharge le fichier d'une pièce jointe (seulement pour les membres de son salon)
+#[get("/attachment/<attachment_id>")]
+async fn get_attachment(
+    attachment_id: i64,
+    user: AuthentifieNavigateur,
+    pool: &State<Pool>,
+) -> ReponseFichier {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseFichier::Erreur(ReponseJson::from(e)),
+    };
+    let resultat = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let attachment = connection_bd.recupere_piece_jointe(attachment_id, user_id)?;
+            let chemin = connection_bd.chemin_piece_jointe(attachment.id);
+            Ok((attachment, chemin))
+        })
+        .await;
+    let (attachment, chemin) = match resultat {
+        Ok(resultat) => resultat,
+        Err(e) => return ReponseFichier::Erreur(ReponseJson::from(e)),
+    };
+
+    match Fichier::ouvre(&attachment, chemin).await {
+        Ok(fichier) => ReponseFichier::Ok(Box::new(fichier)),
+        Err(_) => ReponseFichier::Erreur(ReponseJson::from(Erreur::Introuvable(String::from(
+            "Le fichier de cette pièce jointe est introuvable.",
+        )))),
+    }
+}
+
+/// Récupère une page de l'historique d'un salon
+#[get("/room/<room_id>/messages?<before>&<limit>")]
+async fn get_room_messages(
+    room_id: i64,
+    before: Option<i64>,
+    limit: Option<i64>,
+    user: Authentifie,
+    pool: &State<Pool>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    pool.execute(move |connection_bd| -> Result<ReponseJson, Erreur> {
+        connection_bd.verification_membre(user_id, room_id)?;
+
+        let limit = limit
+            .unwrap_or(MESSAGES_PAR_PAGE)
+            .clamp(1, MESSAGES_PAR_PAGE_MAX);
+        let (messages, has_more) = connection_bd
+            .recupere_messages_room(room_id, before, limit)?;
+
+        Ok(ReponseJson::Ok(MessagePage { messages, has_more }.to_json()))
+    })
+    .await
+    .unwrap_or_else(ReponseJson::from)
+}
+
+/// Recherche des messages dans les salons de l'utilisateur (voir `FormSearch` pour les filtres)
+#[get("/search?<search..>")]
+async fn get_search(
+    search: FormSearch,
+    user: Authentifie,
+    pool: &State<Pool>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    pool.execute(move |connection_bd| -> Result<ReponseJson, Erreur> {
+        let resultats = connection_bd.recherche_messages(user_id, &search)?;
+
+        Ok(ReponseJson::Ok(SearchResults { results: resultats }.to_json()))
+    })
+    .await
+    .unwrap_or_else(ReponseJson::from)
+}
+
+/// Récupère une page de la boîte de mentions de l'utilisateur, des plus récentes aux plus anciennes
+#[get("/mentions?<before>&<limit>")]
+async fn get_mentions(
+    before: Option<i64>,
+    limit: Option<i64>,
+    user: Authentifie,
+    pool: &State<Pool>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    pool.execute(move |connection_bd| -> Result<ReponseJson, Erreur> {
+        let limit = limit
+            .unwrap_or(MESSAGES_PAR_PAGE)
+            .clamp(1, MESSAGES_PAR_PAGE_MAX);
+        let (messages, has_more) = connection_bd
+            .recupere_mentions(user_id, before, limit)?;
+
+        Ok(ReponseJson::Ok(MessagePage { messages, has_more }.to_json()))
+    })
+    .await
+    .unwrap_or_else(ReponseJson::from)
+}
+
+/// Récupère les réponses à un message
+#[get("/message/<message_id>/replies")]
+async fn get_replies(
+    message_id: i64,
+    user: Authentifie,
+    pool: &State<Pool>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
     };
+    pool.execute(move |connection_bd| -> Result<ReponseJson, Erreur> {
+        let parent = connection_bd.message_select_id(message_id)?;
 
-    let message = connection_bd.ajout_message(form).unwrap().serialize();
-    let users = match connection_bd.select_users_room(room_id) {
+        connection_bd.verification_membre(user_id, parent.room_id)?;
+
+        let messages = connection_bd.recupere_reponses(message_id)?;
+
+        Ok(ReponseJson::Ok(Replies { messages }.to_json()))
+    })
+    .await
+    .unwrap_or_else(ReponseJson::from)
+}
+
+/// Modifie un message
+#[patch("/message/<message_id>", data = "<form>")]
+async fn patch_message(
+    message_id: i64,
+    form: Form<FormEditMessage>,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    let form = form.into_inner();
+
+    let (reponse, diffusions) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let (message, mentionnes) = connection_bd.modifie_message(message_id, user_id, form)?;
+
+            let users = connection_bd.select_users_room(message.room_id)?;
+            let diffusions = vec![
+                connection_bd.journalise_evenement(&users, message.serialize_edit())?,
+                connection_bd.journalise_evenement(&mentionnes, message.serialize_mention())?,
+            ];
+
+            Ok((ReponseJson::Ok(String::from("{}")), diffusions))
+        })
+        .await
+        .unwrap_or_else(|e| (ReponseJson::from(e), Vec::new()));
+    diffuse_evenements(event_streams, diffusions).await;
+
+    reponse
+}
+
+/// Supprime un message
+#[delete("/message/<message_id>")]
+async fn delete_message(
+    message_id: i64,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+
+    let (reponse, diffusions) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let message = connection_bd.supprime_message(message_id, user_id)?;
+
+            let users = connection_bd.select_users_room(message.room_id)?;
+            let deleted = DeletedMessage {
+                id: message.id,
+                room_id: message.room_id,
+            };
+            let mut diffusions = vec![connection_bd.journalise_evenement(&users, deleted.serialize())?];
+            if let Some(parent_id) = message.parent_id {
+                let thread = connection_bd.etat_fil(parent_id, message.room_id)?;
+                diffusions.push(connection_bd.journalise_evenement(&users, thread.serialize())?);
+            }
+
+            Ok((ReponseJson::Ok(String::from("{}")), diffusions))
+        })
+        .await
+        .unwrap_or_else(|e| (ReponseJson::from(e), Vec::new()));
+    diffuse_evenements(event_streams, diffusions).await;
+
+    reponse
+}
+
+/// Ajoute une réaction à un message
+#[post("/message/<message_id>/reaction", data = "<form>")]
+async fn post_reaction(
+    message_id: i64,
+    form: Form<FormReaction>,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    let form = form.into_inner();
+
+    let (reponse, diffusions) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let reaction = connection_bd.ajout_reaction(message_id, user_id, form)?;
+
+            let users = connection_bd.select_users_room(reaction.room_id)?;
+            let diffusions = vec![connection_bd.journalise_evenement(&users, reaction.serialize())?];
+
+            Ok((ReponseJson::Created(String::from("{}")), diffusions))
+        })
+        .await
+        .unwrap_or_else(|e| (ReponseJson::from(e), Vec::new()));
+    diffuse_evenements(event_streams, diffusions).await;
+
+    reponse
+}
+
+/// Retire une réaction d'un message
+#[delete("/message/<message_id>/reaction", data = "<form>")]
+async fn delete_reaction(
+    message_id: i64,
+    form: Form<FormReaction>,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    let form = form.into_inner();
+
+    let (reponse, diffusions) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let reaction = connection_bd.supprime_reaction(message_id, user_id, form)?;
+
+            let users = connection_bd.select_users_room(reaction.room_id)?;
+            let diffusions = vec![connection_bd.journalise_evenement(&users, reaction.serialize())?];
+
+            Ok((ReponseJson::Ok(String::from("{}")), diffusions))
+        })
+        .await
+        .unwrap_or_else(|e| (ReponseJson::from(e), Vec::new()));
+    diffuse_evenements(event_streams, diffusions).await;
+
+    reponse
+}
+
+/// Épingle un message dans son salon
+#[post("/message/<message_id>/pin")]
+async fn post_pin(
+    message_id: i64,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+
+    let (reponse, diffusions) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let pin = connection_bd.ajout_pin(message_id, user_id)?;
+
+            let users = connection_bd.select_users_room(pin.room_id)?;
+            let diffusions = vec![connection_bd.journalise_evenement(&users, pin.serialize())?];
+
+            Ok((ReponseJson::Created(String::from("{}")), diffusions))
+        })
+        .await
+        .unwrap_or_else(|e| (ReponseJson::from(e), Vec::new()));
+    diffuse_evenements(event_streams, diffusions).await;
+
+    reponse
+}
+
+/// Désépingle un message
+#[delete("/message/<message_id>/pin")]
+async fn delete_pin(
+    message_id: i64,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+
+    let (reponse, diffusions) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let unpin = connection_bd.supprime_pin(message_id, user_id)?;
+
+            let users = connection_bd.select_users_room(unpin.room_id)?;
+            let diffusions = vec![connection_bd.journalise_evenement(&users, unpin.serialize())?];
+
+            Ok((ReponseJson::Ok(String::from("{}")), diffusions))
+        })
+        .await
+        .unwrap_or_else(|e| (ReponseJson::from(e), Vec::new()));
+    diffuse_evenements(event_streams, diffusions).await;
+
+    reponse
+}
+
+/// Récupère les messages épinglés d'un salon
+#[get("/room/<room_id>/pins")]
+async fn get_pins(room_id: i64, user: Authentifie, pool: &State<Pool>) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    pool.execute(move |connection_bd| -> Result<ReponseJson, Erreur> {
+        connection_bd.verification_membre(user_id, room_id)?;
+
+        let pins = connection_bd.recupere_pins(room_id)?;
+
+        Ok(ReponseJson::Ok(Pins { pins }.to_json()))
+    })
+    .await
+    .unwrap_or_else(ReponseJson::from)
+}
+
+/// Marque les messages d'un salon comme lus jusqu'à un message
+#[post("/room/<room_id>/read", data = "<form>")]
+async fn post_read(
+    room_id: i64,
+    form: Form<FormRead>,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    let form = form.into_inner();
+
+    let (reponse, diffusions) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let lecture = connection_bd.marque_lu(room_id, user_id, &form)?;
+
+            let users = connection_bd.select_users_room(room_id)?;
+            let diffusions = vec![connection_bd.journalise_evenement(&users, lecture.serialize())?];
+
+            Ok((ReponseJson::Ok(String::from("{}")), diffusions))
+        })
+        .await
+        .unwrap_or_else(|e| (ReponseJson::from(e), Vec::new()));
+    diffuse_evenements(event_streams, diffusions).await;
+
+    reponse
+}
+
+/// Indique que l'utilisateur écrit dans un salon
+///
+/// Les autres membres du salon sont avertis, puis avertis à nouveau quand l'indicateur expire.
+#[post("/room/<room_id>/typing")]
+async fn post_typing(
+    room_id: i64,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+    ecritures: &State<Ecritures>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+
+    let users = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            connection_bd.verification_membre(user_id, room_id)?;
+
+            Ok(connection_bd
+                .select_users_room(room_id)?
+                .into_iter()
+                .filter(|membre| *membre != user_id)
+                .collect::<Vec<i64>>())
+        })
+        .await;
+    let users = match users {
         Ok(users) => users,
-        Err(_) => {
-            return ReponseJson::BadRequest(String::from("{ \"reason\": \"Ce salon n'exists pas\" }"))
-        }
+        Err(e) => return ReponseJson::from(e),
     };
 
-    let lock = event_streams.read().await;
-    for user_id in users {
-        if let Some(event_stream) = lock.get(&user_id) {
-            event_stream.send(message.to_string()).unwrap();
-        }
+    let mut typing = Typing {
+        room_id,
+        user_id,
+        typing: true,
+    };
+    if ecritures.commence(room_id, user_id) {
+        diffuse_ephemere(event_streams, &users, typing.serialize()).await;
     }
 
-    ReponseJson::Created(format!("{{ \"api_key\": \"{}\" }}", user))
+    let event_senders: Vec<Sender<(i64, String)>> = {
+        let lock = event_streams.read().await;
+        users
+            .iter()
+            .filter_map(|user_id| lock.get(user_id).cloned())
+            .collect()
+    };
+    let ecritures = ecritures.inner().clone();
+    rocket::tokio::spawn(async move {
+        rocket::tokio::time::sleep(DUREE_ECRITURE).await;
+        if ecritures.termine_si_expire(typing.room_id, typing.user_id) {
+            typing.typing = false;
+            for event_sender in event_senders {
+                let _ = event_sender.send((EVENEMENT_EPHEMERE, typing.serialize()));
+            }
+        }
+    });
+
+    ReponseJson::Accepted(String::from("{}"))
 }
 
 /// Invite un utilisateur dans un salon
 #[post("/invite", data = "<form>")]
 async fn post_invite(
     form: Form<FormAddUserRoom>,
+    user: Authentifie,
+    pool: &State<Pool>,
     event_streams: &State<EventStreams>,
 ) -> ReponseJson {
-    let connection_bd = connection_bd();
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
     let form = form.into_inner();
-    let user = match connection_bd.verification_api_key_de_utilisateur(form.user_id, form.api_key.as_str()) {
-        Ok(user) => user,
-        Err(_) => {
-            return ReponseJson::Unauthorized(String::from(
-                "{ \"reason\": \"Mauvais id ou api key\" }",
-            ));
-        }
+
+    let resultat = pool
+        .execute(move |connection_bd| {
+            let invitation = || -> Result<_, Erreur> {
+                let (room, other_user_id) = connection_bd.ajout_user_room(user_id, form)?;
+                let room = connection_bd.recupere_room(other_user_id, room.id)?;
+                let (messages, _) =
+                    connection_bd.recupere_messages_room(room.id, None, MESSAGES_PAR_PAGE)?;
+                let lectures = connection_bd.recupere_lectures(other_user_id, Some(room.id))?;
+
+                let diffusions: Vec<Diffusion> = [room.serialize()]
+                    .into_iter()
+                    .chain(messages.iter().map(|message| message.serialize()))
+                    .chain(lectures.iter().map(|lecture| lecture.serialize()))
+                    .map(|data| connection_bd.journalise_evenement(&[other_user_id], data))
+                    .collect::<rusqlite::Result<_>>()?;
+
+                let membres: Vec<i64> = connection_bd
+                    .select_users_room(room.id)?
+                    .into_iter()
+                    .filter(|user_id| *user_id != other_user_id)
+                    .collect();
+                Ok((room.id, other_user_id, membres, diffusions))
+            };
+
+            invitation().map_err(ReponseJson::from)
+        })
+        .await;
+    let (room_id, other_user_id, membres, diffusions) = match resultat {
+        Ok(resultat) => resultat,
+        Err(reponse) => return reponse,
     };
+    diffuse_evenements(event_streams, diffusions).await;
 
-    let room = match connection_bd.ajout_user_room(form) {
-        Ok(room) => room,
-        Err(e) => {
-            return ReponseJson::BadRequest(format!(
-                "{{ \"api_key\": \"{}\", \"reason\": \"{}\" }}",
-                user, e
-            ));
-        }
+    let connectes = utilisateurs_connectes(event_streams, &membres).await;
+    let invite_connecte = utilisateurs_connectes(event_streams, &[other_user_id]).await;
+    // L'invitation est faite même si les présences n'ont pas pu être journalisées
+    let diffusions = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let mut diffusions = connection_bd
+                .recupere_presences(&membres, &connectes)?
+                .into_iter()
+                .map(|presence| {
+                    connection_bd.journalise_evenement(&[other_user_id], presence.serialize())
+                })
+                .collect::<rusqlite::Result<Vec<Diffusion>>>()?;
+            let membre = connection_bd.recupere_membre(room_id, other_user_id)?;
+            diffusions.push(connection_bd.journalise_evenement(&membres, membre.serialize())?);
+            for presence in connection_bd.recupere_presences(&[other_user_id], &invite_connecte)? {
+                diffusions.push(connection_bd.journalise_evenement(&membres, presence.serialize())?);
+            }
+            Ok(diffusions)
+        })
+        .await
+        .unwrap_or_default();
+    diffuse_evenements(event_streams, diffusions).await;
+
+    ReponseJson::Created(String::from("{}"))
+}
+
+/// Modifie le nom et (ou) le sujet d'un salon
+#[patch("/room/<room_id>", data = "<form>")]
+async fn patch_room(
+    room_id: i64,
+    form: Form<FormEditRoom>,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    let form = form.into_inner();
+
+    let (reponse, diffusions) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let settings = connection_bd.modifie_room(room_id, user_id, &form)?;
+
+            let users = connection_bd.select_users_room(room_id)?;
+            let diffusions = vec![connection_bd.journalise_evenement(&users, settings.serialize())?];
+
+            Ok((ReponseJson::Ok(String::from("{}")), diffusions))
+        })
+        .await
+        .unwrap_or_else(|e| (ReponseJson::from(e), Vec::new()));
+    diffuse_evenements(event_streams, diffusions).await;
+
+    reponse
+}
+
+/// Quitte un salon
+#[post("/room/<room_id>/leave")]
+async fn post_leave(
+    room_id: i64,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
     };
 
+    let (reponse, diffusions) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let (retire, proprietaire) = connection_bd.quitte_room(room_id, user_id)?;
+
+            let restants = connection_bd.select_users_room(room_id)?;
+            let mut diffusions = vec![connection_bd.journalise_evenement(&restants, retire.serialize())?];
+            if let Some(proprietaire) = proprietaire {
+                diffusions.push(connection_bd.journalise_evenement(&restants, proprietaire.serialize())?);
+            }
+            diffusions.push(connection_bd.journalise_evenement(&[retire.user_id], retire.serialize_room_remove())?);
+
+            Ok((ReponseJson::Ok(String::from("{}")), diffusions))
+        })
+        .await
+        .unwrap_or_else(|e| (ReponseJson::from(e), Vec::new()));
+    diffuse_evenements(event_streams, diffusions).await;
+
+    reponse
+}
+
+/// Expulse un membre d'un salon
+#[post("/room/<room_id>/kick", data = "<form>")]
+async fn post_kick(
+    room_id: i64,
+    form: Form<FormMember>,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    expulse(room_id, user_id, form.into_inner(), false, pool, event_streams).await
+}
+
+/// Expulse un membre d'un salon et l'empêche d'y être invité à nouveau
+#[post("/room/<room_id>/ban", data = "<form>")]
+async fn post_ban(
+    room_id: i64,
+    form: Form<FormMember>,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    expulse(room_id, user_id, form.into_inner(), true, pool, event_streams).await
+}
+
+/// Retire un membre d'un salon et l'annonce aux membres restants et à lui
+async fn expulse(
+    room_id: i64,
+    user_id: i64,
+    form: FormMember,
+    ban: bool,
+    pool: &Pool,
+    event_streams: &EventStreams,
+) -> ReponseJson {
+    let (reponse, diffusions) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let retire = connection_bd.expulse_membre(room_id, user_id, &form, ban)?;
+
+            let restants = connection_bd.select_users_room(room_id)?;
+            let diffusions = vec![
+                connection_bd.journalise_evenement(&restants, retire.serialize())?,
+                connection_bd.journalise_evenement(&[retire.user_id], retire.serialize_room_remove())?,
+            ];
+
+            Ok((ReponseJson::Ok(String::from("{}")), diffusions))
+        })
+        .await
+        .unwrap_or_else(|e| (ReponseJson::from(e), Vec::new()));
+    diffuse_evenements(event_streams, diffusions).await;
+
+    reponse
+}
+
+/// Change le rôle d'un membre d'un salon
+#[post("/room/<room_id>/role", data = "<form>")]
+async fn post_role(
+    room_id: i64,
+    form: Form<FormRole>,
+    user: Authentifie,
+    pool: &State<Pool>,
+    event_streams: &State<EventStreams>,
+) -> ReponseJson {
+    let user_id = match user.user_id() {
+        Ok(user_id) => user_id,
+        Err(e) => return ReponseJson::from(e),
+    };
+    let form = form.into_inner();
+
+    let (reponse, diffusions) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let membre = connection_bd.change_role(room_id, user_id, &form)?;
+
+            let users = connection_bd.select_users_room(room_id)?;
+            let diffusions = vec![connection_bd.journalise_evenement(&users, membre.serialize())?];
+
+            Ok((ReponseJson::Ok(String::from("{}")), diffusions))
+        })
+        .await
+        .unwrap_or_else(|e| (ReponseJson::from(e), Vec::new()));
+    diffuse_evenements(event_streams, diffusions).await;
+
+    reponse
+}
+
+/// Envoie un événement journalisé (voir `Database::journalise_evenement`) aux Event Streams ouverts
+async fn diffuse_evenement(event_streams: &EventStreams, diffusion: Diffusion) {
     let lock = event_streams.read().await;
-    if let Some(event_stream) = lock.get(&room.1) {
-        event_stream.send(room.0.serialize()).unwrap();
-        for message in connection_bd.recupere_messages(room.1).unwrap() {
-            event_stream.send(message.serialize()).unwrap();
+    for (user_id, id) in diffusion.destinataires {
+        if let Some(event_stream) = lock.get(&user_id) {
+            let _ = event_stream.send((id, diffusion.data.to_string()));
         }
     }
-
-    ReponseJson::Created(format!("{{ \"api_key\": \"{}\" }}", user))
 }
 
-static mut IS_UNIT_TEST: bool = false;
-fn connection_bd() -> Database {
-    Database::new(unsafe { IS_UNIT_TEST }).unwrap()
+/// Envoie des événements journalisés aux Event Streams ouverts, dans l'ordre
+async fn diffuse_evenements(event_streams: &EventStreams, diffusions: Vec<Diffusion>) {
+    for diffusion in diffusions {
+        diffuse_evenement(event_streams, diffusion).await;
+    }
 }
 
-pub fn build(is_unit_test: bool) -> Rocket<Build> {
-    let c: EventStreams = RwLock::new(HashMap::<i64, Sender<String>>::new());
-    unsafe {
-        IS_UNIT_TEST = is_unit_test;
+/// Envoie un événement éphémère (qui n'est pas journalisé) aux Event Streams ouverts
+async fn diffuse_ephemere(event_streams: &EventStreams, users: &[i64], data: String) {
+    let lock = event_streams.read().await;
+    for user_id in users {
+        if let Some(event_stream) = lock.get(user_id) {
+            let _ = event_stream.send((EVENEMENT_EPHEMERE, data.to_string()));
+        }
     }
-    connection_bd().cree_tables().unwrap();
+}
+
+pub fn build(configuration: Configuration) -> Rocket<Build> {
+    let c: EventStreams = Arc::new(RwLock::new(HashMap::<i64, Sender<(i64, String)>>::new()));
+    let pool = Pool::new(&configuration).unwrap();
+    let intervalle_purge = configuration.intervalle_purge;
+    let connection_bd = pool.connection().unwrap();
+    connection_bd.migre().unwrap();
+    connection_bd.nettoie_evenements().unwrap();
+    connection_bd.nettoie_sessions().unwrap();
+    std::fs::create_dir_all(connection_bd.dossier_pieces_jointes()).unwrap();
+    drop(connection_bd);
 
     rocket::build()
-        .attach(crate::cors::CORS)
+        .attach(crate::cors::CORS {
+            origines: configuration.origines_front.clone(),
+        })
         .manage(c)
+        .manage(pool)
+        .manage(Ecritures::default())
+        .attach(AdHoc::on_liftoff("Messages programmés", |rocket| {
+            Box::pin(async move {
+                let pool = rocket.state::<Pool>().unwrap().clone();
+                let event_streams = rocket.state::<EventStreams>().unwrap().clone();
+                rocket::tokio::spawn(distribue_messages_programmes(pool, event_streams));
+            })
+        }))
+        .attach(AdHoc::on_liftoff("Données expirées", move |rocket| {
+            Box::pin(async move {
+                let pool = rocket.state::<Pool>().unwrap().clone();
+                let event_streams = rocket.state::<EventStreams>().unwrap().clone();
+                rocket::tokio::spawn(purge_donnees_expirees(
+                    pool,
+                    event_streams,
+                    intervalle_purge,
+                ));
+            })
+        }))
         .mount(
             "/",
             routes![
                 post_user,
                 post_login,
+                post_refresh,
+                post_password,
+                post_password_reset,
+                get_sessions,
+                delete_session,
                 get_events,
                 get_user,
                 post_message,
+                get_scheduled,
+                patch_scheduled,
+                delete_scheduled,
+                post_attachment,
+                get_attachment,
+                get_room_messages,
+                get_replies,
+                get_search,
+                get_mentions,
+                patch_message,
+                delete_message,
+                post_reaction,
+                delete_reaction,
+                post_pin,
+                delete_pin,
+                get_pins,
                 post_room,
-                post_invite
+                post_read,
+                post_typing,
+                post_invite,
+                post_dm,
+                patch_room,
+                post_leave,
+                post_kick,
+                post_ban,
+                post_role,
+                cors::preflight
             ],
         )
         .mount("/", FileServer::from(relative!("static")))
 }
 
-#[launch]
-fn rocket() -> _ {
-    build(false)
+/// Démarre le serveur, ou exécute une commande de migration (voir `commande_migration`)
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    match arguments.first() {
+        Some(commande) => {
+            let pool = Pool::new(&Configuration::depuis_env()).unwrap();
+            let connection_bd = pool.connection().unwrap();
+            let resultat = match commande.as_str() {
+                "--reset-password" => {
+                    commande_reinitialisation(&connection_bd, arguments.get(1).map(String::as_str))
+                }
+                _ => commande_migration(&connection_bd, commande.as_str()),
+            };
+            match resultat {
+                Ok(sortie) => println!("{}", sortie),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => {
+            let _ = rocket::execute(build(Configuration::depuis_env()).launch());
+        }
+    }
 }
@@ -0,0 +1,116 @@
+//! Gestion des mentions dans les messages
+//!
+//! Ce module implémente des méthodes pour retrouver les utilisateurs mentionnés (`@username`)
+//! dans le texte d'un message, les enregistrer et récupérer les messages où un utilisateur
+//! est mentionné (sa boîte de mentions). Seuls les membres du salon peuvent être mentionnés.
+
+use lib::{split_mentions, Message};
+use rusqlite::Result;
+
+use crate::database::Database;
+use crate::message::{map_message, MESSAGE_COLONNES};
+
+impl Database {
+    /// Enregistre les mentions du texte d'un message, en retirant celles qui n'y sont plus
+    ///
+    /// Retourne les utilisateurs qui viennent d'être mentionnés (sans l'auteur du message).
+    pub fn synchronise_mentions(&self, message: &Message) -> Result<Vec<i64>> {
+        let mut mentionnes = Vec::new();
+        for (mention, _) in split_mentions(message.text.as_str())
+            .into_iter()
+            .filter(|(_, est_mention)| *est_mention)
+        {
+            let user_id: Option<i64> = self
+                .connection
+                .query_row(
+                    "
+                    SELECT user.id FROM user
+                    INNER JOIN user_room ON user_room.user_id = user.id AND user_room.room_id = ?2
+                    WHERE user.username = ?1
+                    ",
+                    (&mention[1..], message.room_id),
+                    |row| row.get(0),
+                )
+                .ok();
+            match user_id {
+                Some(user_id) if user_id != message.user_id && !mentionnes.contains(&user_id) => {
+                    mentionnes.push(user_id)
+                }
+                _ => {}
+            }
+        }
+
+        self.connection.execute(
+            format!(
+                "DELETE FROM mention WHERE message_id = ?1 AND user_id NOT IN ({})",
+                mentionnes
+                    .iter()
+                    .map(|user_id| user_id.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+            .as_str(),
+            [message.id],
+        )?;
+
+        let mut nouveaux = Vec::new();
+        for user_id in mentionnes {
+            let ajoute = self.connection.execute(
+                "INSERT OR IGNORE INTO mention (message_id, user_id) VALUES (?1, ?2)",
+                (message.id, user_id),
+            )?;
+            if ajoute > 0 {
+                nouveaux.push(user_id);
+            }
+        }
+
+        Ok(nouveaux)
+    }
+
+    /// Supprime les mentions d'un message et de ses réponses
+    pub fn supprime_mentions(&self, message_id: i64) -> Result<usize> {
+        self.connection.execute(
+            "DELETE FROM mention WHERE message_id IN (SELECT id FROM message WHERE id = ?1 OR parent_id = ?1)",
+            [message_id],
+        )
+    }
+
+    /// Récupère une page des messages où un utilisateur est mentionné, plus anciens que le curseur `before`
+    ///
+    /// Seuls les salons dont l'utilisateur fait encore partie sont gardés. Les messages sont
+    /// retournés du plus récent au plus ancien, avec `true` s'il en reste d'autres.
+    pub fn recupere_mentions(
+        &self,
+        user_id: i64,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<(Vec<Message>, bool)> {
+        let mut stmt = self.connection.prepare(
+            format!(
+                "
+                SELECT {MESSAGE_COLONNES} FROM mention
+                INNER JOIN message ON message.id = mention.message_id
+                INNER JOIN user_room ON user_room.room_id = message.room_id AND user_room.user_id = mention.user_id
+                WHERE mention.user_id = ?1 AND (?2 IS NULL OR message.id < ?2)
+                ORDER BY message.id DESC
+                LIMIT ?3
+                "
+            )
+            .as_str(),
+        )?;
+        let rows = stmt.query_map((user_id, before, limit + 1), map_message)?;
+
+        let mut messages = Vec::new();
+        for message in rows {
+            let mut message = message?;
+            self.ajoute_reactions(&mut message)?;
+            self.ajoute_pieces_jointes(&mut message)?;
+            messages.push(message);
+        }
+
+        let has_more = messages.len() as i64 > limit;
+        messages.truncate(limit as usize);
+
+        Ok((messages, has_more))
+    }
+}
@@ -1,67 +1,331 @@
 //! Gestion des messages dans une application de chat
 //!
-//! Ce module implémente des méthodes pour gérer l'ajout de messages dans les salons de discussion,
-//! ainsi que la récupération de tous les messages associés à un utilisateur dans une base de données.
-
-use chrono::Utc;
-use lib::Message;
-use rocket::serde::{Deserialize, Serialize};
-use rusqlite::{Result, Row};
-
-use crate::{database::Database, date_time_sql::DateTimeSql};
-
-#[derive(Debug, Clone, FromForm, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq, UriDisplayQuery))]
-#[serde(crate = "rocket::serde")]
-pub struct FormMessage {
-    pub user_id: i64,
-    pub api_key: String,
-    pub room_id: i64,
-    pub text: String,
-}
+//! Ce module implémente des méthodes pour gérer l'ajout, la modification et la suppression de messages
+//! dans les salons de discussion et leurs fils de réponses, ainsi que la récupération des messages
+//! associés à un utilisateur dans une base de données.
+
+use chrono::{DateTime, Duration, Utc};
+use lib::{FormEditMessage, FormMessage, Message, Thread};
+use rusqlite::{Result, Row, Transaction, TransactionBehavior};
+
+use crate::{database::Database, date_time_sql::DateTimeSql, erreur::Erreur};
+
+/// Colonnes d'un message (voir `map_message`), avec son nombre de réponses
+pub const MESSAGE_COLONNES: &str =
+    "message.id, message.date, message.room_id, message.user_id, message.text, message.parent_id,
+    (SELECT COUNT(*) FROM message AS reponse WHERE reponse.parent_id = message.id), message.expires_at";
+
+/// Durée de vie maximale en secondes d'un message éphémère (un an)
+pub const DUREE_VIE_MAX: i64 = 365 * 24 * 3600;
 
 impl Database {
     /// Ajoute un message dans un salon
-    pub fn ajout_message(&self, form: FormMessage) -> Result<Message> {
+    ///
+    /// Le message, ses pièces jointes et ses mentions sont enregistrés ensemble.
+    /// Retourne aussi les utilisateurs qui y sont mentionnés (voir `synchronise_mentions`).
+    pub fn ajout_message(
+        &self,
+        user_id: i64,
+        form: FormMessage,
+    ) -> Result<(Message, Vec<i64>), Erreur> {
         let now = Utc::now();
+        let expires_at = match form.ttl {
+            Some(ttl) => Some(expiration(now, ttl)?),
+            None => None,
+        };
+        let transaction =
+            Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)?;
         self.connection.execute(
-            "INSERT INTO message (date, room_id, user_id, text) VALUES (?1, ?2, ?3, ?4)",
+            "INSERT INTO message (date, room_id, user_id, text, parent_id, expires_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
             (
                 now.timestamp(),
                 form.room_id,
-                form.user_id,
+                user_id,
                 form.text.to_string(),
+                form.parent_id,
+                expires_at.map(|expires_at| expires_at.timestamp()),
             ),
         )?;
 
-        Ok(Message {
+        let mut message = Message {
+            id: self.connection.last_insert_rowid(),
             date: now,
             room_id: form.room_id,
-            user_id: form.user_id,
+            user_id,
             text: form.text,
-        })
+            parent_id: form.parent_id,
+            reply_count: 0,
+            reactions: Vec::new(),
+            attachments: Vec::new(),
+            expires_at,
+        };
+        self.lie_pieces_jointes(&mut message, &form.attachment_ids)?;
+        let mentionnes = self.synchronise_mentions(&message)?;
+        transaction.commit()?;
+
+        Ok((message, mentionnes))
+    }
+
+    /// Vérifie qu'un utilisateur peut envoyer un message : il fait partie du salon,
+    /// le message auquel il répond et les pièces jointes y sont, et sa durée de vie est positive
+    /// (au plus `DUREE_VIE_MAX`)
+    pub fn verification_message(&self, user_id: i64, form: &FormMessage) -> Result<(), Erreur> {
+        match self.est_dans_room(user_id, form.room_id) {
+            Ok(true) => {}
+            _ => return Err(Erreur::PasMembre(String::from("Tu n'es pas dans ce salon."))),
+        }
+        if form.ttl.is_some_and(|ttl| ttl <= 0) {
+            return Err(Erreur::Invalide(String::from(
+                "La durée de vie d'un message doit être positive.",
+            )));
+        }
+        if form.ttl.is_some_and(|ttl| ttl > DUREE_VIE_MAX) {
+            return Err(duree_vie_trop_longue());
+        }
+        if let Some(parent_id) = form.parent_id {
+            self.verification_parent(parent_id, form.room_id)?;
+        }
+
+        self.verification_pieces_jointes(&form.attachment_ids, user_id, form.room_id)
+    }
+
+    /// Vérifie qu'on peut répondre à un message dans un salon
+    pub fn verification_parent(&self, parent_id: i64, room_id: i64) -> Result<(), Erreur> {
+        let parent = self.message_select_id(parent_id)?;
+
+        if parent.room_id != room_id {
+            return Err(Erreur::Invalide(String::from(
+                "Ce message n'est pas dans ce salon.",
+            )));
+        }
+        if parent.parent_id.is_some() {
+            return Err(Erreur::Invalide(String::from(
+                "On ne peut pas répondre à une réponse.",
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Modifie le texte d'un message (seulement par son auteur)
+    ///
+    /// Retourne aussi les utilisateurs qui viennent d'y être mentionnés.
+    pub fn modifie_message(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        form: FormEditMessage,
+    ) -> Result<(Message, Vec<i64>), Erreur> {
+        let mut message = self.verification_auteur_message(message_id, user_id)?;
+
+        self.connection.execute(
+            "UPDATE message SET text = ?1 WHERE id = ?2",
+            (form.text.as_str(), message_id),
+        )?;
+
+        message.text = form.text;
+        let mentionnes = self.synchronise_mentions(&message)?;
+
+        Ok((message, mentionnes))
+    }
+
+    /// Supprime un message et ses réponses (seulement par son auteur)
+    pub fn supprime_message(&self, message_id: i64, user_id: i64) -> Result<Message, Erreur> {
+        let message = self.verification_auteur_message(message_id, user_id)?;
+        self.efface_message(message_id)?;
+
+        Ok(message)
+    }
+
+    /// Supprime définitivement un message et ses réponses, avec leurs réactions, pièces jointes,
+    /// mentions et épingles
+    ///
+    /// Retourne `false` si le message avait déjà été supprimé entre-temps.
+    pub fn efface_message(&self, message_id: i64) -> Result<bool, Erreur> {
+        let transaction =
+            Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)?;
+        self.supprime_reactions(message_id)?;
+        let pieces_jointes = self.supprime_pieces_jointes(message_id)?;
+        self.supprime_mentions(message_id)?;
+        self.supprime_pins(message_id)?;
+        let supprimes = transaction.execute(
+            "DELETE FROM message WHERE id = ?1 OR parent_id = ?1",
+            [message_id],
+        )?;
+        transaction.commit()?;
+        self.supprime_fichiers(&pieces_jointes);
+
+        Ok(supprimes > 0)
+    }
+
+    /// Récupère un message
+    pub fn message_select_id(&self, message_id: i64) -> Result<Message, Erreur> {
+        let mut stmt = self
+            .connection
+            .prepare(format!("SELECT {MESSAGE_COLONNES} FROM message WHERE id = ?1").as_str())?;
+
+        let mut rows = stmt.query_map([message_id], map_message)?;
+
+        let mut message = match rows.next() {
+            Some(Ok(bd_message)) => bd_message,
+            _ => {
+                return Err(Erreur::Introuvable(format!(
+                    "no message with the id {}",
+                    message_id
+                )))
+            }
+        };
+        self.ajoute_reactions(&mut message)?;
+        self.ajoute_pieces_jointes(&mut message)?;
+
+        Ok(message)
     }
 
-    /// Récupère tous les messages
-    pub fn recupere_messages(&self, user_id: i64) -> Result<Vec<Message>> {
-        let mut stmt =
-        self.connection.prepare("SELECT message.date, message.room_id, message.user_id, message.text FROM user_room INNER JOIN message ON message.room_id = user_room.room_id WHERE user_room.user_id = ?1 ORDER BY message.date")?;
-        let rows = stmt.query_map([user_id], map_message)?;
+    /// Récupère les `limit` derniers messages (hors réponses) de chaque salon d'un utilisateur
+    pub fn recupere_messages_recents(&self, user_id: i64, limit: i64) -> Result<Vec<Message>> {
+        let mut stmt = self.connection.prepare(
+            format!(
+                "
+                SELECT {MESSAGE_COLONNES} FROM message WHERE message.id IN (
+                    SELECT id FROM (
+                        SELECT message.id,
+                            ROW_NUMBER() OVER (PARTITION BY message.room_id ORDER BY message.id DESC) AS rang
+                        FROM user_room INNER JOIN message ON message.room_id = user_room.room_id
+                        WHERE user_room.user_id = ?1 AND message.parent_id IS NULL
+                    )
+                    WHERE rang <= ?2
+                )
+                ORDER BY message.id
+                "
+            )
+            .as_str(),
+        )?;
+        let rows = stmt.query_map((user_id, limit), map_message)?;
 
         let mut messages = Vec::new();
         for message in rows {
-            messages.push(message?);
+            let mut message = message?;
+            self.ajoute_reactions(&mut message)?;
+            self.ajoute_pieces_jointes(&mut message)?;
+            messages.push(message);
         }
 
         Ok(messages)
     }
+
+    /// Récupère une page de messages (hors réponses) d'un salon, plus anciens que le curseur `before`
+    ///
+    /// Les messages sont retournés du plus ancien au plus récent,
+    /// avec `true` s'il reste des messages plus anciens.
+    pub fn recupere_messages_room(
+        &self,
+        room_id: i64,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<(Vec<Message>, bool)> {
+        let mut stmt = self.connection.prepare(
+            format!(
+                "
+                SELECT {MESSAGE_COLONNES} FROM message
+                WHERE room_id = ?1 AND parent_id IS NULL AND (?2 IS NULL OR id < ?2)
+                ORDER BY id DESC
+                LIMIT ?3
+                "
+            )
+            .as_str(),
+        )?;
+        let rows = stmt.query_map((room_id, before, limit + 1), map_message)?;
+
+        let mut messages = Vec::new();
+        for message in rows {
+            let mut message = message?;
+            self.ajoute_reactions(&mut message)?;
+            self.ajoute_pieces_jointes(&mut message)?;
+            messages.push(message);
+        }
+
+        let has_more = messages.len() as i64 > limit;
+        messages.truncate(limit as usize);
+        messages.reverse();
+
+        Ok((messages, has_more))
+    }
+
+    /// Récupère toutes les réponses à un message
+    pub fn recupere_reponses(&self, parent_id: i64) -> Result<Vec<Message>> {
+        let mut stmt = self.connection.prepare(
+            format!("SELECT {MESSAGE_COLONNES} FROM message WHERE parent_id = ?1 ORDER BY id")
+                .as_str(),
+        )?;
+        let rows = stmt.query_map([parent_id], map_message)?;
+
+        let mut messages = Vec::new();
+        for message in rows {
+            let mut message = message?;
+            self.ajoute_reactions(&mut message)?;
+            self.ajoute_pieces_jointes(&mut message)?;
+            messages.push(message);
+        }
+
+        Ok(messages)
+    }
+
+    /// Récupère l'état d'un fil de discussion (son nombre de réponses)
+    pub fn etat_fil(&self, parent_id: i64, room_id: i64) -> Result<Thread> {
+        Ok(Thread {
+            parent_id,
+            room_id,
+            reply_count: self.connection.query_row(
+                "SELECT COUNT(*) FROM message WHERE parent_id = ?1",
+                [parent_id],
+                |row| row.get(0),
+            )?,
+        })
+    }
+
+    fn verification_auteur_message(
+        &self,
+        message_id: i64,
+        user_id: i64,
+    ) -> Result<Message, Erreur> {
+        let message = self.message_select_id(message_id)?;
+
+        if message.user_id != user_id {
+            return Err(Erreur::PasAuteur(String::from(
+                "Tu ne peux pas modifier le message de quelqu'un d'autre.",
+            )));
+        }
+
+        Ok(message)
+    }
+}
+
+/// Date d'expiration d'un message éphémère envoyé à `date`
+fn expiration(date: DateTime<Utc>, ttl: i64) -> Result<DateTime<Utc>, Erreur> {
+    Duration::try_seconds(ttl)
+        .and_then(|duree| date.checked_add_signed(duree))
+        .ok_or_else(duree_vie_trop_longue)
+}
+
+fn duree_vie_trop_longue() -> Erreur {
+    Erreur::Invalide(String::from(
+        "La durée de vie d'un message ne peut pas dépasser un an.",
+    ))
 }
 
-fn map_message(row: &Row) -> Result<Message> {
+pub fn map_message(row: &Row) -> Result<Message> {
     Ok(Message {
-        date: DateTimeSql::parse(row.get(0)?).unwrap(),
-        room_id: row.get(1)?,
-        user_id: row.get(2)?,
-        text: row.get(3)?,
+        id: row.get(0)?,
+        date: DateTimeSql::parse(row.get(1)?).unwrap(),
+        room_id: row.get(2)?,
+        user_id: row.get(3)?,
+        text: row.get(4)?,
+        parent_id: row.get(5)?,
+        reply_count: row.get(6)?,
+        reactions: Vec::new(),
+        attachments: Vec::new(),
+        expires_at: row
+            .get::<usize, Option<i64>>(7)?
+            .and_then(DateTimeSql::parse),
     })
 }
@@ -0,0 +1,218 @@
+//! Migrations du schéma de la base de donnée
+//!
+//! Ce module applique dans l'ordre les migrations embarquées (dossier `migrations`) qui n'ont pas
+//! encore été appliquées, et les enregistre dans la table `schema_version`. Une base créée avant
+//! les migrations est au schéma initial (la première migration ne fait alors rien).
+//!
+//! Chaque migration est appliquée dans une transaction, avec sa version : une migration
+//! qui échoue ne laisse pas la base à moitié migrée. Les clés étrangères ne sont pas vérifiées
+//! pendant les migrations.
+
+use chrono::Utc;
+use rusqlite::{Connection, Result, Transaction, TransactionBehavior};
+
+use crate::database::Database;
+
+/// Migration du schéma, appliquée une seule fois
+#[derive(Debug)]
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    sql: &'static str,
+}
+
+/// Migrations du schéma, de la plus ancienne à la plus récente
+///
+/// Une migration appliquée ne doit plus être modifiée : un changement du schéma
+/// s'ajoute dans une nouvelle migration, à la fin de la liste.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Schéma initial",
+        sql: include_str!("../migrations/0001_schema_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "Identifiants, fils, réactions, pièces jointes et recherche des messages",
+        sql: include_str!("../migrations/0002_messages.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "Réglages, rôles et conversations privées des salons",
+        sql: include_str!("../migrations/0003_salons.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "Journal des événements et présence",
+        sql: include_str!("../migrations/0004_evenements.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "Sessions des utilisateurs",
+        sql: include_str!("../migrations/0005_sessions.sql"),
+    },
+    Migration {
+        version: 6,
+        description: "Une session par appareil",
+        sql: include_str!("../migrations/0006_appareils.sql"),
+    },
+    Migration {
+        version: 7,
+        description: "Jetons d'accès et de rafraîchissement",
+        sql: include_str!("../migrations/0007_rafraichissement.sql"),
+    },
+    Migration {
+        version: 8,
+        description: "Réinitialisation des mots de passe",
+        sql: include_str!("../migrations/0008_reinitialisation.sql"),
+    },
+    Migration {
+        version: 9,
+        description: "Durée de conservation des salons",
+        sql: include_str!("../migrations/0009_conservation.sql"),
+    },
+    Migration {
+        version: 10,
+        description: "Nettoyage du journal des événements",
+        sql: include_str!("../migrations/0010_nettoyage_evenements.sql"),
+    },
+    Migration {
+        version: 11,
+        description: "Empreintes des jetons",
+        sql: include_str!("../migrations/0011_empreintes_jetons.sql"),
+    },
+    Migration {
+        version: 12,
+        description: "Clés étrangères des messages et des pièces jointes",
+        sql: include_str!("../migrations/0012_cles_etrangeres.sql"),
+    },
+];
+
+/// Version du schéma une fois toutes les migrations appliquées
+pub fn derniere_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+impl Database {
+    /// Applique les migrations en attente
+    ///
+    /// Retourne les versions appliquées. Les autres connexions qui migrent en même temps
+    /// attendent la fin de chaque migration et ne l'appliquent pas une seconde fois.
+    pub fn migre(&self) -> Result<Vec<i64>> {
+        self.cree_table_versions()?;
+
+        // Les tables reconstruites par une migration sont supprimées avant d'être remplacées,
+        // sans supprimer en cascade ce qui les référence
+        self.connection.pragma_update(None, "foreign_keys", false)?;
+        let appliquees = self.applique_migrations();
+        self.connection.pragma_update(None, "foreign_keys", true)?;
+        appliquees
+    }
+
+    fn applique_migrations(&self) -> Result<Vec<i64>> {
+        let mut appliquees = Vec::new();
+        for migration in MIGRATIONS {
+            let transaction =
+                Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)?;
+            if version_schema(&transaction)? >= migration.version {
+                continue;
+            }
+
+            transaction.execute_batch(migration.sql)?;
+            transaction.execute(
+                "INSERT INTO schema_version (version, description, date) VALUES (?1, ?2, ?3)",
+                (
+                    migration.version,
+                    migration.description,
+                    Utc::now().timestamp(),
+                ),
+            )?;
+            transaction.commit()?;
+            appliquees.push(migration.version);
+        }
+
+        Ok(appliquees)
+    }
+
+    /// Version actuelle du schéma (`0` si aucune migration n'a été appliquée)
+    pub fn version_schema(&self) -> Result<i64> {
+        version_schema(&self.connection)
+    }
+
+    fn cree_table_versions(&self) -> Result<()> {
+        self.connection.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS schema_version
+            (
+                version INTEGER NOT NULL PRIMARY KEY,
+                description TEXT NOT NULL,
+                date INTEGER NOT NULL
+            );
+            ",
+        )
+    }
+}
+
+fn version_schema(connection: &Connection) -> Result<i64> {
+    let versions_existe: bool = connection.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE name = 'schema_version')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !versions_existe {
+        return Ok(0);
+    }
+
+    connection.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+        [],
+        |row| row.get(0),
+    )
+}
+
+/// Exécute une commande de migration de la ligne de commande, sans démarrer le serveur
+///
+/// - `--schema-status` affiche la version du schéma et les migrations en attente
+/// - `--migrate` applique les migrations en attente
+pub fn commande_migration(database: &Database, commande: &str) -> Result<String, String> {
+    let erreur = |e: rusqlite::Error| format!("Erreur de la base de donnée : {}", e);
+    match commande {
+        "--schema-status" => {
+            let version = database.version_schema().map_err(erreur)?;
+            let mut etat = vec![format!(
+                "Version du schéma : {} (dernière : {})",
+                version,
+                derniere_version()
+            )];
+            etat.extend(MIGRATIONS.iter().map(|migration| {
+                let appliquee = match migration.version <= version {
+                    true => "x",
+                    false => " ",
+                };
+                format!(
+                    "[{}] {:04} {}",
+                    appliquee, migration.version, migration.description
+                )
+            }));
+            Ok(etat.join("\n"))
+        }
+        "--migrate" => {
+            let appliquees = database.migre().map_err(erreur)?;
+            Ok(match appliquees.is_empty() {
+                true => String::from("Le schéma est déjà à jour."),
+                false => format!(
+                    "Migrations appliquées : {}",
+                    appliquees
+                        .iter()
+                        .map(|version| format!("{:04}", version))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+            })
+        }
+        _ => Err(format!(
+            "Option inconnue : {}\nUsage : rusty_messenger_api [--schema-status | --migrate | --reset-password <utilisateur>]",
+            commande
+        )),
+    }
+}
@@ -0,0 +1,155 @@
+//! Changement et réinitialisation des mots de passe
+//!
+//! Un utilisateur connecté change son mot de passe en donnant l'ancien : ses autres sessions
+//! sont fermées. S'il l'a oublié, un administrateur crée en ligne de commande un jeton à usage
+//! unique (`--reset-password <utilisateur>`) qui permet de choisir un nouveau mot de passe :
+//! toutes ses sessions sont alors fermées.
+//!
+//! Un nouveau mot de passe invalide les jetons de réinitialisation de l'utilisateur. Comme pour
+//! les sessions, seule l'empreinte des jetons est gardée.
+
+use chrono::{Duration, Utc};
+use lib::{FormChangePassword, FormResetPassword};
+use pwhash::bcrypt;
+use rusqlite::{Transaction, TransactionBehavior};
+
+use crate::database::Database;
+use crate::erreur::Erreur;
+use crate::session::{empreinte_jeton, nouveau_jeton, SessionActive};
+
+/// Nombre d'heures pendant lesquelles un jeton de réinitialisation est valide
+const DUREE_REINITIALISATION_HEURES: i64 = 24;
+
+impl Database {
+    /// Change le mot de passe d'un utilisateur qui donne l'ancien
+    ///
+    /// Retourne ses autres sessions, qui sont fermées.
+    pub fn change_mot_de_passe(
+        &self,
+        session: SessionActive,
+        form: &FormChangePassword,
+    ) -> Result<Vec<SessionActive>, Erreur> {
+        let bd_user = self.user_select_id(session.user_id)?;
+        if !bcrypt::verify(form.old_password.as_str(), bd_user.pass.as_str()) {
+            return Err(Erreur::Interdit(String::from("Mauvais mot de passe")));
+        }
+        let hash = hache_mot_de_passe(form.new_password.as_str())?;
+
+        let transaction =
+            Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)?;
+        self.modifie_mot_de_passe(session.user_id, hash.as_str())?;
+        let sessions = self.ferme_sessions(session.user_id, Some(session.id))?;
+        transaction.commit()?;
+        Ok(sessions)
+    }
+
+    /// Crée un jeton de réinitialisation du mot de passe d'un utilisateur
+    pub fn cree_jeton_reinitialisation(&self, username: &str) -> Result<String, Erreur> {
+        let bd_user = self.user_select_username(username)?;
+        let token = nouveau_jeton();
+        let expires_at = Utc::now() + Duration::hours(DUREE_REINITIALISATION_HEURES);
+
+        self.connection.execute(
+            "INSERT INTO password_reset (token_hash, user_id, expires_at) VALUES (?1, ?2, ?3)",
+            (
+                empreinte_jeton(token.as_str()),
+                bd_user.id,
+                expires_at.timestamp(),
+            ),
+        )?;
+        Ok(token)
+    }
+
+    /// Change le mot de passe avec un jeton de réinitialisation, qui n'est plus valide ensuite
+    ///
+    /// Retourne les sessions de l'utilisateur, qui sont toutes fermées.
+    pub fn reinitialise_mot_de_passe(
+        &self,
+        form: &FormResetPassword,
+    ) -> Result<Vec<SessionActive>, Erreur> {
+        let hash = hache_mot_de_passe(form.new_password.as_str())?;
+
+        let transaction =
+            Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)?;
+        // Une seule des requêtes qui utilisent le même jeton en même temps le supprime
+        let user_id = match self.connection.query_row(
+            "DELETE FROM password_reset WHERE token_hash = ?1 AND expires_at > ?2 RETURNING user_id",
+            (empreinte_jeton(form.token.as_str()), Utc::now().timestamp()),
+            |row| row.get::<usize, i64>(0),
+        ) {
+            Ok(user_id) => user_id,
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                return Err(Erreur::NonAutorise(String::from(
+                    "Jeton invalide ou expiré",
+                )))
+            }
+            Err(e) => return Err(Erreur::from(e)),
+        };
+
+        self.modifie_mot_de_passe(user_id, hash.as_str())?;
+        let sessions = self.ferme_sessions(user_id, None)?;
+        transaction.commit()?;
+        Ok(sessions)
+    }
+
+    /// Supprime les jetons de réinitialisation expirés
+    pub fn nettoie_jetons_reinitialisation(&self) -> rusqlite::Result<usize> {
+        self.connection.execute(
+            "DELETE FROM password_reset WHERE expires_at <= ?1",
+            [Utc::now().timestamp()],
+        )
+    }
+
+    /// Remplace le mot de passe d'un utilisateur (`hash`, voir `hache_mot_de_passe`)
+    /// et supprime ses jetons de réinitialisation
+    fn modifie_mot_de_passe(&self, user_id: i64, hash: &str) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "UPDATE user SET password = ?1 WHERE id = ?2",
+            (hash, user_id),
+        )?;
+        self.connection
+            .execute("DELETE FROM password_reset WHERE user_id = ?1", [user_id])?;
+        Ok(())
+    }
+}
+
+/// Vérifie un nouveau mot de passe et le hache, avant d'ouvrir la transaction qui le change
+fn hache_mot_de_passe(password: &str) -> Result<String, Erreur> {
+    verification_mot_de_passe(password)?;
+    Ok(bcrypt::hash(password).unwrap())
+}
+
+fn verification_mot_de_passe(password: &str) -> Result<(), Erreur> {
+    match password.is_empty() {
+        true => Err(Erreur::Invalide(String::from(
+            "Il faut au moins une lettre dans le mot de passe",
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// Crée un jeton de réinitialisation du mot de passe d'un utilisateur, de la ligne de commande
+///
+/// Le jeton est à transmettre à l'utilisateur, qui choisit son nouveau mot de passe avec.
+pub fn commande_reinitialisation(
+    database: &Database,
+    username: Option<&str>,
+) -> Result<String, String> {
+    let username = match username {
+        Some(username) => username,
+        None => {
+            return Err(String::from(
+                "Usage : rusty_messenger_api --reset-password <utilisateur>",
+            ))
+        }
+    };
+
+    match database.cree_jeton_reinitialisation(username) {
+        Ok(token) => Ok(format!(
+            "Jeton de réinitialisation de {} (valide {} heures) : {}",
+            username, DUREE_REINITIALISATION_HEURES, token
+        )),
+        Err(Erreur::Introuvable(_)) => Err(format!("Pas d'utilisateur avec ce nom {}", username)),
+        Err(e) => Err(e.raison().to_string()),
+    }
+}
@@ -0,0 +1,122 @@
+//! Gestion des messages épinglés
+//!
+//! Ce module implémente des méthodes pour épingler et désépingler les messages d'un salon,
+//! et pour récupérer les messages épinglés d'un salon. Tous les membres du salon peuvent
+//! épingler et désépingler ses messages.
+
+use chrono::Utc;
+use lib::{Pin, Unpin};
+use rusqlite::{ErrorCode, Result, Row};
+
+use crate::database::Database;
+use crate::date_time_sql::DateTimeSql;
+use crate::erreur::Erreur;
+use crate::message::{map_message, MESSAGE_COLONNES};
+
+impl Database {
+    /// Épingle un message d'un salon dont l'utilisateur fait partie
+    pub fn ajout_pin(&self, message_id: i64, user_id: i64) -> Result<Pin, Erreur> {
+        let message = self.message_select_id(message_id)?;
+        self.verification_membre(user_id, message.room_id)?;
+
+        match self.connection.execute(
+            "INSERT INTO pin (message_id, room_id, user_id, date) VALUES (?1, ?2, ?3, ?4)",
+            (message.id, message.room_id, user_id, Utc::now().timestamp()),
+        ) {
+            Ok(_) => self.pin_select_id(message_id).map_err(Erreur::from),
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == ErrorCode::ConstraintViolation =>
+            {
+                Err(Erreur::Conflit(String::from(
+                    "Ce message est déjà épinglé.",
+                )))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Désépingle un message d'un salon dont l'utilisateur fait partie
+    pub fn supprime_pin(&self, message_id: i64, user_id: i64) -> Result<Unpin, Erreur> {
+        let message = self.message_select_id(message_id)?;
+        self.verification_membre(user_id, message.room_id)?;
+
+        match self
+            .connection
+            .execute("DELETE FROM pin WHERE message_id = ?1", [message_id])
+        {
+            Ok(0) => Err(Erreur::Introuvable(String::from(
+                "Ce message n'est pas épinglé.",
+            ))),
+            Ok(_) => Ok(Unpin {
+                room_id: message.room_id,
+                message_id,
+            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Récupère les messages épinglés d'un salon, du dernier épinglé au premier
+    pub fn recupere_pins(&self, room_id: i64) -> Result<Vec<Pin>> {
+        let mut stmt = self.connection.prepare(
+            format!(
+                "
+                SELECT {MESSAGE_COLONNES}, pin.user_id, pin.date FROM pin
+                INNER JOIN message ON message.id = pin.message_id
+                WHERE pin.room_id = ?1
+                ORDER BY pin.date DESC, pin.rowid DESC
+                "
+            )
+            .as_str(),
+        )?;
+        let rows = stmt.query_map([room_id], map_pin)?;
+
+        let mut pins = Vec::new();
+        for pin in rows {
+            let mut pin = pin?;
+            self.ajoute_reactions(&mut pin.message)?;
+            self.ajoute_pieces_jointes(&mut pin.message)?;
+            pins.push(pin);
+        }
+
+        Ok(pins)
+    }
+
+    /// Supprime les épingles d'un message et de ses réponses
+    pub fn supprime_pins(&self, message_id: i64) -> Result<usize> {
+        self.connection.execute(
+            "DELETE FROM pin WHERE message_id IN (SELECT id FROM message WHERE id = ?1 OR parent_id = ?1)",
+            [message_id],
+        )
+    }
+
+    /// Récupère l'épingle d'un message
+    fn pin_select_id(&self, message_id: i64) -> Result<Pin> {
+        let mut pin = self.connection.query_row(
+            format!(
+                "
+                SELECT {MESSAGE_COLONNES}, pin.user_id, pin.date FROM pin
+                INNER JOIN message ON message.id = pin.message_id
+                WHERE pin.message_id = ?1
+                "
+            )
+            .as_str(),
+            [message_id],
+            map_pin,
+        )?;
+        self.ajoute_reactions(&mut pin.message)?;
+        self.ajoute_pieces_jointes(&mut pin.message)?;
+
+        Ok(pin)
+    }
+}
+
+/// Lit une épingle : les colonnes du message (`MESSAGE_COLONNES`) puis `pin.user_id` et `pin.date`
+fn map_pin(row: &Row) -> Result<Pin> {
+    let message = map_message(row)?;
+    Ok(Pin {
+        room_id: message.room_id,
+        user_id: row.get(8)?,
+        date: DateTimeSql::parse(row.get(9)?).unwrap(),
+        message,
+    })
+}
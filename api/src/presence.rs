@@ -0,0 +1,191 @@
+//! Présence des utilisateurs
+//!
+//! Ce module déduit la présence d'un utilisateur de ses Event Streams ouverts : il est en ligne
+//! tant qu'un Event Stream est ouvert, absent pendant `ABSENCE` après la fermeture du dernier,
+//! puis hors ligne. Le moment de la dernière connexion est conservé dans `user.last_seen`.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use lib::{PresenceStatus, UserPresence};
+use rocket::tokio::runtime::Handle;
+use rocket::tokio::time::sleep;
+use rusqlite::Result;
+
+use crate::database::{Database, Pool};
+use crate::date_time_sql::DateTimeSql;
+use crate::erreur::Erreur;
+use crate::event::Diffusion;
+use crate::{diffuse_evenement, EventStreams};
+
+/// Durée pendant laquelle un utilisateur sans Event Stream ouvert est absent avant d'être hors ligne
+pub const ABSENCE: Duration = Duration::from_secs(5 * 60);
+
+/// Garde d'un Event Stream ouvert, qui met à jour la présence de l'utilisateur à sa fermeture
+pub struct Connexion {
+    pub user_id: i64,
+    pub event_streams: EventStreams,
+    pub pool: Pool,
+}
+
+impl Drop for Connexion {
+    fn drop(&mut self) {
+        if let Ok(handle) = Handle::try_current() {
+            handle.spawn(deconnexion(
+                self.pool.clone(),
+                self.event_streams.clone(),
+                self.user_id,
+            ));
+        }
+    }
+}
+
+/// Utilisateurs (parmi `users`) qui ont au moins un Event Stream ouvert
+pub async fn utilisateurs_connectes(event_streams: &EventStreams, users: &[i64]) -> Vec<i64> {
+    let mut connectes = Vec::new();
+    for user_id in users {
+        if nombre_connexions(event_streams, *user_id).await > 0 {
+            connectes.push(*user_id);
+        }
+    }
+    connectes
+}
+
+/// Nombre d'Event Streams ouverts par un utilisateur
+pub async fn nombre_connexions(event_streams: &EventStreams, user_id: i64) -> usize {
+    let lock = event_streams.read().await;
+    lock.get(&user_id)
+        .map_or(0, |event_sender| event_sender.receiver_count())
+}
+
+/// Annonce la connexion d'un utilisateur à ses contacts
+///
+/// L'annonce est abandonnée si la base de donnée ne répond pas (voir `presence_annoncee`).
+pub async fn connexion(pool: &Pool, event_streams: &EventStreams, user_id: i64) {
+    let diffusion = pool
+        .execute(move |connection_bd| -> Result<Diffusion, Erreur> {
+            connection_bd.met_a_jour_last_seen(user_id)?;
+            let presence = connection_bd.recupere_presences(&[user_id], &[user_id])?;
+            let contacts = connection_bd.recupere_contacts(user_id)?;
+            Ok(connection_bd.journalise_evenement(&contacts, presence[0].serialize())?)
+        })
+        .await;
+    if let Some(diffusion) = presence_annoncee(user_id, diffusion) {
+        diffuse_evenement(event_streams, diffusion).await;
+    }
+}
+
+/// Annonce l'absence, puis le départ, d'un utilisateur qui n'a plus d'Event Stream ouvert
+async fn deconnexion(pool: Pool, event_streams: EventStreams, user_id: i64) {
+    if annonce_deconnexion(&pool, &event_streams, user_id, PresenceStatus::Away).await {
+        sleep(ABSENCE).await;
+        annonce_deconnexion(&pool, &event_streams, user_id, PresenceStatus::Offline).await;
+    }
+}
+
+/// Annonce une présence aux contacts d'un utilisateur, s'il n'est pas revenu entre-temps
+async fn annonce_deconnexion(
+    pool: &Pool,
+    event_streams: &EventStreams,
+    user_id: i64,
+    status: PresenceStatus,
+) -> bool {
+    if nombre_connexions(event_streams, user_id).await > 0 {
+        return false;
+    }
+
+    let diffusion = pool
+        .execute(move |connection_bd| -> Result<Option<Diffusion>, Erreur> {
+            if status == PresenceStatus::Away {
+                connection_bd.met_a_jour_last_seen(user_id)?;
+            }
+            let presence = connection_bd.recupere_presences(&[user_id], &[])?;
+            // Une connexion plus récente s'occupe de l'annonce
+            if presence[0].status != status {
+                return Ok(None);
+            }
+            let contacts = connection_bd.recupere_contacts(user_id)?;
+            Ok(Some(connection_bd.journalise_evenement(
+                &contacts,
+                presence[0].serialize(),
+            )?))
+        })
+        .await;
+    match presence_annoncee(user_id, diffusion).flatten() {
+        Some(diffusion) => diffuse_evenement(event_streams, diffusion).await,
+        None => return false,
+    }
+
+    true
+}
+
+/// Diffusion d'une présence, ou `None` (et l'erreur est journalisée par Rocket) si la base
+/// de donnée n'a pas pu la journaliser
+fn presence_annoncee<T>(user_id: i64, diffusion: Result<T, Erreur>) -> Option<T> {
+    match diffusion {
+        Ok(diffusion) => Some(diffusion),
+        Err(e) => {
+            rocket::warn!(
+                "Présence de l'utilisateur {} non annoncée : {:?}",
+                user_id,
+                e
+            );
+            None
+        }
+    }
+}
+
+impl Database {
+    /// Enregistre le moment où un utilisateur a été vu
+    pub fn met_a_jour_last_seen(&self, user_id: i64) -> Result<usize> {
+        self.connection.execute(
+            "UPDATE user SET last_seen = ?1 WHERE id = ?2",
+            (Utc::now().timestamp(), user_id),
+        )
+    }
+
+    /// Récupère les utilisateurs qui partagent au moins un salon avec un utilisateur
+    pub fn recupere_contacts(&self, user_id: i64) -> Result<Vec<i64>> {
+        let mut stmt = self.connection.prepare(
+            "
+            SELECT DISTINCT contact.user_id
+            FROM user_room INNER JOIN user_room AS contact ON contact.room_id = user_room.room_id
+            WHERE user_room.user_id = ?1 AND contact.user_id != ?1
+            ",
+        )?;
+        let rows = stmt.query([user_id])?;
+
+        rows.mapped(|row| row.get(0)).collect()
+    }
+
+    /// Récupère la présence d'utilisateurs, selon ceux qui ont un Event Stream ouvert
+    pub fn recupere_presences(
+        &self,
+        users: &[i64],
+        connectes: &[i64],
+    ) -> Result<Vec<UserPresence>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT id, username, last_seen FROM user WHERE id = ?1")?;
+
+        let absence = (Utc::now() - chrono::Duration::from_std(ABSENCE).unwrap()).timestamp();
+        let mut presences = Vec::new();
+        for user_id in users {
+            presences.push(stmt.query_row([user_id], |row| {
+                let last_seen: Option<i64> = row.get(2)?;
+                Ok(UserPresence {
+                    user_id: row.get(0)?,
+                    username: row.get(1)?,
+                    status: match (connectes.contains(user_id), last_seen) {
+                        (true, _) => PresenceStatus::Online,
+                        (false, Some(last_seen)) if last_seen > absence => PresenceStatus::Away,
+                        (false, _) => PresenceStatus::Offline,
+                    },
+                    last_seen: last_seen.and_then(DateTimeSql::parse),
+                })
+            })?);
+        }
+
+        Ok(presences)
+    }
+}
@@ -0,0 +1,135 @@
+//! Gestion des réactions aux messages
+//!
+//! Ce module implémente des méthodes pour ajouter et retirer les réactions (emoji) des utilisateurs
+//! aux messages des salons dont ils font partie, et pour les récupérer regroupées par emoji.
+
+use lib::{FormReaction, Message, Reaction};
+use rusqlite::{ErrorCode, Result};
+
+use crate::database::Database;
+use crate::erreur::Erreur;
+
+/// Nombre maximal de caractères d'un emoji (les séquences d'emoji en ont plusieurs)
+const EMOJI_TAILLE_MAX: usize = 8;
+
+impl Database {
+    /// Ajoute la réaction d'un utilisateur à un message d'un salon dont il fait partie
+    pub fn ajout_reaction(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        form: FormReaction,
+    ) -> Result<Reaction, Erreur> {
+        verification_emoji(form.emoji.as_str())?;
+        let message = self.message_select_id(message_id)?;
+
+        match self.connection.execute(
+            "INSERT INTO reaction (message_id, user_id, emoji) SELECT ?1, ?2, ?3 FROM user_room WHERE user_id = ?2 AND room_id = ?4",
+            (message_id, user_id, form.emoji.as_str(), message.room_id),
+        ) {
+            Ok(0) => Err(Erreur::PasMembre(String::from("Tu ne peux pas réagir à un message d'un salon que tu n'y est pas."))),
+            Ok(_) => self
+                .reaction_select_emoji(&message, form.emoji)
+                .map_err(Erreur::from),
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == ErrorCode::ConstraintViolation =>
+            {
+                Err(Erreur::Conflit(String::from("Tu as déjà réagi avec cet emoji.")))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Retire la réaction d'un utilisateur à un message d'un salon dont il fait partie
+    pub fn supprime_reaction(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        form: FormReaction,
+    ) -> Result<Reaction, Erreur> {
+        let message = self.message_select_id(message_id)?;
+
+        match self.connection.execute(
+            "DELETE FROM reaction WHERE message_id = ?1 AND user_id = ?2 AND emoji = ?3 AND EXISTS(SELECT 1 FROM user_room WHERE user_id = ?2 AND room_id = ?4)",
+            (message_id, user_id, form.emoji.as_str(), message.room_id),
+        ) {
+            Ok(0) => Err(Erreur::Introuvable(String::from("Tu n'as pas réagi avec cet emoji."))),
+            Ok(_) => self
+                .reaction_select_emoji(&message, form.emoji)
+                .map_err(Erreur::from),
+            Err(_) => Err(Erreur::Interne(String::from("internal error while deleting the reaction"))),
+        }
+    }
+
+    /// Remplit les réactions d'un message, regroupées par emoji dans l'ordre où elles ont été ajoutées
+    pub fn ajoute_reactions(&self, message: &mut Message) -> Result<()> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT emoji, user_id FROM reaction WHERE message_id = ?1 ORDER BY rowid")?;
+        let rows = stmt.query_map([message.id], |row| {
+            Ok((row.get::<usize, String>(0)?, row.get::<usize, i64>(1)?))
+        })?;
+
+        let mut reactions: Vec<Reaction> = Vec::new();
+        for row in rows {
+            let (emoji, user_id) = row?;
+            match reactions
+                .iter_mut()
+                .find(|reaction| reaction.emoji == emoji)
+            {
+                Some(reaction) => reaction.user_ids.push(user_id),
+                None => reactions.push(Reaction {
+                    message_id: message.id,
+                    room_id: message.room_id,
+                    emoji,
+                    user_ids: vec![user_id],
+                }),
+            }
+        }
+
+        message.reactions = reactions;
+        Ok(())
+    }
+
+    /// Supprime les réactions d'un message et de ses réponses
+    pub fn supprime_reactions(&self, message_id: i64) -> Result<usize> {
+        self.connection.execute(
+            "DELETE FROM reaction WHERE message_id IN (SELECT id FROM message WHERE id = ?1 OR parent_id = ?1)",
+            [message_id],
+        )
+    }
+
+    /// Récupère les utilisateurs ayant réagi à un message avec un emoji
+    fn reaction_select_emoji(&self, message: &Message, emoji: String) -> Result<Reaction> {
+        let mut stmt = self.connection.prepare(
+            "SELECT user_id FROM reaction WHERE message_id = ?1 AND emoji = ?2 ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map((message.id, emoji.as_str()), |row| row.get(0))?;
+
+        let mut user_ids = Vec::new();
+        for user_id in rows {
+            user_ids.push(user_id?);
+        }
+
+        Ok(Reaction {
+            message_id: message.id,
+            room_id: message.room_id,
+            emoji,
+            user_ids,
+        })
+    }
+}
+
+/// Vérifie qu'une réaction est un emoji (aucun caractère ASCII)
+fn verification_emoji(emoji: &str) -> Result<(), Erreur> {
+    if emoji.is_empty()
+        || emoji.chars().count() > EMOJI_TAILLE_MAX
+        || emoji.chars().any(|c| c.is_ascii())
+    {
+        return Err(Erreur::Invalide(String::from(
+            "Ce n'est pas un emoji valide.",
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,162 @@
+//! Messages éphémères et durée de conservation des salons
+//!
+//! Ce module implémente la tâche de fond qui supprime définitivement les messages expirés :
+//! les messages éphémères dont la durée de vie (`ttl`) est écoulée, et ceux plus anciens
+//! que la durée de conservation de leur salon. Les membres du salon reçoivent leur suppression
+//! comme pour `delete_message`. La même tâche supprime du journal les événements trop anciens
+//! (voir `nettoie_evenements`), les sessions expirées, les jetons de réinitialisation expirés
+//! et les pièces jointes jamais liées à un message (voir `nettoie_pieces_jointes_non_liees`).
+
+use std::time::Duration;
+
+use chrono::Utc;
+use lib::{DeletedMessage, Message};
+use rocket::tokio::time::sleep;
+use rusqlite::Result;
+
+use crate::database::{Database, Pool};
+use crate::erreur::Erreur;
+use crate::event::Diffusion;
+use crate::message::{map_message, MESSAGE_COLONNES};
+use crate::{diffuse_evenement, EventStreams};
+
+/// Intervalle par défaut entre deux suppressions des messages expirés
+pub const INTERVALLE_PURGE: Duration = Duration::from_secs(5 * 60);
+
+/// Nombre maximal de messages expirés supprimés à la fois
+const TAILLE_LOT_PURGE: i64 = 500;
+
+impl Database {
+    /// Récupère des messages dont la durée de vie est écoulée (au plus `TAILLE_LOT_PURGE`)
+    pub fn recupere_messages_ephemeres_expires(&self) -> Result<Vec<Message>> {
+        let mut stmt = self.connection.prepare(
+            format!(
+                "
+                SELECT {MESSAGE_COLONNES} FROM message
+                WHERE message.expires_at <= ?1
+                ORDER BY message.expires_at
+                LIMIT ?2
+                "
+            )
+            .as_str(),
+        )?;
+        let rows = stmt.query((Utc::now().timestamp(), TAILLE_LOT_PURGE))?;
+
+        rows.mapped(map_message).collect()
+    }
+
+    /// Récupère des messages plus anciens que la durée de conservation de leur salon
+    /// (au plus `TAILLE_LOT_PURGE`)
+    ///
+    /// `CROSS JOIN` parcourt les salons d'abord, puis leurs messages avec l'index `message_room_id_date`.
+    pub fn recupere_messages_hors_conservation(&self) -> Result<Vec<Message>> {
+        let mut stmt = self.connection.prepare(
+            format!(
+                "
+                SELECT {MESSAGE_COLONNES} FROM room
+                CROSS JOIN message ON message.room_id = room.id
+                    AND message.date <= ?1 - room.retention
+                WHERE room.retention IS NOT NULL
+                LIMIT ?2
+                "
+            )
+            .as_str(),
+        )?;
+        let rows = stmt.query((Utc::now().timestamp(), TAILLE_LOT_PURGE))?;
+
+        rows.mapped(map_message).collect()
+    }
+}
+
+/// Supprime les messages expirés, les anciens événements, les sessions et les jetons
+/// de réinitialisation expirés et les pièces jointes non liées toutes les `intervalle`,
+/// tant que le serveur tourne
+pub async fn purge_donnees_expirees(pool: Pool, event_streams: EventStreams, intervalle: Duration) {
+    loop {
+        sleep(intervalle).await;
+
+        purge_lots(
+            &pool,
+            &event_streams,
+            Database::recupere_messages_ephemeres_expires,
+        )
+        .await;
+        purge_lots(
+            &pool,
+            &event_streams,
+            Database::recupere_messages_hors_conservation,
+        )
+        .await;
+        let _ = pool
+            .execute(|connection_bd| -> Result<(), Erreur> {
+                let _ = connection_bd.nettoie_evenements();
+                let _ = connection_bd.nettoie_sessions();
+                let _ = connection_bd.nettoie_jetons_reinitialisation();
+                let _ = connection_bd.nettoie_pieces_jointes_non_liees();
+                Ok(())
+            })
+            .await;
+    }
+}
+
+/// Supprime par lots les messages expirés que retourne `recupere`, jusqu'au dernier
+///
+/// Chaque lot utilise sa propre connexion : les routes peuvent écrire entre deux lots.
+/// La purge s'arrête aussi sur un lot dont rien n'a pu être supprimé, ou dont une suppression
+/// n'a pas pu être journalisée, et sans connexion disponible (elle reprendra à la prochaine purge).
+async fn purge_lots(
+    pool: &Pool,
+    event_streams: &EventStreams,
+    recupere: fn(&Database) -> Result<Vec<Message>>,
+) {
+    loop {
+        let (termine, diffusions) = pool
+            .execute(move |connection_bd| -> Result<_, Erreur> {
+                let expires = recupere(connection_bd).unwrap_or_default();
+                let nombre = expires.len();
+                let mut diffusions = Vec::new();
+                for message in expires {
+                    match efface_message_expire(connection_bd, message) {
+                        Ok(diffusions_message) => diffusions.extend(diffusions_message),
+                        Err(_) => return Ok((true, diffusions)),
+                    }
+                }
+                let termine = nombre < TAILLE_LOT_PURGE as usize || diffusions.is_empty();
+                Ok((termine, diffusions))
+            })
+            .await
+            .unwrap_or((true, Vec::new()));
+        for diffusion in diffusions {
+            diffuse_evenement(event_streams, diffusion).await;
+        }
+        if termine {
+            break;
+        }
+    }
+}
+
+/// Supprime un message expiré et journalise sa suppression (et le nouvel état de son fil)
+///
+/// Rien n'est journalisé s'il a déjà été supprimé (par son auteur, avec le message auquel
+/// il répond, ou par une autre purge).
+fn efface_message_expire(
+    connection_bd: &Database,
+    message: Message,
+) -> Result<Vec<Diffusion>, Erreur> {
+    if !connection_bd.efface_message(message.id)? {
+        return Ok(Vec::new());
+    }
+    let users = connection_bd.select_users_room(message.room_id)?;
+
+    let deleted = DeletedMessage {
+        id: message.id,
+        room_id: message.room_id,
+    };
+    let mut diffusions = vec![connection_bd.journalise_evenement(&users, deleted.serialize())?];
+    if let Some(parent_id) = message.parent_id {
+        let thread = connection_bd.etat_fil(parent_id, message.room_id)?;
+        diffusions.push(connection_bd.journalise_evenement(&users, thread.serialize())?);
+    }
+
+    Ok(diffusions)
+}
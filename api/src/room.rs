@@ -3,97 +3,516 @@
 //! Ce module implémente des méthodes pour gérer la création de salons, l'ajout d'utilisateurs
 //! à des salons existants, la récupération des salons associés à un utilisateur,
 //! ainsi que la récupération d'informations sur des salons spécifiques et leurs utilisateurs dans une base de données.
+//! Il gère aussi le dernier message lu par chaque utilisateur d'un salon, ainsi que le rôle
+//! des membres (propriétaire, administrateur, membre) qui permet d'expulser, de bannir
+//! et de modifier le nom et le sujet du salon. Les conversations privées sont des salons
+//! uniques pour chaque paire d'utilisateurs, où on ne peut ni inviter ni quitter.
 
-use lib::Room;
-use rocket::serde::{Deserialize, Serialize};
-use rusqlite::{Result, Row};
+use lib::{
+    FormAddRoom, FormAddUserRoom, FormDirectRoom, FormEditRoom, FormMember, FormRead, FormRole,
+    ReadReceipt, RemovedMember, Room, RoomKind, RoomMember, RoomRole, RoomSettings,
+};
+use rusqlite::{ErrorCode, Result, Row, Transaction, TransactionBehavior};
 
 use crate::database::Database;
+use crate::erreur::Erreur;
 
-#[derive(Debug, Clone, FromForm, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq, UriDisplayQuery))]
-#[serde(crate = "rocket::serde")]
-pub struct FormAddRoom {
-    pub user_id: i64,
-    pub api_key: String,
-    pub name: String,
-}
-
-#[derive(Debug, Clone, FromForm, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq, UriDisplayQuery))]
-#[serde(crate = "rocket::serde")]
-pub struct FormAddUserRoom {
-    pub user_id: i64,
-    pub api_key: String,
-    pub other_user_username: String,
-    pub room_id: i64,
-}
+/// Colonnes d'un salon (voir `map_room`) pour l'utilisateur `?1`, avec ses messages non lus
+const ROOM_COLONNES: &str = "room.id, room.name,
+    (SELECT COUNT(*) FROM message
+        WHERE message.room_id = room.id AND message.parent_id IS NULL
+        AND message.user_id != user_room.user_id AND message.id > user_room.last_read),
+    (SELECT MAX(message.id) FROM message WHERE message.room_id = room.id AND message.parent_id IS NULL),
+    room.topic, room.kind, room.retention";
 
 impl Database {
-    /// Crée un salon et ajout l'utilisateur qui l'a créé
-    pub fn ajout_room(&self, form: FormAddRoom) -> Result<Room> {
+    /// Crée un salon et ajout l'utilisateur qui l'a créé (son propriétaire)
+    pub fn ajout_room(&self, user_id: i64, form: FormAddRoom) -> Result<Room> {
         self.connection
             .execute("INSERT INTO room (name) VALUES (?1)", (form.name.as_str(),))?;
 
+        let room_id = self.connection.last_insert_rowid();
         let new_room = Room {
-            id: self.connection.last_insert_rowid(),
+            id: room_id,
             name: form.name,
+            topic: String::new(),
+            kind: RoomKind::Group,
+            retention: None,
+            unread_count: 0,
+            last_message_id: None,
+            members: vec![RoomMember {
+                room_id,
+                user_id,
+                role: RoomRole::Owner,
+            }],
         };
 
         self.connection.execute(
-            "INSERT INTO user_room (room_id, user_id) VALUES (?1,?2)",
-            (new_room.id, form.user_id),
+            "INSERT INTO user_room (room_id, user_id, role) VALUES (?1, ?2, ?3)",
+            (new_room.id, user_id, RoomRole::Owner.as_str()),
         )?;
 
         Ok(new_room)
     }
 
-    /// Ajout un utilisateur dans un salon
-    pub fn ajout_user_room(&self, form: FormAddUserRoom) -> Result<(Room, i64), String> {
+    /// Ajout un utilisateur dans un salon (s'il n'en a pas été banni)
+    pub fn ajout_user_room(
+        &self,
+        user_id: i64,
+        form: FormAddUserRoom,
+    ) -> Result<(Room, i64), Erreur> {
         let room = self.room_select_id(form.room_id)?;
+        if room.kind == RoomKind::Direct {
+            return Err(Erreur::Interdit(String::from(
+                "Tu ne peux pas inviter quelqu'un dans une conversation privée.",
+            )));
+        }
         let other_user = self.user_select_username(form.other_user_username.as_str())?;
 
+        let banni: bool = self.connection.query_row(
+            "SELECT EXISTS(SELECT 1 FROM room_ban WHERE user_id = ?1 AND room_id = ?2)",
+            (other_user.id, form.room_id),
+            |row| row.get(0),
+        )?;
+        if banni {
+            return Err(Erreur::Interdit(String::from(
+                "Cet utilisateur est banni de ce salon.",
+            )));
+        }
+
         match self.connection.execute(
             "INSERT INTO user_room (user_id, room_id) SELECT ?1, ?2 FROM user_room WHERE user_id = ?3 AND room_id = ?2",
-            (other_user.id, form.room_id, form.user_id),
+            (other_user.id, form.room_id, user_id),
         ) {
-            Ok(0) => Err(String::from("Tu ne peux pas invité quelqu'un dans un salon que tu n'y est pas.")),
+            Ok(0) => Err(Erreur::PasMembre(String::from("Tu ne peux pas invité quelqu'un dans un salon que tu n'y est pas."))),
             Ok(_) => Ok((room, other_user.id)),
-            Err(_) => Err(String::from("Cet utilisateur est déjà dans ce salon.")),
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == ErrorCode::ConstraintViolation =>
+            {
+                Err(Erreur::Conflit(String::from("Cet utilisateur est déjà dans ce salon.")))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Récupère la conversation privée entre deux utilisateurs ou la crée
+    ///
+    /// Retourne le salon (pour l'utilisateur qui l'a demandé) et, s'il vient d'être créé,
+    /// l'id de l'autre utilisateur.
+    pub fn ajout_room_directe(
+        &self,
+        user_id: i64,
+        form: &FormDirectRoom,
+    ) -> Result<(Room, Option<i64>), Erreur> {
+        let other_user = self.user_select_username(form.other_user_username.as_str())?;
+        if other_user.id == user_id {
+            return Err(Erreur::Invalide(String::from(
+                "Tu ne peux pas ouvrir une conversation privée avec toi-même.",
+            )));
+        }
+        let paire = (
+            user_id.min(other_user.id),
+            user_id.max(other_user.id),
+        );
+
+        // La conversation de la paire est créée, ou celle qui existe déjà est retrouvée,
+        // dans la même transaction : deux demandes simultanées n'en créent pas deux
+        let transaction =
+            Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)?;
+        self.connection.execute(
+            "INSERT INTO room (name, kind) VALUES ('', ?1)",
+            [RoomKind::Direct.as_str()],
+        )?;
+        let nouvelle = self.connection.last_insert_rowid();
+        let room_id: i64 = self.connection.query_row(
+            "
+            INSERT INTO room_direct (user_id_1, user_id_2, room_id) VALUES (?1, ?2, ?3)
+            ON CONFLICT (user_id_1, user_id_2) DO UPDATE SET room_id = room_direct.room_id
+            RETURNING room_id
+            ",
+            (paire.0, paire.1, nouvelle),
+            |row| row.get(0),
+        )?;
+        if room_id != nouvelle {
+            // Le salon créé pour rien est annulé avec la transaction
+            drop(transaction);
+            let room = self.recupere_room(user_id, room_id)?;
+            return Ok((room, None));
+        }
+
+        for membre in [user_id, other_user.id] {
+            self.connection.execute(
+                "INSERT INTO user_room (room_id, user_id) VALUES (?1, ?2)",
+                (room_id, membre),
+            )?;
         }
+        transaction.commit()?;
+
+        let room = self.recupere_room(user_id, room_id)?;
+        Ok((room, Some(other_user.id)))
     }
 
     /// Récupère tous les salons qu'un utilisateur à access
     pub fn recupere_rooms(&self, user_id: i64) -> Result<Vec<Room>> {
-        let mut stmt = self.connection.prepare("SELECT room.id, room.name FROM user_room INNER JOIN room on room.id = user_room.room_id WHERE user_id = ?1")?;
-        let rows = stmt.query([user_id])?;
+        let mut stmt = self.connection.prepare(
+            format!("SELECT {ROOM_COLONNES} FROM user_room INNER JOIN room on room.id = user_room.room_id WHERE user_id = ?1").as_str(),
+        )?;
+        let rows = stmt.query_map([user_id], map_room)?;
+
+        let mut rooms = Vec::new();
+        for room in rows {
+            let mut room = room?;
+            room.members = self.recupere_membres(room.id)?;
+            rooms.push(room);
+        }
+
+        Ok(rooms)
+    }
+
+    /// Récupère un salon d'un utilisateur, avec ses messages non lus
+    pub fn recupere_room(&self, user_id: i64, room_id: i64) -> Result<Room> {
+        let mut room = self.connection.query_row(
+            format!("SELECT {ROOM_COLONNES} FROM user_room INNER JOIN room on room.id = user_room.room_id WHERE user_id = ?1 AND room_id = ?2").as_str(),
+            (user_id, room_id),
+            map_room,
+        )?;
+        room.members = self.recupere_membres(room_id)?;
+
+        Ok(room)
+    }
 
-        rows.mapped(|row| {
-            Ok(Room {
-                id: row.get::<usize, i64>(0)?,
-                name: row.get::<usize, String>(1)?,
+    /// Récupère les membres d'un salon et leur rôle
+    pub fn recupere_membres(&self, room_id: i64) -> Result<Vec<RoomMember>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT room_id, user_id, role FROM user_room WHERE room_id = ?1")?;
+        let rows = stmt.query([room_id])?;
+
+        rows.mapped(map_room_member).collect()
+    }
+
+    /// Récupère le rôle d'un membre d'un salon
+    pub fn recupere_membre(&self, room_id: i64, user_id: i64) -> Result<RoomMember, Erreur> {
+        self.connection
+            .query_row(
+                "SELECT room_id, user_id, role FROM user_room WHERE room_id = ?1 AND user_id = ?2",
+                (room_id, user_id),
+                map_room_member,
+            )
+            .map_err(|_| {
+                Erreur::Introuvable(String::from("Cet utilisateur n'est pas dans ce salon."))
             })
+    }
+
+    /// Expulse (et bannit si `ban`) un membre d'un salon
+    ///
+    /// Seuls les administrateurs et le propriétaire peuvent retirer un membre de rang inférieur.
+    pub fn expulse_membre(
+        &self,
+        room_id: i64,
+        user_id: i64,
+        form: &FormMember,
+        ban: bool,
+    ) -> Result<RemovedMember, Erreur> {
+        self.verification_gestion(room_id, user_id, form.other_user_id)?;
+
+        self.connection.execute(
+            "DELETE FROM user_room WHERE user_id = ?1 AND room_id = ?2",
+            (form.other_user_id, room_id),
+        )?;
+        if ban {
+            self.connection.execute(
+                "INSERT OR IGNORE INTO room_ban (user_id, room_id) VALUES (?1, ?2)",
+                (form.other_user_id, room_id),
+            )?;
+        }
+
+        Ok(RemovedMember {
+            room_id,
+            user_id: form.other_user_id,
+            banned: ban,
         })
-        .collect()
+    }
+
+    /// Change le rôle d'un membre d'un salon (promotion en administrateur ou rétrogradation)
+    ///
+    /// Seuls les administrateurs et le propriétaire peuvent changer le rôle d'un membre de rang inférieur.
+    pub fn change_role(
+        &self,
+        room_id: i64,
+        user_id: i64,
+        form: &FormRole,
+    ) -> Result<RoomMember, Erreur> {
+        let role = match RoomRole::parse(form.role.as_str()) {
+            Some(RoomRole::Owner) | None => {
+                return Err(Erreur::Invalide(String::from(
+                    "Ce n'est pas un rôle valide.",
+                )));
+            }
+            Some(role) => role,
+        };
+        let mut membre = self.verification_gestion(room_id, user_id, form.other_user_id)?;
+
+        self.connection.execute(
+            "UPDATE user_room SET role = ?1 WHERE user_id = ?2 AND room_id = ?3",
+            (role.as_str(), form.other_user_id, room_id),
+        )?;
+
+        membre.role = role;
+        Ok(membre)
+    }
+
+    /// Modifie le nom et (ou) le sujet d'un salon (seulement par un administrateur)
+    pub fn modifie_room(
+        &self,
+        room_id: i64,
+        user_id: i64,
+        form: &FormEditRoom,
+    ) -> Result<RoomSettings, Erreur> {
+        self.verification_admin(room_id, user_id, "modifier ce salon")?;
+        if form.name.as_ref().is_some_and(|name| name.is_empty()) {
+            return Err(Erreur::Invalide(String::from(
+                "Il faut au moins une lettre dans le nom du salon",
+            )));
+        }
+        if form.retention.is_some_and(|retention| retention < 0) {
+            return Err(Erreur::Invalide(String::from(
+                "La durée de conservation ne peut pas être négative.",
+            )));
+        }
+
+        self.connection.execute(
+            "UPDATE room SET name = COALESCE(?1, name), topic = COALESCE(?2, topic) WHERE id = ?3",
+            (form.name.as_deref(), form.topic.as_deref(), room_id),
+        )?;
+        if let Some(retention) = form.retention {
+            self.connection.execute(
+                "UPDATE room SET retention = NULLIF(?1, 0) WHERE id = ?2",
+                (retention, room_id),
+            )?;
+        }
+
+        let room = self.room_select_id(room_id)?;
+        Ok(RoomSettings {
+            room_id: room.id,
+            name: room.name,
+            topic: room.topic,
+            retention: room.retention,
+        })
+    }
+
+    /// Retire un utilisateur d'un salon qu'il quitte
+    ///
+    /// Si c'était le propriétaire, le plus ancien administrateur (sinon le plus ancien membre)
+    /// devient propriétaire et est retourné. Le salon est supprimé quand son dernier membre le quitte.
+    /// Tout est fait dans la même transaction : deux départs simultanés ne laissent pas le salon
+    /// sans propriétaire, ou sans membres.
+    pub fn quitte_room(
+        &self,
+        room_id: i64,
+        user_id: i64,
+    ) -> Result<(RemovedMember, Option<RoomMember>), Erreur> {
+        let transaction =
+            Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)?;
+        let membre = self
+            .recupere_membre(room_id, user_id)
+            .map_err(|_| Erreur::PasMembre(String::from("Tu n'es pas dans ce salon.")))?;
+        if self.room_select_id(room_id)?.kind == RoomKind::Direct {
+            return Err(Erreur::Interdit(String::from(
+                "Tu ne peux pas quitter une conversation privée.",
+            )));
+        }
+
+        self.connection.execute(
+            "DELETE FROM user_room WHERE user_id = ?1 AND room_id = ?2",
+            (user_id, room_id),
+        )?;
+        let retire = RemovedMember {
+            room_id,
+            user_id,
+            banned: false,
+        };
+
+        let successeur: Option<i64> = self
+            .connection
+            .query_row(
+                "SELECT user_id FROM user_room WHERE room_id = ?1 ORDER BY role = 'admin' DESC, rowid LIMIT 1",
+                [room_id],
+                |row| row.get(0),
+            )
+            .ok();
+        let (proprietaire, pieces_jointes) = match (successeur, membre.role) {
+            (None, _) => (None, self.supprime_room(room_id)?),
+            (Some(successeur), RoomRole::Owner) => {
+                self.connection.execute(
+                    "UPDATE user_room SET role = ?1 WHERE user_id = ?2 AND room_id = ?3",
+                    (RoomRole::Owner.as_str(), successeur, room_id),
+                )?;
+                (Some(self.recupere_membre(room_id, successeur)?), Vec::new())
+            }
+            (Some(_), _) => (None, Vec::new()),
+        };
+        transaction.commit()?;
+        self.supprime_fichiers(&pieces_jointes);
+
+        Ok((retire, proprietaire))
+    }
+
+    /// Supprime un salon qui n'a plus de membres, avec ses messages et ses pièces jointes
+    ///
+    /// Doit être appelée dans une transaction : retourne les pièces jointes dont les fichiers
+    /// sont à supprimer une fois la transaction validée (voir `supprime_fichiers`).
+    fn supprime_room(&self, room_id: i64) -> Result<Vec<i64>> {
+        let messages = self
+            .connection
+            .prepare("SELECT id FROM message WHERE room_id = ?1 AND parent_id IS NULL")?
+            .query_map([room_id], |row| row.get::<usize, i64>(0))?
+            .collect::<Result<Vec<i64>>>()?;
+        for message_id in messages {
+            self.supprime_reactions(message_id)?;
+            self.supprime_mentions(message_id)?;
+        }
+        let pieces_jointes = self.supprime_pieces_jointes_room(room_id)?;
+
+        for requete in [
+            "DELETE FROM pin WHERE room_id = ?1",
+            "DELETE FROM scheduled_message WHERE room_id = ?1",
+            "DELETE FROM message WHERE room_id = ?1",
+            "DELETE FROM room_ban WHERE room_id = ?1",
+            "DELETE FROM room_direct WHERE room_id = ?1",
+            "DELETE FROM room WHERE id = ?1",
+        ] {
+            self.connection.execute(requete, [room_id])?;
+        }
+
+        Ok(pieces_jointes)
+    }
+
+    /// Vérifie qu'un utilisateur est administrateur (ou propriétaire) d'un salon
+    fn verification_admin(
+        &self,
+        room_id: i64,
+        user_id: i64,
+        action: &str,
+    ) -> Result<RoomMember, Erreur> {
+        let membre = self
+            .recupere_membre(room_id, user_id)
+            .map_err(|_| Erreur::PasMembre(String::from("Tu n'es pas dans ce salon.")))?;
+        if membre.role < RoomRole::Admin {
+            return Err(Erreur::Interdit(format!(
+                "Tu dois être administrateur pour {action}."
+            )));
+        }
+
+        Ok(membre)
+    }
+
+    /// Vérifie qu'un utilisateur peut gérer un autre membre d'un salon et retourne ce membre
+    fn verification_gestion(
+        &self,
+        room_id: i64,
+        user_id: i64,
+        other_user_id: i64,
+    ) -> Result<RoomMember, Erreur> {
+        let gestionnaire =
+            self.verification_admin(room_id, user_id, "gérer les membres de ce salon")?;
+
+        let membre = self.recupere_membre(room_id, other_user_id)?;
+        if membre.role >= gestionnaire.role {
+            return Err(Erreur::Interdit(String::from(
+                "Tu ne peux pas gérer un membre qui a un rôle égal ou supérieur au tien.",
+            )));
+        }
+
+        Ok(membre)
     }
 
     /// Récupère un salon
-    pub fn room_select_id(&self, room_id: i64) -> Result<Room, String> {
+    pub fn room_select_id(&self, room_id: i64) -> Result<Room, Erreur> {
         let mut stmt = self
             .connection
-            .prepare("SELECT id, name FROM room WHERE id = ?1")
-            .map_err(|_| String::from("cant prepare"))?;
+            .prepare("SELECT id, name, 0, NULL, topic, kind, retention FROM room WHERE id = ?1")?;
 
-        let mut rows = stmt
-            .query_map([room_id], map_room)
-            .map_err(|_| String::from("cant querry"))?;
+        let mut rows = stmt.query_map([room_id], map_room)?;
 
         match rows.next() {
             Some(Ok(bd_room)) => Ok(bd_room),
-            _ => Err(format!("no room with the id {}", room_id)),
+            _ => Err(Erreur::Introuvable(format!(
+                "no room with the id {}",
+                room_id
+            ))),
+        }
+    }
+
+    /// Vérifie qu'un utilisateur fait partie d'un salon
+    pub fn est_dans_room(&self, user_id: i64, room_id: i64) -> Result<bool> {
+        self.connection.query_row(
+            "SELECT EXISTS(SELECT 1 FROM user_room WHERE user_id = ?1 AND room_id = ?2)",
+            (user_id, room_id),
+            |row| row.get(0),
+        )
+    }
+
+    /// Vérifie que l'utilisateur est membre du salon
+    pub fn verification_membre(&self, user_id: i64, room_id: i64) -> Result<(), Erreur> {
+        match self.est_dans_room(user_id, room_id)? {
+            true => Ok(()),
+            false => Err(Erreur::PasMembre(String::from("Tu n'es pas dans ce salon."))),
         }
     }
 
+    /// Marque les messages d'un salon comme lus jusqu'à un message
+    ///
+    /// Le marqueur ne recule jamais, le marqueur conservé est retourné.
+    pub fn marque_lu(
+        &self,
+        room_id: i64,
+        user_id: i64,
+        form: &FormRead,
+    ) -> Result<ReadReceipt, Erreur> {
+        let message = self.message_select_id(form.message_id)?;
+        if message.room_id != room_id {
+            return Err(Erreur::Invalide(String::from(
+                "Ce message n'est pas dans ce salon.",
+            )));
+        }
+
+        let modifies = self.connection.execute(
+            "UPDATE user_room SET last_read = MAX(last_read, ?3) WHERE user_id = ?1 AND room_id = ?2",
+            (user_id, room_id, form.message_id),
+        )?;
+        if modifies == 0 {
+            return Err(Erreur::PasMembre(String::from("Tu n'es pas dans ce salon.")));
+        }
+
+        self.connection
+            .query_row(
+                "SELECT room_id, user_id, last_read FROM user_room WHERE user_id = ?1 AND room_id = ?2",
+                (user_id, room_id),
+                map_read_receipt,
+            )
+            .map_err(Erreur::from)
+    }
+
+    /// Récupère le dernier message lu par chaque utilisateur des salons d'un utilisateur
+    /// (ou seulement du salon `room_id`)
+    pub fn recupere_lectures(
+        &self,
+        user_id: i64,
+        room_id: Option<i64>,
+    ) -> Result<Vec<ReadReceipt>> {
+        let mut stmt = self.connection.prepare(
+            "
+            SELECT membre.room_id, membre.user_id, membre.last_read
+            FROM user_room INNER JOIN user_room AS membre ON membre.room_id = user_room.room_id
+            WHERE user_room.user_id = ?1 AND (?2 IS NULL OR user_room.room_id = ?2) AND membre.last_read > 0
+            ",
+        )?;
+        let rows = stmt.query((user_id, room_id))?;
+
+        rows.mapped(map_read_receipt).collect()
+    }
+
     /// Récupère tous les utilisateurs d'un salon
     pub fn select_users_room(&self, room_id: i64) -> Result<Vec<i64>> {
         let mut stmt = self
@@ -109,5 +528,27 @@ fn map_room(row: &Row) -> Result<Room> {
     Ok(Room {
         id: row.get(0)?,
         name: row.get(1)?,
+        topic: row.get(4)?,
+        kind: RoomKind::parse(row.get::<usize, String>(5)?.as_str()).unwrap_or(RoomKind::Group),
+        retention: row.get(6)?,
+        unread_count: row.get(2)?,
+        last_message_id: row.get(3)?,
+        members: Vec::new(),
+    })
+}
+
+fn map_room_member(row: &Row) -> Result<RoomMember> {
+    Ok(RoomMember {
+        room_id: row.get(0)?,
+        user_id: row.get(1)?,
+        role: RoomRole::parse(row.get::<usize, String>(2)?.as_str()).unwrap_or(RoomRole::Member),
+    })
+}
+
+fn map_read_receipt(row: &Row) -> Result<ReadReceipt> {
+    Ok(ReadReceipt {
+        room_id: row.get(0)?,
+        user_id: row.get(1)?,
+        message_id: row.get(2)?,
     })
 }
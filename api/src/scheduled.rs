@@ -0,0 +1,286 @@
+//! Messages programmés
+//!
+//! Ce module implémente des méthodes pour enregistrer un message à envoyer plus tard
+//! (`send_at`), pour que son auteur puisse lister, modifier et annuler ses messages en attente,
+//! et la tâche de fond qui envoie les messages arrivés à échéance comme `post_message`.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use lib::{FormEditScheduled, FormMessage, ScheduledMessage};
+use rocket::tokio::time::sleep;
+use rusqlite::{Result, Row};
+
+use crate::database::{Database, Pool};
+use crate::date_time_sql::DateTimeSql;
+use crate::erreur::Erreur;
+use crate::{publie_message, EventStreams};
+
+/// Intervalle entre deux recherches des messages programmés arrivés à échéance
+pub const INTERVALLE_PROGRAMMES: Duration = Duration::from_secs(1);
+
+/// Nombre maximal de messages programmés envoyés à la fois
+const TAILLE_LOT_PROGRAMMES: i64 = 500;
+
+/// Nombre maximal de secondes entre maintenant et la date d'envoi d'un message programmé (un an)
+const DELAI_PROGRAMMATION_MAX: i64 = 365 * 24 * 3600;
+
+/// Colonnes d'un message programmé (voir `map_scheduled_message`)
+const SCHEDULED_COLONNES: &str = "scheduled_message.id, scheduled_message.send_at,
+    scheduled_message.room_id, scheduled_message.user_id, scheduled_message.text,
+    scheduled_message.parent_id";
+
+impl Database {
+    /// Enregistre un message (déjà vérifié, voir `verification_message`) à envoyer à la date `send_at`
+    pub fn ajout_message_programme(
+        &self,
+        user_id: i64,
+        form: &FormMessage,
+        send_at: i64,
+    ) -> Result<ScheduledMessage, Erreur> {
+        verification_date_envoi(send_at)?;
+        if !form.attachment_ids.is_empty() {
+            return Err(Erreur::Invalide(String::from(
+                "Un message programmé ne peut pas avoir de pièces jointes.",
+            )));
+        }
+        if form.ttl.is_some() {
+            return Err(Erreur::Invalide(String::from(
+                "Un message programmé ne peut pas être éphémère.",
+            )));
+        }
+
+        self.connection
+            .execute(
+                "INSERT INTO scheduled_message (send_at, room_id, user_id, text, parent_id) VALUES (?1, ?2, ?3, ?4, ?5)",
+                (
+                    send_at,
+                    form.room_id,
+                    user_id,
+                    form.text.as_str(),
+                    form.parent_id,
+                ),
+            )?;
+
+        self.message_programme_select_id(self.connection.last_insert_rowid(), user_id)
+    }
+
+    /// Récupère les messages programmés d'un utilisateur, du prochain envoyé au dernier
+    ///
+    /// Seuls les salons dont l'utilisateur fait encore partie sont gardés.
+    pub fn recupere_messages_programmes(&self, user_id: i64) -> Result<Vec<ScheduledMessage>> {
+        let mut stmt = self.connection.prepare(
+            format!(
+                "
+                SELECT {SCHEDULED_COLONNES} FROM scheduled_message
+                INNER JOIN user_room ON user_room.room_id = scheduled_message.room_id AND user_room.user_id = scheduled_message.user_id
+                WHERE scheduled_message.user_id = ?1
+                ORDER BY scheduled_message.send_at, scheduled_message.id
+                "
+            )
+            .as_str(),
+        )?;
+        let rows = stmt.query([user_id])?;
+
+        rows.mapped(map_scheduled_message).collect()
+    }
+
+    /// Modifie le texte et (ou) la date d'envoi d'un message programmé (seulement par son auteur)
+    pub fn modifie_message_programme(
+        &self,
+        scheduled_id: i64,
+        user_id: i64,
+        form: &FormEditScheduled,
+    ) -> Result<ScheduledMessage, Erreur> {
+        self.message_programme_select_id(scheduled_id, user_id)?;
+        if let Some(send_at) = form.send_at {
+            verification_date_envoi(send_at)?;
+        }
+
+        self.connection
+            .execute(
+                "UPDATE scheduled_message SET text = COALESCE(?1, text), send_at = COALESCE(?2, send_at) WHERE id = ?3",
+                (form.text.as_deref(), form.send_at, scheduled_id),
+            )?;
+
+        self.message_programme_select_id(scheduled_id, user_id)
+    }
+
+    /// Annule un message programmé (seulement par son auteur)
+    pub fn supprime_message_programme(
+        &self,
+        scheduled_id: i64,
+        user_id: i64,
+    ) -> Result<(), Erreur> {
+        let supprimes = self.connection.execute(
+            "DELETE FROM scheduled_message WHERE id = ?1 AND user_id = ?2",
+            (scheduled_id, user_id),
+        )?;
+        if supprimes == 0 {
+            return Err(Erreur::Introuvable(format!(
+                "Pas de message programmé avec l'id {}",
+                scheduled_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Récupère les messages programmés arrivés à échéance (au plus `TAILLE_LOT_PROGRAMMES`)
+    pub fn recupere_messages_dus(&self) -> Result<Vec<ScheduledMessage>> {
+        let mut stmt = self.connection.prepare(
+            format!(
+                "SELECT {SCHEDULED_COLONNES} FROM scheduled_message WHERE send_at <= ?1 ORDER BY send_at, id LIMIT ?2"
+            )
+            .as_str(),
+        )?;
+        let rows = stmt.query((Utc::now().timestamp(), TAILLE_LOT_PROGRAMMES))?;
+
+        rows.mapped(map_scheduled_message).collect()
+    }
+
+    /// Retire un message programmé envoyé ou abandonné
+    ///
+    /// Retourne `false` s'il a déjà été retiré (annulé entre-temps).
+    fn retire_message_programme(&self, scheduled_id: i64) -> Result<bool> {
+        self.connection
+            .execute(
+                "DELETE FROM scheduled_message WHERE id = ?1",
+                [scheduled_id],
+            )
+            .map(|retires| retires > 0)
+    }
+
+    /// Récupère un message programmé de son auteur
+    fn message_programme_select_id(
+        &self,
+        scheduled_id: i64,
+        user_id: i64,
+    ) -> Result<ScheduledMessage, Erreur> {
+        self.connection
+            .query_row(
+                format!(
+                    "SELECT {SCHEDULED_COLONNES} FROM scheduled_message WHERE id = ?1 AND user_id = ?2"
+                )
+                .as_str(),
+                (scheduled_id, user_id),
+                map_scheduled_message,
+            )
+            .map_err(|_| Erreur::Introuvable(format!("Pas de message programmé avec l'id {}", scheduled_id)))
+    }
+}
+
+/// Envoie les messages programmés quand ils arrivent à échéance, tant que le serveur tourne
+///
+/// Les messages dus sont envoyés par lots, jusqu'au dernier. L'envoi s'arrête aussi sur un lot
+/// dont aucun message n'a pu être retiré : il sera réessayé au prochain intervalle.
+pub async fn distribue_messages_programmes(pool: Pool, event_streams: EventStreams) {
+    loop {
+        sleep(INTERVALLE_PROGRAMMES).await;
+
+        loop {
+            let dus = pool
+                .execute(|connection_bd| -> Result<_, Erreur> {
+                    Ok(connection_bd.recupere_messages_dus()?)
+                })
+                .await
+                .unwrap_or_default();
+            let nombre = dus.len();
+            let mut retires = 0;
+            for programme in dus {
+                if envoie_message_programme(&pool, &event_streams, programme).await {
+                    retires += 1;
+                }
+            }
+            if nombre < TAILLE_LOT_PROGRAMMES as usize || retires == 0 {
+                break;
+            }
+        }
+    }
+}
+
+/// Envoie un message programmé comme `post_message`, puis le retire
+///
+/// Le message est abandonné (retiré sans être envoyé) si son auteur ne peut plus l'envoyer
+/// (il a quitté le salon, ou le message auquel il répond a été supprimé). Il reste programmé
+/// si la base de donnée n'a pas pu l'envoyer : il sera réessayé à la prochaine distribution.
+/// Retourne `true` s'il a été retiré.
+async fn envoie_message_programme(
+    pool: &Pool,
+    event_streams: &EventStreams,
+    programme: ScheduledMessage,
+) -> bool {
+    let (scheduled_id, user_id) = (programme.id, programme.user_id);
+    let verification = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            // Annulé depuis qu'il a été récupéré
+            if connection_bd
+                .message_programme_select_id(scheduled_id, user_id)
+                .is_err()
+            {
+                return Ok(None);
+            }
+            let form = FormMessage {
+                room_id: programme.room_id,
+                text: programme.text,
+                parent_id: programme.parent_id,
+                attachment_ids: Vec::new(),
+                send_at: None,
+                ttl: None,
+            };
+            match connection_bd.verification_message(user_id, &form) {
+                Ok(()) => Ok(Some(form)),
+                Err(Erreur::Interne(e)) => Err(Erreur::Interne(e)),
+                Err(_) => {
+                    connection_bd.retire_message_programme(scheduled_id)?;
+                    Ok(None)
+                }
+            }
+        })
+        .await;
+    let form = match verification {
+        Ok(Some(form)) => form,
+        Ok(None) => return true,
+        Err(_) => return false,
+    };
+
+    if publie_message(pool, event_streams, user_id, form)
+        .await
+        .is_err()
+    {
+        return false;
+    }
+    pool.execute(move |connection_bd| -> Result<_, Erreur> {
+        Ok(connection_bd.retire_message_programme(scheduled_id)?)
+    })
+    .await
+    .is_ok()
+}
+
+/// Vérifie que la date d'envoi d'un message programmé est dans le futur, à moins d'un an
+fn verification_date_envoi(send_at: i64) -> Result<(), Erreur> {
+    let maintenant = Utc::now().timestamp();
+    if send_at <= maintenant {
+        return Err(Erreur::Invalide(String::from(
+            "La date d'envoi doit être dans le futur.",
+        )));
+    }
+    if send_at - maintenant > DELAI_PROGRAMMATION_MAX || DateTimeSql::parse(send_at).is_none() {
+        return Err(Erreur::Invalide(String::from(
+            "La date d'envoi doit être dans moins d'un an.",
+        )));
+    }
+
+    Ok(())
+}
+
+fn map_scheduled_message(row: &Row) -> Result<ScheduledMessage> {
+    Ok(ScheduledMessage {
+        id: row.get(0)?,
+        send_at: row.get::<usize, DateTimeSql>(1)?.0.and_utc(),
+        room_id: row.get(2)?,
+        user_id: row.get(3)?,
+        text: row.get(4)?,
+        parent_id: row.get(5)?,
+    })
+}
@@ -0,0 +1,104 @@
+//! Recherche plein texte dans les messages
+//!
+//! Ce module implémente la recherche des messages (et des réponses) avec l'index FTS5 `message_fts`,
+//! seulement dans les salons dont l'utilisateur fait partie. Les résultats peuvent être filtrés
+//! par salon, par auteur et par date.
+
+use lib::{FormSearch, SearchResult};
+
+use crate::database::Database;
+use crate::erreur::Erreur;
+use crate::message::{map_message, MESSAGE_COLONNES};
+
+/// Nombre de résultats d'une recherche si le client ne le précise pas
+pub const RESULTATS_PAR_RECHERCHE: i64 = 20;
+/// Nombre maximal de résultats d'une recherche
+pub const RESULTATS_PAR_RECHERCHE_MAX: i64 = 100;
+
+impl Database {
+    /// Recherche des messages dans les salons d'un utilisateur, les plus pertinents en premier
+    pub fn recherche_messages(
+        &self,
+        user_id: i64,
+        form: &FormSearch,
+    ) -> Result<Vec<SearchResult>, Erreur> {
+        let requete = match requete_fts(form.q.as_str()) {
+            Some(requete) => requete,
+            None => {
+                return Err(Erreur::Invalide(String::from(
+                    "Il faut au moins une lettre dans la recherche.",
+                )))
+            }
+        };
+        let limit = form
+            .limit
+            .unwrap_or(RESULTATS_PAR_RECHERCHE)
+            .clamp(1, RESULTATS_PAR_RECHERCHE_MAX);
+
+        let mut stmt = self
+            .connection
+            .prepare(
+                format!(
+                    "
+                    SELECT {MESSAGE_COLONNES}, snippet(message_fts, 0, '<mark>', '</mark>', '…', 12)
+                    FROM message_fts
+                    INNER JOIN message ON message.id = message_fts.rowid
+                    INNER JOIN user_room ON user_room.room_id = message.room_id AND user_room.user_id = ?1
+                    WHERE message_fts MATCH ?2
+                        AND (?3 IS NULL OR message.room_id = ?3)
+                        AND (?4 IS NULL OR message.user_id = ?4)
+                        AND (?5 IS NULL OR message.date >= ?5)
+                        AND (?6 IS NULL OR message.date < ?6)
+                    ORDER BY rank, message.id DESC
+                    LIMIT ?7
+                    "
+                )
+                .as_str(),
+            )?;
+        let rows = stmt.query_map(
+            (
+                user_id,
+                requete,
+                form.room_id,
+                form.author_id,
+                form.after,
+                form.before,
+                limit,
+            ),
+            |row| {
+                Ok(SearchResult {
+                    message: map_message(row)?,
+                    snippet: row.get(8)?,
+                })
+            },
+        )?;
+
+        let mut resultats = Vec::new();
+        for resultat in rows {
+            let mut resultat = resultat?;
+            self.ajoute_reactions(&mut resultat.message)?;
+            self.ajoute_pieces_jointes(&mut resultat.message)?;
+            resultats.push(resultat);
+        }
+
+        Ok(resultats)
+    }
+}
+
+/// Transforme la recherche de l'utilisateur en requête FTS5
+///
+/// Chaque mot est cherché comme préfixe et entre guillemets, pour que la syntaxe
+/// de FTS5 (`AND`, `*`, `"`, ...) ne puisse pas être utilisée. Les mots sans lettre
+/// ni chiffre sont ignorés.
+fn requete_fts(recherche: &str) -> Option<String> {
+    let mots: Vec<String> = recherche
+        .split_whitespace()
+        .filter(|mot| mot.chars().any(char::is_alphanumeric))
+        .map(|mot| format!("\"{}\"*", mot.replace('"', "\"\"")))
+        .collect();
+
+    match mots.is_empty() {
+        true => None,
+        false => Some(mots.join(" ")),
+    }
+}
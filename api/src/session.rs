@@ -0,0 +1,422 @@
+//! Sessions des utilisateurs
+//!
+//! Ce module implémente les sessions ouvertes à la création d'un utilisateur et à la connexion.
+//! Chacune a un jeton d'accès aléatoire, que le client envoie dans l'en-tête
+//! `Authorization: Bearer <jeton>`. Le jeton ne change pas d'une requête à l'autre : plusieurs
+//! requêtes d'un même client peuvent être envoyées en même temps.
+//!
+//! Le jeton d'accès expire vite. Le client l'échange avant avec son jeton de rafraîchissement
+//! (`POST /refresh`) contre deux nouveaux jetons. La session expire si elle n'est pas utilisée
+//! pendant un moment, et dans tous les cas après une durée maximale (voir `DureesSession`).
+//!
+//! Un utilisateur a une session par appareil. Il peut voir ses sessions (appareil, ouverture,
+//! dernière utilisation) et les fermer : l'Event Stream de l'appareil est alors fermé aussi.
+//!
+//! Les gardes `Authentifie` et `AuthentifieNavigateur` retrouvent l'utilisateur d'une requête
+//! à partir de son jeton. La base de donnée ne garde que l'empreinte des jetons
+//! (voir `empreinte_jeton`) : ils ne peuvent pas être réutilisés si elle est divulguée.
+
+use chrono::{Duration, Utc};
+use lib::{Session, SessionInfo};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rocket::http::{Cookie, CookieJar, SameSite};
+use rocket::request::{FromRequest, Outcome, Request};
+use rusqlite::{Result, Row};
+use sha2::{Digest, Sha256};
+use std::env;
+
+use crate::database::{Database, Pool};
+use crate::date_time_sql::DateTimeSql;
+use crate::erreur::Erreur;
+use crate::{EventStreams, SESSION_FERMEE};
+
+/// Nombre de caractères (lettres et chiffres) d'un jeton de session
+const LONGUEUR_JETON: usize = 48;
+
+/// Cookie qui contient le jeton de session, pour les routes de `AuthentifieNavigateur`
+pub const COOKIE_SESSION: &str = "session";
+
+/// Nombre maximal de caractères gardés de l'en-tête `User-Agent`
+const LONGUEUR_USER_AGENT_MAX: usize = 256;
+
+/// Durées de validité des sessions
+#[derive(Debug, Clone, Copy)]
+pub struct DureesSession {
+    /// Validité d'un jeton d'accès
+    pub acces: Duration,
+    /// Durée sans utilisation après laquelle la session expire
+    pub inactivite: Duration,
+    /// Durée après laquelle la session expire, même utilisée
+    pub absolue: Duration,
+}
+
+impl Default for DureesSession {
+    fn default() -> DureesSession {
+        DureesSession {
+            acces: Duration::minutes(15),
+            inactivite: Duration::days(7),
+            absolue: Duration::days(30),
+        }
+    }
+}
+
+impl DureesSession {
+    /// Lit les durées des variables d'environnement `SESSION_ACCESS_MINUTES`, `SESSION_IDLE_DAYS`
+    /// et `SESSION_MAX_DAYS`, ou prend celles par défaut
+    pub fn depuis_env() -> DureesSession {
+        let variable = |nom: &str| {
+            env::var(nom)
+                .ok()
+                .and_then(|valeur| valeur.parse::<i64>().ok())
+        };
+        let defaut = DureesSession::default();
+        DureesSession {
+            acces: variable("SESSION_ACCESS_MINUTES").map_or(defaut.acces, Duration::minutes),
+            inactivite: variable("SESSION_IDLE_DAYS").map_or(defaut.inactivite, Duration::days),
+            absolue: variable("SESSION_MAX_DAYS").map_or(defaut.absolue, Duration::days),
+        }
+    }
+}
+
+/// Génère un jeton de session (ou de réinitialisation du mot de passe)
+pub fn nouveau_jeton() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(LONGUEUR_JETON)
+        .map(char::from)
+        .collect()
+}
+
+/// Empreinte (SHA-256) d'un jeton, gardée dans la base de donnée à la place du jeton
+pub fn empreinte_jeton(jeton: &str) -> String {
+    format!("{:x}", Sha256::digest(jeton.as_bytes()))
+}
+
+/// Session (valide) d'une requête
+#[derive(Debug, Clone, Copy)]
+pub struct SessionActive {
+    pub id: i64,
+    pub user_id: i64,
+}
+
+/// Utilisateur authentifié par le jeton de session de l'en-tête `Authorization`
+///
+/// La garde réussit toujours : l'erreur d'authentification est renvoyée par la route
+/// (voir `user_id`), dans le même format que ses autres erreurs.
+pub struct Authentifie(Result<SessionActive, Erreur>);
+
+impl Authentifie {
+    /// Identifiant de l'utilisateur, ou l'erreur s'il n'est pas authentifié
+    pub fn user_id(self) -> Result<i64, Erreur> {
+        self.0.map(|session| session.user_id)
+    }
+
+    /// Session de la requête, ou l'erreur si l'utilisateur n'est pas authentifié
+    pub fn session(self) -> Result<SessionActive, Erreur> {
+        self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authentifie {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Authentifie(authentifie(request, false).await))
+    }
+}
+
+/// Utilisateur authentifié par l'en-tête `Authorization`, ou à défaut par le cookie de session
+///
+/// Pour les routes `GET` ouvertes directement par le navigateur (`EventSource`, images des pièces
+/// jointes), qui ne peuvent pas ajouter d'en-tête. Les routes qui modifient quelque chose
+/// n'acceptent pas le cookie : une autre page ne peut pas agir au nom de l'utilisateur.
+pub struct AuthentifieNavigateur(Result<SessionActive, Erreur>);
+
+impl AuthentifieNavigateur {
+    /// Identifiant de l'utilisateur, ou l'erreur s'il n'est pas authentifié
+    pub fn user_id(self) -> Result<i64, Erreur> {
+        self.0.map(|session| session.user_id)
+    }
+
+    /// Session de la requête, ou l'erreur si l'utilisateur n'est pas authentifié
+    pub fn session(self) -> Result<SessionActive, Erreur> {
+        self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthentifieNavigateur {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(AuthentifieNavigateur(authentifie(request, true).await))
+    }
+}
+
+/// Appareil du client (en-tête `User-Agent`), pour reconnaître ses sessions
+pub struct UserAgent(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for UserAgent {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(UserAgent(
+            request
+                .headers()
+                .get_one("User-Agent")
+                .unwrap_or_default()
+                .chars()
+                .take(LONGUEUR_USER_AGENT_MAX)
+                .collect(),
+        ))
+    }
+}
+
+/// Retrouve la session du jeton de la requête
+///
+/// Le cookie n'est lu (si `cookie`) que si la requête n'a pas d'en-tête `Authorization`.
+async fn authentifie(request: &Request<'_>, cookie: bool) -> Result<SessionActive, Erreur> {
+    let jeton = match request.headers().get_one("Authorization") {
+        Some(entete) => entete.strip_prefix("Bearer ").map(str::to_string),
+        None if cookie => request
+            .cookies()
+            .get(COOKIE_SESSION)
+            .map(|cookie| cookie.value().to_string()),
+        None => None,
+    };
+    let jeton = match jeton {
+        Some(jeton) => jeton,
+        None => {
+            return Err(Erreur::NonAutorise(String::from(
+                "Jeton de session manquant",
+            )))
+        }
+    };
+
+    match request.rocket().state::<Pool>() {
+        Some(pool) => {
+            pool.execute(move |connection_bd| connection_bd.verification_session(jeton.as_str()))
+                .await
+        }
+        None => Err(Erreur::Interne(String::from(
+            "Base de donnée indisponible",
+        ))),
+    }
+}
+
+/// Pose le cookie de session (jeton d'accès), pour les routes de `AuthentifieNavigateur`
+pub fn ajoute_cookie(cookies: &CookieJar<'_>, session: &Session) {
+    let mut cookie = Cookie::new(COOKIE_SESSION, session.token.to_string());
+    cookie.set_http_only(true);
+    cookie.set_same_site(SameSite::Strict);
+    cookie.set_max_age(rocket::time::Duration::seconds(
+        (session.expires_at - Utc::now()).num_seconds(),
+    ));
+    cookies.add(cookie);
+}
+
+/// Ferme l'Event Stream ouvert avec une session (voir `SESSION_FERMEE`)
+pub async fn ferme_event_stream(event_streams: &EventStreams, session: SessionActive) {
+    let lock = event_streams.read().await;
+    if let Some(event_stream) = lock.get(&session.user_id) {
+        let _ = event_stream.send((SESSION_FERMEE, session.id.to_string()));
+    }
+}
+
+impl Database {
+    /// Ouvre une session pour un utilisateur sur un appareil
+    pub fn ouvre_session(&self, user_id: i64, user_agent: &str) -> Result<Session> {
+        let maintenant = Utc::now();
+        let expires_at = maintenant + self.durees_session().absolue;
+        let token = nouveau_jeton();
+        let refresh_token = nouveau_jeton();
+        // Le jeton d'accès n'est pas valide plus longtemps que la session
+        let access_expires_at = (maintenant + self.durees_session().acces).min(expires_at);
+
+        self.connection.execute(
+            "
+            INSERT INTO session (token_hash, refresh_token_hash, access_expires_at, user_id,
+                user_agent, created_at, last_used, expires_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6, ?7)
+            ",
+            (
+                empreinte_jeton(token.as_str()),
+                empreinte_jeton(refresh_token.as_str()),
+                access_expires_at.timestamp(),
+                user_id,
+                user_agent,
+                maintenant.timestamp(),
+                expires_at.timestamp(),
+            ),
+        )?;
+
+        Ok(Session {
+            user_id,
+            token,
+            expires_at: access_expires_at,
+            refresh_token,
+        })
+    }
+
+    /// Échange le jeton de rafraîchissement d'une session contre de nouveaux jetons
+    ///
+    /// L'ancien jeton d'accès et l'ancien jeton de rafraîchissement ne sont plus valides.
+    pub fn rafraichit_session(&self, refresh_token: &str) -> Result<Session, Erreur> {
+        let maintenant = Utc::now();
+        let token = nouveau_jeton();
+        let nouveau_refresh_token = nouveau_jeton();
+        // Une seule des requêtes qui utilisent le même jeton en même temps le remplace
+        let mut stmt = self.connection.prepare(
+            "
+            UPDATE session SET token_hash = ?1, refresh_token_hash = ?2,
+                access_expires_at = MIN(?3, expires_at), last_used = ?4
+            WHERE refresh_token_hash = ?5 AND expires_at > ?4 AND last_used > ?6
+            RETURNING user_id, access_expires_at
+            ",
+        )?;
+
+        let mut rows = stmt.query_map(
+            (
+                empreinte_jeton(token.as_str()),
+                empreinte_jeton(nouveau_refresh_token.as_str()),
+                (maintenant + self.durees_session().acces).timestamp(),
+                maintenant.timestamp(),
+                empreinte_jeton(refresh_token),
+                (maintenant - self.durees_session().inactivite).timestamp(),
+            ),
+            |row| Ok((row.get(0)?, row.get::<usize, DateTimeSql>(1)?)),
+        )?;
+
+        match rows.next() {
+            Some(session) => {
+                let (user_id, access_expires_at) = session?;
+                Ok(Session {
+                    user_id,
+                    token,
+                    expires_at: access_expires_at.0.and_utc(),
+                    refresh_token: nouveau_refresh_token,
+                })
+            }
+            None => Err(Erreur::NonAutorise(String::from(
+                "Session invalide ou expirée",
+            ))),
+        }
+    }
+
+    /// Retrouve la session d'un jeton d'accès valide et note sa dernière utilisation
+    pub fn verification_session(&self, token: &str) -> Result<SessionActive, Erreur> {
+        let maintenant = Utc::now().timestamp();
+        // La session est marquée utilisée par la même requête qui la vérifie
+        let mut stmt = self.connection.prepare(
+            "
+            UPDATE session SET last_used = ?2
+            WHERE token_hash = ?1 AND access_expires_at > ?2 AND expires_at > ?2 AND last_used > ?3
+            RETURNING id, user_id
+            ",
+        )?;
+
+        let inactive = (Utc::now() - self.durees_session().inactivite).timestamp();
+        let mut rows = stmt.query_map((empreinte_jeton(token), maintenant, inactive), |row| {
+            Ok(SessionActive {
+                id: row.get(0)?,
+                user_id: row.get(1)?,
+            })
+        })?;
+
+        match rows.next() {
+            Some(session) => Ok(session?),
+            None => Err(self.erreur_jeton_acces(token, maintenant, inactive)?),
+        }
+    }
+
+    /// Erreur d'un jeton d'accès refusé : le front rafraîchit la session si elle est encore valide
+    fn erreur_jeton_acces(&self, token: &str, maintenant: i64, inactive: i64) -> Result<Erreur> {
+        let session_valide: bool = self.connection.query_row(
+            "
+            SELECT EXISTS(
+                SELECT 1 FROM session WHERE token_hash = ?1 AND expires_at > ?2 AND last_used > ?3
+            )
+            ",
+            (empreinte_jeton(token), maintenant, inactive),
+            |row| row.get(0),
+        )?;
+
+        Ok(match session_valide {
+            true => Erreur::JetonExpire(String::from("Jeton d'accès expiré")),
+            false => Erreur::NonAutorise(String::from("Session invalide ou expirée")),
+        })
+    }
+
+    /// Récupère les sessions ouvertes d'un utilisateur, de la dernière utilisée à la plus ancienne
+    ///
+    /// `session_id` est la session de la requête, marquée `current`.
+    pub fn recupere_sessions(&self, user_id: i64, session_id: i64) -> Result<Vec<SessionInfo>> {
+        let mut stmt = self.connection.prepare(
+            "
+            SELECT id, user_agent, created_at, last_used, id = ?3 FROM session
+            WHERE user_id = ?1 AND expires_at > ?2 AND last_used > ?4
+            ORDER BY last_used DESC, id DESC
+            ",
+        )?;
+        let inactive = (Utc::now() - self.durees_session().inactivite).timestamp();
+        let rows = stmt.query((user_id, Utc::now().timestamp(), session_id, inactive))?;
+
+        rows.mapped(map_session_info).collect()
+    }
+
+    /// Ferme une session de l'utilisateur
+    pub fn ferme_session(&self, session_id: i64, user_id: i64) -> Result<SessionActive, Erreur> {
+        let supprimees = self.connection.execute(
+            "DELETE FROM session WHERE id = ?1 AND user_id = ?2",
+            (session_id, user_id),
+        )?;
+
+        match supprimees {
+            0 => Err(Erreur::Introuvable(String::from("Session introuvable"))),
+            _ => Ok(SessionActive {
+                id: session_id,
+                user_id,
+            }),
+        }
+    }
+
+    /// Ferme les sessions d'un utilisateur, sauf `sauf` s'il est donné, et les retourne
+    pub fn ferme_sessions(&self, user_id: i64, sauf: Option<i64>) -> Result<Vec<SessionActive>> {
+        let mut stmt = self
+            .connection
+            .prepare("DELETE FROM session WHERE user_id = ?1 AND id IS NOT ?2 RETURNING id")?;
+        let rows = stmt.query((user_id, sauf))?;
+
+        rows.mapped(|row| {
+            Ok(SessionActive {
+                id: row.get(0)?,
+                user_id,
+            })
+        })
+        .collect()
+    }
+
+    /// Supprime les sessions expirées ou inactives
+    pub fn nettoie_sessions(&self) -> Result<usize> {
+        self.connection.execute(
+            "DELETE FROM session WHERE expires_at <= ?1 OR last_used <= ?2",
+            (
+                Utc::now().timestamp(),
+                (Utc::now() - self.durees_session().inactivite).timestamp(),
+            ),
+        )
+    }
+}
+
+fn map_session_info(row: &Row) -> Result<SessionInfo> {
+    Ok(SessionInfo {
+        id: row.get(0)?,
+        user_agent: row.get(1)?,
+        created_at: DateTimeSql::parse(row.get(2)?).unwrap(),
+        last_used: DateTimeSql::parse(row.get(3)?).unwrap(),
+        current: row.get(4)?,
+    })
+}
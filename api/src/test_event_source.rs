@@ -4,63 +4,109 @@
 //! d'événements lors des tests, effectuant des requêtes HTTP pour récupérer des événements
 //! et vérifiant s'ils correspondent aux événements attendus dans une application Rocket.
 
-use json::parse;
 use lib::EventMessage;
+use rocket::http::Header;
 use rocket::local::asynchronous::{Client, LocalResponse};
 use rocket::tokio::io::Lines;
 use rocket::tokio::io::{AsyncBufReadExt, BufReader};
 
-use crate::user::UserPass;
+use crate::tests::UserSession;
 
 /// Pseudo Event Source pour les tests
 #[derive(Debug)]
 pub struct TestEventSource<'a> {
     username: String,
     stream: Lines<BufReader<LocalResponse<'a>>>,
+    last_event_id: Option<i64>,
 }
 
 impl<'a> TestEventSource<'a> {
     pub async fn new<'c>(
         client: &'c Client,
-        user: &UserPass,
+        user: &UserSession,
     ) -> Result<TestEventSource<'c>, String> {
-        let response = client
-            .get(format!(
-                "/events/{}?api_key={}",
-                user.id,
-                user.api_key.as_str()
-            ))
-            .dispatch()
-            .await;
-    
+        TestEventSource::connect(
+            client,
+            user,
+            String::from("/events"),
+            None,
+        )
+        .await
+    }
+
+    /// Se reconnecte en donnant le dernier événement reçu dans le paramètre `since`
+    pub async fn new_since<'c>(
+        client: &'c Client,
+        user: &UserSession,
+        since: i64,
+    ) -> Result<TestEventSource<'c>, String> {
+        TestEventSource::connect(
+            client,
+            user,
+            format!("/events?since={}", since),
+            None,
+        )
+        .await
+    }
+
+    /// Se reconnecte en donnant le dernier événement reçu dans l'en-tête `Last-Event-ID`
+    pub async fn new_last_event_id<'c>(
+        client: &'c Client,
+        user: &UserSession,
+        last_event_id: i64,
+    ) -> Result<TestEventSource<'c>, String> {
+        TestEventSource::connect(
+            client,
+            user,
+            String::from("/events"),
+            Some(last_event_id),
+        )
+        .await
+    }
+
+    async fn connect<'c>(
+        client: &'c Client,
+        user: &UserSession,
+        url: String,
+        last_event_id: Option<i64>,
+    ) -> Result<TestEventSource<'c>, String> {
+        let mut request = client.get(url).header(user.auth());
+        if let Some(last_event_id) = last_event_id {
+            request = request.header(Header::new("Last-Event-ID", last_event_id.to_string()));
+        }
+        let response = request.dispatch().await;
+
         match response.status().code {
             200 => Ok(TestEventSource {
                 username: user.username.to_string(),
                 stream: BufReader::new(response).lines(),
+                last_event_id: None,
             }),
             _ => Err(response.into_string().await.unwrap()),
         }
     }
 
-    async fn next(&mut self) -> Result<Option<EventMessage>, String> {
+    /// Identifiant du dernier événement reçu
+    pub fn last_event_id(&self) -> Option<i64> {
+        self.last_event_id
+    }
+
+    pub async fn next(&mut self) -> Result<Option<EventMessage>, String> {
         let mut line: Option<String> = None;
         for _ in 0..5 {
             line = match self.stream.next_line().await {
                 Ok(Some(line)) if line.starts_with("data:") => Some(line),
+                Ok(Some(line)) if line.starts_with("id:") => {
+                    self.last_event_id = line[3..].trim().parse::<i64>().ok();
+                    continue;
+                }
                 Ok(_) => continue,
                 _ => return Err(String::from("Error Next Line")),
             };
             break;
         }
         match line {
-            Some(line) => {
-                let value = parse(&line[5..]).unwrap();
-
-                match EventMessage::parse(&value) {
-                    Ok(message) => Ok(Some(message)),
-                    Err(message) => Err(String::from(message)),
-                }
-            }
+            Some(line) => EventMessage::parse(line[5..].trim()).map(Some),
             None => Ok(None),
         }
     }
@@ -68,9 +114,13 @@ impl<'a> TestEventSource<'a> {
     pub async fn test_next(&mut self, event: EventMessage) {
         match (self.next().await, event) {
             (Ok(Some(EventMessage::Message(message))), EventMessage::Message(event)) => {
+                assert_eq!(message.id, event.id);
                 assert_eq!(message.user_id, event.user_id);
                 assert_eq!(message.room_id, event.room_id);
                 assert_eq!(message.text, event.text);
+                assert_eq!(message.parent_id, event.parent_id);
+                assert_eq!(message.attachments, event.attachments);
+                assert_eq!(message.expires_at.is_some(), event.expires_at.is_some());
             }
             (Ok(Some(EventMessage::Message(message))), event) => {
                 panic!(
@@ -78,9 +128,149 @@ impl<'a> TestEventSource<'a> {
                     self.username, message, event
                 );
             }
+            (Ok(Some(EventMessage::MessageEdit(message))), EventMessage::MessageEdit(event)) => {
+                assert_eq!(message.id, event.id);
+                assert_eq!(message.room_id, event.room_id);
+                assert_eq!(message.text, event.text);
+            }
+            (Ok(Some(EventMessage::Mention(message))), EventMessage::Mention(event)) => {
+                assert_eq!(message.id, event.id);
+                assert_eq!(message.room_id, event.room_id);
+                assert_eq!(message.text, event.text);
+            }
+            (Ok(Some(EventMessage::Mention(message))), event) => {
+                panic!(
+                    "{}: Didn't expected a mention: {:?} for event: {:?}",
+                    self.username, message, event
+                );
+            }
+            (Ok(Some(EventMessage::MessageEdit(message))), event) => {
+                panic!(
+                    "{}: Didn't expected a message edit: {:?} for event: {:?}",
+                    self.username, message, event
+                );
+            }
+            (
+                Ok(Some(EventMessage::MessageDelete(deleted))),
+                EventMessage::MessageDelete(event),
+            ) => {
+                assert_eq!(deleted, event);
+            }
+            (Ok(Some(EventMessage::MessageDelete(deleted))), event) => {
+                panic!(
+                    "{}: Didn't expected a message deletion: {:?} for event: {:?}",
+                    self.username, deleted, event
+                );
+            }
+            (Ok(Some(EventMessage::Thread(thread))), EventMessage::Thread(event)) => {
+                assert_eq!(thread, event);
+            }
+            (Ok(Some(EventMessage::Thread(thread))), event) => {
+                panic!(
+                    "{}: Didn't expected a thread: {:?} for event: {:?}",
+                    self.username, thread, event
+                );
+            }
+            (Ok(Some(EventMessage::Reaction(reaction))), EventMessage::Reaction(event)) => {
+                assert_eq!(reaction, event);
+            }
+            (Ok(Some(EventMessage::Reaction(reaction))), event) => {
+                panic!(
+                    "{}: Didn't expected a reaction: {:?} for event: {:?}",
+                    self.username, reaction, event
+                );
+            }
+            (Ok(Some(EventMessage::ReadReceipt(lecture))), EventMessage::ReadReceipt(event)) => {
+                assert_eq!(lecture, event);
+            }
+            (Ok(Some(EventMessage::ReadReceipt(lecture))), event) => {
+                panic!(
+                    "{}: Didn't expected a read receipt: {:?} for event: {:?}",
+                    self.username, lecture, event
+                );
+            }
+            (Ok(Some(EventMessage::Typing(typing))), EventMessage::Typing(event)) => {
+                assert_eq!(typing, event);
+            }
+            (Ok(Some(EventMessage::Typing(typing))), event) => {
+                panic!(
+                    "{}: Didn't expected a typing: {:?} for event: {:?}",
+                    self.username, typing, event
+                );
+            }
+            (Ok(Some(EventMessage::Presence(presence))), EventMessage::Presence(event)) => {
+                assert_eq!(presence.user_id, event.user_id);
+                assert_eq!(presence.username, event.username);
+                assert_eq!(presence.status, event.status);
+            }
+            (Ok(Some(EventMessage::Presence(presence))), event) => {
+                panic!(
+                    "{}: Didn't expected a presence: {:?} for event: {:?}",
+                    self.username, presence, event
+                );
+            }
+            (Ok(Some(EventMessage::Member(member))), EventMessage::Member(event)) => {
+                assert_eq!(member, event);
+            }
+            (Ok(Some(EventMessage::Member(member))), event) => {
+                panic!(
+                    "{}: Didn't expected a member: {:?} for event: {:?}",
+                    self.username, member, event
+                );
+            }
+            (Ok(Some(EventMessage::MemberRemove(removed))), EventMessage::MemberRemove(event)) => {
+                assert_eq!(removed, event);
+            }
+            (Ok(Some(EventMessage::MemberRemove(removed))), event) => {
+                panic!(
+                    "{}: Didn't expected a member removal: {:?} for event: {:?}",
+                    self.username, removed, event
+                );
+            }
+            (Ok(Some(EventMessage::RoomRemove(removed))), EventMessage::RoomRemove(event)) => {
+                assert_eq!(removed, event);
+            }
+            (Ok(Some(EventMessage::RoomRemove(removed))), event) => {
+                panic!(
+                    "{}: Didn't expected a room removal: {:?} for event: {:?}",
+                    self.username, removed, event
+                );
+            }
+            (Ok(Some(EventMessage::Pin(pin))), EventMessage::Pin(event)) => {
+                assert_eq!(pin.room_id, event.room_id);
+                assert_eq!(pin.user_id, event.user_id);
+                assert_eq!(pin.message.id, event.message.id);
+                assert_eq!(pin.message.text, event.message.text);
+            }
+            (Ok(Some(EventMessage::Pin(pin))), event) => {
+                panic!(
+                    "{}: Didn't expected a pin: {:?} for event: {:?}",
+                    self.username, pin, event
+                );
+            }
+            (Ok(Some(EventMessage::Unpin(unpin))), EventMessage::Unpin(event)) => {
+                assert_eq!(unpin, event);
+            }
+            (Ok(Some(EventMessage::Unpin(unpin))), event) => {
+                panic!(
+                    "{}: Didn't expected an unpin: {:?} for event: {:?}",
+                    self.username, unpin, event
+                );
+            }
+            (Ok(Some(EventMessage::RoomUpdate(settings))), EventMessage::RoomUpdate(event)) => {
+                assert_eq!(settings, event);
+            }
+            (Ok(Some(EventMessage::RoomUpdate(settings))), event) => {
+                panic!(
+                    "{}: Didn't expected a room update: {:?} for event: {:?}",
+                    self.username, settings, event
+                );
+            }
             (Ok(Some(EventMessage::Room(room))), EventMessage::Room(event)) => {
                 assert_eq!(room.id, event.id);
                 assert_eq!(room.name, event.name);
+                assert_eq!(room.topic, event.topic);
+                assert_eq!(room.kind, event.kind);
             }
             (Ok(Some(EventMessage::Room(room))), event) => {
                 panic!(
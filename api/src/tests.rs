@@ -6,27 +6,26 @@
 use chrono::Utc;
 use dotenv::dotenv;
 use json::JsonValue;
-use lib::{EventMessage, Message, Room};
+use lib::{
+    ApiError, Attachment, DeletedMessage, ErrorCode, EventMessage, Message, Pin, PresenceStatus,
+    Reaction, ReadReceipt, RemovedMember, Room, RoomKind, RoomMember, RoomRole, RoomSettings,
+    ScheduledMessage, SearchResult, Session, Sessions, Thread, Typing, Unpin, UserPresence,
+};
 use rocket::http::uri::fmt::{Query, UriDisplay};
-use rocket::http::ContentType;
+use rocket::http::{ContentType, Header};
 use rocket::local::asynchronous::{Client, LocalResponse};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Once;
 use std::{env, fs};
 
+use crate::attachment::DELAI_PIECE_JOINTE_NON_LIEE;
+use crate::mot_de_passe::commande_reinitialisation;
+use crate::session::{empreinte_jeton, DureesSession};
 use crate::test_event_source::TestEventSource;
-use crate::user::UserPass;
 
 use super::*;
 
-static mut ROOMID: i64 = 0;
-
-pub fn next_room_id() -> i64 {
-    unsafe {
-        ROOMID += 1;
-        ROOMID
-    }
-}
-
 #[async_test]
 async fn test_adduser() {
     let client = initialize().await;
@@ -67,25 +66,480 @@ async fn test_login() {
     );
     let user = login(&client, &form_user).await.unwrap();
     assert_eq!(add_user.id, user.id);
-    assert_ne!(add_user.api_key, user.api_key);
+    assert_ne!(add_user.token, user.token);
     assert_eq!(user.username, get_user(&client, user.id).await.unwrap());
 }
 
+#[async_test]
+async fn test_session() {
+    let client = initialize().await;
+
+    let user = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_session".to_string(),
+            password: "test_session".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    // Plusieurs requêtes peuvent utiliser le même jeton
+    let room = user
+        .addroom(&client, String::from("Room Session #1"))
+        .await
+        .unwrap();
+    user.addmessage(&client, room.id, String::from("Un"))
+        .await
+        .unwrap();
+    user.addmessage(&client, room.id, String::from("Deux"))
+        .await
+        .unwrap();
+
+    // Le cookie de session suffit pour l'Event Stream, mais pas pour modifier quelque chose
+    let response = client.get("/events").dispatch().await;
+    assert_eq!(response.status().code, 200);
+    drop(response);
+    let response = client
+        .post(format!("/room/{}/leave", room.id))
+        .dispatch()
+        .await;
+    assert_eq!(
+        into_erreur(response).await,
+        (
+            401,
+            ApiError {
+                code: ErrorCode::Unauthorized,
+                reason: String::from("Jeton de session manquant"),
+            }
+        )
+    );
+
+    let response = client
+        .get("/scheduled")
+        .header(Header::new("Authorization", "Bearer"))
+        .dispatch()
+        .await;
+    assert_eq!(into_erreur(response).await.0, 401);
+}
+
+#[async_test]
+async fn test_sessions_appareils() {
+    let client = initialize().await;
+
+    let form_user = FormAddUser {
+        username: "test_sessions_appareils".to_string(),
+        password: "test_sessions_appareils".to_string(),
+    };
+    let portable = add_user(&client, &form_user).await.unwrap();
+    let response = client
+        .post(uri!(post_login))
+        .header(ContentType::Form)
+        .header(Header::new("User-Agent", "Téléphone"))
+        .body((&form_user as &dyn UriDisplay<Query>).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status().code, 202);
+    let telephone = UserSession {
+        id: portable.id,
+        username: portable.username.to_string(),
+        pass: portable.pass.to_string(),
+        token: into_json(response).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string(),
+    };
+    let autre = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_sessions_appareils_2".to_string(),
+            password: "test_sessions_appareils_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    // La connexion du téléphone ne ferme pas la session du portable
+    let mut evenements_portable = TestEventSource::new(&client, &portable).await.unwrap();
+    let mut evenements_telephone = TestEventSource::new(&client, &telephone).await.unwrap();
+
+    let response = client
+        .get("/sessions")
+        .header(telephone.auth())
+        .dispatch()
+        .await;
+    assert_eq!(response.status().code, 200);
+    let sessions = into_reponse::<Sessions>(response).await.unwrap().sessions;
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0].user_agent, "Téléphone");
+    assert!(sessions[0].current);
+    assert!(!sessions[1].current);
+    let session_telephone = sessions[0].id;
+
+    // Un utilisateur ne peut pas fermer la session d'un autre
+    let response = client
+        .delete(format!("/sessions/{}", session_telephone))
+        .header(autre.auth())
+        .dispatch()
+        .await;
+    assert_eq!(
+        into_erreur(response).await,
+        (
+            404,
+            ApiError {
+                code: ErrorCode::NotFound,
+                reason: String::from("Session introuvable"),
+            }
+        )
+    );
+
+    // Fermer la session du téléphone ferme son Event Stream, pas celui du portable
+    let response = client
+        .delete(format!("/sessions/{}", session_telephone))
+        .header(portable.auth())
+        .dispatch()
+        .await;
+    assert_eq!(response.status().code, 200);
+    assert_eq!(evenements_telephone.next().await, Ok(None));
+    assert_eq!(
+        portable
+            .addroom(&client, String::from("Room Sessions #1"))
+            .await
+            .unwrap()
+            .name,
+        "Room Sessions #1"
+    );
+    assert!(matches!(
+        evenements_portable.next().await,
+        Ok(Some(EventMessage::Room(_)))
+    ));
+    assert_eq!(
+        telephone
+            .addroom(&client, String::from("Room Sessions #2"))
+            .await
+            .unwrap_err(),
+        "Session invalide ou expirée"
+    );
+
+    let response = client
+        .get("/sessions")
+        .header(portable.auth())
+        .dispatch()
+        .await;
+    assert_eq!(
+        into_reponse::<Sessions>(response)
+            .await
+            .unwrap()
+            .sessions
+            .len(),
+        1
+    );
+}
+
+#[async_test]
+async fn test_refresh() {
+    let client = initialize().await;
+
+    let form_user = FormAddUser {
+        username: "test_refresh".to_string(),
+        password: "test_refresh".to_string(),
+    };
+    let user = add_user(&client, &form_user).await.unwrap();
+    let session = login_session(&client, &form_user).await.unwrap();
+    assert!(session.expires_at <= Utc::now() + chrono::Duration::minutes(15));
+
+    // Le jeton de rafraîchissement donne de nouveaux jetons, les anciens ne sont plus valides
+    let rafraichie = refresh(&client, session.refresh_token.as_str())
+        .await
+        .unwrap();
+    assert_eq!(rafraichie.user_id, user.id);
+    assert_ne!(rafraichie.token, session.token);
+    assert_eq!(
+        refresh(&client, session.refresh_token.as_str())
+            .await
+            .unwrap_err(),
+        "Session invalide ou expirée"
+    );
+    let user = UserSession {
+        token: session.token,
+        ..user
+    };
+    assert_eq!(
+        user.addroom(&client, String::from("Room Refresh #1"))
+            .await
+            .unwrap_err(),
+        "Session invalide ou expirée"
+    );
+    let user = UserSession {
+        token: rafraichie.token,
+        ..user
+    };
+    user.addroom(&client, String::from("Room Refresh #1"))
+        .await
+        .unwrap();
+    // Le cookie de session a été remplacé par le nouveau jeton d'accès
+    let response = client.get("/events").dispatch().await;
+    assert_eq!(response.status().code, 200);
+    drop(response);
+
+    // Un jeton d'accès expiré se rafraîchit
+    let client = initialize_durees(DureesSession {
+        acces: chrono::Duration::zero(),
+        ..DureesSession::default()
+    })
+    .await;
+    add_user(&client, &form_user).await.unwrap();
+    let session = login_session(&client, &form_user).await.unwrap();
+    let user = UserSession {
+        token: session.token,
+        ..user
+    };
+    let response = client
+        .post(uri!(post_room))
+        .header(ContentType::Form)
+        .header(user.auth())
+        .body("name=Room%20Refresh%20%232")
+        .dispatch()
+        .await;
+    assert_eq!(
+        into_erreur(response).await,
+        (
+            401,
+            ApiError {
+                code: ErrorCode::TokenExpired,
+                reason: String::from("Jeton d'accès expiré"),
+            }
+        )
+    );
+    refresh(&client, session.refresh_token.as_str())
+        .await
+        .unwrap();
+
+    // Une session inactive ou trop vieille ne se rafraîchit plus
+    for durees_session in [
+        DureesSession {
+            inactivite: chrono::Duration::zero(),
+            ..DureesSession::default()
+        },
+        DureesSession {
+            absolue: chrono::Duration::zero(),
+            ..DureesSession::default()
+        },
+    ] {
+        let client = initialize_durees(durees_session).await;
+        add_user(&client, &form_user).await.unwrap();
+        let session = login_session(&client, &form_user).await.unwrap();
+        assert_eq!(
+            refresh(&client, session.refresh_token.as_str())
+                .await
+                .unwrap_err(),
+            "Session invalide ou expirée"
+        );
+    }
+}
+
+#[async_test]
+async fn test_password() {
+    let client = initialize().await;
+
+    let form_user = FormAddUser {
+        username: "test_password".to_string(),
+        password: "test_password".to_string(),
+    };
+    let user = add_user(&client, &form_user).await.unwrap();
+    let autre_appareil = login(&client, &form_user).await.unwrap();
+    let mut evenements = TestEventSource::new(&client, &autre_appareil)
+        .await
+        .unwrap();
+
+    let change = |old_password: &str, new_password: &str| FormChangePassword {
+        old_password: old_password.to_string(),
+        new_password: new_password.to_string(),
+    };
+    assert_eq!(
+        user.change_password(&client, &change("Wrong password", "nouveau"))
+            .await
+            .unwrap_err(),
+        (
+            403,
+            ApiError {
+                code: ErrorCode::Forbidden,
+                reason: String::from("Mauvais mot de passe"),
+            }
+        )
+    );
+    assert_eq!(
+        user.change_password(&client, &change("test_password", ""))
+            .await
+            .unwrap_err()
+            .0,
+        400
+    );
+
+    let database = client
+        .rocket()
+        .state::<Pool>()
+        .unwrap()
+        .connection()
+        .unwrap();
+    let sortie = commande_reinitialisation(&database, Some("test_password")).unwrap();
+    let token = sortie.rsplit(' ').next().unwrap().to_string();
+    drop(database);
+
+    // Les autres sessions sont fermées, pas celle qui a changé le mot de passe, et les jetons
+    // de réinitialisation ne sont plus valides
+    user.change_password(&client, &change("test_password", "nouveau"))
+        .await
+        .unwrap();
+    assert_eq!(
+        reset_password(
+            &client,
+            &FormResetPassword {
+                token,
+                new_password: String::from("autre"),
+            }
+        )
+        .await
+        .unwrap_err()
+        .0,
+        401
+    );
+    assert_eq!(evenements.next().await, Ok(None));
+    assert_eq!(
+        autre_appareil
+            .addroom(&client, String::from("Room Password #1"))
+            .await
+            .unwrap_err(),
+        "Session invalide ou expirée"
+    );
+    user.addroom(&client, String::from("Room Password #1"))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        login(&client, &form_user).await.unwrap_err(),
+        "Mauvais identifiant ou mot de passe"
+    );
+    login(
+        &client,
+        &FormAddUser {
+            username: "test_password".to_string(),
+            password: "nouveau".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+}
+
+#[async_test]
+async fn test_reset_password() {
+    let client = initialize().await;
+
+    let form_user = FormAddUser {
+        username: "test_reset_password".to_string(),
+        password: "test_reset_password".to_string(),
+    };
+    let user = add_user(&client, &form_user).await.unwrap();
+
+    let database = client
+        .rocket()
+        .state::<Pool>()
+        .unwrap()
+        .connection()
+        .unwrap();
+    assert!(commande_reinitialisation(&database, None).is_err());
+    assert!(commande_reinitialisation(&database, Some("test_reset_password_inconnu")).is_err());
+    let jeton = |sortie: String| sortie.rsplit(' ').next().unwrap().to_string();
+    let token = jeton(commande_reinitialisation(&database, Some("test_reset_password")).unwrap());
+    let autre_token =
+        jeton(commande_reinitialisation(&database, Some("test_reset_password")).unwrap());
+
+    // Seule l'empreinte du jeton est gardée
+    let jetons_gardes = |token_hash: String| {
+        database
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM password_reset WHERE token_hash = ?1",
+                [token_hash],
+                |row| row.get::<usize, i64>(0),
+            )
+            .unwrap()
+    };
+    assert_eq!(jetons_gardes(empreinte_jeton(token.as_str())), 1);
+    assert_eq!(jetons_gardes(token.to_string()), 0);
+    drop(database);
+
+    let reset = |token: &str| FormResetPassword {
+        token: token.to_string(),
+        new_password: "nouveau".to_string(),
+    };
+    assert_eq!(
+        reset_password(&client, &reset("mauvais jeton"))
+            .await
+            .unwrap_err(),
+        (
+            401,
+            ApiError {
+                code: ErrorCode::Unauthorized,
+                reason: String::from("Jeton invalide ou expiré"),
+            }
+        )
+    );
+
+    // Le jeton ne sert qu'une fois, invalide les autres jetons et ferme toutes les sessions
+    reset_password(&client, &reset(token.as_str()))
+        .await
+        .unwrap();
+    assert_eq!(
+        reset_password(&client, &reset(token.as_str()))
+            .await
+            .unwrap_err()
+            .0,
+        401
+    );
+    assert_eq!(
+        reset_password(&client, &reset(autre_token.as_str()))
+            .await
+            .unwrap_err()
+            .0,
+        401
+    );
+    assert_eq!(
+        user.addroom(&client, String::from("Room Reset #1"))
+            .await
+            .unwrap_err(),
+        "Session invalide ou expirée"
+    );
+    assert_eq!(
+        login(&client, &form_user).await.unwrap_err(),
+        "Mauvais identifiant ou mot de passe"
+    );
+    login(
+        &client,
+        &FormAddUser {
+            username: "test_reset_password".to_string(),
+            password: "nouveau".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+}
+
 #[async_test]
 async fn test_room() {
     let client = initialize().await;
 
     assert_eq!(
-        UserPass {
+        UserSession {
             id: -1,
             username: "test_room".to_string(),
             pass: "test_room".to_string(),
-            api_key: "no key".to_string(),
+            token: "no key".to_string(),
         }
         .addroom(&client, String::from("Room Room #1"))
         .await
         .unwrap_err(),
-        "Mauvais id ou api key"
+        "Session invalide ou expirée"
     );
 }
 
@@ -94,19 +548,19 @@ async fn test_invite() {
     let client = initialize().await;
 
     assert_eq!(
-        UserPass {
+        UserSession {
             id: -1,
             username: "test_invite_1".to_string(),
             pass: "test_invite_1".to_string(),
-            api_key: "wrong_key".to_string(),
+            token: "wrong_key".to_string(),
         }
         .invite(&client, "test_invite_1".to_string(), 1)
         .await
         .unwrap_err(),
-        "Mauvais id ou api key"
+        "Session invalide ou expirée"
     );
 
-    let mut user_1 = add_user(
+    let user_1 = add_user(
         &client,
         &FormAddUser {
             username: "test_invite_1".to_string(),
@@ -121,7 +575,7 @@ async fn test_invite() {
         .await
         .unwrap();
 
-    let mut user_2 = add_user(
+    let user_2 = add_user(
         &client,
         &FormAddUser {
             username: "test_invite_2".to_string(),
@@ -178,16 +632,20 @@ async fn test_wrong_event() {
     assert_eq!(
         TestEventSource::new(
             &client,
-            &UserPass {
+            &UserSession {
                 id: user_1.id,
                 username: user_1.username.to_string(),
                 pass: user_1.pass.to_string(),
-                api_key: "wrong_key".to_string(),
+                token: "wrong_key".to_string(),
             },
         )
         .await
+        .map_err(|e| ApiError::parse(e.as_str()))
         .unwrap_err(),
-        "Mauvais id ou api key"
+        Ok(ApiError {
+            code: ErrorCode::Unauthorized,
+            reason: String::from("Session invalide ou expirée"),
+        })
     );
 }
 
@@ -195,7 +653,7 @@ async fn test_wrong_event() {
 async fn test_event() {
     let client = initialize().await;
 
-    let mut user_1 = add_user(
+    let user_1 = add_user(
         &client,
         &FormAddUser {
             username: "test_event_1".to_string(),
@@ -223,7 +681,7 @@ async fn test_event() {
         .test_next(EventMessage::Message(message.clone()))
         .await;
 
-    let mut user_2 = add_user(
+    let user_2 = add_user(
         &client,
         &FormAddUser {
             username: "test_event_2".to_string(),
@@ -239,6 +697,17 @@ async fn test_event() {
         .await
         .unwrap();
 
+    user_1_events
+        .test_next(EventMessage::Member(RoomMember {
+            room_id: room.id,
+            user_id: user_2.id,
+            role: RoomRole::Member,
+        }))
+        .await;
+    user_1_events
+        .test_next(presence(&user_2, PresenceStatus::Offline))
+        .await;
+
     let mut user_2_events = TestEventSource::new(&client, &user_2).await.unwrap();
     user_2_events
         .test_next(EventMessage::Room(room.clone()))
@@ -246,6 +715,12 @@ async fn test_event() {
     user_2_events
         .test_next(EventMessage::Message(message))
         .await;
+    user_2_events
+        .test_next(presence(&user_1, PresenceStatus::Online))
+        .await;
+    user_1_events
+        .test_next(presence(&user_2, PresenceStatus::Online))
+        .await;
 
     let message2 = user_2
         .addmessage(&client, room.id, String::from("Bonjour"))
@@ -259,157 +734,3363 @@ async fn test_event() {
         .await;
 }
 
-static INIT: Once = Once::new();
+#[async_test]
+async fn test_edit_message() {
+    let client = initialize().await;
 
-pub async fn initialize() -> Client {
-    INIT.call_once(|| {
-        dotenv().unwrap();
-        let database_url = env::var("DATABASE_URL_TEST").unwrap();
-        if fs::metadata(database_url.clone()).is_ok() {
-            fs::remove_file(database_url).unwrap();
-        }
-    });
-    Client::tracked(build(true)).await.unwrap()
-}
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_edit_message_1".to_string(),
+            password: "test_edit_message_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_edit_message_2".to_string(),
+            password: "test_edit_message_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
 
-async fn add_user(client: &Client, login: &FormAddUser) -> Result<UserPass, String> {
-    let response = client
-        .post(uri!(post_user))
-        .header(ContentType::Form)
-        .body((login as &dyn UriDisplay<Query>).to_string())
-        .dispatch()
-        .await;
-    let status = response.status().code;
-    let result = into_json(response).await;
+    let room = user_1
+        .addroom(&client, String::from("Room Edit #1"))
+        .await
+        .unwrap();
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+
+    let message = user_1
+        .addmessage(&client, room.id, String::from("Salut"))
+        .await
+        .unwrap();
+
+    let mut user_2_events = TestEventSource::new(&client, &user_2).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_2_events
+        .test_next(EventMessage::Message(message.clone()))
+        .await;
+    user_2_events
+        .test_next(presence(&user_1, PresenceStatus::Offline))
+        .await;
+
+    assert_eq!(
+        user_2
+            .editmessage(&client, &message, String::from("Pirate"))
+            .await
+            .unwrap_err(),
+        "Tu ne peux pas modifier le message de quelqu'un d'autre."
+    );
+    assert_eq!(
+        user_1
+            .editmessage(
+                &client,
+                &Message {
+                    id: -1,
+                    ..message.clone()
+                },
+                String::from("Salut!")
+            )
+            .await
+            .unwrap_err(),
+        "no message with the id -1"
+    );
+
+    let edited = user_1
+        .editmessage(&client, &message, String::from("Salut!"))
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::MessageEdit(edited))
+        .await;
+}
+
+#[async_test]
+async fn test_delete_message() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_delete_message_1".to_string(),
+            password: "test_delete_message_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_delete_message_2".to_string(),
+            password: "test_delete_message_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Delete #1"))
+        .await
+        .unwrap();
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+
+    let message = user_1
+        .addmessage(&client, room.id, String::from("Oups"))
+        .await
+        .unwrap();
+
+    let mut user_1_events = TestEventSource::new(&client, &user_1).await.unwrap();
+    user_1_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_1_events
+        .test_next(EventMessage::Message(message.clone()))
+        .await;
+    user_1_events
+        .test_next(presence(&user_2, PresenceStatus::Offline))
+        .await;
+
+    assert_eq!(
+        user_2.deletemessage(&client, &message).await.unwrap_err(),
+        "Tu ne peux pas modifier le message de quelqu'un d'autre."
+    );
+
+    user_1.deletemessage(&client, &message).await.unwrap();
+    user_1_events
+        .test_next(EventMessage::MessageDelete(DeletedMessage {
+            id: message.id,
+            room_id: room.id,
+        }))
+        .await;
+
+    assert_eq!(
+        user_1.deletemessage(&client, &message).await.unwrap_err(),
+        format!("no message with the id {}", message.id)
+    );
+}
+
+#[async_test]
+async fn test_room_messages() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_room_messages_1".to_string(),
+            password: "test_room_messages_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_room_messages_2".to_string(),
+            password: "test_room_messages_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Messages #1"))
+        .await
+        .unwrap();
+
+    let mut messages = Vec::new();
+    for i in 0..5 {
+        messages.push(
+            user_1
+                .addmessage(&client, room.id, format!("Message {}", i))
+                .await
+                .unwrap(),
+        );
+    }
+
+    assert_eq!(
+        user_2
+            .getmessages(&client, room.id, None, None)
+            .await
+            .unwrap_err(),
+        "Tu n'es pas dans ce salon."
+    );
+
+    let (page, has_more) = user_1
+        .getmessages(&client, room.id, None, Some(2))
+        .await
+        .unwrap();
+    assert!(has_more);
+    assert_eq!(
+        page.iter().map(|m| m.id).collect::<Vec<i64>>(),
+        vec![messages[3].id, messages[4].id]
+    );
+
+    let (page, has_more) = user_1
+        .getmessages(&client, room.id, Some(page[0].id), Some(2))
+        .await
+        .unwrap();
+    assert!(has_more);
+    assert_eq!(
+        page.iter().map(|m| m.text.as_str()).collect::<Vec<&str>>(),
+        vec!["Message 1", "Message 2"]
+    );
+
+    let (page, has_more) = user_1
+        .getmessages(&client, room.id, Some(page[0].id), Some(2))
+        .await
+        .unwrap();
+    assert!(!has_more);
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].id, messages[0].id);
+}
+
+#[async_test]
+async fn test_event_since() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_event_since_1".to_string(),
+            password: "test_event_since_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Since #1"))
+        .await
+        .unwrap();
+    let message_1 = user_1
+        .addmessage(&client, room.id, String::from("Premier"))
+        .await
+        .unwrap();
+
+    let mut user_1_events = TestEventSource::new(&client, &user_1).await.unwrap();
+    user_1_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_1_events
+        .test_next(EventMessage::Message(message_1.clone()))
+        .await;
+    let message_2 = user_1
+        .addmessage(&client, room.id, String::from("Deuxième"))
+        .await
+        .unwrap();
+    user_1_events
+        .test_next(EventMessage::Message(message_2.clone()))
+        .await;
+    let since = user_1_events.last_event_id().unwrap();
+    drop(user_1_events);
+
+    let message_3 = user_1
+        .addmessage(&client, room.id, String::from("Troisième"))
+        .await
+        .unwrap();
+    let message_4 = user_1
+        .addmessage(&client, room.id, String::from("Quatrième"))
+        .await
+        .unwrap();
+
+    let mut user_1_events = TestEventSource::new_since(&client, &user_1, since)
+        .await
+        .unwrap();
+    user_1_events
+        .test_next(EventMessage::Message(message_3.clone()))
+        .await;
+    user_1_events
+        .test_next(EventMessage::Message(message_4.clone()))
+        .await;
+    let since_4 = user_1_events.last_event_id().unwrap();
+    assert!(since_4 > since);
+
+    let mut user_1_events_2 = TestEventSource::new_last_event_id(&client, &user_1, since)
+        .await
+        .unwrap();
+    user_1_events_2
+        .test_next(EventMessage::Message(message_3))
+        .await;
+    user_1_events_2
+        .test_next(EventMessage::Message(message_4))
+        .await;
+
+    let message_5 = user_1
+        .addmessage(&client, room.id, String::from("Cinquième"))
+        .await
+        .unwrap();
+    user_1_events
+        .test_next(EventMessage::Message(message_5.clone()))
+        .await;
+    user_1_events_2
+        .test_next(EventMessage::Message(message_5))
+        .await;
+    assert!(user_1_events.last_event_id().unwrap() > since_4);
+    drop(user_1_events_2);
+
+    // Un événement diffusé avant un autre journalisé plus tôt n'empêche pas son envoi
+    let renomme = |name: &str| RoomSettings {
+        room_id: room.id,
+        name: name.to_string(),
+        topic: String::new(),
+        retention: None,
+    };
+    let (avant, apres) = (renomme("Journalisé avant"), renomme("Journalisé après"));
+    let (data_avant, data_apres) = (avant.serialize(), apres.serialize());
+    let user_id = user_1.id;
+    let (diffusion_avant, diffusion_apres) = client
+        .rocket()
+        .state::<Pool>()
+        .unwrap()
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            Ok((
+                connection_bd.journalise_evenement(&[user_id], data_avant)?,
+                connection_bd.journalise_evenement(&[user_id], data_apres)?,
+            ))
+        })
+        .await
+        .unwrap();
+    let event_streams = client.rocket().state::<EventStreams>().unwrap();
+    diffuse_evenement(event_streams, diffusion_apres).await;
+    diffuse_evenement(event_streams, diffusion_avant).await;
+    user_1_events
+        .test_next(EventMessage::RoomUpdate(avant))
+        .await;
+    user_1_events
+        .test_next(EventMessage::RoomUpdate(apres))
+        .await;
+
+    let message_6 = user_1
+        .addmessage(&client, room.id, String::from("Sixième"))
+        .await
+        .unwrap();
+    user_1_events
+        .test_next(EventMessage::Message(message_6))
+        .await;
+}
+
+#[async_test]
+async fn test_thread() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_thread_1".to_string(),
+            password: "test_thread_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_thread_2".to_string(),
+            password: "test_thread_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Thread #1"))
+        .await
+        .unwrap();
+    let other_room = user_1
+        .addroom(&client, String::from("Room Thread #2"))
+        .await
+        .unwrap();
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+
+    let message = user_1
+        .addmessage(&client, room.id, String::from("Question?"))
+        .await
+        .unwrap();
+    let other_message = user_1
+        .addmessage(&client, other_room.id, String::from("Ailleurs"))
+        .await
+        .unwrap();
+
+    let mut user_1_events = TestEventSource::new(&client, &user_1).await.unwrap();
+    user_1_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_1_events
+        .test_next(EventMessage::Room(other_room.clone()))
+        .await;
+    user_1_events
+        .test_next(EventMessage::Message(message.clone()))
+        .await;
+    user_1_events
+        .test_next(EventMessage::Message(other_message.clone()))
+        .await;
+    user_1_events
+        .test_next(presence(&user_2, PresenceStatus::Offline))
+        .await;
+
+    let reply = user_2
+        .addreply(&client, room.id, message.id, String::from("Réponse"))
+        .await
+        .unwrap();
+    user_1_events
+        .test_next(EventMessage::Message(reply.clone()))
+        .await;
+    user_1_events
+        .test_next(EventMessage::Thread(Thread {
+            parent_id: message.id,
+            room_id: room.id,
+            reply_count: 1,
+        }))
+        .await;
+
+    assert_eq!(
+        user_2
+            .addreply(&client, room.id, reply.id, String::from("Réponse?"))
+            .await
+            .unwrap_err(),
+        "On ne peut pas répondre à une réponse."
+    );
+    assert_eq!(
+        user_2
+            .addreply(&client, room.id, other_message.id, String::from("Réponse?"))
+            .await
+            .unwrap_err(),
+        "Ce message n'est pas dans ce salon."
+    );
+    assert_eq!(
+        user_2
+            .getreplies(&client, other_message.id)
+            .await
+            .unwrap_err(),
+        "Tu n'es pas dans ce salon."
+    );
+
+    let replies = user_2.getreplies(&client, message.id).await.unwrap();
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].id, reply.id);
+    assert_eq!(replies[0].parent_id, Some(message.id));
+
+    let (page, _) = user_1
+        .getmessages(&client, room.id, None, None)
+        .await
+        .unwrap();
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].id, message.id);
+    assert_eq!(page[0].reply_count, 1);
+
+    user_2.deletemessage(&client, &reply).await.unwrap();
+    user_1_events
+        .test_next(EventMessage::MessageDelete(DeletedMessage {
+            id: reply.id,
+            room_id: room.id,
+        }))
+        .await;
+    user_1_events
+        .test_next(EventMessage::Thread(Thread {
+            parent_id: message.id,
+            room_id: room.id,
+            reply_count: 0,
+        }))
+        .await;
+}
+
+#[async_test]
+async fn test_reaction() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_reaction_1".to_string(),
+            password: "test_reaction_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_reaction_2".to_string(),
+            password: "test_reaction_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_3 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_reaction_3".to_string(),
+            password: "test_reaction_3".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Reaction #1"))
+        .await
+        .unwrap();
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+    let message = user_1
+        .addmessage(&client, room.id, String::from("Bonjour"))
+        .await
+        .unwrap();
+
+    let mut user_1_events = TestEventSource::new(&client, &user_1).await.unwrap();
+    user_1_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_1_events
+        .test_next(EventMessage::Message(message.clone()))
+        .await;
+    user_1_events
+        .test_next(presence(&user_2, PresenceStatus::Offline))
+        .await;
+
+    user_2.addreaction(&client, &message, "👍").await.unwrap();
+    user_1_events
+        .test_next(EventMessage::Reaction(Reaction {
+            message_id: message.id,
+            room_id: room.id,
+            emoji: String::from("👍"),
+            user_ids: vec![user_2.id],
+        }))
+        .await;
+    user_1.addreaction(&client, &message, "👍").await.unwrap();
+    user_1_events
+        .test_next(EventMessage::Reaction(Reaction {
+            message_id: message.id,
+            room_id: room.id,
+            emoji: String::from("👍"),
+            user_ids: vec![user_2.id, user_1.id],
+        }))
+        .await;
+
+    assert_eq!(
+        user_2
+            .addreaction(&client, &message, "👍")
+            .await
+            .unwrap_err(),
+        "Tu as déjà réagi avec cet emoji."
+    );
+    assert_eq!(
+        user_2
+            .addreaction(&client, &message, "ok")
+            .await
+            .unwrap_err(),
+        "Ce n'est pas un emoji valide."
+    );
+    assert_eq!(
+        user_3
+            .addreaction(&client, &message, "👍")
+            .await
+            .unwrap_err(),
+        "Tu ne peux pas réagir à un message d'un salon que tu n'y est pas."
+    );
+
+    let (page, _) = user_1
+        .getmessages(&client, room.id, None, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        page[0].reactions,
+        vec![Reaction {
+            message_id: message.id,
+            room_id: room.id,
+            emoji: String::from("👍"),
+            user_ids: vec![user_2.id, user_1.id],
+        }]
+    );
+
+    user_2
+        .removereaction(&client, &message, "👍")
+        .await
+        .unwrap();
+    user_1_events
+        .test_next(EventMessage::Reaction(Reaction {
+            message_id: message.id,
+            room_id: room.id,
+            emoji: String::from("👍"),
+            user_ids: vec![user_1.id],
+        }))
+        .await;
+    assert_eq!(
+        user_2
+            .removereaction(&client, &message, "👍")
+            .await
+            .unwrap_err(),
+        "Tu n'as pas réagi avec cet emoji."
+    );
+}
+
+#[async_test]
+async fn test_read() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_read_1".to_string(),
+            password: "test_read_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_read_2".to_string(),
+            password: "test_read_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_3 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_read_3".to_string(),
+            password: "test_read_3".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Read #1"))
+        .await
+        .unwrap();
+    let other_room = user_1
+        .addroom(&client, String::from("Room Read #2"))
+        .await
+        .unwrap();
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+    let message_1 = user_1
+        .addmessage(&client, room.id, String::from("Premier"))
+        .await
+        .unwrap();
+    let message_2 = user_1
+        .addmessage(&client, room.id, String::from("Deuxième"))
+        .await
+        .unwrap();
+    let other_message = user_1
+        .addmessage(&client, other_room.id, String::from("Ailleurs"))
+        .await
+        .unwrap();
+
+    let mut user_2_events = TestEventSource::new(&client, &user_2).await.unwrap();
+    match user_2_events.next().await {
+        Ok(Some(EventMessage::Room(event))) => {
+            assert_eq!(event.id, room.id);
+            assert_eq!(event.unread_count, 2);
+            assert_eq!(event.last_message_id, Some(message_2.id));
+        }
+        event => panic!("Expected the room, got: {:?}", event),
+    }
+    user_2_events
+        .test_next(EventMessage::Message(message_1.clone()))
+        .await;
+    user_2_events
+        .test_next(EventMessage::Message(message_2.clone()))
+        .await;
+    user_2_events
+        .test_next(presence(&user_1, PresenceStatus::Offline))
+        .await;
+
+    user_2
+        .markread(&client, room.id, message_2.id)
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::ReadReceipt(ReadReceipt {
+            room_id: room.id,
+            user_id: user_2.id,
+            message_id: message_2.id,
+        }))
+        .await;
+    user_2
+        .markread(&client, room.id, message_1.id)
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::ReadReceipt(ReadReceipt {
+            room_id: room.id,
+            user_id: user_2.id,
+            message_id: message_2.id,
+        }))
+        .await;
+
+    assert_eq!(
+        user_2
+            .markread(&client, room.id, other_message.id)
+            .await
+            .unwrap_err(),
+        "Ce message n'est pas dans ce salon."
+    );
+    assert_eq!(
+        user_3
+            .markread(&client, room.id, message_1.id)
+            .await
+            .unwrap_err(),
+        "Tu n'es pas dans ce salon."
+    );
+
+    let mut user_1_events = TestEventSource::new(&client, &user_1).await.unwrap();
+    match user_1_events.next().await {
+        Ok(Some(EventMessage::Room(event))) => {
+            assert_eq!(event.id, room.id);
+            assert_eq!(event.unread_count, 0);
+        }
+        event => panic!("Expected the room, got: {:?}", event),
+    }
+    user_1_events
+        .test_next(EventMessage::Room(other_room.clone()))
+        .await;
+    user_1_events
+        .test_next(EventMessage::Message(message_1.clone()))
+        .await;
+    user_1_events
+        .test_next(EventMessage::Message(message_2.clone()))
+        .await;
+    user_1_events
+        .test_next(EventMessage::Message(other_message.clone()))
+        .await;
+    user_1_events
+        .test_next(EventMessage::ReadReceipt(ReadReceipt {
+            room_id: room.id,
+            user_id: user_2.id,
+            message_id: message_2.id,
+        }))
+        .await;
+    user_1_events
+        .test_next(presence(&user_2, PresenceStatus::Online))
+        .await;
+}
+
+#[async_test]
+async fn test_typing() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_typing_1".to_string(),
+            password: "test_typing_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_typing_2".to_string(),
+            password: "test_typing_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_3 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_typing_3".to_string(),
+            password: "test_typing_3".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Typing #1"))
+        .await
+        .unwrap();
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+
+    let mut user_2_events = TestEventSource::new(&client, &user_2).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_2_events
+        .test_next(presence(&user_1, PresenceStatus::Offline))
+        .await;
+
+    user_1.typing(&client, room.id).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::Typing(Typing {
+            room_id: room.id,
+            user_id: user_1.id,
+            typing: true,
+        }))
+        .await;
+    let last_event_id = user_2_events.last_event_id();
+
+    assert_eq!(
+        user_3.typing(&client, room.id).await.unwrap_err(),
+        "Tu n'es pas dans ce salon."
+    );
+
+    user_2_events
+        .test_next(EventMessage::Typing(Typing {
+            room_id: room.id,
+            user_id: user_1.id,
+            typing: false,
+        }))
+        .await;
+    assert_eq!(user_2_events.last_event_id(), last_event_id);
+}
+
+#[async_test]
+async fn test_presence() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_presence_1".to_string(),
+            password: "test_presence_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_presence_2".to_string(),
+            password: "test_presence_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Presence #1"))
+        .await
+        .unwrap();
+
+    let mut user_1_events = TestEventSource::new(&client, &user_1).await.unwrap();
+    user_1_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+
+    user_1_events
+        .test_next(EventMessage::Member(RoomMember {
+            room_id: room.id,
+            user_id: user_2.id,
+            role: RoomRole::Member,
+        }))
+        .await;
+    user_1_events
+        .test_next(presence(&user_2, PresenceStatus::Offline))
+        .await;
+
+    let mut user_2_events = TestEventSource::new(&client, &user_2).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_2_events
+        .test_next(presence(&user_1, PresenceStatus::Online))
+        .await;
+    user_1_events
+        .test_next(presence(&user_2, PresenceStatus::Online))
+        .await;
+
+    drop(user_2_events);
+    match user_1_events.next().await {
+        Ok(Some(EventMessage::Presence(event))) => {
+            assert_eq!(event.user_id, user_2.id);
+            assert_eq!(event.status, PresenceStatus::Away);
+            assert!(event.last_seen.is_some());
+        }
+        event => panic!("Expected the presence, got: {:?}", event),
+    }
+}
+
+/// Présence attendue d'un utilisateur (la date `last_seen` n'est pas comparée)
+fn presence(user: &UserSession, status: PresenceStatus) -> EventMessage {
+    EventMessage::Presence(UserPresence {
+        user_id: user.id,
+        username: user.username.to_string(),
+        status,
+        last_seen: None,
+    })
+}
+
+#[async_test]
+async fn test_attachment() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_attachment_1".to_string(),
+            password: "test_attachment_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_attachment_2".to_string(),
+            password: "test_attachment_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Attachment #1"))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        user_2
+            .upload(&client, room.id, "log.txt", "text/plain", "intrus")
+            .await,
+        Err(String::from("Tu n'es pas dans ce salon."))
+    );
+
+    let attachment = user_1
+        .upload(&client, room.id, "log.txt", "text/plain", "erreur: 42")
+        .await
+        .unwrap();
+    assert_eq!(attachment.name, "log.txt");
+    assert_eq!(attachment.content_type, "text/plain");
+    assert_eq!(attachment.size, 10);
+
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+
+    // Pas encore liée à un message : seulement son auteur y a accès
+    assert_eq!(
+        user_2.download(&client, &attachment).await,
+        Err(String::from("Tu n'as pas accès à cette pièce jointe."))
+    );
+    assert_eq!(
+        user_2
+            .postmessage(
+                &client,
+                room.id,
+                String::from("vol"),
+                None,
+                vec![attachment.clone()]
+            )
+            .await,
+        Err(format!(
+            "La pièce jointe {} n'est pas disponible.",
+            attachment.id
+        ))
+    );
+
+    let mut user_2_events = TestEventSource::new(&client, &user_2).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_2_events
+        .test_next(presence(&user_1, PresenceStatus::Offline))
+        .await;
+
+    let message = user_1
+        .postmessage(
+            &client,
+            room.id,
+            String::from("voici le log"),
+            None,
+            vec![attachment.clone()],
+        )
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::Message(message.clone()))
+        .await;
+
+    assert_eq!(
+        user_2.download(&client, &attachment).await,
+        Ok((String::from("text/plain"), String::from("erreur: 42")))
+    );
+    assert_eq!(
+        user_2
+            .getmessages(&client, room.id, None, None)
+            .await
+            .unwrap()
+            .0[0]
+            .attachments,
+        vec![attachment.clone()]
+    );
+
+    // Une image SVG, qui peut contenir des scripts, est téléchargée au lieu d'être affichée
+    let svg = user_1
+        .upload(
+            &client,
+            room.id,
+            "dessin.svg",
+            "image/svg+xml",
+            "<svg><script>alert(1)</script></svg>",
+        )
+        .await
+        .unwrap();
+    user_1
+        .postmessage(
+            &client,
+            room.id,
+            String::from("dessin"),
+            None,
+            vec![svg.clone()],
+        )
+        .await
+        .unwrap();
+    let response = client
+        .get(uri!(get_attachment(svg.id)))
+        .header(user_2.auth())
+        .dispatch()
+        .await;
+    assert_eq!(
+        response.headers().get_one("Content-Disposition"),
+        Some("attachment; filename=\"dessin.svg\"; filename*=UTF-8''dessin.svg")
+    );
+    assert_eq!(
+        response.headers().get_one("X-Content-Type-Options"),
+        Some("nosniff")
+    );
+    assert_eq!(
+        response.headers().get_one("Content-Security-Policy"),
+        Some("sandbox")
+    );
+
+    // Une pièce jointe ne peut être liée qu'à un seul message
+    assert_eq!(
+        user_1
+            .postmessage(
+                &client,
+                room.id,
+                String::from("encore"),
+                None,
+                vec![attachment.clone()]
+            )
+            .await,
+        Err(format!(
+            "La pièce jointe {} n'est pas disponible.",
+            attachment.id
+        ))
+    );
+
+    // Le nom du fichier ne peut pas sortir de l'en-tête
+    let pool = client.rocket().state::<Pool>().unwrap();
+    let (room_id, user_id) = (room.id, user_1.id);
+    let (hostile, chemin_hostile) = pool
+        .execute(move |connection_bd| -> Result<_, Erreur> {
+            let hostile = connection_bd.ajout_piece_jointe(
+                room_id,
+                user_id,
+                Attachment {
+                    id: 0,
+                    name: String::from("rapport \"final\"\\é.txt"),
+                    content_type: String::from("text/plain"),
+                    size: 4,
+                },
+            )?;
+            let chemin = connection_bd.chemin_piece_jointe(hostile.id);
+            Ok((hostile, chemin))
+        })
+        .await
+        .unwrap();
+    fs::write(&chemin_hostile, "fini").unwrap();
+    let response = client
+        .get(uri!(get_attachment(hostile.id)))
+        .header(user_1.auth())
+        .dispatch()
+        .await;
+    assert_eq!(
+        response.headers().get_one("Content-Disposition"),
+        Some("attachment; filename=\"rapport _final___.txt\"; filename*=UTF-8''rapport%20%22final%22%5C%C3%A9.txt")
+    );
+
+    // Le fichier est supprimé avec le message
+    let chemin = pool
+        .connection()
+        .unwrap()
+        .chemin_piece_jointe(attachment.id);
+    assert!(chemin.exists());
+    user_1.deletemessage(&client, &message).await.unwrap();
+    assert_eq!(
+        user_1.download(&client, &attachment).await,
+        Err(String::from("Tu n'as pas accès à cette pièce jointe."))
+    );
+    assert!(!chemin.exists());
+
+    // Une pièce jointe jamais liée à un message est supprimée après un délai
+    let hostile_id = hostile.id;
+    pool.execute(move |connection_bd| -> Result<_, Erreur> {
+        connection_bd.nettoie_pieces_jointes_non_liees()?;
+        assert!(connection_bd
+            .recupere_piece_jointe(hostile_id, user_id)
+            .is_ok());
+        connection_bd.connection.execute(
+            "UPDATE attachment SET date = date - ?1 WHERE id = ?2",
+            (DELAI_PIECE_JOINTE_NON_LIEE + 1, hostile_id),
+        )?;
+        Ok(connection_bd.nettoie_pieces_jointes_non_liees()?)
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        user_1.download(&client, &hostile).await,
+        Err(String::from("Tu n'as pas accès à cette pièce jointe."))
+    );
+    assert!(!chemin_hostile.exists());
+
+    // Les fichiers sont supprimés avec le salon
+    let chemin_svg = pool.connection().unwrap().chemin_piece_jointe(svg.id);
+    assert!(chemin_svg.exists());
+    user_2.leave(&client, room.id).await.unwrap();
+    user_1.leave(&client, room.id).await.unwrap();
+    assert!(!chemin_svg.exists());
+}
+
+#[async_test]
+async fn test_roles() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_roles_1".to_string(),
+            password: "test_roles_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_roles_2".to_string(),
+            password: "test_roles_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_3 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_roles_3".to_string(),
+            password: "test_roles_3".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Roles #1"))
+        .await
+        .unwrap();
+    user_1
+        .invite(&client, user_3.username.to_string(), room.id)
+        .await
+        .unwrap();
+
+    let mut user_3_events = TestEventSource::new(&client, &user_3).await.unwrap();
+    user_3_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_3_events
+        .test_next(presence(&user_1, PresenceStatus::Offline))
+        .await;
+
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+    user_3_events
+        .test_next(EventMessage::Member(RoomMember {
+            room_id: room.id,
+            user_id: user_2.id,
+            role: RoomRole::Member,
+        }))
+        .await;
+    user_3_events
+        .test_next(presence(&user_2, PresenceStatus::Offline))
+        .await;
+
+    assert_eq!(
+        user_2.kick(&client, room.id, user_3.id).await,
+        Err(String::from(
+            "Tu dois être administrateur pour gérer les membres de ce salon."
+        ))
+    );
+    assert_eq!(
+        user_1.setrole(&client, room.id, user_2.id, "owner").await,
+        Err(String::from("Ce n'est pas un rôle valide."))
+    );
+
+    user_1
+        .setrole(&client, room.id, user_2.id, "admin")
+        .await
+        .unwrap();
+    user_3_events
+        .test_next(EventMessage::Member(RoomMember {
+            room_id: room.id,
+            user_id: user_2.id,
+            role: RoomRole::Admin,
+        }))
+        .await;
+
+    assert_eq!(
+        user_2.setrole(&client, room.id, user_1.id, "member").await,
+        Err(String::from(
+            "Tu ne peux pas gérer un membre qui a un rôle égal ou supérieur au tien."
+        ))
+    );
+    assert_eq!(
+        user_2.kick(&client, room.id, user_1.id).await,
+        Err(String::from(
+            "Tu ne peux pas gérer un membre qui a un rôle égal ou supérieur au tien."
+        ))
+    );
+
+    user_2.kick(&client, room.id, user_3.id).await.unwrap();
+    user_3_events
+        .test_next(EventMessage::RoomRemove(RemovedMember {
+            room_id: room.id,
+            user_id: user_3.id,
+            banned: false,
+        }))
+        .await;
+    assert_eq!(
+        user_3.addmessage(&client, room.id, String::from("?")).await,
+        Err(String::from("Tu n'es pas dans ce salon."))
+    );
+    assert_eq!(
+        user_3.getmessages(&client, room.id, None, None).await,
+        Err(String::from("Tu n'es pas dans ce salon."))
+    );
+
+    user_1
+        .invite(&client, user_3.username.to_string(), room.id)
+        .await
+        .unwrap();
+    user_2.ban(&client, room.id, user_3.id).await.unwrap();
+    assert_eq!(
+        user_2.kick(&client, room.id, user_3.id).await,
+        Err(String::from("Cet utilisateur n'est pas dans ce salon."))
+    );
+    assert_eq!(
+        user_1
+            .invite(&client, user_3.username.to_string(), room.id)
+            .await,
+        Err(String::from("Cet utilisateur est banni de ce salon."))
+    );
+}
+
+#[async_test]
+async fn test_room_settings() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_room_settings_1".to_string(),
+            password: "test_room_settings_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_room_settings_2".to_string(),
+            password: "test_room_settings_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Settings #1"))
+        .await
+        .unwrap();
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+
+    let mut user_2_events = TestEventSource::new(&client, &user_2).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_2_events
+        .test_next(presence(&user_1, PresenceStatus::Offline))
+        .await;
+
+    assert_eq!(
+        user_2
+            .editroom(&client, room.id, Some("Pirate"), None)
+            .await,
+        Err(String::from(
+            "Tu dois être administrateur pour modifier ce salon."
+        ))
+    );
+    assert_eq!(
+        user_1.editroom(&client, room.id, Some(""), None).await,
+        Err(String::from(
+            "Il faut au moins une lettre dans le nom du salon"
+        ))
+    );
+
+    user_1
+        .editroom(&client, room.id, Some("Room Settings #2"), None)
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::RoomUpdate(RoomSettings {
+            room_id: room.id,
+            name: String::from("Room Settings #2"),
+            topic: String::new(),
+            retention: None,
+        }))
+        .await;
+    user_1
+        .editroom(&client, room.id, None, Some("Les réglages"))
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::RoomUpdate(RoomSettings {
+            room_id: room.id,
+            name: String::from("Room Settings #2"),
+            topic: String::from("Les réglages"),
+            retention: None,
+        }))
+        .await;
+
+    let message = user_1
+        .addmessage(&client, room.id, String::from("Je m'en vais"))
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::Message(message.clone()))
+        .await;
+
+    user_1.leave(&client, room.id).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::MemberRemove(RemovedMember {
+            room_id: room.id,
+            user_id: user_1.id,
+            banned: false,
+        }))
+        .await;
+    user_2_events
+        .test_next(EventMessage::Member(RoomMember {
+            room_id: room.id,
+            user_id: user_2.id,
+            role: RoomRole::Owner,
+        }))
+        .await;
+    assert_eq!(
+        user_1.leave(&client, room.id).await,
+        Err(String::from("Tu n'es pas dans ce salon."))
+    );
+    // Les messages d'un membre qui quitte le salon y restent
+    let (messages, _) = user_2
+        .getmessages(&client, room.id, None, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        messages
+            .iter()
+            .map(|message| (message.id, message.text.as_str()))
+            .collect::<Vec<_>>(),
+        vec![(message.id, message.text.as_str())]
+    );
+
+    user_2.leave(&client, room.id).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::RoomRemove(RemovedMember {
+            room_id: room.id,
+            user_id: user_2.id,
+            banned: false,
+        }))
+        .await;
+    assert_eq!(
+        user_2
+            .invite(&client, user_1.username.to_string(), room.id)
+            .await,
+        Err(format!("no room with the id {}", room.id))
+    );
+}
+
+#[async_test]
+async fn test_direct_room() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_direct_room_1".to_string(),
+            password: "test_direct_room_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_direct_room_2".to_string(),
+            password: "test_direct_room_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_3 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_direct_room_3".to_string(),
+            password: "test_direct_room_3".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut user_2_events = TestEventSource::new(&client, &user_2).await.unwrap();
+
+    assert_eq!(
+        user_1.dm(&client, user_1.username.to_string()).await,
+        Err(String::from(
+            "Tu ne peux pas ouvrir une conversation privée avec toi-même."
+        ))
+    );
+    assert_eq!(
+        user_1
+            .dm(&client, String::from("test_direct_room_inconnu"))
+            .await,
+        Err(String::from(
+            "Pas d'utilisateur avec ce nom test_direct_room_inconnu"
+        ))
+    );
+
+    let (room_id, cree) = user_1
+        .dm(&client, user_2.username.to_string())
+        .await
+        .unwrap();
+    assert!(cree);
+    user_2_events
+        .test_next(EventMessage::Room(Room {
+            id: room_id,
+            name: String::new(),
+            topic: String::new(),
+            kind: RoomKind::Direct,
+            retention: None,
+            unread_count: 0,
+            last_message_id: None,
+            members: vec![
+                RoomMember {
+                    room_id,
+                    user_id: user_1.id,
+                    role: RoomRole::Member,
+                },
+                RoomMember {
+                    room_id,
+                    user_id: user_2.id,
+                    role: RoomRole::Member,
+                },
+            ],
+        }))
+        .await;
+    user_2_events
+        .test_next(presence(&user_1, PresenceStatus::Offline))
+        .await;
+
+    assert_eq!(
+        user_2.dm(&client, user_1.username.to_string()).await,
+        Ok((room_id, false))
+    );
+    assert_ne!(
+        user_1
+            .dm(&client, user_3.username.to_string())
+            .await
+            .unwrap()
+            .0,
+        room_id
+    );
+
+    assert_eq!(
+        user_1
+            .invite(&client, user_3.username.to_string(), room_id)
+            .await,
+        Err(String::from(
+            "Tu ne peux pas inviter quelqu'un dans une conversation privée."
+        ))
+    );
+    assert_eq!(
+        user_2.leave(&client, room_id).await,
+        Err(String::from(
+            "Tu ne peux pas quitter une conversation privée."
+        ))
+    );
+    assert_eq!(
+        user_2.editroom(&client, room_id, Some("Privé"), None).await,
+        Err(String::from(
+            "Tu dois être administrateur pour modifier ce salon."
+        ))
+    );
+    assert_eq!(
+        user_3.addmessage(&client, room_id, String::from("?")).await,
+        Err(String::from("Tu n'es pas dans ce salon."))
+    );
+
+    let message = user_2
+        .addmessage(&client, room_id, String::from("Salut"))
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::Message(message))
+        .await;
+
+    // Deux demandes simultanées ne créent qu'une conversation
+    let (dm_2, dm_3) = rocket::tokio::join!(
+        user_2.dm(&client, user_3.username.to_string()),
+        user_3.dm(&client, user_2.username.to_string())
+    );
+    let (dm_2, dm_3) = (dm_2.unwrap(), dm_3.unwrap());
+    assert_eq!(dm_2.0, dm_3.0);
+    assert!(dm_2.1 != dm_3.1);
+}
+
+#[async_test]
+async fn test_search() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_search_1".to_string(),
+            password: "test_search_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_search_2".to_string(),
+            password: "test_search_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_3 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_search_3".to_string(),
+            password: "test_search_3".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Search #1"))
+        .await
+        .unwrap();
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+    let other_room = user_3
+        .addroom(&client, String::from("Room Search #2"))
+        .await
+        .unwrap();
+
+    let message_1 = user_1
+        .addmessage(&client, room.id, String::from("Bonjour tout le monde"))
+        .await
+        .unwrap();
+    let message_2 = user_2
+        .addmessage(&client, room.id, String::from("bonjour à toi"))
+        .await
+        .unwrap();
+    user_3
+        .addmessage(&client, other_room.id, String::from("bonjour secret"))
+        .await
+        .unwrap();
+
+    let resultats = user_1.search(&client, "q=bonjour").await.unwrap();
+    assert_eq!(resultats.len(), 2);
+    assert!(resultats
+        .iter()
+        .all(|resultat| resultat.message.room_id == room.id));
+    let resultat = resultats
+        .iter()
+        .find(|resultat| resultat.message.id == message_1.id)
+        .unwrap();
+    assert_eq!(resultat.message.text, message_1.text);
+    assert_eq!(resultat.snippet, "<mark>Bonjour</mark> tout le monde");
+
+    assert_eq!(user_1.search(&client, "q=bonj").await.unwrap().len(), 2);
+    assert_eq!(
+        user_1
+            .search(&client, "q=bonjour%20monde")
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    let resultats = user_1
+        .search(
+            &client,
+            format!("q=bonjour&author_id={}", user_2.id).as_str(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resultats.len(), 1);
+    assert_eq!(resultats[0].message.id, message_2.id);
+    assert_eq!(resultats[0].snippet, "<mark>bonjour</mark> à toi");
+    assert_eq!(
+        user_1
+            .search(
+                &client,
+                format!("q=bonjour&room_id={}", other_room.id).as_str()
+            )
+            .await
+            .unwrap(),
+        Vec::new()
+    );
+    assert_eq!(
+        user_1
+            .search(
+                &client,
+                format!("q=bonjour&after={}", Utc::now().timestamp() + 60).as_str()
+            )
+            .await
+            .unwrap(),
+        Vec::new()
+    );
+    assert_eq!(
+        user_1.search(&client, "q=%22%20*").await,
+        Err(String::from(
+            "Il faut au moins une lettre dans la recherche."
+        ))
+    );
+
+    user_1
+        .editmessage(&client, &message_1, String::from("Salut"))
+        .await
+        .unwrap();
+    assert_eq!(user_1.search(&client, "q=bonjour").await.unwrap().len(), 1);
+    assert_eq!(user_1.search(&client, "q=salut").await.unwrap().len(), 1);
+
+    user_2.deletemessage(&client, &message_2).await.unwrap();
+    assert_eq!(
+        user_1.search(&client, "q=bonjour").await.unwrap(),
+        Vec::new()
+    );
+}
+
+#[async_test]
+async fn test_mention() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_mention_1".to_string(),
+            password: "test_mention_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_mention_2".to_string(),
+            password: "test_mention_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_3 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_mention_3".to_string(),
+            password: "test_mention_3".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Mention #1"))
+        .await
+        .unwrap();
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+    let other_room = user_2
+        .addroom(&client, String::from("Room Mention #2"))
+        .await
+        .unwrap();
+
+    let mut user_2_events = TestEventSource::new(&client, &user_2).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_2_events
+        .test_next(EventMessage::Room(other_room.clone()))
+        .await;
+    user_2_events
+        .test_next(presence(&user_1, PresenceStatus::Offline))
+        .await;
+
+    // user_3 n'est pas dans le salon et l'auteur ne se mentionne pas lui-même
+    let message = user_1
+        .addmessage(
+            &client,
+            room.id,
+            String::from(
+                "Salut @test_mention_2, @test_mention_3 et @test_mention_1 test@test_mention_2",
+            ),
+        )
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::Message(message.clone()))
+        .await;
+    user_2_events
+        .test_next(EventMessage::Mention(message.clone()))
+        .await;
+
+    let mentions = user_2.getmentions(&client).await.unwrap();
+    assert_eq!(mentions.len(), 1);
+    assert_eq!(mentions[0].id, message.id);
+    assert_eq!(user_1.getmentions(&client).await.unwrap(), Vec::new());
+    assert_eq!(user_3.getmentions(&client).await.unwrap(), Vec::new());
+
+    let message = user_1
+        .editmessage(&client, &message, String::from("Salut"))
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::MessageEdit(message.clone()))
+        .await;
+    assert_eq!(user_2.getmentions(&client).await.unwrap(), Vec::new());
+
+    let message = user_1
+        .editmessage(&client, &message, String::from("Salut @test_mention_2"))
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::MessageEdit(message.clone()))
+        .await;
+    user_2_events
+        .test_next(EventMessage::Mention(message.clone()))
+        .await;
+    assert_eq!(user_2.getmentions(&client).await.unwrap().len(), 1);
+
+    user_1.deletemessage(&client, &message).await.unwrap();
+    user_2_events.next().await.unwrap();
+    assert_eq!(user_2.getmentions(&client).await.unwrap(), Vec::new());
+}
+
+#[async_test]
+async fn test_pin() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_pin_1".to_string(),
+            password: "test_pin_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_pin_2".to_string(),
+            password: "test_pin_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_3 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_pin_3".to_string(),
+            password: "test_pin_3".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Pin #1"))
+        .await
+        .unwrap();
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+
+    let mut user_2_events = TestEventSource::new(&client, &user_2).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_2_events
+        .test_next(presence(&user_1, PresenceStatus::Offline))
+        .await;
+
+    let message = user_1
+        .addmessage(&client, room.id, String::from("Décision importante"))
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::Message(message.clone()))
+        .await;
+
+    user_2.pin(&client, &message).await.unwrap();
+    let pin = Pin {
+        room_id: room.id,
+        user_id: user_2.id,
+        date: Utc::now(),
+        message: message.clone(),
+    };
+    user_2_events.test_next(EventMessage::Pin(pin)).await;
+    assert_eq!(
+        user_1.pin(&client, &message).await,
+        Err(String::from("Ce message est déjà épinglé."))
+    );
+
+    let pins = user_1.getpins(&client, room.id).await.unwrap();
+    assert_eq!(pins.len(), 1);
+    assert_eq!(pins[0].user_id, user_2.id);
+    assert_eq!(pins[0].message.id, message.id);
+    assert_eq!(pins[0].message.text, message.text);
+
+    // user_3 ne fait pas partie du salon
+    assert_eq!(
+        user_3.pin(&client, &message).await,
+        Err(String::from("Tu n'es pas dans ce salon."))
+    );
+    assert_eq!(
+        user_3.unpin(&client, &message).await,
+        Err(String::from("Tu n'es pas dans ce salon."))
+    );
+    assert_eq!(
+        user_3.getpins(&client, room.id).await,
+        Err(String::from("Tu n'es pas dans ce salon."))
+    );
+
+    user_1.unpin(&client, &message).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::Unpin(Unpin {
+            room_id: room.id,
+            message_id: message.id,
+        }))
+        .await;
+    assert_eq!(
+        user_1.unpin(&client, &message).await,
+        Err(String::from("Ce message n'est pas épinglé."))
+    );
+    assert_eq!(user_1.getpins(&client, room.id).await.unwrap(), Vec::new());
+
+    // La suppression d'un message retire son épingle
+    user_1.pin(&client, &message).await.unwrap();
+    user_2_events.next().await.unwrap();
+    user_1.deletemessage(&client, &message).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::MessageDelete(DeletedMessage {
+            id: message.id,
+            room_id: room.id,
+        }))
+        .await;
+    assert_eq!(user_2.getpins(&client, room.id).await.unwrap(), Vec::new());
+}
+
+#[async_test]
+async fn test_scheduled() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_scheduled_1".to_string(),
+            password: "test_scheduled_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_scheduled_2".to_string(),
+            password: "test_scheduled_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_3 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_scheduled_3".to_string(),
+            password: "test_scheduled_3".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Scheduled #1"))
+        .await
+        .unwrap();
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+
+    let now = Utc::now().timestamp();
+    assert_eq!(
+        user_1
+            .schedule(&client, room.id, String::from("Trop tard"), now - 10)
+            .await,
+        Err(String::from("La date d'envoi doit être dans le futur."))
+    );
+    assert_eq!(
+        user_1
+            .schedule(&client, room.id, String::from("Trop loin"), 10000000000000)
+            .await,
+        Err(String::from(
+            "La date d'envoi doit être dans moins d'un an."
+        ))
+    );
+    assert_eq!(
+        user_3
+            .schedule(&client, room.id, String::from("Intrus"), now + 60)
+            .await,
+        Err(String::from("Tu n'es pas dans ce salon."))
+    );
+
+    let envoye = user_1
+        .schedule(&client, room.id, String::from("Programmé"), now + 60)
+        .await
+        .unwrap();
+    let annule = user_1
+        .schedule(&client, room.id, String::from("Annulé"), now + 120)
+        .await
+        .unwrap();
+
+    let programmes = user_1.getscheduled(&client).await.unwrap();
+    assert_eq!(
+        programmes.iter().map(|p| p.id).collect::<Vec<i64>>(),
+        vec![envoye, annule]
+    );
+    assert_eq!(programmes[0].text, "Programmé");
+    assert_eq!(programmes[0].send_at.timestamp(), now + 60);
+    assert_eq!(user_2.getscheduled(&client).await.unwrap(), Vec::new());
+
+    // Seul l'auteur peut modifier ou annuler ses messages programmés
+    assert_eq!(
+        user_2
+            .editscheduled(&client, envoye, Some(String::from("Piraté")), None)
+            .await,
+        Err(format!("Pas de message programmé avec l'id {}", envoye))
+    );
+    assert_eq!(
+        user_2.cancelscheduled(&client, annule).await,
+        Err(format!("Pas de message programmé avec l'id {}", annule))
+    );
+
+    let modifie = user_1
+        .editscheduled(
+            &client,
+            envoye,
+            Some(String::from("Programmé et modifié")),
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(modifie.text, "Programmé et modifié");
+    assert_eq!(modifie.send_at.timestamp(), now + 60);
+    assert_eq!(
+        user_1.editscheduled(&client, envoye, None, Some(now)).await,
+        Err(String::from("La date d'envoi doit être dans le futur."))
+    );
+    user_1.cancelscheduled(&client, annule).await.unwrap();
+    assert_eq!(user_1.getscheduled(&client).await.unwrap().len(), 1);
+    assert_eq!(
+        user_2
+            .getmessages(&client, room.id, None, None)
+            .await
+            .unwrap()
+            .0,
+        Vec::new()
+    );
+
+    // Le message est envoyé dans le salon à sa nouvelle échéance
+    let send_at = Utc::now().timestamp() + 5;
+    let modifie = user_1
+        .editscheduled(&client, envoye, None, Some(send_at))
+        .await
+        .unwrap();
+    assert_eq!(modifie.send_at.timestamp(), send_at);
+    // Il est retiré des messages programmés une fois envoyé
+    for _ in 0..30 {
+        rocket::tokio::time::sleep(scheduled::INTERVALLE_PROGRAMMES).await;
+        if user_1.getscheduled(&client).await.unwrap().is_empty() {
+            break;
+        }
+    }
+    assert_eq!(user_1.getscheduled(&client).await.unwrap(), Vec::new());
+    let messages = user_2
+        .getmessages(&client, room.id, None, None)
+        .await
+        .unwrap()
+        .0;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].user_id, user_1.id);
+    assert_eq!(messages[0].text, "Programmé et modifié");
+}
+
+#[async_test]
+async fn test_retention() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_retention_1".to_string(),
+            password: "test_retention_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_retention_2".to_string(),
+            password: "test_retention_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let room = user_1
+        .addroom(&client, String::from("Room Retention #1"))
+        .await
+        .unwrap();
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+
+    let mut user_2_events = TestEventSource::new(&client, &user_2).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_2_events
+        .test_next(presence(&user_1, PresenceStatus::Offline))
+        .await;
+
+    assert_eq!(
+        user_1
+            .addephemeral(&client, room.id, String::from("Déjà expiré"), 0)
+            .await,
+        Err(String::from(
+            "La durée de vie d'un message doit être positive."
+        ))
+    );
+    assert_eq!(
+        user_1
+            .addephemeral(&client, room.id, String::from("Trop long"), i64::MAX)
+            .await,
+        Err(String::from(
+            "La durée de vie d'un message ne peut pas dépasser un an."
+        ))
+    );
+    assert_eq!(
+        user_2.setretention(&client, room.id, 3600).await,
+        Err(String::from(
+            "Tu dois être administrateur pour modifier ce salon."
+        ))
+    );
+    assert_eq!(
+        user_1.setretention(&client, room.id, -1).await,
+        Err(String::from(
+            "La durée de conservation ne peut pas être négative."
+        ))
+    );
+
+    let garde = user_1
+        .addmessage(&client, room.id, String::from("Gardé"))
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::Message(garde.clone()))
+        .await;
+    let since = user_2_events.last_event_id().unwrap();
+    drop(user_2_events);
+
+    // Un message éphémère est supprimé à la fin de sa durée de vie
+    let ephemere = user_1
+        .addephemeral(&client, room.id, String::from("Éphémère"), 2)
+        .await
+        .unwrap();
+    let messages = user_2
+        .getmessages(&client, room.id, None, None)
+        .await
+        .unwrap()
+        .0;
+    assert_eq!(messages[0].expires_at, None);
+    assert_eq!(
+        messages[1]
+            .expires_at
+            .map(|expires_at| expires_at.timestamp()),
+        ephemere.expires_at.map(|expires_at| expires_at.timestamp())
+    );
+
+    let mut ids = Vec::new();
+    for _ in 0..30 {
+        rocket::tokio::time::sleep(INTERVALLE_PURGE_TEST).await;
+        ids = user_2
+            .getmessages(&client, room.id, None, None)
+            .await
+            .unwrap()
+            .0
+            .iter()
+            .map(|message| message.id)
+            .collect::<Vec<i64>>();
+        if ids.len() == 1 {
+            break;
+        }
+    }
+    assert_eq!(ids, vec![garde.id]);
+
+    let mut user_2_events = TestEventSource::new_since(&client, &user_2, since)
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::Message(ephemere.clone()))
+        .await;
+    user_2_events
+        .test_next(EventMessage::MessageDelete(DeletedMessage {
+            id: ephemere.id,
+            room_id: room.id,
+        }))
+        .await;
+
+    // Les messages plus anciens que la durée de conservation du salon sont supprimés
+    user_1.setretention(&client, room.id, 1).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::RoomUpdate(RoomSettings {
+            room_id: room.id,
+            name: room.name.to_string(),
+            topic: String::new(),
+            retention: Some(1),
+        }))
+        .await;
+    let since = user_2_events.last_event_id().unwrap();
+    drop(user_2_events);
+
+    for _ in 0..30 {
+        rocket::tokio::time::sleep(INTERVALLE_PURGE_TEST).await;
+        ids = user_2
+            .getmessages(&client, room.id, None, None)
+            .await
+            .unwrap()
+            .0
+            .iter()
+            .map(|message| message.id)
+            .collect::<Vec<i64>>();
+        if ids.is_empty() {
+            break;
+        }
+    }
+    assert_eq!(ids, Vec::<i64>::new());
+
+    let mut user_2_events = TestEventSource::new_since(&client, &user_2, since)
+        .await
+        .unwrap();
+    user_2_events
+        .test_next(EventMessage::MessageDelete(DeletedMessage {
+            id: garde.id,
+            room_id: room.id,
+        }))
+        .await;
+
+    user_1.setretention(&client, room.id, 0).await.unwrap();
+    user_2_events
+        .test_next(EventMessage::RoomUpdate(RoomSettings {
+            room_id: room.id,
+            name: room.name.to_string(),
+            topic: String::new(),
+            retention: None,
+        }))
+        .await;
+}
+
+#[async_test]
+async fn test_texte_hostile() {
+    let client = initialize().await;
+
+    let user_1 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_texte_hostile_1".to_string(),
+            password: "test_texte_hostile_1".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    // Guillemets, antislashs, retours à la ligne et un faux événement dans le texte
+    let texte = String::from("Il a dit \"salut\\\" }\r\n\t{ \"objectId\": 3, \"id\": 1 } é😀");
+    let room = user_1.addroom(&client, texte.to_string()).await.unwrap();
+    let message = user_1
+        .addmessage(&client, room.id, texte.to_string())
+        .await
+        .unwrap();
+
+    let mut user_1_events = TestEventSource::new(&client, &user_1).await.unwrap();
+    user_1_events
+        .test_next(EventMessage::Room(room.clone()))
+        .await;
+    user_1_events
+        .test_next(EventMessage::Message(message.clone()))
+        .await;
+
+    let (messages, _) = user_1
+        .getmessages(&client, room.id, None, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        messages
+            .iter()
+            .map(|message| message.text.as_str())
+            .collect::<Vec<&str>>(),
+        vec![texte.as_str()]
+    );
+}
+
+#[async_test]
+async fn test_erreurs() {
+    let client = initialize().await;
+
+    let login_1 = FormAddUser {
+        username: "test_erreurs_1".to_string(),
+        password: "test_erreurs_1".to_string(),
+    };
+    let user_1 = add_user(&client, &login_1).await.unwrap();
+    let user_2 = add_user(
+        &client,
+        &FormAddUser {
+            username: "test_erreurs_2".to_string(),
+            password: "test_erreurs_2".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let response = client
+        .post(uri!(post_user))
+        .header(ContentType::Form)
+        .body((&login_1 as &dyn UriDisplay<Query>).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(
+        into_erreur(response).await,
+        (
+            409,
+            ApiError {
+                code: ErrorCode::UsernameTaken,
+                reason: String::from("Identifiant déjà pris"),
+            }
+        )
+    );
+
+    let mauvais_login = FormAddUser {
+        username: login_1.username.to_string(),
+        password: String::from("mauvais"),
+    };
+    let response = client
+        .post(uri!(post_login))
+        .header(ContentType::Form)
+        .body((&mauvais_login as &dyn UriDisplay<Query>).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(
+        into_erreur(response).await,
+        (
+            401,
+            ApiError {
+                code: ErrorCode::Unauthorized,
+                reason: String::from("Mauvais identifiant ou mot de passe"),
+            }
+        )
+    );
+
+    let response = client.get("/user/1000").dispatch().await;
+    assert_eq!(
+        into_erreur(response).await,
+        (
+            404,
+            ApiError {
+                code: ErrorCode::NotFound,
+                reason: String::from("Mauvais id"),
+            }
+        )
+    );
+
+    let room = user_1
+        .addroom(&client, String::from("test_erreurs"))
+        .await
+        .unwrap();
+    let message = user_1
+        .addmessage(&client, room.id, String::from("Privé"))
+        .await
+        .unwrap();
+
+    let response = client
+        .get(format!("/room/{}/messages", room.id))
+        .header(user_2.auth())
+        .dispatch()
+        .await;
+    assert_eq!(
+        into_erreur(response).await,
+        (
+            403,
+            ApiError {
+                code: ErrorCode::NotRoomMember,
+                reason: String::from("Tu n'es pas dans ce salon."),
+            }
+        )
+    );
+
+    let response = client
+        .get("/message/1000/replies")
+        .header(user_1.auth())
+        .dispatch()
+        .await;
+    assert_eq!(into_erreur(response).await.0, 404);
+
+    // La session reste ouverte après une erreur
+    assert_eq!(
+        user_2.pin(&client, &message).await,
+        Err(String::from("Tu n'es pas dans ce salon."))
+    );
+    user_2
+        .addroom(&client, String::from("test_erreurs_2"))
+        .await
+        .unwrap();
+
+    user_1
+        .invite(&client, user_2.username.to_string(), room.id)
+        .await
+        .unwrap();
+    let response = client
+        .delete(format!("/message/{}", message.id))
+        .header(user_2.auth())
+        .dispatch()
+        .await;
+    assert_eq!(into_erreur(response).await.1.code, ErrorCode::NotAuthor);
+}
+
+#[async_test]
+async fn test_cors() {
+    let client = initialize().await;
+
+    // Seule l'origine du front peut utiliser l'API depuis un navigateur, avec le cookie de session
+    let response = client
+        .options("/user")
+        .header(Header::new("Origin", ORIGINE_FRONT_TEST))
+        .dispatch()
+        .await;
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Origin"),
+        Some(ORIGINE_FRONT_TEST)
+    );
+    assert_eq!(
+        response
+            .headers()
+            .get_one("Access-Control-Allow-Credentials"),
+        Some("true")
+    );
+
+    let response = client
+        .options("/user")
+        .header(Header::new("Origin", "http://intrus.test"))
+        .dispatch()
+        .await;
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Origin"),
+        None
+    );
+    assert_eq!(
+        response
+            .headers()
+            .get_one("Access-Control-Allow-Credentials"),
+        None
+    );
+
+    let response = client.options("/user").dispatch().await;
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Origin"),
+        None
+    );
+}
+
+#[test]
+fn test_migration() {
+    dotenv().unwrap();
+    let chemin = "test_migration.db";
+    if fs::metadata(chemin).is_ok() {
+        fs::remove_file(chemin).unwrap();
+    }
+
+    // Base de donnée au format d'avant les migrations (messages sans identifiant)
+    let ancienne = rusqlite::Connection::open(chemin).unwrap();
+    ancienne
+        .execute_batch(
+            "
+            CREATE TABLE user
+            (
+                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                username TEXT NOT NULL,
+                password TEXT NOT NULL,
+                api_key TEXT NOT NULL
+            );
+            CREATE UNIQUE INDEX user_username on user (username);
+            CREATE TABLE room
+            (
+                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL
+            );
+            CREATE TABLE message
+            (
+                date INTEGER NOT NULL,
+                room_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                text TEXT NOT NULL,
+
+                FOREIGN KEY(user_id, room_id) REFERENCES user_room(user_id, room_id) ON DELETE CASCADE
+            );
+            CREATE TABLE user_room
+            (
+                user_id INTEGER NOT NULL,
+                room_id INTEGER NOT NULL,
+
+                PRIMARY KEY(user_id, room_id),
+                FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE,
+                FOREIGN KEY(room_id) REFERENCES room(id) ON DELETE CASCADE
+            );
+
+            INSERT INTO user (username, password, api_key) VALUES ('ancien_1', 'x', 'cle_1');
+            INSERT INTO user (username, password, api_key) VALUES ('ancien_2', 'x', 'cle_2');
+            INSERT INTO room (name) VALUES ('Ancien salon');
+            INSERT INTO user_room (user_id, room_id) VALUES (1, 1);
+            INSERT INTO user_room (user_id, room_id) VALUES (2, 1);
+            INSERT INTO message (date, room_id, user_id, text) VALUES (1700000000, 1, 1, 'Bonjour');
+            INSERT INTO message (date, room_id, user_id, text) VALUES (1700000060, 1, 2, 'Salut');
+            INSERT INTO message (date, room_id, user_id, text) VALUES (1700000120, 1, 1, 'Au revoir');
+            ",
+        )
+        .unwrap();
+    drop(ancienne);
+
+    let pool = Pool::new(&Configuration {
+        database_url: String::from(chemin),
+        attachments_dir: PathBuf::from("test_migration"),
+        durees_session: DureesSession::default(),
+        intervalle_purge: INTERVALLE_PURGE_TEST,
+        origines_front: Vec::new(),
+    })
+    .unwrap();
+    let database = pool.connection().unwrap();
+    assert_eq!(database.version_schema().unwrap(), 0);
+    assert!(commande_migration(&database, "--schema-status")
+        .unwrap()
+        .starts_with(
+            format!(
+                "Version du schéma : 0 (dernière : {})",
+                migration::derniere_version()
+            )
+            .as_str()
+        ));
+    assert_eq!(
+        database.migre().unwrap(),
+        migration::MIGRATIONS
+            .iter()
+            .map(|migration| migration.version)
+            .collect::<Vec<i64>>()
+    );
+    assert_eq!(
+        database.version_schema().unwrap(),
+        migration::derniere_version()
+    );
+    assert_eq!(database.migre().unwrap(), Vec::<i64>::new());
+    assert_eq!(
+        commande_migration(&database, "--migrate").unwrap(),
+        "Le schéma est déjà à jour."
+    );
+    assert!(commande_migration(&database, "--inconnue").is_err());
+
+    // Les messages gardent leur ordre, et leur ancien rowid devient leur identifiant
+    let (messages, has_more) = database.recupere_messages_room(1, None, 10).unwrap();
+    assert!(!has_more);
+    assert_eq!(
+        messages
+            .iter()
+            .map(|message| (message.id, message.user_id, message.text.as_str()))
+            .collect::<Vec<(i64, i64, &str)>>(),
+        vec![(1, 1, "Bonjour"), (2, 2, "Salut"), (3, 1, "Au revoir")]
+    );
+    assert_eq!(messages[1].date.timestamp(), 1700000060);
+
+    // Le plus ancien membre devient propriétaire, et les anciens messages sont lus
+    let room = database.recupere_room(2, 1).unwrap();
+    assert_eq!(room.topic, "");
+    assert_eq!(room.kind, RoomKind::Group);
+    assert_eq!(room.retention, None);
+    assert_eq!(room.unread_count, 0);
+    assert_eq!(
+        database.recupere_membre(1, 1).unwrap().role,
+        RoomRole::Owner
+    );
+    assert_eq!(
+        database.recupere_membre(1, 2).unwrap().role,
+        RoomRole::Member
+    );
+
+    // Les anciens messages sont dans l'index de recherche, et les nouveaux suivent
+    let recherche = FormSearch {
+        q: String::from("revoir"),
+        room_id: None,
+        author_id: None,
+        after: None,
+        before: None,
+        limit: None,
+    };
+    let resultats = database.recherche_messages(2, &recherche).unwrap();
+    assert_eq!(resultats.len(), 1);
+    assert_eq!(resultats[0].message.id, 3);
+    let (message, _) = database
+        .ajout_message(
+            2,
+            FormMessage {
+                room_id: 1,
+                text: String::from("Nouveau"),
+                parent_id: None,
+                attachment_ids: Vec::new(),
+                send_at: None,
+                ttl: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(message.id, 4);
+
+    // Les anciens utilisateurs peuvent ouvrir une session
+    let session = database.ouvre_session(1, "Firefox").unwrap();
+    assert_eq!(
+        database
+            .verification_session(session.token.as_str())
+            .unwrap()
+            .user_id,
+        1
+    );
+
+    drop(database);
+    drop(pool);
+    fs::remove_file(chemin).unwrap();
+}
+
+static INIT: Once = Once::new();
+/// Nombre de clients créés, pour donner à chacun son dossier
+static CLIENTS: AtomicUsize = AtomicUsize::new(0);
+/// Intervalle entre deux purges des données expirées des clients de test
+const INTERVALLE_PURGE_TEST: std::time::Duration = std::time::Duration::from_secs(1);
+/// Origine du front autorisée par les clients de test
+const ORIGINE_FRONT_TEST: &str = "http://front.test";
+
+/// Crée un client avec sa propre base de donnée et son propre dossier de pièces jointes,
+/// pour que les tests s'exécutent en parallèle
+pub async fn initialize() -> Client {
+    initialize_durees(DureesSession::default()).await
+}
+
+/// Crée un client (voir `initialize`) dont les sessions ont d'autres durées
+pub async fn initialize_durees(durees_session: DureesSession) -> Client {
+    INIT.call_once(|| {
+        dotenv().unwrap();
+        let test_dir = env::var("TEST_DIR").unwrap();
+        if fs::metadata(test_dir.clone()).is_ok() {
+            fs::remove_dir_all(test_dir).unwrap();
+        }
+    });
+    let dossier = PathBuf::from(env::var("TEST_DIR").unwrap())
+        .join(CLIENTS.fetch_add(1, Ordering::SeqCst).to_string());
+    fs::create_dir_all(&dossier).unwrap();
+    let configuration = Configuration {
+        database_url: dossier.join("database.db").to_string_lossy().to_string(),
+        attachments_dir: dossier.join("attachments"),
+        durees_session,
+        intervalle_purge: INTERVALLE_PURGE_TEST,
+        origines_front: vec![String::from(ORIGINE_FRONT_TEST)],
+    };
+    Client::tracked(build(configuration)).await.unwrap()
+}
+
+/// Utilisateur de test, avec le jeton de sa session
+#[derive(Debug)]
+pub struct UserSession {
+    pub id: i64,
+    pub username: String,
+    pub pass: String,
+    pub token: String,
+}
+
+async fn add_user(client: &Client, login: &FormAddUser) -> Result<UserSession, String> {
+    let response = client
+        .post(uri!(post_user))
+        .header(ContentType::Form)
+        .body((login as &dyn UriDisplay<Query>).to_string())
+        .dispatch()
+        .await;
+    let status = response.status().code;
+    let result = into_json(response).await;
     match status {
-        201 => Ok(UserPass {
+        201 => Ok(UserSession {
             id: result["user_id"].as_i64().unwrap(),
             username: login.username.clone(),
             pass: login.password.clone(),
-            api_key: result["api_key"].as_str().unwrap().to_string(),
+            token: result["token"].as_str().unwrap().to_string(),
         }),
         _ => Err(result["reason"].as_str().unwrap().to_string()),
     }
-}
+}
+
+async fn get_user(client: &Client, id: i64) -> Result<String, String> {
+    let response = client.get(format!("/user/{}", id)).dispatch().await;
+    let status = response.status().code;
+    let result = into_json(response).await;
+    match status {
+        200 => Ok(result["username"].as_str().unwrap().to_string()),
+        _ => Err(result["reason"].as_str().unwrap().to_string()),
+    }
+}
+
+async fn login(client: &Client, login: &FormAddUser) -> Result<UserSession, String> {
+    let session = login_session(client, login).await?;
+    Ok(UserSession {
+        id: session.user_id,
+        username: login.username.clone(),
+        pass: login.password.clone(),
+        token: session.token,
+    })
+}
+
+/// Connecte l'utilisateur et renvoie sa session avec le jeton de rafraîchissement
+async fn login_session(client: &Client, login: &FormAddUser) -> Result<Session, String> {
+    let response = client
+        .post(uri!(post_login))
+        .header(ContentType::Form)
+        .body((login as &dyn UriDisplay<Query>).to_string())
+        .dispatch()
+        .await;
+    into_reponse::<Session>(response)
+        .await
+        .map_err(|e| e.reason)
+}
+
+async fn refresh(client: &Client, refresh_token: &str) -> Result<Session, String> {
+    let form = FormRefresh {
+        refresh_token: refresh_token.to_string(),
+    };
+    let response = client
+        .post(uri!(post_refresh))
+        .header(ContentType::Form)
+        .body((&form as &dyn UriDisplay<Query>).to_string())
+        .dispatch()
+        .await;
+    into_reponse::<Session>(response)
+        .await
+        .map_err(|e| e.reason)
+}
+
+async fn reset_password(client: &Client, form: &FormResetPassword) -> Result<(), (u16, ApiError)> {
+    let response = client
+        .post(uri!(post_password_reset))
+        .header(ContentType::Form)
+        .body((form as &dyn UriDisplay<Query>).to_string())
+        .dispatch()
+        .await;
+    match response.status().code {
+        200 => Ok(()),
+        _ => Err(into_erreur(response).await),
+    }
+}
+
+impl UserSession {
+    /// En-tête d'authentification des requêtes de l'utilisateur
+    pub fn auth(&self) -> Header<'static> {
+        Header::new("Authorization", format!("Bearer {}", self.token))
+    }
+
+    async fn change_password(
+        &self,
+        client: &Client,
+        form: &FormChangePassword,
+    ) -> Result<(), (u16, ApiError)> {
+        let response = client
+            .post(uri!(post_password))
+            .header(ContentType::Form)
+            .header(self.auth())
+            .body((form as &dyn UriDisplay<Query>).to_string())
+            .dispatch()
+            .await;
+        match response.status().code {
+            200 => Ok(()),
+            _ => Err(into_erreur(response).await),
+        }
+    }
+
+    async fn addroom(&self, client: &Client, name: String) -> Result<Room, String> {
+        let room = FormAddRoom { name };
+        let response = client
+            .post(uri!(post_room))
+            .header(ContentType::Form)
+            .header(self.auth())
+            .body((&room as &dyn UriDisplay<Query>).to_string())
+            .dispatch()
+            .await;
+        let status = response.status().code;
+        let result = into_json(response).await;
+        match status {
+            201 => Ok(Room {
+                id: result["room_id"].as_i64().unwrap(),
+                name: room.name.to_string(),
+                topic: String::new(),
+                kind: RoomKind::Group,
+                retention: None,
+                unread_count: 0,
+                last_message_id: None,
+                members: vec![RoomMember {
+                    room_id: result["room_id"].as_i64().unwrap(),
+                    user_id: self.id,
+                    role: RoomRole::Owner,
+                }],
+            }),
+            _ => Err(result["reason"].as_str().unwrap().to_string()),
+        }
+    }
+
+    async fn invite(&self, client: &Client, other_user: String, room: i64) -> Result<(), String> {
+        let room = FormAddUserRoom {
+            other_user_username: other_user,
+            room_id: room,
+        };
+        let response = client
+            .post(uri!(post_invite))
+            .header(ContentType::Form)
+            .header(self.auth())
+            .body((&room as &dyn UriDisplay<Query>).to_string())
+            .dispatch()
+            .await;
+        let status = response.status().code;
+        let result = into_json(response).await;
+        match status {
+            201 => Ok(()),
+            _ => Err(result["reason"].as_str().unwrap().to_string()),
+        }
+    }
+
+    async fn addmessage(
+        &self,
+        client: &Client,
+        room_id: i64,
+        text: String,
+    ) -> Result<Message, String> {
+        self.postmessage(client, room_id, text, None, Vec::new())
+            .await
+    }
+
+    async fn addreply(
+        &self,
+        client: &Client,
+        room_id: i64,
+        parent_id: i64,
+        text: String,
+    ) -> Result<Message, String> {
+        self.postmessage(client, room_id, text, Some(parent_id), Vec::new())
+            .await
+    }
+
+    async fn postmessage(
+        &self,
+        client: &Client,
+        room_id: i64,
+        text: String,
+        parent_id: Option<i64>,
+        attachments: Vec<Attachment>,
+    ) -> Result<Message, String> {
+        let message = FormMessage {
+            room_id,
+            text,
+            parent_id,
+            attachment_ids: attachments.iter().map(|attachment| attachment.id).collect(),
+            send_at: None,
+            ttl: None,
+        };
+        self.sendmessage(client, message, attachments).await
+    }
+
+    async fn addephemeral(
+        &self,
+        client: &Client,
+        room_id: i64,
+        text: String,
+        ttl: i64,
+    ) -> Result<Message, String> {
+        let message = FormMessage {
+            room_id,
+            text,
+            parent_id: None,
+            attachment_ids: Vec::new(),
+            send_at: None,
+            ttl: Some(ttl),
+        };
+        self.sendmessage(client, message, Vec::new()).await
+    }
+
+    async fn sendmessage(
+        &self,
+        client: &Client,
+        message: FormMessage,
+        attachments: Vec<Attachment>,
+    ) -> Result<Message, String> {
+        let response = client
+            .post(uri!(post_message))
+            .header(ContentType::Form)
+            .header(self.auth())
+            .body((&message as &dyn UriDisplay<Query>).to_string())
+            .dispatch()
+            .await;
+        let status = response.status().code;
+        let result = into_json(response).await;
+        match status {
+            201 => Ok(Message {
+                id: result["message_id"].as_i64().unwrap(),
+                date: Utc::now(),
+                room_id: message.room_id,
+                user_id: self.id,
+                text: message.text.to_string(),
+                parent_id: message.parent_id,
+                reply_count: 0,
+                reactions: Vec::new(),
+                attachments,
+                expires_at: message
+                    .ttl
+                    .map(|ttl| Utc::now() + chrono::Duration::seconds(ttl)),
+            }),
+            _ => Err(result["reason"].as_str().unwrap().to_string()),
+        }
+    }
 
-async fn get_user(client: &Client, id: i64) -> Result<String, String> {
-    let response = client.get(format!("/user/{}", id)).dispatch().await;
-    let status = response.status().code;
-    let result = into_json(response).await;
-    match status {
-        200 => Ok(result["username"].as_str().unwrap().to_string()),
-        _ => Err(result["reason"].as_str().unwrap().to_string()),
+    async fn editmessage(
+        &self,
+        client: &Client,
+        message: &Message,
+        text: String,
+    ) -> Result<Message, String> {
+        let form = FormEditMessage { text };
+        let response = client
+            .patch(format!("/message/{}", message.id))
+            .header(ContentType::Form)
+            .header(self.auth())
+            .body((&form as &dyn UriDisplay<Query>).to_string())
+            .dispatch()
+            .await;
+        let status = response.status().code;
+        let result = into_json(response).await;
+        match status {
+            200 => Ok(Message {
+                text: form.text,
+                ..message.clone()
+            }),
+            _ => Err(result["reason"].as_str().unwrap().to_string()),
+        }
     }
-}
 
-async fn login(client: &Client, login: &FormAddUser) -> Result<UserPass, String> {
-    let response = client
-        .post(uri!(post_login))
-        .header(ContentType::Form)
-        .body((login as &dyn UriDisplay<Query>).to_string())
-        .dispatch()
-        .await;
-    let status = response.status().code;
-    let result = into_json(response).await;
-    match status {
-        202 => Ok(UserPass {
-            id: result["user_id"].as_i64().unwrap(),
-            username: login.username.clone(),
-            pass: login.password.clone(),
-            api_key: result["api_key"].as_str().unwrap().to_string(),
-        }),
-        _ => Err(result["reason"].as_str().unwrap().to_string()),
+    async fn deletemessage(&self, client: &Client, message: &Message) -> Result<(), String> {
+        let response = client
+            .delete(format!("/message/{}", message.id))
+            .header(self.auth())
+            .dispatch()
+            .await;
+        let status = response.status().code;
+        let result = into_json(response).await;
+        match status {
+            200 => Ok(()),
+            _ => Err(result["reason"].as_str().unwrap().to_string()),
+        }
+    }
+
+    async fn addreaction(
+        &self,
+        client: &Client,
+        message: &Message,
+        emoji: &str,
+    ) -> Result<(), String> {
+        self.sendreaction(client, message, emoji, true).await
     }
-}
 
-impl UserPass {
-    async fn addroom(&mut self, client: &Client, name: String) -> Result<Room, String> {
-        let room = FormAddRoom {
-            user_id: self.id,
-            api_key: self.api_key.to_string(),
-            name: name,
+    async fn removereaction(
+        &self,
+        client: &Client,
+        message: &Message,
+        emoji: &str,
+    ) -> Result<(), String> {
+        self.sendreaction(client, message, emoji, false).await
+    }
+
+    async fn sendreaction(
+        &self,
+        client: &Client,
+        message: &Message,
+        emoji: &str,
+        ajout: bool,
+    ) -> Result<(), String> {
+        let form = FormReaction {
+            emoji: emoji.to_string(),
         };
+        let uri = format!("/message/{}/reaction", message.id);
+        let request = match ajout {
+            true => client.post(uri),
+            false => client.delete(uri),
+        };
+        let response = request
+            .header(ContentType::Form)
+            .header(self.auth())
+            .body((&form as &dyn UriDisplay<Query>).to_string())
+            .dispatch()
+            .await;
+        let status = response.status().code;
+        let result = into_json(response).await;
+        match status {
+            200 | 201 => Ok(()),
+            _ => Err(result["reason"].as_str().unwrap().to_string()),
+        }
+    }
+
+    async fn markread(&self, client: &Client, room_id: i64, message_id: i64) -> Result<(), String> {
+        let form = FormRead { message_id };
         let response = client
-            .post(uri!(post_room))
+            .post(format!("/room/{}/read", room_id))
             .header(ContentType::Form)
-            .body((&room as &dyn UriDisplay<Query>).to_string())
+            .header(self.auth())
+            .body((&form as &dyn UriDisplay<Query>).to_string())
             .dispatch()
             .await;
         let status = response.status().code;
         let result = into_json(response).await;
         match status {
-            201 => {
-                self.api_key = result["api_key"].as_str().unwrap().to_string();
-                Ok(Room {
-                    id: next_room_id(),
-                    name: room.name.to_string(),
-                })
-            }
+            200 => Ok(()),
+            _ => Err(result["reason"].as_str().unwrap().to_string()),
+        }
+    }
+
+    async fn typing(&self, client: &Client, room_id: i64) -> Result<(), String> {
+        let response = client
+            .post(format!("/room/{}/typing", room_id))
+            .header(self.auth())
+            .dispatch()
+            .await;
+        let status = response.status().code;
+        let result = into_json(response).await;
+        match status {
+            202 => Ok(()),
+            _ => Err(result["reason"].as_str().unwrap().to_string()),
+        }
+    }
+
+    async fn getmessages(
+        &self,
+        client: &Client,
+        room_id: i64,
+        before: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<(Vec<Message>, bool), String> {
+        let mut parametres = Vec::new();
+        if let Some(before) = before {
+            parametres.push(format!("before={}", before));
+        }
+        if let Some(limit) = limit {
+            parametres.push(format!("limit={}", limit));
+        }
+        let url = format!("/room/{}/messages?{}", room_id, parametres.join("&"));
+        let response = client.get(url).header(self.auth()).dispatch().await;
+        match into_reponse::<MessagePage>(response).await {
+            Ok(page) => Ok((page.messages, page.has_more)),
+            Err(e) => Err(e.reason),
+        }
+    }
+
+    async fn getreplies(&self, client: &Client, message_id: i64) -> Result<Vec<Message>, String> {
+        let response = client
+            .get(format!("/message/{}/replies", message_id))
+            .header(self.auth())
+            .dispatch()
+            .await;
+        match into_reponse::<Replies>(response).await {
+            Ok(replies) => Ok(replies.messages),
+            Err(e) => Err(e.reason),
+        }
+    }
+
+    async fn upload(
+        &self,
+        client: &Client,
+        room_id: i64,
+        file_name: &str,
+        content_type: &str,
+        data: &str,
+    ) -> Result<Attachment, String> {
+        const BOUNDARY: &str = "X-RUSTY-MESSENGER-BOUNDARY";
+        let body = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{file_name}\"\r\n\
+            Content-Type: {content_type}\r\n\r\n{data}\r\n\
+            --{BOUNDARY}--\r\n"
+        );
+        let response = client
+            .post(format!("/room/{}/attachment", room_id))
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", BOUNDARY)))
+            .header(self.auth())
+            .body(body)
+            .dispatch()
+            .await;
+        match into_reponse::<CreatedAttachment>(response).await {
+            Ok(reponse) => Ok(reponse.attachment),
+            Err(e) => Err(e.reason),
+        }
+    }
+
+    async fn download(
+        &self,
+        client: &Client,
+        attachment: &Attachment,
+    ) -> Result<(String, String), String> {
+        let response = client
+            .get(uri!(get_attachment(attachment.id)))
+            .header(self.auth())
+            .dispatch()
+            .await;
+        match response.status().code {
+            200 => Ok((
+                response.content_type().unwrap().to_string(),
+                response.into_string().await.unwrap(),
+            )),
+            _ => Err(into_erreur(response).await.1.reason),
+        }
+    }
+
+    async fn kick(&self, client: &Client, room_id: i64, other_user_id: i64) -> Result<(), String> {
+        self.sendmember(client, format!("/room/{}/kick", room_id), other_user_id)
+            .await
+    }
+
+    async fn ban(&self, client: &Client, room_id: i64, other_user_id: i64) -> Result<(), String> {
+        self.sendmember(client, format!("/room/{}/ban", room_id), other_user_id)
+            .await
+    }
+
+    async fn sendmember(
+        &self,
+        client: &Client,
+        url: String,
+        other_user_id: i64,
+    ) -> Result<(), String> {
+        let form = FormMember { other_user_id };
+        let response = client
+            .post(url)
+            .header(ContentType::Form)
+            .header(self.auth())
+            .body((&form as &dyn UriDisplay<Query>).to_string())
+            .dispatch()
+            .await;
+        let status = response.status().code;
+        let result = into_json(response).await;
+        match status {
+            200 => Ok(()),
             _ => Err(result["reason"].as_str().unwrap().to_string()),
         }
     }
 
-    async fn invite(
-        &mut self,
+    async fn setrole(
+        &self,
         client: &Client,
-        other_user: String,
-        room: i64,
+        room_id: i64,
+        other_user_id: i64,
+        role: &str,
     ) -> Result<(), String> {
-        let room = FormAddUserRoom {
-            user_id: self.id,
-            api_key: self.api_key.to_string(),
+        let form = FormRole {
+            other_user_id,
+            role: role.to_string(),
+        };
+        let response = client
+            .post(format!("/room/{}/role", room_id))
+            .header(ContentType::Form)
+            .header(self.auth())
+            .body((&form as &dyn UriDisplay<Query>).to_string())
+            .dispatch()
+            .await;
+        let status = response.status().code;
+        let result = into_json(response).await;
+        match status {
+            200 => Ok(()),
+            _ => Err(result["reason"].as_str().unwrap().to_string()),
+        }
+    }
+
+    async fn editroom(
+        &self,
+        client: &Client,
+        room_id: i64,
+        name: Option<&str>,
+        topic: Option<&str>,
+    ) -> Result<(), String> {
+        let form = FormEditRoom {
+            name: name.map(|name| name.to_string()),
+            topic: topic.map(|topic| topic.to_string()),
+            retention: None,
+        };
+        self.sendroomsettings(client, room_id, form).await
+    }
+
+    async fn setretention(
+        &self,
+        client: &Client,
+        room_id: i64,
+        retention: i64,
+    ) -> Result<(), String> {
+        let form = FormEditRoom {
+            name: None,
+            topic: None,
+            retention: Some(retention),
+        };
+        self.sendroomsettings(client, room_id, form).await
+    }
+
+    async fn sendroomsettings(
+        &self,
+        client: &Client,
+        room_id: i64,
+        form: FormEditRoom,
+    ) -> Result<(), String> {
+        let response = client
+            .patch(format!("/room/{}", room_id))
+            .header(ContentType::Form)
+            .header(self.auth())
+            .body((&form as &dyn UriDisplay<Query>).to_string())
+            .dispatch()
+            .await;
+        let status = response.status().code;
+        let result = into_json(response).await;
+        match status {
+            200 => Ok(()),
+            _ => Err(result["reason"].as_str().unwrap().to_string()),
+        }
+    }
+
+    async fn leave(&self, client: &Client, room_id: i64) -> Result<(), String> {
+        let response = client
+            .post(format!("/room/{}/leave", room_id))
+            .header(self.auth())
+            .dispatch()
+            .await;
+        let status = response.status().code;
+        let result = into_json(response).await;
+        match status {
+            200 => Ok(()),
+            _ => Err(result["reason"].as_str().unwrap().to_string()),
+        }
+    }
+
+    /// Ouvre une conversation privée et retourne son id et si elle vient d'être créée
+    async fn dm(&self, client: &Client, other_user: String) -> Result<(i64, bool), String> {
+        let form = FormDirectRoom {
             other_user_username: other_user,
-            room_id: room,
         };
         let response = client
-            .post(uri!(post_invite))
+            .post(uri!(post_dm))
             .header(ContentType::Form)
-            .body((&room as &dyn UriDisplay<Query>).to_string())
+            .header(self.auth())
+            .body((&form as &dyn UriDisplay<Query>).to_string())
             .dispatch()
             .await;
         let status = response.status().code;
         let result = into_json(response).await;
-        match result["api_key"].as_str() {
-            Some(api_key) => self.api_key = api_key.to_string(),
-            None => {}
+        match status {
+            200 => Ok((result["room_id"].as_i64().unwrap(), false)),
+            201 => Ok((result["room_id"].as_i64().unwrap(), true)),
+            _ => Err(result["reason"].as_str().unwrap().to_string()),
+        }
+    }
+
+    /// Recherche des messages, `query` contient les termes et les filtres (`q=...&room_id=...`)
+    async fn search(&self, client: &Client, query: &str) -> Result<Vec<SearchResult>, String> {
+        let response = client
+            .get(format!("/search?{}", query))
+            .header(self.auth())
+            .dispatch()
+            .await;
+        match into_reponse::<SearchResults>(response).await {
+            Ok(resultats) => Ok(resultats.results),
+            Err(e) => Err(e.reason),
+        }
+    }
+
+    async fn getmentions(&self, client: &Client) -> Result<Vec<Message>, String> {
+        let response = client.get("/mentions").header(self.auth()).dispatch().await;
+        match into_reponse::<MessagePage>(response).await {
+            Ok(page) => Ok(page.messages),
+            Err(e) => Err(e.reason),
         }
+    }
+
+    async fn pin(&self, client: &Client, message: &Message) -> Result<(), String> {
+        self.sendpin(client, message, true).await
+    }
+
+    async fn unpin(&self, client: &Client, message: &Message) -> Result<(), String> {
+        self.sendpin(client, message, false).await
+    }
+
+    async fn sendpin(&self, client: &Client, message: &Message, ajout: bool) -> Result<(), String> {
+        let uri = format!("/message/{}/pin", message.id);
+        let request = match ajout {
+            true => client.post(uri),
+            false => client.delete(uri),
+        };
+        let response = request.header(self.auth()).dispatch().await;
+        let status = response.status().code;
+        let result = into_json(response).await;
         match status {
-            201 => Ok(()),
+            200 | 201 => Ok(()),
             _ => Err(result["reason"].as_str().unwrap().to_string()),
         }
     }
 
-    async fn addmessage(
-        &mut self,
+    async fn getpins(&self, client: &Client, room_id: i64) -> Result<Vec<Pin>, String> {
+        let response = client
+            .get(format!("/room/{}/pins", room_id))
+            .header(self.auth())
+            .dispatch()
+            .await;
+        match into_reponse::<Pins>(response).await {
+            Ok(pins) => Ok(pins.pins),
+            Err(e) => Err(e.reason),
+        }
+    }
+
+    /// Programme un message et retourne l'id du message programmé
+    async fn schedule(
+        &self,
         client: &Client,
         room_id: i64,
         text: String,
-    ) -> Result<Message, String> {
+        send_at: i64,
+    ) -> Result<i64, String> {
         let message = FormMessage {
-            user_id: self.id,
-            api_key: self.api_key.to_string(),
-            room_id: room_id,
-            text: text,
+            room_id,
+            text,
+            parent_id: None,
+            attachment_ids: Vec::new(),
+            send_at: Some(send_at),
+            ttl: None,
         };
         let response = client
             .post(uri!(post_message))
             .header(ContentType::Form)
+            .header(self.auth())
             .body((&message as &dyn UriDisplay<Query>).to_string())
             .dispatch()
             .await;
         let status = response.status().code;
         let result = into_json(response).await;
-        match result["api_key"].as_str() {
-            Some(api_key) => self.api_key = api_key.to_string(),
-            None => {}
+        match status {
+            202 => Ok(result["scheduled_id"].as_i64().unwrap()),
+            _ => Err(result["reason"].as_str().unwrap().to_string()),
+        }
+    }
+
+    async fn getscheduled(&self, client: &Client) -> Result<Vec<ScheduledMessage>, String> {
+        let response = client
+            .get(uri!(get_scheduled))
+            .header(self.auth())
+            .dispatch()
+            .await;
+        match into_reponse::<ScheduledMessages>(response).await {
+            Ok(programmes) => Ok(programmes.scheduled),
+            Err(e) => Err(e.reason),
+        }
+    }
+
+    async fn editscheduled(
+        &self,
+        client: &Client,
+        scheduled_id: i64,
+        text: Option<String>,
+        send_at: Option<i64>,
+    ) -> Result<ScheduledMessage, String> {
+        let form = FormEditScheduled { text, send_at };
+        let response = client
+            .patch(format!("/scheduled/{}", scheduled_id))
+            .header(ContentType::Form)
+            .header(self.auth())
+            .body((&form as &dyn UriDisplay<Query>).to_string())
+            .dispatch()
+            .await;
+        match into_reponse::<EditedScheduled>(response).await {
+            Ok(reponse) => Ok(reponse.scheduled),
+            Err(e) => Err(e.reason),
         }
+    }
+
+    async fn cancelscheduled(&self, client: &Client, scheduled_id: i64) -> Result<(), String> {
+        let response = client
+            .delete(format!("/scheduled/{}", scheduled_id))
+            .header(self.auth())
+            .dispatch()
+            .await;
+        let status = response.status().code;
+        let result = into_json(response).await;
         match status {
-            201 => Ok(Message {
-                date: Utc::now(),
-                room_id: message.room_id,
-                user_id: message.user_id,
-                text: message.text.to_string(),
-            }),
+            200 => Ok(()),
             _ => Err(result["reason"].as_str().unwrap().to_string()),
         }
     }
@@ -419,3 +4100,15 @@ pub async fn into_json(res: LocalResponse<'_>) -> JsonValue {
     let res = res.into_string().await.unwrap();
     json::parse(res.as_str()).unwrap()
 }
+
+/// Réponse de l'API, ou l'erreur renvoyée à la place
+pub async fn into_reponse<T: ApiResponse>(res: LocalResponse<'_>) -> Result<T, ApiError> {
+    T::parse(res.into_string().await.unwrap().as_str())
+}
+
+/// Statut et erreur d'une réponse d'erreur de l'API
+pub async fn into_erreur(res: LocalResponse<'_>) -> (u16, ApiError) {
+    let status = res.status().code;
+    let res = res.into_string().await.unwrap();
+    (status, ApiError::parse(res.as_str()).unwrap())
+}
@@ -0,0 +1,40 @@
+//! Indicateurs d'écriture
+//!
+//! Ce module garde en mémoire (sans passer par la base de données) les utilisateurs en train
+//! d'écrire dans un salon, jusqu'à ce que leur indicateur expire.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Durée pendant laquelle un utilisateur est considéré en train d'écrire après son dernier signal
+pub const DUREE_ECRITURE: Duration = Duration::from_secs(5);
+
+/// Expiration de l'indicateur d'écriture de chaque (salon, utilisateur)
+#[derive(Clone, Default)]
+pub struct Ecritures(Arc<Mutex<HashMap<(i64, i64), Instant>>>);
+
+impl Ecritures {
+    /// Indique qu'un utilisateur écrit dans un salon
+    ///
+    /// Retourne `true` s'il n'était pas déjà en train d'écrire.
+    pub fn commence(&self, room_id: i64, user_id: i64) -> bool {
+        let mut lock = self.0.lock().unwrap();
+        lock.insert((room_id, user_id), Instant::now() + DUREE_ECRITURE)
+            .is_none()
+    }
+
+    /// Retire l'indicateur d'un utilisateur s'il a expiré
+    ///
+    /// Retourne `true` si l'indicateur a été retiré.
+    pub fn termine_si_expire(&self, room_id: i64, user_id: i64) -> bool {
+        let mut lock = self.0.lock().unwrap();
+        match lock.get(&(room_id, user_id)) {
+            Some(expiration) if *expiration <= Instant::now() => {
+                lock.remove(&(room_id, user_id));
+                true
+            }
+            _ => false,
+        }
+    }
+}
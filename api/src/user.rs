@@ -1,36 +1,14 @@
-//! Gestion des utilisateurs, authentification et génération de clés d'API
+//! Gestion des utilisateurs
 //!
-//! Ce module implémente des méthodes pour la gestion des utilisateurs, la création et la gestion
-//! des clés d'API, ainsi que les opérations d'authentification dans une base de données.
+//! Ce module implémente des méthodes pour la création des utilisateurs
+//! et leur recherche dans la base de données.
 
-use chrono::Utc;
+use lib::{FormAddUser, Session};
 use pwhash::bcrypt;
-use rand::Rng;
-use rocket::serde::{Deserialize, Serialize};
-use rusqlite::{Result, Row};
+use rusqlite::{ErrorCode, Result, Row};
 
 use crate::database::Database;
-
-/// Generate a new api_key (use new_api_key_2 when already have an api_key)
-pub fn new_api_key() -> String {
-    bcrypt::hash(format!(
-        "{}+{}",
-        Utc::now().timestamp(),
-        rand::thread_rng().gen::<u64>()
-    ))
-    .unwrap()
-}
-
-/// Regenerate an api_key
-pub fn new_api_key_2(previous: &str) -> String {
-    bcrypt::hash(format!(
-        "{}+{}+{}",
-        Utc::now().timestamp(),
-        previous,
-        rand::thread_rng().gen::<u64>()
-    ))
-    .unwrap()
-}
+use crate::erreur::Erreur;
 
 #[derive(Debug)]
 pub struct User {
@@ -43,103 +21,63 @@ pub struct UserPass {
     pub id: i64,
     pub username: String,
     pub pass: String,
-    pub api_key: String,
-}
-
-#[derive(Debug, Clone, FromForm, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq, UriDisplayQuery))]
-#[serde(crate = "rocket::serde")]
-pub struct AuthKey {
-    pub user_id: i64,
-    pub api_key: String,
-}
-
-#[derive(Debug, Clone, FromForm, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq, UriDisplayQuery))]
-#[serde(crate = "rocket::serde")]
-pub struct AuthPass {
-    pub user_id: i64,
-    pub password: String,
-}
-
-#[derive(Debug, Clone, FromForm, Serialize, Deserialize)]
-#[cfg_attr(test, derive(PartialEq, UriDisplayQuery))]
-#[serde(crate = "rocket::serde")]
-pub struct FormAddUser {
-    pub username: String,
-    pub password: String,
 }
 
 impl Database {
-    /// Crée un utilisateur et lui crée une api_key
-    pub fn ajout_user(&self, user: FormAddUser) -> Result<AuthKey> {
-        let napi = new_api_key();
-
-        self.connection.execute(
-            "INSERT INTO user (username, password, api_key) VALUES (?1,?2,?3)",
+    /// Crée un utilisateur et lui ouvre une session sur un appareil
+    pub fn ajout_user(&self, user: FormAddUser, user_agent: &str) -> Result<Session, Erreur> {
+        match self.connection.execute(
+            "INSERT INTO user (username, password) VALUES (?1,?2)",
             (
                 user.username.as_str(),
                 bcrypt::hash(user.password.as_str()).unwrap(),
-                napi.as_str(),
             ),
-        )?;
-
-        Ok(AuthKey {
-            user_id: self.connection.last_insert_rowid(),
-            api_key: napi,
-        })
-    }
+        ) {
+            Ok(_) => {}
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == ErrorCode::ConstraintViolation =>
+            {
+                return Err(Erreur::NomPris(String::from("Identifiant déjà pris")));
+            }
+            Err(e) => return Err(e.into()),
+        }
 
-    /// Supprime l'api_key d'un utilisateur
-    pub fn logout(&self, user_id: i64) -> Result<usize> {
-        self.user_update_api_key("", user_id)
+        Ok(self.ouvre_session(self.connection.last_insert_rowid(), user_agent)?)
     }
 
     /// Récupère tous les informations d'un utilisateur
-    pub fn user_select_id(&self, user_id: i64) -> Result<UserPass, String> {
+    pub fn user_select_id(&self, user_id: i64) -> Result<UserPass, Erreur> {
         let mut stmt = self
             .connection
-            .prepare("SELECT id, username, password, api_key FROM user WHERE id = ?1")
-            .map_err(|_| String::from("cant prepare the querry"))?;
+            .prepare("SELECT id, username, password FROM user WHERE id = ?1")?;
 
-        let mut rows = stmt
-            .query_map([user_id], map_user_pass)
-            .map_err(|_| String::from("cant execute the querry"))?;
+        let mut rows = stmt.query_map([user_id], map_user_pass)?;
 
         match rows.next() {
-            Some(Ok(bd_user)) => Ok(bd_user),
-            _ => Err(format!("no user with the id {}", user_id)),
+            Some(bd_user) => Ok(bd_user?),
+            None => Err(Erreur::Introuvable(format!(
+                "no user with the id {}",
+                user_id
+            ))),
         }
     }
 
     /// Récupère le nom d'un utilisateur
-    pub fn user_select_username(&self, username: &str) -> Result<UserPass, String> {
+    pub fn user_select_username(&self, username: &str) -> Result<UserPass, Erreur> {
         let mut stmt = self
             .connection
-            .prepare("SELECT id, username, password, api_key FROM user WHERE username = ?1")
-            .map_err(|_| String::from("cant prepare the querry"))?;
+            .prepare("SELECT id, username, password FROM user WHERE username = ?1")?;
 
-        let mut rows = stmt
-            .query_map([username], map_user_pass)
-            .map_err(|_| String::from("cant execute the querry"))?;
+        let mut rows = stmt.query_map([username], map_user_pass)?;
 
         match rows.next() {
-            Some(Ok(bd_user)) => Ok(bd_user),
-            _ => Err(format!("Pas d'utilisateur avec ce nom {}", username)),
+            Some(bd_user) => Ok(bd_user?),
+            None => Err(Erreur::Introuvable(format!(
+                "Pas d'utilisateur avec ce nom {}",
+                username
+            ))),
         }
     }
-
-    /// Change l'api_key d'un utilisateur
-    pub fn user_update_api_key(&self, api_key: &str, user_id: i64) -> Result<usize> {
-        self.connection.execute(
-            "
-        UPDATE user
-        SET api_key = ?1
-        WHERE id = ?2
-        ",
-            (api_key, user_id),
-        )
-    }
 }
 
 fn map_user_pass(row: &Row) -> Result<UserPass> {
@@ -147,6 +85,5 @@ fn map_user_pass(row: &Row) -> Result<UserPass> {
         id: row.get(0)?,
         username: row.get(1)?,
         pass: row.get(2)?,
-        api_key: row.get(3)?,
     })
 }
@@ -0,0 +1,234 @@
+//! Module des réglages du compte
+//!
+//! Ce module implémente la page du compte de l'utilisateur : changer son mot de passe
+//! (ses autres sessions sont alors fermées), voir les appareils où il est connecté et fermer
+//! leur session.
+
+use chrono::Local;
+use dioxus::prelude::*;
+use dioxus_router::prelude::*;
+use lib::{ApiError, ApiResponse, FormChangePassword, SessionInfo, Sessions};
+
+use crate::{room::OpRoomId, AccountManager, Route, BASE_API_URL};
+
+#[inline_props]
+pub fn Compte(cx: Scope) -> Element {
+    let account_manager = use_shared_state::<AccountManager>(cx).unwrap();
+    let old_password = use_state(cx, String::new);
+    let new_password = use_state(cx, String::new);
+    let sessions = use_state::<Option<Vec<SessionInfo>>>(cx, || None);
+    let message = use_state::<Option<String>>(cx, || None);
+    let error = use_state::<Option<String>>(cx, || None);
+
+    let navigator = use_navigator(cx);
+    if account_manager.read().utilisateur_actuelle().is_none() {
+        navigator.replace(Route::LogIn {});
+        return render! {div{}};
+    }
+
+    let charges = sessions.get().is_some();
+    use_effect(cx, (&charges,), |(charges,)| {
+        to_owned![account_manager, sessions, error];
+        async move {
+            if !charges {
+                charge_sessions(account_manager, sessions, error).await;
+            }
+        }
+    });
+
+    let appareils: Vec<(i64, String, String, bool)> = sessions
+        .get()
+        .iter()
+        .flatten()
+        .map(|session| {
+            (
+                session.id,
+                match session.user_agent.is_empty() {
+                    true => String::from("Appareil inconnu"),
+                    false => session.user_agent.to_string(),
+                },
+                format!(
+                    "Connecté le {}, utilisé le {}",
+                    session.created_at.with_timezone(&Local).format("%d/%m/%Y %H:%M"),
+                    session.last_used.with_timezone(&Local).format("%d/%m/%Y %H:%M")
+                ),
+                session.current,
+            )
+        })
+        .collect();
+
+    render! {
+        div{
+            id: "account",
+            h1{"Compte"}
+            Link {
+                to: Route::SideBar{ room_id: OpRoomId::new_empty() },
+                "Retour aux salons"
+            }
+            match error.as_ref() {
+                Some(e) => render!{span{class:"Error",e.as_str()}},
+                None => render!{span{}}
+            }
+            match message.as_ref() {
+                Some(m) => render!{span{class:"search-meta",m.as_str()}},
+                None => render!{span{}}
+            }
+            form {
+                id: "change-password",
+                input {
+                    r#type: "password",
+                    name: "old_password",
+                    autocomplete: "off",
+                    placeholder: "mot de passe actuel",
+                    oninput: move |evt| old_password.set(evt.value.clone()),
+                    value: "{old_password}"
+                }
+                input {
+                    r#type: "password",
+                    name: "new_password",
+                    autocomplete: "off",
+                    placeholder: "nouveau mot de passe",
+                    oninput: move |evt| new_password.set(evt.value.clone()),
+                    value: "{new_password}"
+                }
+                button {
+                    prevent_default: "onclick",
+                    onclick: move |_| change_mot_de_passe(cx, account_manager.to_owned(), old_password.to_owned(), new_password.to_owned(), sessions.to_owned(), message.to_owned(), error.to_owned()),
+                    "Changer le mot de passe"
+                }
+            }
+            ul{
+                id: "sessions",
+                for (session_id, appareil, dates, courante) in appareils {
+                    li{
+                        span{ "{appareil}" }
+                        span{ class: "search-meta", "{dates}" }
+                        match courante {
+                            true => render!{span{ class: "search-meta", "Cet appareil" }},
+                            false => render!{
+                                button {
+                                    prevent_default: "onclick",
+                                    onclick: move |_| ferme_session(cx, account_manager.to_owned(), sessions.to_owned(), session_id, error.to_owned()),
+                                    "Déconnecter"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Récupère les sessions ouvertes de l'utilisateur
+async fn charge_sessions(
+    account_manager: UseSharedState<AccountManager>,
+    sessions: UseState<Option<Vec<SessionInfo>>>,
+    error: UseState<Option<String>>,
+) {
+    let token = match account_manager.read().utilisateur_actuelle() {
+        Some(user) => user.token.to_string(),
+        None => return,
+    };
+
+    let url = format!("{BASE_API_URL}/sessions");
+    match reqwest::Client::new().get(&url).bearer_auth(&token).send().await {
+        Ok(response) => {
+            let response_body = response.text().await.unwrap();
+            match Sessions::parse(response_body.as_str()) {
+                Ok(reponse) => sessions.set(Some(reponse.sessions)),
+                Err(e) => error.set(Some(e.reason)),
+            }
+        }
+        Err(_) => error.set(Some(String::from("Perte de connection"))),
+    }
+}
+
+/// Change le mot de passe de l'utilisateur
+///
+/// Les autres sessions étant fermées par le serveur, la liste est rechargée.
+fn change_mot_de_passe<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    old_password: UseState<String>,
+    new_password: UseState<String>,
+    sessions: UseState<Option<Vec<SessionInfo>>>,
+    message: UseState<Option<String>>,
+    error: UseState<Option<String>>,
+) {
+    if new_password.is_empty() {
+        error.set(Some(String::from(
+            "Il faut au moins une lettre dans le mot de passe",
+        )));
+        return;
+    }
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+    let form = FormChangePassword {
+        old_password: old_password.to_string(),
+        new_password: new_password.to_string(),
+    };
+
+    let url = format!("{BASE_API_URL}/password");
+    cx.spawn(async move {
+        match reqwest::Client::new()
+            .post(&url)
+            .bearer_auth(&token)
+            .form(&form)
+            .send()
+            .await
+        {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                let response_body = response.text().await.unwrap();
+                match status {
+                    200 => {
+                        error.set(None);
+                        message.set(Some(String::from("Mot de passe changé")));
+                        old_password.set(String::new());
+                        new_password.set(String::new());
+                        sessions.set(None);
+                    }
+                    _ => error.set(Some(ApiError::reason_of(response_body.as_str()))),
+                }
+            }
+            Err(_) => error.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
+
+/// Ferme la session d'un autre appareil
+fn ferme_session<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    sessions: UseState<Option<Vec<SessionInfo>>>,
+    session_id: i64,
+    error: UseState<Option<String>>,
+) {
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+
+    let url = format!("{BASE_API_URL}/sessions/{}", session_id);
+    cx.spawn(async move {
+        match reqwest::Client::new().delete(&url).bearer_auth(&token).send().await {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                let response_body = response.text().await.unwrap();
+                match status {
+                    200 => {
+                        error.set(None);
+                        sessions.modify(|sessions| {
+                            sessions.as_ref().map(|sessions| {
+                                sessions
+                                    .iter()
+                                    .filter(|session| session.id != session_id)
+                                    .cloned()
+                                    .collect()
+                            })
+                        });
+                    }
+                    _ => error.set(Some(ApiError::reason_of(response_body.as_str()))),
+                }
+            }
+            Err(_) => error.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
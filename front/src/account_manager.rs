@@ -2,38 +2,56 @@
 //!
 //! Ce module implémente un gestionnaire de compte (AccountManager) responsable de la gestion de l'authentification
 //! de l'utilisateur, de la gestion de l'état actuel de l'utilisateur et de la communication
-//! avec des composants asynchrones tels que les messages (Message) et les salons (Room).
+//! avec des composants asynchrones tels que les événements (EventMessage) de l'Event Source.
+//!
+//! La session est rafraîchie avant l'expiration du jeton d'accès (voir `rafraichit_session`),
+//! et quand l'Event Source n'arrive plus à se reconnecter. L'utilisateur ne revient à la page
+//! de connexion que si le serveur refuse le rafraîchissement.
+
+use std::cell::Cell;
+use std::rc::Rc;
 
-use lib::{Message, Room};
+use chrono::{DateTime, Duration, Utc};
+use dioxus::prelude::UseSharedState;
+use lib::{ApiResponse, ErrorCode, EventMessage, FormRefresh, Session};
 
 use crate::{
     async_state::AsyncStateSetter,
     event_source::{MyEventSource, SourceState},
     structs::User,
+    BASE_API_URL,
 };
 
+/// Secondes avant l'expiration du jeton d'accès où la session est rafraîchie
+const MARGE_RAFRAICHISSEMENT: i64 = 60;
+/// Secondes avant de réessayer un rafraîchissement qui n'a pas abouti (perte de connexion)
+const DELAI_NOUVEL_ESSAI: i64 = 5;
+
 /// Gère le système d'authentification de l'utilisateur
 pub struct AccountManager {
     utilisateur_actuelle: Option<User>,
     nombre_tentatives: i64,
     event_source: Option<MyEventSource>,
-    message_sender: AsyncStateSetter<Message>,
-    room_sender: AsyncStateSetter<Room>,
+    /// Moment où rafraîchir la session de l'utilisateur
+    echeance_rafraichissement: Option<DateTime<Utc>>,
+    /// Identifiant du dernier événement reçu, pour ne recevoir que les événements manqués à la reconnexion
+    dernier_evenement: Rc<Cell<Option<i64>>>,
+    event_sender: AsyncStateSetter<EventMessage>,
     source_state_sender: AsyncStateSetter<SourceState>,
 }
 
 impl AccountManager {
     pub fn new(
-        message_sender: AsyncStateSetter<Message>,
-        room_sender: AsyncStateSetter<Room>,
+        event_sender: AsyncStateSetter<EventMessage>,
         source_state_sender: AsyncStateSetter<SourceState>,
     ) -> AccountManager {
         AccountManager {
             utilisateur_actuelle: None,
             nombre_tentatives: 0,
             event_source: None,
-            message_sender: message_sender,
-            room_sender: room_sender,
+            echeance_rafraichissement: None,
+            dernier_evenement: Rc::new(Cell::new(None)),
+            event_sender: event_sender,
             source_state_sender: source_state_sender,
         }
     }
@@ -52,44 +70,114 @@ impl AccountManager {
         }
 
         self.utilisateur_actuelle = user;
+        self.dernier_evenement.set(None);
         match self.utilisateur_actuelle.as_ref() {
             Some(current_user) => {
                 self.nombre_tentatives = 1;
+                self.echeance_rafraichissement =
+                    Some(current_user.expires_at - Duration::seconds(MARGE_RAFRAICHISSEMENT));
                 self.event_source = Some(MyEventSource::new(
-                    current_user.id,
-                    current_user.api_key.as_str(),
-                    &self.message_sender,
-                    &self.room_sender,
+                    &self.dernier_evenement,
+                    &self.event_sender,
                     &self.source_state_sender,
                 ))
             }
-            None => self.nombre_tentatives = 0,
+            None => {
+                self.nombre_tentatives = 0;
+                self.echeance_rafraichissement = None;
+            }
         }
     }
 
+    /// Moment où rafraîchir la session, s'il y a un utilisateur
+    pub fn echeance_rafraichissement(&self) -> Option<DateTime<Utc>> {
+        self.echeance_rafraichissement
+    }
+
+    /// Remplace les jetons de l'utilisateur par ceux de la session rafraîchie
+    ///
+    /// L'Event Source, fermée après trop de tentatives, est rouverte avec le nouveau cookie.
+    pub fn rafraichir_session(&mut self, session: Session) {
+        let user = match self.utilisateur_actuelle.as_mut() {
+            Some(user) => user,
+            None => return,
+        };
+        user.token = session.token;
+        user.refresh_token = session.refresh_token;
+        user.expires_at = session.expires_at;
+        self.echeance_rafraichissement =
+            Some(session.expires_at - Duration::seconds(MARGE_RAFRAICHISSEMENT));
+
+        if self.event_source.is_none() {
+            self.nombre_tentatives = 1;
+            self.event_source = Some(MyEventSource::new(
+                &self.dernier_evenement,
+                &self.event_sender,
+                &self.source_state_sender,
+            ));
+        }
+    }
+
+    /// Réessaie bientôt un rafraîchissement qui n'a pas abouti
+    pub fn reessayer_rafraichissement(&mut self) {
+        self.echeance_rafraichissement = Some(Utc::now() + Duration::seconds(DELAI_NOUVEL_ESSAI));
+    }
+
+    /// Reconnecte l'Event Source, ou rafraîchit la session après trop de tentatives
     pub fn nouvelle_tentative_de_connection(&mut self) {
+        let event_source = match self.event_source.take() {
+            Some(event_source) => event_source,
+            // En attente du rafraîchissement de la session
+            None => return,
+        };
         self.nombre_tentatives += 1;
-        let current_user = self.utilisateur_actuelle().unwrap();
-        self.event_source.as_ref().unwrap().close();
+        event_source.close();
         if self.nombre_tentatives > 3 {
-            self.utilisateur_actuelle = None;
-            self.event_source = None;
+            // Le jeton d'accès du cookie a peut-être expiré
+            self.echeance_rafraichissement = Some(Utc::now());
         } else {
             self.event_source = Some(MyEventSource::new(
-                current_user.id,
-                current_user.api_key.as_str(),
-                &self.message_sender,
-                &self.room_sender,
+                &self.dernier_evenement,
+                &self.event_sender,
                 &self.source_state_sender,
             ));
         }
     }
 
-    pub fn modifier_api_key(&mut self, api_key: String) {
-        self.utilisateur_actuelle.as_mut().unwrap().api_key = api_key;
-    }
-
     pub fn Mettre_est_connecter(&mut self) {
         self.nombre_tentatives = 1;
     }
 }
+
+/// Rafraîchit la session de l'utilisateur actuel
+///
+/// Si le serveur refuse (session expirée ou fermée), l'utilisateur est déconnecté.
+pub async fn rafraichit_session(account_manager: UseSharedState<AccountManager>) {
+    let form = match account_manager.read().utilisateur_actuelle() {
+        Some(user) => FormRefresh {
+            refresh_token: user.refresh_token.to_string(),
+        },
+        None => return,
+    };
+
+    match reqwest::Client::new()
+        .post(format!("{BASE_API_URL}/refresh"))
+        .form(&form)
+        // Pour que le navigateur remplace le cookie de session
+        .fetch_credentials_include()
+        .send()
+        .await
+    {
+        Ok(response) => {
+            let response_body = response.text().await.unwrap_or_default();
+            match Session::parse(response_body.as_str()) {
+                Ok(session) => account_manager.write().rafraichir_session(session),
+                Err(e) if e.code == ErrorCode::Unauthorized => {
+                    account_manager.write().modifier_utilisateur_actuelle(None)
+                }
+                Err(_) => account_manager.write().reessayer_rafraichissement(),
+            }
+        }
+        Err(_) => account_manager.write().reessayer_rafraichissement(),
+    }
+}
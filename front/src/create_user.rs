@@ -10,7 +10,7 @@ use crate::room::OpRoomId;
 use crate::structs::User;
 use crate::BASE_API_URL;
 use crate::{AccountManager, Route};
-use lib::serialize_login;
+use lib::{ApiResponse, FormAddUser, Session};
 
 #[inline_props]
 pub fn CreateUser(cx: Scope) -> Element {
@@ -82,27 +82,33 @@ fn create_user<T>(
         return;
     }
 
-    let form = serialize_login(username.to_string(), password.to_string());
+    let form = FormAddUser {
+        username: username.to_string(),
+        password: password.to_string(),
+    };
 
     let url = format!("{BASE_API_URL}/user");
     cx.spawn(async move {
-        match reqwest::Client::new().post(&url).form(&form).send().await {
+        match reqwest::Client::new()
+            .post(&url)
+            .form(&form)
+            // Pour que le navigateur garde le cookie de session (Event Source et pièces jointes)
+            .fetch_credentials_include()
+            .send()
+            .await
+        {
             Ok(response) => {
-                let status = response.status().as_u16();
                 let response_body = response.text().await.unwrap();
-                let response_data = json::parse(response_body.as_str()).unwrap();
-                match status {
-                    201 => {
-                        account_manager.write().modifier_utilisateur_actuelle(Some(User {
-                            id: response_data["user_id"].as_i64().unwrap(),
-                            username: username.to_string(),
-                            api_key: response_data["api_key"].as_str().unwrap().to_string(),
-                        }));
+                match Session::parse(response_body.as_str()) {
+                    Ok(session) => {
+                        account_manager
+                            .write()
+                            .modifier_utilisateur_actuelle(Some(User::new(session, username.to_string())));
                         navigator.replace(Route::SideBar {
                             room_id: OpRoomId::new_empty(),
                         });
                     }
-                    _ => error.set(Some(response_data["reason"].as_str().unwrap().to_string())),
+                    Err(e) => error.set(Some(e.reason)),
                 }
             }
             Err(_) => error.set(Some(String::from("Perte de connection"))),
@@ -3,9 +3,12 @@
 //! Ce module implémente un gestionnaire d'événements (MyEventSource) qui se connecte à une source distante
 //! via EventSource et écoute les événements pour mettre à jour l'état de l'application côté client.
 
-use lib::{EventMessage, Message, Room};
+use std::cell::Cell;
+use std::rc::Rc;
+
+use lib::EventMessage;
 use wasm_bindgen::{closure::Closure, JsCast};
-use web_sys::{Event, EventSource, MessageEvent};
+use web_sys::{Event, EventSource, EventSourceInit, MessageEvent};
 
 use crate::{async_state::AsyncStateSetter, BASE_API_URL};
 
@@ -28,24 +31,34 @@ impl MyEventSource {
         self.source.close();
     }
 
+    /// Ouvre l'Event Source
+    ///
+    /// `dernier_evenement` contient l'identifiant du dernier événement reçu: s'il y en a un,
+    /// le serveur n'envoie que les événements manqués depuis.
+    /// L'utilisateur est reconnu grâce au cookie de session posé à la connexion.
     pub fn new(
-        user_id: i64,
-        api_key: &str,
-        message_sender: &AsyncStateSetter<Message>,
-        room_sender: &AsyncStateSetter<Room>,
+        dernier_evenement: &Rc<Cell<Option<i64>>>,
+        event_sender: &AsyncStateSetter<EventMessage>,
         source_state_sender: &AsyncStateSetter<SourceState>,
     ) -> MyEventSource {
         source_state_sender.set_state(SourceState::ReConnecting);
 
-        let message_url = format!("{BASE_API_URL}/events/{}?api_key={}", user_id, api_key);
+        let message_url = match dernier_evenement.get() {
+            Some(since) => format!("{BASE_API_URL}/events?since={}", since),
+            None => format!("{BASE_API_URL}/events"),
+        };
 
         let open = source_state_sender.clone();
         let error = source_state_sender.clone();
-        let message_sender_thread = message_sender.clone();
-        let room_sender_thread = room_sender.clone();
+        let event_sender_thread = event_sender.clone();
+        let dernier_evenement = dernier_evenement.clone();
 
         let source = MyEventSource {
-            source: EventSource::new(message_url.as_str()).unwrap(),
+            source: EventSource::new_with_event_source_init_dict(
+                message_url.as_str(),
+                EventSourceInit::new().with_credentials(true),
+            )
+            .unwrap(),
             open_function: Closure::wrap(
                 Box::new(move || open.set_state(SourceState::Connected)) as Box<dyn FnMut()>
             ),
@@ -53,12 +66,13 @@ impl MyEventSource {
                 Box::new(move |_| error.set_state(SourceState::Error)) as Box<dyn FnMut(Event)>
             ),
             message_function: Closure::wrap(Box::new(move |event: MessageEvent| {
-                let value = json::parse(event.data().as_string().unwrap().as_str()).unwrap();
-
-                match EventMessage::parse(&value) {
-                    Ok(EventMessage::Room(room)) => room_sender_thread.set_state(room),
-                    Ok(EventMessage::Message(message)) => message_sender_thread.set_state(message),
-                    Err(s) => panic!("{s}"),
+                if let Ok(id) = event.last_event_id().parse::<i64>() {
+                    dernier_evenement.set(Some(id));
+                }
+                // Un événement illisible (d'un type inconnu par exemple) est ignoré
+                let data = event.data().as_string().unwrap_or_default();
+                if let Ok(event) = EventMessage::parse(data.as_str()) {
+                    event_sender_thread.set_state(event);
                 }
             }) as Box<dyn FnMut(MessageEvent)>),
         };
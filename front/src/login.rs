@@ -4,7 +4,7 @@
 
 use dioxus::prelude::*;
 use dioxus_router::prelude::{use_navigator, Link, Navigator};
-use lib::serialize_login;
+use lib::{ApiResponse, FormAddUser, Session};
 
 use crate::async_state::AsyncStateSetter;
 use crate::room::OpRoomId;
@@ -66,6 +66,10 @@ pub fn LogIn(cx: Scope) -> Element {
                 to: Route::CreateUser{},
                 "Création d'utilisateur"
             }
+            Link{
+                to: Route::Reinitialisation{},
+                "Mot de passe oublié"
+            }
         }
     }
 }
@@ -87,27 +91,31 @@ fn login<T>(
         error.set(Some(String::from("Il faut au moins une lettre dans le mot de passe")));
         return;
     }
-    let form = serialize_login(username.to_string(), password.to_string());
+    let form = FormAddUser {
+        username: username.to_string(),
+        password: password.to_string(),
+    };
 
     let url = format!("{BASE_API_URL}/login");
     cx.spawn(async move {
-        match reqwest::Client::new().post(url).form(&form).send().await {
+        match reqwest::Client::new()
+            .post(url)
+            .form(&form)
+            // Pour que le navigateur garde le cookie de session (Event Source et pièces jointes)
+            .fetch_credentials_include()
+            .send()
+            .await
+        {
             Ok(response) => {
-                let status = response.status().as_u16();
                 let response_body = response.text().await.unwrap();
-                let response_data = json::parse(response_body.as_str()).unwrap();
-                match status {
-                    202 => {
-                        userSetter.set_state(Some(User {
-                            id: response_data["user_id"].as_i64().unwrap(),
-                            username: username.to_string(),
-                            api_key: response_data["api_key"].as_str().unwrap().to_string(),
-                        }));
+                match Session::parse(response_body.as_str()) {
+                    Ok(session) => {
+                        userSetter.set_state(Some(User::new(session, username.to_string())));
                         navigator.replace(Route::SideBar {
                             room_id: OpRoomId::new_empty(),
                         });
                     }
-                    _ => error.set(Some(response_data["reason"].as_str().unwrap().to_string())),
+                    Err(e) => error.set(Some(e.reason)),
                 }
             }
             Err(_) => error.set(Some(String::from("Perte de connection"))),
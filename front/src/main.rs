@@ -5,6 +5,7 @@
 
 #![allow(non_snake_case)]
 
+mod account;
 mod account_manager;
 mod async_state;
 mod messages;
@@ -12,7 +13,14 @@ mod create_user;
 mod event_source;
 mod home;
 mod login;
+mod members;
+mod mentions;
+mod pins;
+mod reset_password;
 mod room;
+mod scheduled;
+mod search;
+mod settings;
 mod side_bar;
 mod structs;
 
@@ -20,17 +28,23 @@ pub const BASE_API_URL: &'static str = "http://192.168.137.1:8000";
 
 use dioxus::prelude::*;
 use dioxus_router::prelude::*;
-use lib::{Message, Room};
+use chrono::Utc;
+use lib::{EventMessage, UserPresence};
 use room::{OpRoomId, RoomData};
 use std::collections::HashMap;
 
-use crate::account_manager::AccountManager;
+use crate::account::Compte;
+use crate::account_manager::{rafraichit_session, AccountManager};
 use crate::async_state::AsyncStateSetter;
 use crate::messages::Conv;
 use crate::create_user::CreateUser;
 use crate::event_source::SourceState;
 use crate::home::Home;
 use crate::login::LogIn;
+use crate::mentions::BoiteMentions;
+use crate::reset_password::Reinitialisation;
+use crate::scheduled::MessagesProgrammes;
+use crate::search::MessageCible;
 use crate::side_bar::SideBar;
 
 #[derive(Routable, Clone)]
@@ -42,6 +56,10 @@ pub enum Route {
     LogIn {},
     #[route("/create-user")]
     CreateUser {},
+    #[route("/reset-password")]
+    Reinitialisation {},
+    #[route("/account")]
+    Compte {},
     #[route("/:room_id")]
     Conv { room_id: i64 },
     #[route("/rooms?:room_id")]
@@ -50,49 +68,49 @@ pub enum Route {
     PageNotFound { route: Vec<String> },
 }
 
-pub struct Rooms(HashMap<i64, RoomData>);
+/// Salons de l'utilisateur, présence de ses contacts et sa boîte de mentions
+pub struct Rooms(HashMap<i64, RoomData>, HashMap<i64, UserPresence>, BoiteMentions);
 pub struct Users(HashMap<i64, Option<String>>);
 
 fn window(cx: Scope) -> Element {
     let _ = use_shared_state_provider::<Rooms>(cx, || Rooms {
         0: HashMap::<i64, RoomData>::new(),
+        1: HashMap::<i64, UserPresence>::new(),
+        2: BoiteMentions::default(),
     });
     let _ = use_shared_state_provider::<Users>(cx, || Users {
         0: HashMap::<i64, Option<String>>::new(),
     });
     let _ = use_shared_state_provider::<SourceState>(cx, || SourceState::Error);
+    let _ = use_shared_state_provider::<MessageCible>(cx, || MessageCible(None));
+    let _ = use_shared_state_provider::<MessagesProgrammes>(cx, || MessagesProgrammes(None));
 
     let rooms = use_shared_state::<Rooms>(cx).unwrap();
     let source_state = use_shared_state::<SourceState>(cx).unwrap();
 
-    let message_sender = AsyncStateSetter::<Message>::new(cx, rooms, |rooms, message| {
-        let mut rooms = rooms.write();
-
-        match rooms.0.get_mut(&message.room_id) {
-            None => panic!("message add room_id:{} doesn't exists", message.room_id),
-            Some(room) => room.messages.push(message),
-        }
-    });
-
-    let room_sender = AsyncStateSetter::<Room>::new(cx, rooms, |rooms, room| {
-        rooms.write().0.insert(
-            room.id,
-            RoomData {
-                name: room.name,
-                messages: Vec::new(),
-            },
-        );
-    });
+    let event_sender = AsyncStateSetter::<EventMessage>::new(cx, rooms, recoit_evenement);
 
     let source_state_sender =
         AsyncStateSetter::<SourceState>::new(cx, source_state, |source_state, state| {
             *source_state.write() = state
         });
     let _ = use_shared_state_provider::<AccountManager>(cx, move || {
-        AccountManager::new(message_sender, room_sender, source_state_sender)
+        AccountManager::new(event_sender, source_state_sender)
     });
 
     let account_manager = use_shared_state::<AccountManager>(cx).unwrap();
+    let echeance = account_manager.read().echeance_rafraichissement();
+    // Relancé à chaque nouvelle échéance (connexion, rafraîchissement, échec)
+    use_future(cx, (&echeance,), |(echeance,)| {
+        let account_manager = account_manager.to_owned();
+        async move {
+            if let Some(echeance) = echeance {
+                let attente = (echeance - Utc::now()).to_std().unwrap_or_default();
+                async_std::task::sleep(attente).await;
+                rafraichit_session(account_manager).await;
+            }
+        }
+    });
     if account_manager.read().utilisateur_actuelle().is_some() {
         match *source_state.read() {
             SourceState::Error => account_manager.write().nouvelle_tentative_de_connection(),
@@ -108,6 +126,91 @@ fn window(cx: Scope) -> Element {
     }
 }
 
+/// Applique un événement de l'Event Source sur les salons et les présences
+fn recoit_evenement(rooms: &UseSharedState<Rooms>, event: EventMessage) {
+    let mut rooms = rooms.write();
+
+    match event {
+        EventMessage::Room(room) => {
+            rooms.0.insert(room.id, RoomData::new(room));
+        }
+        // Le message d'un salon inconnu (quitté entre-temps) est ignoré
+        EventMessage::Message(message) => {
+            if let Some(room) = rooms.0.get_mut(&message.room_id) {
+                room.ecrivent.retain(|user_id| *user_id != message.user_id);
+                room.ajoute_message(message)
+            }
+        }
+        EventMessage::MessageEdit(message) => {
+            rooms.2.modifie(&message);
+            if let Some(room) = rooms.0.get_mut(&message.room_id) {
+                room.modifie_message(message);
+            }
+        }
+        EventMessage::MessageDelete(deleted) => {
+            rooms.2.supprime(deleted.id);
+            if let Some(room) = rooms.0.get_mut(&deleted.room_id) {
+                room.supprime_message(deleted.id);
+            }
+        }
+        EventMessage::Thread(thread) => {
+            if let Some(room) = rooms.0.get_mut(&thread.room_id) {
+                room.met_a_jour_fil(thread);
+            }
+        }
+        EventMessage::Reaction(reaction) => {
+            if let Some(room) = rooms.0.get_mut(&reaction.room_id) {
+                room.met_a_jour_reaction(reaction);
+            }
+        }
+        EventMessage::ReadReceipt(lecture) => {
+            if let Some(room) = rooms.0.get_mut(&lecture.room_id) {
+                room.met_a_jour_lecture(lecture);
+            }
+        }
+        EventMessage::Typing(typing) => {
+            if let Some(room) = rooms.0.get_mut(&typing.room_id) {
+                room.met_a_jour_ecriture(typing);
+            }
+        }
+        EventMessage::Presence(presence) => {
+            rooms.1.insert(presence.user_id, presence);
+        }
+        EventMessage::Member(member) => {
+            if let Some(room) = rooms.0.get_mut(&member.room_id) {
+                room.membres.insert(member.user_id, member.role);
+            }
+        }
+        EventMessage::MemberRemove(removed) => {
+            if let Some(room) = rooms.0.get_mut(&removed.room_id) {
+                room.membres.remove(&removed.user_id);
+            }
+        }
+        EventMessage::RoomRemove(removed) => {
+            rooms.0.remove(&removed.room_id);
+            rooms.2.messages.retain(|m| m.room_id != removed.room_id);
+        }
+        EventMessage::Mention(message) => rooms.2.ajoute(message),
+        EventMessage::Pin(pin) => {
+            if let Some(room) = rooms.0.get_mut(&pin.room_id) {
+                room.ajoute_epingle(pin);
+            }
+        }
+        EventMessage::Unpin(unpin) => {
+            if let Some(room) = rooms.0.get_mut(&unpin.room_id) {
+                room.retire_epingle(unpin.message_id);
+            }
+        }
+        EventMessage::RoomUpdate(settings) => {
+            if let Some(room) = rooms.0.get_mut(&settings.room_id) {
+                room.name = settings.name;
+                room.topic = settings.topic;
+                room.retention = settings.retention;
+            }
+        }
+    }
+}
+
 #[inline_props]
 fn PageNotFound(cx: Scope, route: Vec<String>) -> Element {
     render! {
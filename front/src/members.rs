@@ -0,0 +1,147 @@
+//! Module des membres d'un salon
+//!
+//! Ce module implémente la liste des membres d'un salon avec leur rôle et leur présence.
+//! Les administrateurs et le propriétaire peuvent y expulser, bannir, promouvoir
+//! ou rétrograder les membres de rang inférieur.
+
+use dioxus::prelude::*;
+use lib::{ApiError, FormMember, FormRole, RoomRole};
+
+use crate::async_state::AsyncStateSetter;
+use crate::messages::{classe_presence, nom_utilisateur};
+use crate::{AccountManager, Rooms, Users, BASE_API_URL};
+
+#[inline_props]
+pub fn Membres(cx: Scope, room_id: i64) -> Element {
+    let account_manager = use_shared_state::<AccountManager>(cx).unwrap();
+    let rooms = use_shared_state::<Rooms>(cx).unwrap();
+    let users = use_shared_state::<Users>(cx).unwrap();
+    let error = use_state::<Option<String>>(cx, || None);
+
+    let users_setter =
+        AsyncStateSetter::<(i64, String)>::new(cx, users, move |users, (user_id, username)| {
+            users.write().0.insert(user_id, Some(username));
+        });
+
+    let user_id = match account_manager.read().utilisateur_actuelle() {
+        Some(user) => user.id,
+        None => return render! {div{}},
+    };
+    let membres: Vec<(i64, String, &'static str, RoomRole, bool)> = {
+        let rooms = rooms.read();
+        let membres = match rooms.0.get(room_id) {
+            Some(room_data) => &room_data.membres,
+            None => return render! {div{}},
+        };
+        let mon_role = membres.get(&user_id).copied().unwrap_or(RoomRole::Member);
+        let mut membres: Vec<(i64, String, &'static str, RoomRole, bool)> = membres
+            .iter()
+            .map(|(membre_id, role)| {
+                (
+                    *membre_id,
+                    nom_utilisateur(cx, users, &users_setter, *membre_id),
+                    classe_presence(&rooms, *membre_id),
+                    *role,
+                    mon_role >= RoomRole::Admin && *role < mon_role,
+                )
+            })
+            .collect();
+        membres.sort_by(|a, b| b.3.partial_cmp(&a.3).unwrap().then(a.1.cmp(&b.1)));
+        membres
+    };
+
+    render! {
+        div{
+            id: "members",
+            ul{
+                for (membre_id, username, presence, role, gere) in membres.into_iter() {
+                    li{
+                        class: "member",
+                        span{ class: presence }
+                        span{ class: "member-name", username }
+                        span{
+                            class: "member-role",
+                            match role {
+                                RoomRole::Owner => "propriétaire",
+                                RoomRole::Admin => "admin",
+                                RoomRole::Member => "",
+                            }
+                        }
+                        match gere {
+                            true => render!{
+                                span{
+                                    class: "member-actions",
+                                    button {
+                                        prevent_default: "onclick",
+                                        onclick: move |_| {
+                                            let role = match role {
+                                                RoomRole::Member => RoomRole::Admin,
+                                                _ => RoomRole::Member,
+                                            };
+                                            gere_membre(cx, account_manager.to_owned(), *room_id, "role", membre_id, Some(role), error.to_owned())
+                                        },
+                                        match role {
+                                            RoomRole::Member => "Promouvoir",
+                                            _ => "Rétrograder",
+                                        }
+                                    }
+                                    button {
+                                        prevent_default: "onclick",
+                                        onclick: move |_| gere_membre(cx, account_manager.to_owned(), *room_id, "kick", membre_id, None, error.to_owned()),
+                                        "Expulser"
+                                    }
+                                    button {
+                                        prevent_default: "onclick",
+                                        onclick: move |_| gere_membre(cx, account_manager.to_owned(), *room_id, "ban", membre_id, None, error.to_owned()),
+                                        "Bannir"
+                                    }
+                                }
+                            },
+                            false => render!{span{}}
+                        }
+                    }
+                }
+            }
+            match error.as_ref() {
+                Some(e) => render!{span{class:"Error",e.as_str()}},
+                None => render!{span{}}
+            }
+        }
+    }
+}
+
+/// Expulse, bannit (`action` `kick` ou `ban`) ou change le rôle (`action` `role`) d'un membre
+fn gere_membre<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    room_id: i64,
+    action: &'static str,
+    other_user_id: i64,
+    role: Option<RoomRole>,
+    error: UseState<Option<String>>,
+) {
+    let url = format!("{BASE_API_URL}/room/{}/{}", room_id, action);
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+    let request = reqwest::Client::new().post(&url).bearer_auth(&token);
+    let request = match role {
+        Some(role) => request.form(&FormRole {
+            other_user_id,
+            role: role.as_str().to_string(),
+        }),
+        None => request.form(&FormMember { other_user_id }),
+    };
+
+    cx.spawn(async move {
+        match request.send().await {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                let response_body = response.text().await.unwrap();
+                match status {
+                    200 => error.set(None),
+                    _ => error.set(Some(ApiError::reason_of(response_body.as_str()))),
+                }
+            }
+            Err(_) => error.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
@@ -0,0 +1,166 @@
+//! Module de la boîte de mentions
+//!
+//! Ce module implémente la liste des messages où l'utilisateur est mentionné (`@username`),
+//! affichée dans la barre latérale. Les mentions reçues par l'Event Source y sont ajoutées
+//! et comptées jusqu'à l'ouverture de la liste, qui récupère la boîte complète sur le serveur.
+
+use chrono::Local;
+use dioxus::prelude::*;
+use dioxus_router::prelude::*;
+use lib::{ApiResponse, Message, MessagePage};
+
+use crate::async_state::AsyncStateSetter;
+use crate::messages::{morceaux_texte, nom_utilisateur};
+use crate::search::MessageCible;
+use crate::{AccountManager, Rooms, Route, Users, BASE_API_URL};
+
+/// Messages où l'utilisateur est mentionné, du plus récent au plus ancien
+#[derive(Debug, Default)]
+pub struct BoiteMentions {
+    pub messages: Vec<Message>,
+    /// Mentions reçues depuis la dernière ouverture de la boîte
+    pub non_vues: i64,
+}
+
+impl BoiteMentions {
+    /// Ajoute une mention reçue
+    pub fn ajoute(&mut self, message: Message) {
+        if !self.messages.iter().any(|m| m.id == message.id) {
+            self.messages.insert(0, message);
+            self.non_vues += 1;
+        }
+    }
+
+    /// Modifie le texte d'un message mentionnant l'utilisateur
+    pub fn modifie(&mut self, message: &Message) {
+        if let Some(mention) = self.messages.iter_mut().find(|m| m.id == message.id) {
+            mention.text = message.text.to_string();
+        }
+    }
+
+    /// Retire un message supprimé et ses réponses
+    pub fn supprime(&mut self, message_id: i64) {
+        self.messages
+            .retain(|m| m.id != message_id && m.parent_id != Some(message_id));
+    }
+}
+
+pub fn Mentions(cx: Scope) -> Element {
+    let account_manager = use_shared_state::<AccountManager>(cx).unwrap();
+    let rooms = use_shared_state::<Rooms>(cx).unwrap();
+    let users = use_shared_state::<Users>(cx).unwrap();
+    let cible = use_shared_state::<MessageCible>(cx).unwrap();
+    let navigator = use_navigator(cx);
+    let ouvert = use_state(cx, || false);
+
+    let users_setter =
+        AsyncStateSetter::<(i64, String)>::new(cx, users, move |users, (user_id, username)| {
+            users.write().0.insert(user_id, Some(username));
+        });
+
+    let user_id = match account_manager.read().utilisateur_actuelle() {
+        Some(user) => user.id,
+        None => return render! {div{}},
+    };
+    let (non_vues, mentions) = {
+        let rooms = rooms.read();
+        let mentions: Vec<(i64, i64, String, Vec<(String, &'static str)>)> = rooms
+            .2
+            .messages
+            .iter()
+            .map(|message| {
+                let salon = rooms
+                    .0
+                    .get(&message.room_id)
+                    .map_or(String::new(), |room_data| room_data.titre(user_id, &rooms.1));
+                (
+                    message.room_id,
+                    message.parent_id.unwrap_or(message.id),
+                    format!(
+                        "{} · {} · {}",
+                        salon,
+                        nom_utilisateur(cx, users, &users_setter, message.user_id),
+                        message.date.with_timezone(&Local).format("%d/%m/%Y %H:%M")
+                    ),
+                    morceaux_texte(&account_manager.read(), message.text.as_str()),
+                )
+            })
+            .collect();
+        (rooms.2.non_vues, mentions)
+    };
+
+    render! {
+        div{
+            id: "mentions",
+            button {
+                prevent_default: "onclick",
+                onclick: move |_| {
+                    if !*ouvert.get() {
+                        charge_mentions(cx, account_manager.to_owned(), rooms.to_owned());
+                    }
+                    ouvert.set(!*ouvert.get());
+                },
+                "Mentions"
+                match non_vues {
+                    0 => render!{span{}},
+                    _ => render!{span{ class: "unread", "{non_vues}" }}
+                }
+            }
+            match *ouvert.get() {
+                true => render!{
+                    ul {
+                        id: "mention-list",
+                        match mentions.is_empty() {
+                            true => render!{li{ "Aucune mention" }},
+                            false => render!{span{}}
+                        }
+                        for (salon_id, message_id, entete, morceaux) in mentions {
+                            li {
+                                class: "search-result",
+                                onclick: move |_| {
+                                    cible.write().0 = Some((salon_id, message_id));
+                                    navigator.push(Route::Conv { room_id: salon_id });
+                                },
+                                div { class: "search-meta", "{entete}" }
+                                div {
+                                    for (morceau, classe) in morceaux {
+                                        span{ class: classe, "{morceau}" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                false => render!{span{}}
+            }
+        }
+    }
+}
+
+/// Récupère la boîte de mentions sur le serveur et la marque comme vue
+fn charge_mentions<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    rooms: UseSharedState<Rooms>,
+) {
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+
+    let url = format!("{BASE_API_URL}/mentions");
+    cx.spawn(async move {
+        let messages = match reqwest::Client::new().get(&url).bearer_auth(&token).send().await {
+            Ok(response) => {
+                let response_body = response.text().await.unwrap();
+                MessagePage::parse(response_body.as_str())
+                    .ok()
+                    .map(|page| page.messages)
+            }
+            Err(_) => None,
+        };
+
+        let mut lock = rooms.write();
+        if let Some(messages) = messages {
+            lock.2.messages = messages;
+        }
+        lock.2.non_vues = 0;
+    });
+}
@@ -2,21 +2,31 @@
 //!
 //! Ce module implémente la page pour que les utilisateurs puissent ce communiquez dans des salons.
 
-use chrono::Local;
+use chrono::{Local, Utc};
 use dioxus::prelude::*;
 use dioxus_router::prelude::use_navigator;
 use dioxus_router::prelude::Link;
-use lib::Message;
-use std::collections::HashMap;
+use lib::{
+    split_mentions, ApiError, ApiResponse, Attachment, CreatedAttachment, FormAddUserRoom,
+    FormEditMessage, FormMessage, FormReaction, FormRead, Message, MessagePage, PresenceStatus,
+    Replies, RoomKind, UserProfile,
+};
+use dioxus::html::FileEngine;
+use reqwest::multipart::Part;
+use std::sync::Arc;
 
 use crate::async_state::AsyncStateSetter;
+use crate::members::Membres;
+use crate::pins::{toggle_epingle, Epingles};
 use crate::room::OpRoomId;
+use crate::scheduled::{charge_programmes, timestamp_date_heure, MessagesProgrammes, Programmes};
+use crate::search::MessageCible;
+use crate::settings::Reglages;
 use crate::side_bar::SideBar;
 use crate::Rooms;
 use crate::Route;
 use crate::BASE_API_URL;
 use crate::{AccountManager, Users};
-use lib::serialize_message;
 
 #[inline_props]
 pub fn Conv(cx: Scope, room_id: i64) -> Element {
@@ -29,19 +39,130 @@ pub fn Conv(cx: Scope, room_id: i64) -> Element {
     }
 
     let rooms = use_shared_state::<Rooms>(cx).unwrap();
+    let programmes = use_shared_state::<MessagesProgrammes>(cx).unwrap();
 
     let room_data = rooms.read();
-    let room_data = room_data.0.get(room_id).unwrap();
+    let room_data = match room_data.0.get(room_id) {
+        Some(room_data) => room_data,
+        None => {
+            // L'utilisateur a été retiré du salon
+            navigator.replace(Route::SideBar {
+                room_id: OpRoomId::new_empty(),
+            });
+            return render! {div{}};
+        }
+    };
 
     let username = use_state(cx, || String::new());
     let message = use_state(cx, || String::new());
     let error_invite = use_state::<Option<String>>(cx, || None);
     let error_message = use_state::<Option<String>>(cx, || None);
+    let editing = use_state::<Option<i64>>(cx, || None);
+    let thread = use_state::<Option<i64>>(cx, || None);
+    let dernier_signal_ecriture = use_state::<i64>(cx, || 0);
+    let pieces_jointes = use_state::<Vec<Attachment>>(cx, || Vec::new());
+    let envoi_programme = use_state(cx, || String::new());
+    let duree_vie = use_state(cx, || String::new());
+    let history_label = match room_data.chargement {
+        true => "Chargement",
+        false => "Messages précédents",
+    };
+    let send_label = match (editing.get(), thread.get()) {
+        (Some(_), _) => "Modifier",
+        (None, _) if !envoi_programme.is_empty() => "Programmer",
+        (None, Some(_)) => "Répondre",
+        (None, None) => "Envoyer",
+    };
+    let user_id = account_manager.read().utilisateur_actuelle().unwrap().id;
+    let titre = room_data.titre(user_id, &rooms.read().1);
+    let vus = room_data.vu_par(user_id);
+    let dernier_message = room_data.messages.last().map(|m| m.id);
+    let deja_lu = dernier_message.map_or(true, |dernier_message| {
+        room_data.lectures.get(&user_id).copied().unwrap_or(0) >= dernier_message
+    });
+    use_effect(
+        cx,
+        (room_id, &dernier_message, &deja_lu),
+        |(room_id, dernier_message, deja_lu)| {
+            to_owned![account_manager];
+            async move {
+                if let (Some(message_id), false) = (dernier_message, deja_lu) {
+                    marque_lu(account_manager, room_id, message_id).await;
+                }
+            }
+        },
+    );
+    let cible = use_shared_state::<MessageCible>(cx).unwrap();
+    let cible_id = match cible.read().0 {
+        Some((cible_room_id, message_id)) if cible_room_id == *room_id => Some(message_id),
+        _ => None,
+    };
+    let cible_chargee =
+        cible_id.map_or(false, |cible_id| room_data.messages.iter().any(|m| m.id == cible_id));
+    let plus_ancien = room_data.messages.first().map(|m| m.id);
+    use_effect(
+        cx,
+        (room_id, &cible_id, &cible_chargee, &room_data.has_more, &plus_ancien),
+        |(room_id, cible_id, cible_chargee, has_more, _)| {
+            to_owned![account_manager, rooms, cible];
+            async move {
+                match (cible_id, cible_chargee, has_more) {
+                    (Some(cible_id), true, _) => {
+                        if let Some(element) = web_sys::window()
+                            .and_then(|window| window.document())
+                            .and_then(|document| {
+                                document.get_element_by_id(format!("message-{cible_id}").as_str())
+                            })
+                        {
+                            element.scroll_into_view();
+                        }
+                        cible.write().0 = None;
+                    }
+                    // Le message est plus ancien que ceux chargés
+                    (Some(_), false, true) => charge_page(account_manager, rooms, room_id).await,
+                    (Some(_), false, false) => cible.write().0 = None,
+                    (None, _, _) => {}
+                }
+            }
+        },
+    );
+    let ecrivent = {
+        let users = use_shared_state::<Users>(cx).unwrap();
+        let users = users.read();
+        let noms: Vec<String> = room_data
+            .ecrivent
+            .iter()
+            .map(|user_id| match users.0.get(user_id) {
+                Some(Some(username)) => username.to_string(),
+                _ => String::from("Quelqu'un"),
+            })
+            .collect();
+        match noms.len() {
+            0 => None,
+            1 => Some(format!("{} est en train d'écrire…", noms[0])),
+            _ => Some(format!("{} sont en train d'écrire…", noms.join(", "))),
+        }
+    };
+    let thread_parent = thread
+        .get()
+        .and_then(|parent_id| room_data.messages.iter().find(|m| m.id == parent_id));
+    let thread_reponses = thread
+        .get()
+        .and_then(|parent_id| room_data.reponses.get(&parent_id));
 
     render! {
         SideBar{room_id: OpRoomId::from(*room_id) }
         div{
             id:"conv",
+            onscroll: move |_| {
+                let en_haut = web_sys::window()
+                    .and_then(|window| window.document())
+                    .and_then(|document| document.get_element_by_id("conv"))
+                    .map_or(false, |conv| conv.scroll_top() <= 0);
+                if en_haut {
+                    charge_historique(cx, account_manager.to_owned(), rooms.to_owned(), *room_id);
+                }
+            },
             div{
                 id: "convHeader",
                 Link{
@@ -49,47 +170,109 @@ pub fn Conv(cx: Scope, room_id: i64) -> Element {
                     "<"
                 }
 
-                span { room_data.name.as_str() }
+                span { "{titre}" }
+                span { class: "topic", room_data.topic.as_str() }
+                Epingles{ room_id: *room_id }
             }
-            match error_invite.as_ref() {
-                Some(e) => render!{span{class:"Error",e.as_str()}},
-                None => render!{span{}}
-            }
-            form {
-                id: "invite",
-                input {
-                    r#type: "text",
-                    name: "username",
-                    id: "username",
-                    autocomplete: "off",
-                    placeholder: "Envoyer une invitation...",
-                    autofocus: true,
-                    oninput: move |evt| username.set(evt.value.clone()),
-                    value: "{username}"
-                }
-                button {
-                    id: "send",
-                    prevent_default: "onclick",
-                    onclick: move |_| send_invite(cx, account_manager.to_owned(), username.to_owned(), room_id, error_invite.to_owned()),
-                    "Envoyer"
-                }
+            match room_data.kind {
+                RoomKind::Group => render!{
+                    Reglages{ room_id: *room_id }
+                    Membres{ room_id: *room_id }
+                    match error_invite.as_ref() {
+                        Some(e) => render!{span{class:"Error",e.as_str()}},
+                        None => render!{span{}}
+                    }
+                    form {
+                        id: "invite",
+                        input {
+                            r#type: "text",
+                            name: "username",
+                            id: "username",
+                            autocomplete: "off",
+                            placeholder: "Envoyer une invitation...",
+                            autofocus: true,
+                            oninput: move |evt| username.set(evt.value.clone()),
+                            value: "{username}"
+                        }
+                        button {
+                            id: "send",
+                            prevent_default: "onclick",
+                            onclick: move |_| send_invite(cx, account_manager.to_owned(), username.to_owned(), room_id, error_invite.to_owned()),
+                            "Envoyer"
+                        }
+                    }
+                },
+                RoomKind::Direct => render!{span{}}
             }
 
             div{
                 id: "messages",
+                match room_data.has_more {
+                    true => render!{
+                        button {
+                            id: "history",
+                            prevent_default: "onclick",
+                            onclick: move |_| charge_historique(cx, account_manager.to_owned(), rooms.to_owned(), *room_id),
+                            history_label
+                        }
+                    },
+                    false => render!{div{}}
+                }
                 match room_data.messages.is_empty() {
                     true => render!{div{}},
                     false => render!{
                         for msg in room_data.messages.iter() {
-                            message_element(cx, msg)
+                            message_element(cx, msg, vus.get(&msg.id), editing, thread, message, error_message)
                         }
                     },
                 }
             }
+            match thread_parent {
+                Some(parent) => render!{
+                    div{
+                        id: "thread",
+                        div{
+                            id: "threadHeader",
+                            span { "Fil de discussion" }
+                            button {
+                                prevent_default: "onclick",
+                                onclick: move |_| thread.set(None),
+                                "Fermer"
+                            }
+                        }
+                        message_element(cx, parent, None, editing, thread, message, error_message)
+                        match thread_reponses {
+                            Some(reponses) => render!{
+                                for msg in reponses.iter() {
+                                    message_element(cx, msg, None, editing, thread, message, error_message)
+                                }
+                            },
+                            None => render!{span{ "Chargement" }}
+                        }
+                    }
+                },
+                None => render!{div{}}
+            }
             match error_message.as_ref() {
                 Some(e) => render!{span{class:"Error",e.as_str()}},
                 None => render!{span{}}
             }
+            match ecrivent {
+                Some(ecrivent) => render!{span{ id: "typing", ecrivent }},
+                None => render!{span{}}
+            }
+            match pieces_jointes.is_empty() {
+                true => render!{span{}},
+                false => render!{
+                    div{
+                        id: "pending-attachments",
+                        for attachment in pieces_jointes.iter() {
+                            span{ class: "attachment-pending", "📎 {attachment.name}" }
+                        }
+                    }
+                },
+            }
+            Programmes{ room_id: *room_id }
             form {
                 id: "new-message",
                 input {
@@ -100,58 +283,430 @@ pub fn Conv(cx: Scope, room_id: i64) -> Element {
                     autocomplete: "off",
                     placeholder: "Envoyer un message...",
                     autofocus: true,
-                    oninput: move |evt| message.set(evt.value.clone()),
+                    oninput: move |evt| {
+                        message.set(evt.value.clone());
+                        signale_ecriture(cx, account_manager.to_owned(), *room_id, dernier_signal_ecriture.to_owned());
+                    },
                     value: "{message}"
                 }
+                input {
+                    r#type: "file",
+                    id: "attachment",
+                    multiple: true,
+                    onchange: move |evt| envoie_pieces_jointes(cx, account_manager.to_owned(), *room_id, evt.files.clone(), pieces_jointes.to_owned(), error_message.to_owned()),
+                }
+                select {
+                    id: "ttl",
+                    title: "Message éphémère",
+                    onchange: move |evt| duree_vie.set(evt.value.clone()),
+                    for (duree, libelle) in DUREES_VIE {
+                        option {
+                            value: "{duree}",
+                            selected: duree_vie.get() == duree,
+                            "{libelle}"
+                        }
+                    }
+                }
+                input {
+                    r#type: "datetime-local",
+                    id: "send-at",
+                    title: "Programmer l'envoi",
+                    oninput: move |evt| envoi_programme.set(evt.value.clone()),
+                    value: "{envoi_programme}"
+                }
+                match editing.get() {
+                    Some(_) => render!{
+                        button {
+                            id: "cancel",
+                            prevent_default: "onclick",
+                            onclick: move |_| {
+                                editing.set(None);
+                                message.set(String::new());
+                            },
+                            "Annuler"
+                        }
+                    },
+                    None => render!{span{}}
+                }
                 button {
                     id: "send",
                     prevent_default: "onclick",
-                    onclick: move |_| send_message(cx, account_manager.to_owned(), message.to_owned(), room_id, error_message.to_owned()),
-                    "Envoyer"
+                    onclick: move |_| match *editing.get() {
+                        Some(message_id) => edit_message(cx, account_manager.to_owned(), message.to_owned(), message_id, editing.to_owned(), error_message.to_owned()),
+                        None => send_message(cx, account_manager.to_owned(), programmes.to_owned(), message.to_owned(), room_id, *thread.get(), pieces_jointes.to_owned(), envoi_programme.to_owned(), duree_vie.to_owned(), error_message.to_owned()),
+                    },
+                    send_label
                 }
             }
         }
     }
 }
 
+/// Récupère la page de messages précédant le plus ancien message affiché
+fn charge_historique<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    rooms: UseSharedState<Rooms>,
+    room_id: i64,
+) {
+    cx.spawn(charge_page(account_manager, rooms, room_id));
+}
+
+/// Charge la page de messages précédant le plus ancien message chargé d'un salon
+async fn charge_page(
+    account_manager: UseSharedState<AccountManager>,
+    rooms: UseSharedState<Rooms>,
+    room_id: i64,
+) {
+    let before = {
+        let mut lock = rooms.write();
+        let room = match lock.0.get_mut(&room_id) {
+            Some(room) => room,
+            None => return,
+        };
+        if room.chargement || !room.has_more {
+            return;
+        }
+        room.chargement = true;
+        room.messages.first().map(|message| message.id)
+    };
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+    let mut query: Vec<(&str, String)> = Vec::new();
+    if let Some(before) = before {
+        query.push(("before", before.to_string()));
+    }
+
+    let url = format!("{BASE_API_URL}/room/{}/messages", room_id);
+    let page = match reqwest::Client::new()
+        .get(&url)
+        .bearer_auth(&token)
+        .query(&query)
+        .send()
+        .await
+    {
+        Ok(response) => {
+            let response_body = response.text().await.unwrap();
+            MessagePage::parse(response_body.as_str())
+                .ok()
+                .map(|page| (page.messages, page.has_more))
+        }
+        Err(_) => None,
+    };
+
+    let mut lock = rooms.write();
+    if let Some(room) = lock.0.get_mut(&room_id) {
+        match page {
+            Some((messages, has_more)) => room.ajoute_historique(messages, has_more),
+            None => room.chargement = false,
+        }
+    }
+}
+
+/// Indique aux autres membres que l'utilisateur écrit (au plus une fois par intervalle)
+fn signale_ecriture<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    room_id: i64,
+    dernier_signal_ecriture: UseState<i64>,
+) {
+    /// Intervalle entre deux signaux, plus court que l'expiration de l'indicateur sur le serveur
+    const INTERVALLE_ECRITURE_MS: i64 = 3000;
+
+    let maintenant = Utc::now().timestamp_millis();
+    if maintenant - *dernier_signal_ecriture.get() < INTERVALLE_ECRITURE_MS {
+        return;
+    }
+    dernier_signal_ecriture.set(maintenant);
+
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+
+    let url = format!("{BASE_API_URL}/room/{}/typing", room_id);
+    cx.spawn(async move {
+        let _ = reqwest::Client::new().post(&url).bearer_auth(&token).send().await;
+    });
+}
+
+/// Indique au serveur que les messages d'un salon sont lus jusqu'à un message
+async fn marque_lu(account_manager: UseSharedState<AccountManager>, room_id: i64, message_id: i64) {
+    let token = match account_manager.read().utilisateur_actuelle() {
+        Some(current_user) => current_user.token.to_string(),
+        None => return,
+    };
+    let form = FormRead { message_id };
+
+    let url = format!("{BASE_API_URL}/room/{}/read", room_id);
+    let _ = reqwest::Client::new()
+        .post(&url)
+        .bearer_auth(&token)
+        .form(&form)
+        .send()
+        .await;
+}
+
+/// Récupère les réponses d'un message et ouvre son fil de discussion
+fn ouvre_fil<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    rooms: UseSharedState<Rooms>,
+    room_id: i64,
+    parent_id: i64,
+    thread: UseState<Option<i64>>,
+) {
+    thread.set(Some(parent_id));
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+
+    let url = format!("{BASE_API_URL}/message/{}/replies", parent_id);
+    cx.spawn(async move {
+        if let Ok(response) = reqwest::Client::new().get(&url).bearer_auth(&token).send().await {
+            let response_body = response.text().await.unwrap();
+            if let Ok(reponses) = Replies::parse(response_body.as_str()) {
+                let mut lock = rooms.write();
+                if let Some(room) = lock.0.get_mut(&room_id) {
+                    room.ajoute_reponses(parent_id, reponses.messages);
+                }
+            }
+        }
+    });
+}
+
+/// Durées de vie proposées pour un message éphémère (en secondes, vide pour un message permanent)
+const DUREES_VIE: [(&str, &str); 4] = [
+    ("", "Permanent"),
+    ("60", "⏳ 1 minute"),
+    ("3600", "⏳ 1 heure"),
+    ("86400", "⏳ 1 jour"),
+];
+
+/// Envoie un message, ou le programme si une date d'envoi est choisie
 fn send_message<T>(
     cx: Scope<T>,
     account_manager: UseSharedState<AccountManager>,
+    programmes: UseSharedState<MessagesProgrammes>,
     message: UseState<String>,
     room_id: &i64,
+    parent_id: Option<i64>,
+    pieces_jointes: UseState<Vec<Attachment>>,
+    envoi_programme: UseState<String>,
+    duree_vie: UseState<String>,
     error_message: UseState<Option<String>>,
 ) {
-    if message.is_empty() {
+    if message.is_empty() && pieces_jointes.is_empty() {
         error_message.set(Some(String::from("Il faut au moins une lettre dans le message")));
         return;
     }
-    let form: HashMap<&str, String> = {
-        let lock = account_manager.read();
-        let current_user = lock.utilisateur_actuelle().unwrap();
-        serialize_message(
-            *room_id,
-            current_user.id,
-            current_user.api_key.to_string(),
-            message.to_string(),
-        )
+    let send_at = match envoi_programme.is_empty() {
+        true => None,
+        false => match timestamp_date_heure(envoi_programme.as_str()) {
+            Some(send_at) => Some(send_at),
+            None => {
+                error_message.set(Some(String::from("Ce n'est pas une date valide.")));
+                return;
+            }
+        },
+    };
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+    let form = FormMessage {
+        room_id: *room_id,
+        text: message.to_string(),
+        parent_id,
+        attachment_ids: pieces_jointes.iter().map(|attachment| attachment.id).collect(),
+        send_at,
+        ttl: duree_vie.parse::<i64>().ok(),
     };
 
     let url = format!("{BASE_API_URL}/message");
     cx.spawn(async move {
-        match reqwest::Client::new().post(&url).form(&form).send().await {
+        match reqwest::Client::new()
+            .post(&url)
+            .bearer_auth(&token)
+            .form(&form.fields())
+            .send()
+            .await
+        {
             Ok(response) => {
                 let status = response.status().as_u16();
                 let response_body = response.text().await.unwrap();
-                let response_data = json::parse(response_body.as_str()).unwrap();
                 match status {
-                    201 => {
-                        account_manager
-                            .write_silent()
-                            .modifier_api_key(response_data["api_key"].as_str().unwrap().to_string());
+                    201 | 202 => {
+                        error_message.set(None);
+                        message.set(String::new());
+                        pieces_jointes.set(Vec::new());
+                        envoi_programme.set(String::new());
+                        if status == 202 {
+                            charge_programmes(account_manager, programmes).await;
+                        }
+                    }
+                    _ => error_message.set(Some(ApiError::reason_of(response_body.as_str()))),
+                }
+            }
+            Err(_) => error_message.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
+
+/// Envoie les fichiers choisis dans un salon, en attendant de les joindre au prochain message
+fn envoie_pieces_jointes<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    room_id: i64,
+    fichiers: Option<Arc<dyn FileEngine>>,
+    pieces_jointes: UseState<Vec<Attachment>>,
+    error_message: UseState<Option<String>>,
+) {
+    let fichiers = match fichiers {
+        Some(fichiers) => fichiers,
+        None => return,
+    };
+
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+
+    let url = format!("{BASE_API_URL}/room/{}/attachment", room_id);
+    cx.spawn(async move {
+        for nom in fichiers.files() {
+            let contenu = match fichiers.read_file(nom.as_str()).await {
+                Some(contenu) => contenu,
+                None => continue,
+            };
+            let fichier = Part::bytes(contenu)
+                .file_name(nom.clone())
+                .mime_str(type_de_fichier(nom.as_str()))
+                .unwrap();
+            let form = reqwest::multipart::Form::new().part("file", fichier);
+
+            match reqwest::Client::new()
+                .post(&url)
+                .bearer_auth(&token)
+                .multipart(form)
+                .send()
+                .await
+            {
+                Ok(response) => {
+                    let response_body = response.text().await.unwrap();
+                    match CreatedAttachment::parse(response_body.as_str()) {
+                        Ok(reponse) => {
+                            pieces_jointes.with_mut(|pieces_jointes| pieces_jointes.push(reponse.attachment));
+                            error_message.set(None);
+                        }
+                        Err(e) => error_message.set(Some(e.reason)),
+                    }
+                }
+                Err(_) => error_message.set(Some(String::from("Perte de connection"))),
+            }
+        }
+    });
+}
+
+/// Type MIME d'un fichier selon son extension (le navigateur ne le donne pas)
+fn type_de_fichier(nom: &str) -> &'static str {
+    match nom.rsplit('.').next().map(|extension| extension.to_lowercase()).as_deref() {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("txt") | Some("log") => "text/plain",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+fn edit_message<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    message: UseState<String>,
+    message_id: i64,
+    editing: UseState<Option<i64>>,
+    error_message: UseState<Option<String>>,
+) {
+    if message.is_empty() {
+        error_message.set(Some(String::from("Il faut au moins une lettre dans le message")));
+        return;
+    }
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+    let form = FormEditMessage {
+        text: message.to_string(),
+    };
+
+    let url = format!("{BASE_API_URL}/message/{}", message_id);
+    cx.spawn(async move {
+        match reqwest::Client::new()
+            .patch(&url)
+            .bearer_auth(&token)
+            .form(&form)
+            .send()
+            .await
+        {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                let response_body = response.text().await.unwrap();
+                match status {
+                    200 => {
                         error_message.set(None);
+                        editing.set(None);
                         message.set(String::new());
                     }
-                    _ => error_message.set(Some(response_data["reason"].as_str().unwrap().to_string())),
+                    _ => error_message
+                        .set(Some(ApiError::reason_of(response_body.as_str()))),
+                }
+            }
+            Err(_) => error_message.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
+
+fn delete_message<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    message_id: i64,
+    error_message: UseState<Option<String>>,
+) {
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+
+    let url = format!("{BASE_API_URL}/message/{}", message_id);
+    cx.spawn(async move {
+        match reqwest::Client::new().delete(&url).bearer_auth(&token).send().await {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                let response_body = response.text().await.unwrap();
+                match status {
+                    200 => error_message.set(None),
+                    _ => error_message
+                        .set(Some(ApiError::reason_of(response_body.as_str()))),
+                }
+            }
+            Err(_) => error_message.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
+
+/// Ajoute ou retire la réaction de l'utilisateur à un message
+fn toggle_reaction<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    message_id: i64,
+    emoji: String,
+    retire: bool,
+    error_message: UseState<Option<String>>,
+) {
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+    let form = FormReaction {
+        emoji,
+    };
+
+    let url = format!("{BASE_API_URL}/message/{}/reaction", message_id);
+    cx.spawn(async move {
+        let request = match retire {
+            true => reqwest::Client::new().delete(&url),
+            false => reqwest::Client::new().post(&url),
+        };
+        match request.bearer_auth(&token).form(&form).send().await {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                let response_body = response.text().await.unwrap();
+                match status {
+                    200 | 201 => error_message.set(None),
+                    _ => error_message
+                        .set(Some(ApiError::reason_of(response_body.as_str()))),
                 }
             }
             Err(_) => error_message.set(Some(String::from("Perte de connection"))),
@@ -170,37 +725,31 @@ fn send_invite<T>(
         error_invite.set(Some(String::from("Il faut au moins une lettre dans le nom")));
         return;
     }
-    let form: HashMap<&str, String> = {
-        let lock = account_manager.read();
-        let current_user = lock.utilisateur_actuelle().unwrap();
-        HashMap::<&'static str, String>::from([
-            ("user_id", current_user.id.to_string()),
-            ("api_key", current_user.api_key.to_string()),
-            ("other_user_username", username.to_string()),
-            ("room_id", room_id.to_string()),
-        ])
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+    let form = FormAddUserRoom {
+        other_user_username: username.to_string(),
+        room_id: *room_id,
     };
 
     let url = format!("{BASE_API_URL}/invite");
     cx.spawn(async move {
-        match reqwest::Client::new().post(&url).form(&form).send().await {
+        match reqwest::Client::new()
+            .post(&url)
+            .bearer_auth(&token)
+            .form(&form)
+            .send()
+            .await
+        {
             Ok(response) => {
                 let status = response.status().as_u16();
                 let response_body = response.text().await.unwrap();
-                let response_data = json::parse(response_body.as_str()).unwrap();
-                match response_data["api_key"].as_str() {
-                    Some(api_key) => account_manager
-                        .write_silent()
-                        .modifier_api_key(api_key.to_string()),
-                    None => {}
-                }
                 match status {
                     201 => {
                         error_invite.set(None);
                         username.set(String::new());
                     }
                     _ => error_invite
-                        .set(Some(response_data["reason"].as_str().unwrap().to_string())),
+                        .set(Some(ApiError::reason_of(response_body.as_str()))),
                 }
             }
             Err(_) => error_invite.set(Some(String::from("Perte de connection"))),
@@ -208,68 +757,268 @@ fn send_invite<T>(
     });
 }
 
-fn message_element<'a, T>(cx: Scope<'a, T>, message: &Message) -> Element<'a> {
+fn message_element<'a, T>(
+    cx: Scope<'a, T>,
+    message: &Message,
+    vu_par: Option<&Vec<i64>>,
+    editing: &'a UseState<Option<i64>>,
+    thread: &'a UseState<Option<i64>>,
+    message_text: &'a UseState<String>,
+    error_message: &'a UseState<Option<String>>,
+) -> Element<'a> {
     let users = use_shared_state::<Users>(cx).unwrap();
     let account_manager = use_shared_state::<AccountManager>(cx).unwrap();
+    let rooms = use_shared_state::<Rooms>(cx).unwrap();
 
     let message_user_id = message.user_id;
-    let users_setter = AsyncStateSetter::<String>::new(cx, users, move |users, username| {
-        users.write().0.insert(message_user_id, Some(username));
-    });
+    let users_setter =
+        AsyncStateSetter::<(i64, String)>::new(cx, users, move |users, (user_id, username)| {
+            users.write().0.insert(user_id, Some(username));
+        });
 
-    let username = users
-        .read()
-        .0
-        .get(&message_user_id)
-        .map(|username| username.as_ref().map(|username| username.to_string()));
-    let username = match username {
-        Some(Some(username)) => username,
-        Some(None) => String::from("Chargement"),
-        None => {
-            users.write().0.insert(message_user_id, None);
-            cx.spawn(async move {
-                if let Ok(response) = reqwest::Client::new()
-                    .get(format!("{BASE_API_URL}/user/{}", message_user_id))
-                    .send()
-                    .await
-                {
-                    let status = response.status().as_u16();
-                    let response_body = response.text().await.unwrap();
-                    let response_data = json::parse(response_body.as_str()).unwrap();
-                    if status == 200 {
-                        users_setter
-                            .set_state(response_data["username"].as_str().unwrap().to_string());
-                    }
-                }
-            });
-            String::from("Chargement")
-        }
+    let username = nom_utilisateur(cx, users, &users_setter, message_user_id);
+    let presence = classe_presence(&rooms.read(), message_user_id);
+    let vu_par = match vu_par {
+        Some(vu_par) => format!(
+            "Vu par {}",
+            vu_par
+                .iter()
+                .map(|user_id| nom_utilisateur(cx, users, &users_setter, *user_id))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        None => String::new(),
     };
 
     const MESSAGE_ME: &'static str = "messageMe";
     const MESSAGE_OTHER: &'static str = "messageOther";
+    /// Emoji proposés pour réagir à un message
+    const EMOJIS: [&'static str; 4] = ["👍", "❤️", "😂", "😮"];
+
+    let current_user_id = account_manager
+        .read()
+        .utilisateur_actuelle()
+        .map(|user| user.id);
+    let is_me = current_user_id == Some(message_user_id);
+    let reactions: Vec<(String, String, bool)> = message
+        .reactions
+        .iter()
+        .map(|reaction| {
+            (
+                reaction.emoji.clone(),
+                format!("{} {}", reaction.emoji, reaction.user_ids.len()),
+                current_user_id.map_or(false, |user_id| reaction.user_ids.contains(&user_id)),
+            )
+        })
+        .collect();
+    let nouvelles_reactions: Vec<&'static str> = EMOJIS
+        .into_iter()
+        .filter(|emoji| !message.reactions.iter().any(|r| r.emoji == *emoji))
+        .collect();
+    let message_id = message.id;
+    let room_id = message.room_id;
+    let text = message.text.clone();
+    let epingle = rooms
+        .read()
+        .0
+        .get(&room_id)
+        .map_or(false, |room_data| room_data.est_epingle(message_id));
+    let pin_label = match epingle {
+        true => "Désépingler",
+        false => "Épingler",
+    };
+    let morceaux = morceaux_texte(&account_manager.read(), message.text.as_str());
+    // Le navigateur envoie le cookie de session avec ces liens et ces images
+    let pieces_jointes: Vec<(String, String, bool)> = message
+        .attachments
+        .iter()
+        .map(|attachment| {
+            (
+                format!("{BASE_API_URL}/attachment/{}", attachment.id),
+                format!("📎 {} ({} Ko)", attachment.name, (attachment.size + 1023) / 1024),
+                attachment.is_image(),
+            )
+        })
+        .collect();
+    let expiration = message.expires_at.map_or(String::new(), |expires_at| {
+        format!(
+            "⏳ {}",
+            expires_at.with_timezone(&Local).format("%d/%m/%Y %H:%M")
+        )
+    });
+    let thread_label = match message.reply_count {
+        0 => String::from("Répondre"),
+        1 => String::from("1 réponse"),
+        n => format!("{n} réponses"),
+    };
 
     render! {
         div{
-            class: match account_manager.read().utilisateur_actuelle() {
-                Some(user) => if user.id == message_user_id { MESSAGE_ME } else { MESSAGE_OTHER },
-                None => MESSAGE_OTHER
-            },
+            id: "message-{message_id}",
+            class: if is_me { MESSAGE_ME } else { MESSAGE_OTHER },
             div{
                 class: "message-header",
                 span{
                     class: "message-username",
+                    span{ class: presence }
                     username
                 }
                 span{
                     class: "message-date",
                     message.date.with_timezone(&Local).naive_local().to_string()
                 }
+                span{
+                    class: "message-expiration",
+                    title: "Supprimé à cette date",
+                    "{expiration}"
+                }
+                span{
+                    class: "message-actions",
+                    button {
+                        prevent_default: "onclick",
+                        onclick: move |_| toggle_epingle(cx, account_manager.to_owned(), message_id, epingle, error_message.to_owned()),
+                        pin_label
+                    }
+                    match is_me {
+                        true => render!{
+                            button {
+                                prevent_default: "onclick",
+                                onclick: move |_| {
+                                    editing.set(Some(message_id));
+                                    message_text.set(text.clone());
+                                },
+                                "Modifier"
+                            }
+                            button {
+                                prevent_default: "onclick",
+                                onclick: move |_| delete_message(cx, account_manager.to_owned(), message_id, error_message.to_owned()),
+                                "Supprimer"
+                            }
+                        },
+                        false => render!{span{}}
+                    }
+                }
             }
             span{
                 class: "message-text",
-                message.text.as_str()
+                for (morceau, classe) in morceaux.into_iter() {
+                    span{ class: classe, "{morceau}" }
+                }
+            }
+            div{
+                class: "message-attachments",
+                for (url, label, image) in pieces_jointes.into_iter() {
+                    match image {
+                        true => render!{
+                            a{
+                                href: "{url}",
+                                target: "_blank",
+                                img{ class: "attachment-image", src: "{url}", alt: "{label}" }
+                            }
+                        },
+                        false => render!{
+                            a{ class: "attachment-file", href: "{url}", download: true, label }
+                        },
+                    }
+                }
             }
+            div{
+                class: "message-reactions",
+                for (emoji, label, mine) in reactions.into_iter() {
+                    button {
+                        class: if mine { "reaction reactionMe" } else { "reaction" },
+                        prevent_default: "onclick",
+                        onclick: move |_| toggle_reaction(cx, account_manager.to_owned(), message_id, emoji.clone(), mine, error_message.to_owned()),
+                        label
+                    }
+                }
+                for emoji in nouvelles_reactions.into_iter() {
+                    button {
+                        class: "reaction reactionAdd",
+                        prevent_default: "onclick",
+                        onclick: move |_| toggle_reaction(cx, account_manager.to_owned(), message_id, emoji.to_string(), false, error_message.to_owned()),
+                        emoji
+                    }
+                }
+            }
+            match message.parent_id {
+                None => render!{
+                    button {
+                        class: "message-thread",
+                        prevent_default: "onclick",
+                        onclick: move |_| ouvre_fil(cx, account_manager.to_owned(), rooms.to_owned(), room_id, message_id, thread.to_owned()),
+                        thread_label
+                    }
+                },
+                Some(_) => render!{span{}}
+            }
+            match vu_par.is_empty() {
+                true => render!{span{}},
+                false => render!{span{ class: "message-seen", vu_par }}
+            }
+        }
+    }
+}
+
+/// Classe CSS de la présence d'un utilisateur (hors ligne tant qu'elle n'est pas connue)
+pub fn classe_presence(rooms: &Rooms, user_id: i64) -> &'static str {
+    match rooms.1.get(&user_id).map(|presence| presence.status) {
+        Some(PresenceStatus::Online) => "presence online",
+        Some(PresenceStatus::Away) => "presence away",
+        _ => "presence offline",
+    }
+}
+
+/// Découpe le texte d'un message avec la classe de chaque morceau, pour surligner les mentions
+/// (et davantage celles de l'utilisateur actuel)
+pub fn morceaux_texte(account_manager: &AccountManager, text: &str) -> Vec<(String, &'static str)> {
+    let moi = account_manager
+        .utilisateur_actuelle()
+        .map(|user| format!("@{}", user.username));
+    split_mentions(text)
+        .into_iter()
+        .map(|(morceau, mention)| {
+            let classe = match (mention, moi.as_deref() == Some(morceau)) {
+                (true, true) => "mention me",
+                (true, false) => "mention",
+                (false, _) => "",
+            };
+            (morceau.to_string(), classe)
+        })
+        .collect()
+}
+
+/// Récupère le nom d'un utilisateur (le demande au serveur s'il n'est pas encore connu)
+pub fn nom_utilisateur<T>(
+    cx: Scope<T>,
+    users: &UseSharedState<Users>,
+    users_setter: &AsyncStateSetter<(i64, String)>,
+    user_id: i64,
+) -> String {
+    let username = users
+        .read()
+        .0
+        .get(&user_id)
+        .map(|username| username.as_ref().map(|username| username.to_string()));
+    match username {
+        Some(Some(username)) => username,
+        Some(None) => String::from("Chargement"),
+        None => {
+            users.write().0.insert(user_id, None);
+            let users_setter = users_setter.clone();
+            cx.spawn(async move {
+                if let Ok(response) = reqwest::Client::new()
+                    .get(format!("{BASE_API_URL}/user/{}", user_id))
+                    .send()
+                    .await
+                {
+                    let response_body = response.text().await.unwrap();
+                    if let Ok(profil) = UserProfile::parse(response_body.as_str()) {
+                        users_setter.set_state((user_id, profil.username));
+                    }
+                }
+            });
+            String::from("Chargement")
         }
     }
 }
@@ -0,0 +1,187 @@
+//! Module des messages épinglés d'un salon
+//!
+//! Ce module implémente le tiroir des messages épinglés, ouvert depuis l'en-tête
+//! de la conversation. Un clic sur un message épinglé l'affiche dans la conversation
+//! (voir `MessageCible`), et tous les membres du salon peuvent le désépingler.
+
+use chrono::Local;
+use dioxus::prelude::*;
+use lib::{ApiError, ApiResponse, Pins};
+
+use crate::async_state::AsyncStateSetter;
+use crate::messages::{morceaux_texte, nom_utilisateur};
+use crate::search::MessageCible;
+use crate::{AccountManager, Rooms, Users, BASE_API_URL};
+
+#[inline_props]
+pub fn Epingles(cx: Scope, room_id: i64) -> Element {
+    let account_manager = use_shared_state::<AccountManager>(cx).unwrap();
+    let rooms = use_shared_state::<Rooms>(cx).unwrap();
+    let users = use_shared_state::<Users>(cx).unwrap();
+    let cible = use_shared_state::<MessageCible>(cx).unwrap();
+    let ouvert = use_state(cx, || false);
+    let error = use_state::<Option<String>>(cx, || None);
+
+    let users_setter =
+        AsyncStateSetter::<(i64, String)>::new(cx, users, move |users, (user_id, username)| {
+            users.write().0.insert(user_id, Some(username));
+        });
+
+    let chargees = rooms
+        .read()
+        .0
+        .get(room_id)
+        .map_or(true, |room_data| room_data.epingles.is_some());
+    use_effect(cx, (room_id, &chargees), |(room_id, chargees)| {
+        to_owned![account_manager, rooms];
+        async move {
+            if !chargees {
+                charge_epingles(account_manager, rooms, room_id).await;
+            }
+        }
+    });
+
+    let epingles: Vec<(i64, i64, String, String, Vec<(String, &'static str)>)> = {
+        let rooms = rooms.read();
+        rooms
+            .0
+            .get(room_id)
+            .and_then(|room_data| room_data.epingles.as_ref())
+            .map_or(Vec::new(), |epingles| {
+                epingles
+                    .iter()
+                    .map(|pin| {
+                        let message = &pin.message;
+                        (
+                            message.id,
+                            message.parent_id.unwrap_or(message.id),
+                            format!(
+                                "{} · {}",
+                                nom_utilisateur(cx, users, &users_setter, message.user_id),
+                                message.date.with_timezone(&Local).format("%d/%m/%Y %H:%M")
+                            ),
+                            format!(
+                                "Épinglé par {}",
+                                nom_utilisateur(cx, users, &users_setter, pin.user_id)
+                            ),
+                            morceaux_texte(&account_manager.read(), message.text.as_str()),
+                        )
+                    })
+                    .collect()
+            })
+    };
+    let nombre = epingles.len();
+
+    render! {
+        div{
+            id: "pins",
+            button {
+                id: "pinsToggle",
+                prevent_default: "onclick",
+                onclick: move |_| ouvert.set(!*ouvert.get()),
+                "📌 {nombre}"
+            }
+            match *ouvert.get() {
+                true => render!{
+                    div {
+                        id: "pin-drawer",
+                        match error.as_ref() {
+                            Some(e) => render!{span{class:"Error",e.as_str()}},
+                            None => render!{span{}}
+                        }
+                        match epingles.is_empty() {
+                            true => render!{span{ "Aucun message épinglé" }},
+                            false => render!{span{}}
+                        }
+                        ul {
+                            for (message_id, cible_id, entete, epingle_par, morceaux) in epingles {
+                                li {
+                                    class: "pin",
+                                    div {
+                                        class: "pin-message",
+                                        onclick: move |_| {
+                                            cible.write().0 = Some((*room_id, cible_id));
+                                            ouvert.set(false);
+                                        },
+                                        div { class: "search-meta", "{entete}" }
+                                        div {
+                                            for (morceau, classe) in morceaux {
+                                                span{ class: classe, "{morceau}" }
+                                            }
+                                        }
+                                    }
+                                    div {
+                                        class: "search-meta",
+                                        "{epingle_par}"
+                                        button {
+                                            prevent_default: "onclick",
+                                            onclick: move |_| toggle_epingle(cx, account_manager.to_owned(), message_id, true, error.to_owned()),
+                                            "Désépingler"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                false => render!{span{}}
+            }
+        }
+    }
+}
+
+/// Récupère les messages épinglés d'un salon
+async fn charge_epingles(
+    account_manager: UseSharedState<AccountManager>,
+    rooms: UseSharedState<Rooms>,
+    room_id: i64,
+) {
+    let token = match account_manager.read().utilisateur_actuelle() {
+        Some(user) => user.token.to_string(),
+        None => return,
+    };
+
+    let url = format!("{BASE_API_URL}/room/{room_id}/pins");
+    if let Ok(response) = reqwest::Client::new().get(&url).bearer_auth(&token).send().await {
+        let response_body = response.text().await.unwrap();
+        let epingles = match Pins::parse(response_body.as_str()) {
+            Ok(epingles) => epingles.pins,
+            Err(_) => return,
+        };
+
+        if let Some(room_data) = rooms.write().0.get_mut(&room_id) {
+            room_data.epingles = Some(epingles);
+        }
+    }
+}
+
+/// Épingle ou désépingle un message
+pub fn toggle_epingle<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    message_id: i64,
+    retire: bool,
+    error_message: UseState<Option<String>>,
+) {
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+
+    let url = format!("{BASE_API_URL}/message/{}/pin", message_id);
+    cx.spawn(async move {
+        let request = match retire {
+            true => reqwest::Client::new().delete(&url),
+            false => reqwest::Client::new().post(&url),
+        };
+        match request.bearer_auth(&token).send().await {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                let response_body = response.text().await.unwrap();
+                match status {
+                    200 | 201 => error_message.set(None),
+                    _ => error_message
+                        .set(Some(ApiError::reason_of(response_body.as_str()))),
+                }
+            }
+            Err(_) => error_message.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
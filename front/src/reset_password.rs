@@ -0,0 +1,98 @@
+//! Module de réinitialisation du mot de passe
+//!
+//! Ce module implémente la page où un utilisateur qui a oublié son mot de passe en choisit
+//! un nouveau, avec le jeton que lui a donné un administrateur.
+
+use dioxus::prelude::*;
+use dioxus_router::prelude::{use_navigator, Link, Navigator};
+use lib::{ApiError, FormResetPassword};
+
+use crate::{Route, BASE_API_URL};
+
+#[inline_props]
+pub fn Reinitialisation(cx: Scope) -> Element {
+    let token = use_state(cx, String::new);
+    let new_password = use_state(cx, String::new);
+    let error = use_state::<Option<String>>(cx, || None);
+    let navigator = use_navigator(cx);
+
+    render! {
+        div{
+            id: "reset-password",
+            h1{"Réinitialisation du mot de passe"}
+            match error.as_ref() {
+                Some(e) => render!{span{class:"Error",e.as_str()}},
+                None => render!{span{}}
+            }
+            form {
+                input {
+                    r#type: "text",
+                    name: "token",
+                    autofocus: true,
+                    autocomplete: "off",
+                    placeholder: "jeton de réinitialisation",
+                    oninput: move |evt| token.set(evt.value.clone()),
+                    value: "{token}"
+                }
+                input {
+                    r#type: "password",
+                    name: "new_password",
+                    autocomplete: "off",
+                    placeholder: "nouveau mot de passe",
+                    oninput: move |evt| new_password.set(evt.value.clone()),
+                    value: "{new_password}"
+                }
+                button {
+                    prevent_default: "onclick",
+                    onclick: move |_| reinitialise(cx, navigator.to_owned(), token.to_string(), new_password.to_string(), error.to_owned()),
+                    "Envoyer"
+                }
+            }
+            Link{
+                to: Route::LogIn{},
+                "Connection d'utilisateur"
+            }
+        }
+    }
+}
+
+/// Choisit le nouveau mot de passe et retourne à la page de connexion si la requête réussit
+fn reinitialise<T>(
+    cx: Scope<T>,
+    navigator: Navigator,
+    token: String,
+    new_password: String,
+    error: UseState<Option<String>>,
+) {
+    if token.is_empty() {
+        error.set(Some(String::from("Il faut le jeton donné par un administrateur")));
+        return;
+    }
+    if new_password.is_empty() {
+        error.set(Some(String::from(
+            "Il faut au moins une lettre dans le mot de passe",
+        )));
+        return;
+    }
+    let form = FormResetPassword {
+        token,
+        new_password,
+    };
+
+    let url = format!("{BASE_API_URL}/password/reset");
+    cx.spawn(async move {
+        match reqwest::Client::new().post(&url).form(&form).send().await {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                let response_body = response.text().await.unwrap();
+                match status {
+                    200 => {
+                        navigator.replace(Route::LogIn {});
+                    }
+                    _ => error.set(Some(ApiError::reason_of(response_body.as_str()))),
+                }
+            }
+            Err(_) => error.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
@@ -3,17 +3,253 @@
 //! Ce module implémente des structures et des traits pour gérer les identifiants de salle (OpRoomId)
 //! et extraire des informations de la requête dans une application Rust utilisant `dioxus` et `dioxus_router`.
 
+use std::collections::HashMap;
 use std::fmt::Display;
 
 use dioxus::prelude::Props;
 
 use dioxus_router::routable::FromQuery;
-use lib::Message;
+use lib::{
+    Message, Pin, Reaction, ReadReceipt, Room, RoomKind, RoomRole, Thread, Typing, UserPresence,
+};
 
 #[derive(Debug)]
 pub struct RoomData {
     pub name: String,
+    pub topic: String,
+    pub kind: RoomKind,
+    /// Durée de conservation des messages en secondes (voir `Room::retention`)
+    pub retention: Option<i64>,
     pub messages: Vec<Message>,
+    /// Il reste des messages plus anciens à récupérer sur le serveur
+    pub has_more: bool,
+    /// Une page d'historique est en cours de chargement
+    pub chargement: bool,
+    /// Réponses des fils de discussion ouverts, selon l'id du message parent
+    pub reponses: HashMap<i64, Vec<Message>>,
+    /// Messages non lus selon le serveur, jusqu'au message `last_message_id`
+    pub unread_count: i64,
+    pub last_message_id: Option<i64>,
+    /// Dernier message lu par chaque membre du salon
+    pub lectures: HashMap<i64, i64>,
+    /// Membres en train d'écrire
+    pub ecrivent: Vec<i64>,
+    /// Rôle de chaque membre du salon
+    pub membres: HashMap<i64, RoomRole>,
+    /// Messages épinglés, du dernier épinglé au premier (`None` tant qu'ils ne sont pas chargés)
+    pub epingles: Option<Vec<Pin>>,
+}
+
+impl RoomData {
+    pub fn new(room: Room) -> RoomData {
+        RoomData {
+            name: room.name,
+            topic: room.topic,
+            kind: room.kind,
+            retention: room.retention,
+            messages: Vec::new(),
+            has_more: true,
+            chargement: false,
+            reponses: HashMap::new(),
+            unread_count: room.unread_count,
+            last_message_id: room.last_message_id,
+            lectures: HashMap::new(),
+            ecrivent: Vec::new(),
+            membres: room
+                .members
+                .iter()
+                .map(|member| (member.user_id, member.role))
+                .collect(),
+            epingles: None,
+        }
+    }
+
+    /// Titre du salon : son nom, ou le nom de l'autre utilisateur d'une conversation privée
+    pub fn titre(&self, user_id: i64, presences: &HashMap<i64, UserPresence>) -> String {
+        match self.kind {
+            RoomKind::Group => self.name.to_string(),
+            RoomKind::Direct => self
+                .membres
+                .keys()
+                .find(|membre_id| **membre_id != user_id)
+                .and_then(|membre_id| presences.get(membre_id))
+                .map_or(String::from("Conversation privée"), |presence| {
+                    presence.username.to_string()
+                }),
+        }
+    }
+
+    /// Nombre de messages non lus par un utilisateur
+    ///
+    /// Les messages reçus après le calcul du serveur sont ajoutés à son compte.
+    pub fn non_lus(&self, user_id: i64) -> i64 {
+        let dernier_lu = self.lectures.get(&user_id).copied().unwrap_or(0);
+        let nouveaux = self
+            .messages
+            .iter()
+            .filter(|m| m.user_id != user_id && m.id > dernier_lu);
+
+        match self.last_message_id {
+            Some(last_message_id) if dernier_lu < last_message_id => {
+                self.unread_count + nouveaux.filter(|m| m.id > last_message_id).count() as i64
+            }
+            _ => nouveaux.count() as i64,
+        }
+    }
+
+    /// Ajoute ou retire un membre en train d'écrire
+    pub fn met_a_jour_ecriture(&mut self, typing: Typing) {
+        self.ecrivent.retain(|user_id| *user_id != typing.user_id);
+        if typing.typing {
+            self.ecrivent.push(typing.user_id);
+        }
+    }
+
+    /// Avance le dernier message lu par un membre du salon
+    pub fn met_a_jour_lecture(&mut self, lecture: ReadReceipt) {
+        let dernier_lu = self.lectures.entry(lecture.user_id).or_insert(0);
+        *dernier_lu = (*dernier_lu).max(lecture.message_id);
+    }
+
+    /// Membres (autres que `user_id`) ayant lu jusqu'à chaque message affiché
+    pub fn vu_par(&self, user_id: i64) -> HashMap<i64, Vec<i64>> {
+        let mut vus = HashMap::<i64, Vec<i64>>::new();
+        for (membre, dernier_lu) in self.lectures.iter() {
+            if *membre == user_id {
+                continue;
+            }
+            if let Some(message) = self.messages.iter().rev().find(|m| m.id <= *dernier_lu) {
+                vus.entry(message.id).or_default().push(*membre);
+            }
+        }
+        vus
+    }
+
+    /// Ajoute un message à sa place (selon son id), s'il n'a pas déjà été reçu
+    ///
+    /// Une réponse n'est ajoutée que si son fil de discussion a déjà été chargé.
+    pub fn ajoute_message(&mut self, message: Message) {
+        let messages = match message.parent_id {
+            None => &mut self.messages,
+            Some(parent_id) => match self.reponses.get_mut(&parent_id) {
+                Some(reponses) => reponses,
+                None => return,
+            },
+        };
+        match messages.binary_search_by_key(&message.id, |m| m.id) {
+            Ok(_) => {}
+            Err(position) => messages.insert(position, message),
+        }
+    }
+
+    /// Remplace un message modifié
+    pub fn modifie_message(&mut self, message: Message) {
+        let messages = match message.parent_id {
+            None => Some(&mut self.messages),
+            Some(parent_id) => self.reponses.get_mut(&parent_id),
+        };
+        if let Some(old) =
+            messages.and_then(|messages| messages.iter_mut().find(|m| m.id == message.id))
+        {
+            old.text = message.text.to_string();
+        }
+        if let Some(pin) = self
+            .epingles
+            .iter_mut()
+            .flatten()
+            .find(|pin| pin.message.id == message.id)
+        {
+            pin.message.text = message.text;
+        }
+    }
+
+    /// Retire un message supprimé (et ses réponses)
+    pub fn supprime_message(&mut self, message_id: i64) {
+        self.messages.retain(|m| m.id != message_id);
+        self.reponses.remove(&message_id);
+        for reponses in self.reponses.values_mut() {
+            reponses.retain(|m| m.id != message_id);
+        }
+        if let Some(epingles) = self.epingles.as_mut() {
+            epingles.retain(|pin| {
+                pin.message.id != message_id && pin.message.parent_id != Some(message_id)
+            });
+        }
+    }
+
+    /// Ajoute un message épinglé en tête de liste (si les épingles ont déjà été chargées)
+    pub fn ajoute_epingle(&mut self, pin: Pin) {
+        if let Some(epingles) = self.epingles.as_mut() {
+            epingles.retain(|epingle| epingle.message.id != pin.message.id);
+            epingles.insert(0, pin);
+        }
+    }
+
+    /// Retire un message désépinglé
+    pub fn retire_epingle(&mut self, message_id: i64) {
+        if let Some(epingles) = self.epingles.as_mut() {
+            epingles.retain(|pin| pin.message.id != message_id);
+        }
+    }
+
+    /// Indique si un message est épinglé
+    pub fn est_epingle(&self, message_id: i64) -> bool {
+        self.epingles
+            .iter()
+            .flatten()
+            .any(|pin| pin.message.id == message_id)
+    }
+
+    /// Met à jour le nombre de réponses d'un message
+    pub fn met_a_jour_fil(&mut self, thread: Thread) {
+        if let Some(parent) = self.messages.iter_mut().find(|m| m.id == thread.parent_id) {
+            parent.reply_count = thread.reply_count;
+        }
+    }
+
+    /// Remplace les utilisateurs ayant réagi à un message avec un emoji
+    pub fn met_a_jour_reaction(&mut self, reaction: Reaction) {
+        let message = self
+            .messages
+            .iter_mut()
+            .chain(self.reponses.values_mut().flatten())
+            .find(|m| m.id == reaction.message_id);
+        let message = match message {
+            Some(message) => message,
+            None => return,
+        };
+
+        match message
+            .reactions
+            .iter()
+            .position(|r| r.emoji == reaction.emoji)
+        {
+            Some(position) if reaction.user_ids.is_empty() => {
+                message.reactions.remove(position);
+            }
+            Some(position) => message.reactions[position] = reaction,
+            None if reaction.user_ids.is_empty() => {}
+            None => message.reactions.push(reaction),
+        }
+    }
+
+    /// Remplace les réponses chargées d'un fil de discussion
+    pub fn ajoute_reponses(&mut self, parent_id: i64, reponses: Vec<Message>) {
+        self.reponses.insert(parent_id, reponses);
+    }
+
+    /// Ajoute une page de messages plus anciens au début de la conversation
+    pub fn ajoute_historique(&mut self, messages: Vec<Message>, has_more: bool) {
+        let premier = self.messages.first().map(|message| message.id);
+        let mut messages: Vec<Message> = messages
+            .into_iter()
+            .filter(|message| premier.map_or(true, |premier| message.id < premier))
+            .collect();
+        messages.append(&mut self.messages);
+        self.messages = messages;
+        self.has_more = has_more;
+        self.chargement = false;
+    }
 }
 
 #[derive(Clone, PartialEq, Props)]
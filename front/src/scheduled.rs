@@ -0,0 +1,244 @@
+//! Module des messages programmés
+//!
+//! Ce module implémente la liste des messages programmés de l'utilisateur dans un salon,
+//! affichée au-dessus du formulaire d'envoi, pour modifier leur texte et leur date d'envoi
+//! ou les annuler. Un message disparaît de la liste à sa date d'envoi.
+
+use chrono::{Local, NaiveDateTime, TimeZone, Utc};
+use dioxus::prelude::*;
+use lib::{
+    ApiError, ApiResponse, EditedScheduled, FormEditScheduled, ScheduledMessage,
+    ScheduledMessages,
+};
+
+use crate::{AccountManager, Rooms, BASE_API_URL};
+
+/// Format d'un champ `datetime-local`
+const FORMAT_DATE_HEURE: &str = "%Y-%m-%dT%H:%M";
+
+/// Messages programmés de l'utilisateur (`None` tant qu'ils ne sont pas chargés)
+pub struct MessagesProgrammes(pub Option<Vec<ScheduledMessage>>);
+
+#[inline_props]
+pub fn Programmes(cx: Scope, room_id: i64) -> Element {
+    let account_manager = use_shared_state::<AccountManager>(cx).unwrap();
+    let programmes = use_shared_state::<MessagesProgrammes>(cx).unwrap();
+    // Les messages reçus dans le salon font disparaître ceux qui viennent d'être envoyés
+    let _ = use_shared_state::<Rooms>(cx).unwrap();
+    let edition = use_state::<Option<(i64, String, String)>>(cx, || None);
+    let error = use_state::<Option<String>>(cx, || None);
+
+    let charges = programmes.read().0.is_some();
+    use_effect(cx, (&charges,), |(charges,)| {
+        to_owned![account_manager, programmes];
+        async move {
+            if !charges {
+                charge_programmes(account_manager, programmes).await;
+            }
+        }
+    });
+
+    let maintenant = Utc::now();
+    let liste: Vec<(i64, String, String, String)> = programmes
+        .read()
+        .0
+        .iter()
+        .flatten()
+        .filter(|programme| programme.room_id == *room_id && programme.send_at > maintenant)
+        .map(|programme| {
+            let date = programme.send_at.with_timezone(&Local);
+            (
+                programme.id,
+                programme.text.to_string(),
+                date.format("%d/%m/%Y %H:%M").to_string(),
+                date.format(FORMAT_DATE_HEURE).to_string(),
+            )
+        })
+        .collect();
+    if liste.is_empty() {
+        return render! {div{}};
+    }
+
+    render! {
+        div{
+            id: "scheduled",
+            span{ class: "search-meta", "Messages programmés" }
+            match error.as_ref() {
+                Some(e) => render!{span{class:"Error",e.as_str()}},
+                None => render!{span{}}
+            }
+            ul{
+                for (scheduled_id, text, date, valeur_date) in liste {
+                    li{
+                        class: "scheduled-message",
+                        span{ class: "search-meta", "⏰ {date}" }
+                        span{ "{text}" }
+                        button {
+                            prevent_default: "onclick",
+                            onclick: move |_| edition.set(Some((scheduled_id, text.clone(), valeur_date.clone()))),
+                            "Modifier"
+                        }
+                        button {
+                            prevent_default: "onclick",
+                            onclick: move |_| annule_programme(cx, account_manager.to_owned(), programmes.to_owned(), scheduled_id, error.to_owned()),
+                            "Annuler"
+                        }
+                    }
+                }
+            }
+            match edition.get().clone() {
+                Some((scheduled_id, text, valeur_date)) => {
+                    let (texte_actuel, date_actuelle) = (text.clone(), valeur_date.clone());
+                    render!{
+                        form {
+                            input {
+                                r#type: "text",
+                                autocomplete: "off",
+                                oninput: move |evt| edition.set(Some((scheduled_id, evt.value.clone(), date_actuelle.clone()))),
+                                value: "{text}"
+                            }
+                            input {
+                                r#type: "datetime-local",
+                                oninput: move |evt| edition.set(Some((scheduled_id, texte_actuel.clone(), evt.value.clone()))),
+                                value: "{valeur_date}"
+                            }
+                            button {
+                                prevent_default: "onclick",
+                                onclick: move |_| modifie_programme(cx, account_manager.to_owned(), programmes.to_owned(), edition.to_owned(), error.to_owned()),
+                                "Enregistrer"
+                            }
+                            button {
+                                prevent_default: "onclick",
+                                onclick: move |_| edition.set(None),
+                                "Fermer"
+                            }
+                        }
+                    }
+                }
+                None => render!{span{}}
+            }
+        }
+    }
+}
+
+/// Transforme la valeur d'un champ `datetime-local` (heure locale) en timestamp
+pub fn timestamp_date_heure(valeur: &str) -> Option<i64> {
+    let date = NaiveDateTime::parse_from_str(valeur, FORMAT_DATE_HEURE).ok()?;
+    Local
+        .from_local_datetime(&date)
+        .earliest()
+        .map(|date| date.timestamp())
+}
+
+/// Récupère les messages programmés de l'utilisateur
+pub async fn charge_programmes(
+    account_manager: UseSharedState<AccountManager>,
+    programmes: UseSharedState<MessagesProgrammes>,
+) {
+    let token = match account_manager.read().utilisateur_actuelle() {
+        Some(user) => user.token.to_string(),
+        None => return,
+    };
+
+    let url = format!("{BASE_API_URL}/scheduled");
+    if let Ok(response) = reqwest::Client::new().get(&url).bearer_auth(&token).send().await {
+        let response_body = response.text().await.unwrap();
+        if let Ok(reponse) = ScheduledMessages::parse(response_body.as_str()) {
+            programmes.write().0 = Some(reponse.scheduled);
+        }
+    }
+}
+
+/// Modifie le texte et la date d'envoi du message programmé en cours d'édition
+fn modifie_programme<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    programmes: UseSharedState<MessagesProgrammes>,
+    edition: UseState<Option<(i64, String, String)>>,
+    error: UseState<Option<String>>,
+) {
+    let (scheduled_id, text, valeur_date) = match edition.get() {
+        Some(edition) => edition.clone(),
+        None => return,
+    };
+    if text.is_empty() {
+        error.set(Some(String::from("Il faut au moins une lettre dans le message")));
+        return;
+    }
+    let send_at = match timestamp_date_heure(valeur_date.as_str()) {
+        Some(send_at) => send_at,
+        None => {
+            error.set(Some(String::from("Ce n'est pas une date valide.")));
+            return;
+        }
+    };
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+    let form = FormEditScheduled {
+        text: Some(text),
+        send_at: Some(send_at),
+    };
+
+    let url = format!("{BASE_API_URL}/scheduled/{}", scheduled_id);
+    cx.spawn(async move {
+        match reqwest::Client::new()
+            .patch(&url)
+            .bearer_auth(&token)
+            .form(&form)
+            .send()
+            .await
+        {
+            Ok(response) => {
+                let response_body = response.text().await.unwrap();
+                match EditedScheduled::parse(response_body.as_str()) {
+                    Ok(reponse) => {
+                        error.set(None);
+                        edition.set(None);
+                        let modifie = reponse.scheduled;
+                        if let Some(programme) = programmes
+                            .write()
+                            .0
+                            .iter_mut()
+                            .flatten()
+                            .find(|programme| programme.id == modifie.id)
+                        {
+                            *programme = modifie;
+                        }
+                    }
+                    Err(e) => error.set(Some(e.reason)),
+                }
+            }
+            Err(_) => error.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
+
+/// Annule un message programmé
+fn annule_programme<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    programmes: UseSharedState<MessagesProgrammes>,
+    scheduled_id: i64,
+    error: UseState<Option<String>>,
+) {
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+
+    let url = format!("{BASE_API_URL}/scheduled/{}", scheduled_id);
+    cx.spawn(async move {
+        match reqwest::Client::new().delete(&url).bearer_auth(&token).send().await {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                let response_body = response.text().await.unwrap();
+                match status {
+                    200 => {
+                        error.set(None);
+                        if let Some(liste) = programmes.write().0.as_mut() {
+                            liste.retain(|programme| programme.id != scheduled_id);
+                        }
+                    }
+                    _ => error.set(Some(ApiError::reason_of(response_body.as_str()))),
+                }
+            }
+            Err(_) => error.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
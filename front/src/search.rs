@@ -0,0 +1,244 @@
+//! Module de recherche des messages
+//!
+//! Ce module implémente la barre de recherche plein texte, avec ses filtres (salon, auteur
+//! et dates), et la liste des résultats. Un clic sur un résultat ouvre le salon et y affiche
+//! le message (voir `MessageCible`).
+
+use chrono::{Local, NaiveDate, TimeZone};
+use dioxus::prelude::*;
+use dioxus_router::prelude::*;
+use lib::{ApiResponse, FormSearch, SearchResult, SearchResults};
+
+use crate::async_state::AsyncStateSetter;
+use crate::messages::nom_utilisateur;
+use crate::{AccountManager, Rooms, Route, Users, BASE_API_URL};
+
+/// Message (salon et id) à afficher à l'ouverture d'une conversation
+pub struct MessageCible(pub Option<(i64, i64)>);
+
+pub fn Recherche(cx: Scope) -> Element {
+    let account_manager = use_shared_state::<AccountManager>(cx).unwrap();
+    let rooms = use_shared_state::<Rooms>(cx).unwrap();
+    let users = use_shared_state::<Users>(cx).unwrap();
+    let cible = use_shared_state::<MessageCible>(cx).unwrap();
+    let navigator = use_navigator(cx);
+    let q = use_state(cx, || String::new());
+    let room_id = use_state::<Option<i64>>(cx, || None);
+    let author_id = use_state::<Option<i64>>(cx, || None);
+    let after = use_state(cx, || String::new());
+    let before = use_state(cx, || String::new());
+    let resultats = use_state::<Option<Vec<SearchResult>>>(cx, || None);
+    let error = use_state::<Option<String>>(cx, || None);
+
+    let users_setter =
+        AsyncStateSetter::<(i64, String)>::new(cx, users, move |users, (user_id, username)| {
+            users.write().0.insert(user_id, Some(username));
+        });
+
+    let user_id = match account_manager.read().utilisateur_actuelle() {
+        Some(user) => user.id,
+        None => return render! {div{}},
+    };
+    let (salons, auteurs) = {
+        let rooms = rooms.read();
+        let mut salons: Vec<(i64, String)> = rooms
+            .0
+            .iter()
+            .map(|(room_id, room_data)| (*room_id, room_data.titre(user_id, &rooms.1)))
+            .collect();
+        salons.sort_by(|a, b| a.1.cmp(&b.1));
+        let mut auteurs: Vec<(i64, String)> = rooms
+            .1
+            .values()
+            .map(|presence| (presence.user_id, presence.username.to_string()))
+            .collect();
+        auteurs.sort_by(|a, b| a.1.cmp(&b.1));
+        (salons, auteurs)
+    };
+    let resultats_affiches: Option<Vec<(i64, i64, String, Vec<(String, bool)>)>> =
+        resultats.get().as_ref().map(|resultats| {
+            let rooms = rooms.read();
+            resultats
+                .iter()
+                .map(|resultat| {
+                    let message = &resultat.message;
+                    let salon = rooms
+                        .0
+                        .get(&message.room_id)
+                        .map_or(String::new(), |room_data| room_data.titre(user_id, &rooms.1));
+                    (
+                        message.room_id,
+                        message.parent_id.unwrap_or(message.id),
+                        format!(
+                            "{} · {} · {}",
+                            salon,
+                            nom_utilisateur(cx, users, &users_setter, message.user_id),
+                            message.date.with_timezone(&Local).format("%d/%m/%Y %H:%M")
+                        ),
+                        decoupe_extrait(resultat.snippet.as_str()),
+                    )
+                })
+                .collect()
+        });
+
+    render! {
+        div{
+            id: "search",
+            form {
+                input {
+                    r#type: "search",
+                    name: "q",
+                    autocomplete: "off",
+                    placeholder: "Rechercher...",
+                    oninput: move |evt| q.set(evt.value.clone()),
+                    value: "{q}"
+                }
+                button {
+                    prevent_default: "onclick",
+                    onclick: move |_| recherche(cx, account_manager.to_owned(), q.to_string(), *room_id.get(), *author_id.get(), after.to_string(), before.to_string(), resultats.to_owned(), error.to_owned()),
+                    "🔍"
+                }
+            }
+            div {
+                class: "search-filters",
+                select {
+                    onchange: move |evt| room_id.set(evt.value.parse().ok()),
+                    option { value: "", "Tous les salons" }
+                    for (id, titre) in salons {
+                        option { value: "{id}", "{titre}" }
+                    }
+                }
+                select {
+                    onchange: move |evt| author_id.set(evt.value.parse().ok()),
+                    option { value: "", "Tous les auteurs" }
+                    option { value: "{user_id}", "Moi" }
+                    for (id, username) in auteurs {
+                        option { value: "{id}", "{username}" }
+                    }
+                }
+                input {
+                    r#type: "date",
+                    title: "Depuis le",
+                    oninput: move |evt| after.set(evt.value.clone()),
+                    value: "{after}"
+                }
+                input {
+                    r#type: "date",
+                    title: "Jusqu'au",
+                    oninput: move |evt| before.set(evt.value.clone()),
+                    value: "{before}"
+                }
+            }
+            match error.as_ref() {
+                Some(e) => render!{span{class:"Error",e.as_str()}},
+                None => render!{span{}}
+            }
+            match resultats_affiches {
+                Some(resultats_affiches) => render!{
+                    ul {
+                        id: "search-results",
+                        match resultats_affiches.is_empty() {
+                            true => render!{li{ "Aucun résultat" }},
+                            false => render!{span{}}
+                        }
+                        for (salon_id, message_id, entete, extrait) in resultats_affiches {
+                            li {
+                                class: "search-result",
+                                onclick: move |_| {
+                                    cible.write().0 = Some((salon_id, message_id));
+                                    navigator.push(Route::Conv { room_id: salon_id });
+                                },
+                                div { class: "search-meta", "{entete}" }
+                                div {
+                                    class: "snippet",
+                                    for (texte, trouve) in extrait {
+                                        match trouve {
+                                            true => render!{mark{ "{texte}" }},
+                                            false => render!{span{ "{texte}" }}
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                None => render!{span{}}
+            }
+        }
+    }
+}
+
+/// Découpe l'extrait d'un résultat en morceaux, avec `true` pour les termes trouvés
+/// (entre `<mark>` et `</mark>`)
+fn decoupe_extrait(extrait: &str) -> Vec<(String, bool)> {
+    let mut morceaux = Vec::new();
+    for (i, morceau) in extrait.split("<mark>").enumerate() {
+        match (i, morceau.split_once("</mark>")) {
+            (0, _) | (_, None) => morceaux.push((morceau.to_string(), i != 0)),
+            (_, Some((trouve, reste))) => {
+                morceaux.push((trouve.to_string(), true));
+                morceaux.push((reste.to_string(), false));
+            }
+        }
+    }
+    morceaux
+}
+
+/// Transforme une date (`AAAA-MM-JJ`) en timestamp du début de cette journée,
+/// ou de la suivante si `fin` (pour l'inclure)
+fn timestamp_date(date: &str, fin: bool) -> Option<i64> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    let date = match fin {
+        true => date.succ_opt()?,
+        false => date,
+    };
+    Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+        .earliest()
+        .map(|date| date.timestamp())
+}
+
+/// Recherche des messages dans les salons de l'utilisateur
+fn recherche<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    q: String,
+    room_id: Option<i64>,
+    author_id: Option<i64>,
+    after: String,
+    before: String,
+    resultats: UseState<Option<Vec<SearchResult>>>,
+    error: UseState<Option<String>>,
+) {
+    if q.trim().is_empty() {
+        resultats.set(None);
+        return;
+    }
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+    let query = FormSearch {
+        q,
+        room_id,
+        author_id,
+        after: timestamp_date(after.as_str(), false),
+        before: timestamp_date(before.as_str(), true),
+        limit: None,
+    };
+
+    let url = format!("{BASE_API_URL}/search");
+    cx.spawn(async move {
+        let request = reqwest::Client::new().get(&url).bearer_auth(&token).query(&query);
+        match request.send().await {
+            Ok(response) => {
+                let response_body = response.text().await.unwrap();
+                match SearchResults::parse(response_body.as_str()) {
+                    Ok(reponse) => {
+                        error.set(None);
+                        resultats.set(Some(reponse.results));
+                    }
+                    Err(e) => error.set(Some(e.reason)),
+                }
+            }
+            Err(_) => error.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
@@ -0,0 +1,210 @@
+//! Module des réglages d'un salon
+//!
+//! Ce module implémente le panneau pour renommer un salon, changer son sujet et la durée
+//! de conservation de ses messages (administrateurs et propriétaire seulement) ou le quitter.
+//! Tous les membres y voient la durée de conservation des messages.
+
+use dioxus::prelude::*;
+use lib::{ApiError, FormEditRoom, RoomRole};
+
+use crate::{AccountManager, Rooms, BASE_API_URL};
+
+/// Durées de conservation des messages proposées (en secondes, `0` pour les garder indéfiniment)
+const DUREES_CONSERVATION: [(i64, &str); 5] = [
+    (0, "Indéfiniment"),
+    (3600, "1 heure"),
+    (86400, "1 jour"),
+    (604800, "7 jours"),
+    (2592000, "30 jours"),
+];
+
+/// Libellé d'une durée de conservation des messages
+fn libelle_conservation(retention: Option<i64>) -> String {
+    let secondes = retention.unwrap_or(0);
+    match DUREES_CONSERVATION.iter().find(|(duree, _)| *duree == secondes) {
+        Some((_, libelle)) => libelle.to_string(),
+        None => format!("{} secondes", secondes),
+    }
+}
+
+#[inline_props]
+pub fn Reglages(cx: Scope, room_id: i64) -> Element {
+    let account_manager = use_shared_state::<AccountManager>(cx).unwrap();
+    let rooms = use_shared_state::<Rooms>(cx).unwrap();
+    let ouvert = use_state(cx, || false);
+    let name = use_state(cx, String::new);
+    let topic = use_state(cx, String::new);
+    let retention = use_state(cx, String::new);
+    let error = use_state::<Option<String>>(cx, || None);
+
+    let user_id = match account_manager.read().utilisateur_actuelle() {
+        Some(user) => user.id,
+        None => return render! {div{}},
+    };
+    let (nom_actuel, sujet_actuel, conservation_actuelle, admin) = {
+        let rooms = rooms.read();
+        match rooms.0.get(room_id) {
+            Some(room_data) => (
+                room_data.name.to_string(),
+                room_data.topic.to_string(),
+                room_data.retention,
+                room_data.membres.get(&user_id).copied().unwrap_or(RoomRole::Member)
+                    >= RoomRole::Admin,
+            ),
+            None => return render! {div{}},
+        }
+    };
+
+    render! {
+        div{
+            id: "settings",
+            button {
+                id: "settingsToggle",
+                prevent_default: "onclick",
+                onclick: move |_| {
+                    name.set(nom_actuel.to_string());
+                    topic.set(sujet_actuel.to_string());
+                    retention.set(conservation_actuelle.unwrap_or(0).to_string());
+                    error.set(None);
+                    ouvert.set(!*ouvert.get());
+                },
+                "Réglages"
+            }
+            match *ouvert.get() {
+                true => render!{
+                    form {
+                        span {
+                            class: "retention",
+                            "Conservation des messages : {libelle_conservation(conservation_actuelle)}"
+                        }
+                        match admin {
+                            true => render!{
+                                input {
+                                    r#type: "text",
+                                    name: "name",
+                                    autocomplete: "off",
+                                    placeholder: "Nom du salon",
+                                    oninput: move |evt| name.set(evt.value.clone()),
+                                    value: "{name}"
+                                }
+                                input {
+                                    r#type: "text",
+                                    name: "topic",
+                                    autocomplete: "off",
+                                    placeholder: "Sujet du salon",
+                                    oninput: move |evt| topic.set(evt.value.clone()),
+                                    value: "{topic}"
+                                }
+                                select {
+                                    name: "retention",
+                                    title: "Conservation des messages",
+                                    onchange: move |evt| retention.set(evt.value.clone()),
+                                    for (duree, libelle) in DUREES_CONSERVATION {
+                                        option {
+                                            value: "{duree}",
+                                            selected: retention.get() == &duree.to_string(),
+                                            "{libelle}"
+                                        }
+                                    }
+                                }
+                                button {
+                                    prevent_default: "onclick",
+                                    onclick: move |_| modifie_salon(cx, account_manager.to_owned(), *room_id, name.to_string(), topic.to_string(), retention.to_string(), ouvert.to_owned(), error.to_owned()),
+                                    "Enregistrer"
+                                }
+                            },
+                            false => render!{span{}}
+                        }
+                        button {
+                            id: "leave",
+                            prevent_default: "onclick",
+                            onclick: move |_| quitte_salon(cx, account_manager.to_owned(), *room_id, error.to_owned()),
+                            "Quitter le salon"
+                        }
+                    }
+                },
+                false => render!{span{}}
+            }
+            match error.as_ref() {
+                Some(e) => render!{span{class:"Error",e.as_str()}},
+                None => render!{span{}}
+            }
+        }
+    }
+}
+
+/// Renomme un salon, change son sujet et la durée de conservation de ses messages
+fn modifie_salon<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    room_id: i64,
+    name: String,
+    topic: String,
+    retention: String,
+    ouvert: UseState<bool>,
+    error: UseState<Option<String>>,
+) {
+    if name.is_empty() {
+        error.set(Some(String::from(
+            "Il faut au moins une lettre dans le nom du salon",
+        )));
+        return;
+    }
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+    let form = FormEditRoom {
+        name: Some(name),
+        topic: Some(topic),
+        retention: retention.parse::<i64>().ok(),
+    };
+
+    let url = format!("{BASE_API_URL}/room/{}", room_id);
+    cx.spawn(async move {
+        match reqwest::Client::new()
+            .patch(&url)
+            .bearer_auth(&token)
+            .form(&form)
+            .send()
+            .await
+        {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                let response_body = response.text().await.unwrap();
+                match status {
+                    200 => {
+                        error.set(None);
+                        ouvert.set(false);
+                    }
+                    _ => error.set(Some(ApiError::reason_of(response_body.as_str()))),
+                }
+            }
+            Err(_) => error.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
+
+/// Quitte un salon
+///
+/// Le salon est retiré de la liste à la réception de l'événement du serveur.
+fn quitte_salon<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    room_id: i64,
+    error: UseState<Option<String>>,
+) {
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+
+    let url = format!("{BASE_API_URL}/room/{}/leave", room_id);
+    cx.spawn(async move {
+        match reqwest::Client::new().post(&url).bearer_auth(&token).send().await {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                let response_body = response.text().await.unwrap();
+                match status {
+                    200 => error.set(None),
+                    _ => error.set(Some(ApiError::reason_of(response_body.as_str()))),
+                }
+            }
+            Err(_) => error.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
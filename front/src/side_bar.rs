@@ -1,17 +1,19 @@
 //! Module de la barre latérale et gestion des salons
 //!
 //! Ce module implémente la logique de la barre latérale de l'application,
-//! permettant de gérer les salons et d'afficher leur état. Il utilise des
+//! permettant de gérer les salons et d'afficher leur état. Les conversations privées
+//! sont listées à part, sous le nom de l'autre utilisateur. Il utilise des
 //! fonctionnalités de rendu et de gestion d'état fournies par `dioxus`,
 //! ainsi que des requêtes HTTP avec `reqwest` pour communiquer avec le serveur.
 
-use std::collections::HashMap;
-
+use chrono::Local;
 use dioxus::prelude::*;
 use dioxus_router::prelude::*;
+use lib::{ApiResponse, CreatedRoom, FormAddRoom, FormDirectRoom, RoomKind};
 
 use crate::{
-    event_source::SourceState, room::OpRoomId, AccountManager, Rooms, Route, BASE_API_URL,
+    event_source::SourceState, mentions::Mentions, messages::classe_presence, room::OpRoomId,
+    search::Recherche, AccountManager, Rooms, Route, BASE_API_URL,
 };
 
 #[inline_props]
@@ -20,6 +22,7 @@ pub fn SideBar(cx: Scope, room_id: OpRoomId) -> Element {
     let source_state = use_shared_state::<SourceState>(cx).unwrap();
     let rooms = use_shared_state::<Rooms>(cx).unwrap();
     let name = use_state(cx, || String::new());
+    let username = use_state(cx, || String::new());
     let error = use_state::<Option<String>>(cx, || None);
 
     let navigator = use_navigator(cx);
@@ -34,11 +37,38 @@ pub fn SideBar(cx: Scope, room_id: OpRoomId) -> Element {
         SourceState::Connected => "connected",
     };
 
+    let user_id = account_manager.read().utilisateur_actuelle().unwrap().id;
     let rooms = rooms.read();
-    let rooms = rooms.0.iter();
-
-    const CLASS_ROOM: &'static str = "room";
-    const CLASS_ROOM_ACTIVE: &'static str = "room active";
+    let mut contacts: Vec<(&str, &'static str, String)> = rooms
+        .1
+        .values()
+        .map(|presence| {
+            (
+                presence.username.as_str(),
+                classe_presence(&rooms, presence.user_id),
+                presence.last_seen.map_or(String::new(), |last_seen| {
+                    format!("Vu à {}", last_seen.with_timezone(&Local).format("%H:%M"))
+                }),
+            )
+        })
+        .collect();
+    contacts.sort_by(|a, b| a.0.cmp(b.0));
+    let (directes, rooms): (Vec<_>, Vec<_>) = rooms
+        .0
+        .iter()
+        .map(|(room_id, room_data)| {
+            (
+                *room_id,
+                room_data.titre(user_id, &rooms.1),
+                room_data.non_lus(user_id),
+                room_data.kind,
+            )
+        })
+        .partition(|(_, _, _, kind)| *kind == RoomKind::Direct);
+    let actif = match cx.props.room_id.as_ref() {
+        Some(Some(current_room_id)) => Some(*current_room_id),
+        _ => None,
+    };
 
     render! {
         div {
@@ -51,18 +81,31 @@ pub fn SideBar(cx: Scope, room_id: OpRoomId) -> Element {
                 id: "status",
                 class: state
             }
+            Link {
+                to: Route::Compte{},
+                "Compte"
+            }
+            Recherche {}
+            Mentions {}
             ul {
                 id: "rooms",
-                for (room_id, room_data) in rooms {
+                for (room_id, titre, non_lus, _) in rooms {
+                    room_element(cx, actif, room_id, titre, non_lus)
+                }
+            }
+            ul {
+                id: "dms",
+                for (room_id, titre, non_lus, _) in directes {
+                    room_element(cx, actif, room_id, titre, non_lus)
+                }
+            }
+            ul {
+                id: "contacts",
+                for (username, presence, vu) in contacts {
                     li {
-                        Link {
-                            class: match cx.props.room_id.as_ref() {
-                                Some(Some(current_room_id)) => if *current_room_id == *room_id { CLASS_ROOM_ACTIVE } else { CLASS_ROOM },
-                                _ => CLASS_ROOM
-                            },
-                            to: Route::Conv{ room_id: *room_id },
-                            room_data.name.as_str()
-                        }
+                        title: "{vu}",
+                        span{ class: presence }
+                        username
                     }
                 }
             }
@@ -89,10 +132,104 @@ pub fn SideBar(cx: Scope, room_id: OpRoomId) -> Element {
                     "+"
                 }
             }
+            form {
+                id: "new-dm",
+                input {
+                    r#type: "text",
+                    name: "username",
+                    id: "username",
+                    autocomplete: "off",
+                    placeholder: "conversation privée avec",
+                    oninput: move |evt| username.set(evt.value.clone()),
+                    value: "{username}"
+                }
+                button {
+                    id: "send",
+                    prevent_default: "onclick",
+                    onclick: move |_| open_dm(cx, account_manager.to_owned(), navigator.clone(), username.to_owned(), error.to_owned()),
+                    "+"
+                }
+            }
+        }
+    }
+}
+
+const CLASS_ROOM: &'static str = "room";
+const CLASS_ROOM_ACTIVE: &'static str = "room active";
+
+/// Lien vers un salon avec son nombre de messages non lus
+fn room_element<T>(
+    cx: Scope<T>,
+    actif: Option<i64>,
+    room_id: i64,
+    titre: String,
+    non_lus: i64,
+) -> Element {
+    render! {
+        li {
+            Link {
+                class: if actif == Some(room_id) { CLASS_ROOM_ACTIVE } else { CLASS_ROOM },
+                to: Route::Conv{ room_id: room_id },
+                "{titre}"
+                match non_lus {
+                    0 => render!{span{}},
+                    _ => render!{span{ class: "unread", "{non_lus}" }}
+                }
+            }
         }
     }
 }
 
+/// Ouvre la conversation privée avec un utilisateur
+///
+/// Si elle existait déjà, elle est affichée, sinon elle sera ajoutée à la liste
+/// à la réception de l'événement du serveur.
+fn open_dm<T>(
+    cx: Scope<T>,
+    account_manager: UseSharedState<AccountManager>,
+    navigator: Navigator,
+    username: UseState<String>,
+    error: UseState<Option<String>>,
+) {
+    if username.is_empty() {
+        error.set(Some(String::from("Il faut au moins une lettre dans le nom")));
+        return;
+    }
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+    let form = FormDirectRoom {
+        other_user_username: username.to_string(),
+    };
+
+    let url = format!("{BASE_API_URL}/dm");
+    cx.spawn(async move {
+        match reqwest::Client::new()
+            .post(&url)
+            .bearer_auth(&token)
+            .form(&form)
+            .send()
+            .await
+        {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                let response_body = response.text().await.unwrap();
+                match CreatedRoom::parse(response_body.as_str()) {
+                    Ok(reponse) => {
+                        error.set(None);
+                        username.set(String::new());
+                        if status == 200 {
+                            navigator.push(Route::Conv {
+                                room_id: reponse.room_id,
+                            });
+                        }
+                    }
+                    Err(e) => error.set(Some(e.reason)),
+                }
+            }
+            Err(_) => error.set(Some(String::from("Perte de connection"))),
+        }
+    });
+}
+
 fn create_room<T>(
     cx: Scope<T>,
     account_manager: UseSharedState<AccountManager>,
@@ -103,32 +240,28 @@ fn create_room<T>(
         error.set(Some(String::from("Il faut au moins une lettre dans le nom du salon")));
         return;
     }
-    let form: HashMap<&str, String> = {
-        let account_manager = account_manager.read();
-        let current_user = account_manager.utilisateur_actuelle().unwrap();
-        HashMap::<&'static str, String>::from([
-            ("user_id", current_user.id.to_string()),
-            ("api_key", current_user.api_key.to_string()),
-            ("name", name.to_string()),
-        ])
+    let token = account_manager.read().utilisateur_actuelle().unwrap().token.to_string();
+    let form = FormAddRoom {
+        name: name.to_string(),
     };
 
     let url = format!("{BASE_API_URL}/room");
     cx.spawn(async move {
-        match reqwest::Client::new().post(&url).form(&form).send().await {
+        match reqwest::Client::new()
+            .post(&url)
+            .bearer_auth(&token)
+            .form(&form)
+            .send()
+            .await
+        {
             Ok(response) => {
-                let status = response.status().as_u16();
                 let response_body = response.text().await.unwrap();
-                let response_data = json::parse(response_body.as_str()).unwrap();
-                match status {
-                    201 => {
-                        account_manager
-                            .write_silent()
-                            .modifier_api_key(response_data["api_key"].as_str().unwrap().to_string());
+                match CreatedRoom::parse(response_body.as_str()) {
+                    Ok(_) => {
                         error.set(None);
                         name.set(String::new());
                     }
-                    _ => error.set(Some(response_data["reason"].as_str().unwrap().to_string())),
+                    Err(e) => error.set(Some(e.reason)),
                 }
             }
             Err(_) => error.set(Some(String::from("Perte de connection"))),
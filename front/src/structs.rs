@@ -1,12 +1,32 @@
 //! Module  qui implémente le struct User.
 //! 
-//! Il contient son id, nom et mot de passe.
+//! Il contient son id, son nom et les jetons de sa session.
 
 #![allow(non_snake_case)]
 
+use chrono::{DateTime, Utc};
+use lib::Session;
+
 #[derive(Debug, PartialEq)]
 pub struct User {
     pub id: i64,
     pub username: String,
-    pub api_key: String,
+    /// Jeton d'accès, à envoyer dans l'en-tête `Authorization` des requêtes
+    pub token: String,
+    /// Jeton pour rafraîchir la session avant l'expiration du jeton d'accès
+    pub refresh_token: String,
+    /// Expiration du jeton d'accès
+    pub expires_at: DateTime<Utc>,
+}
+
+impl User {
+    pub fn new(session: Session, username: String) -> User {
+        User {
+            id: session.user_id,
+            username,
+            token: session.token,
+            refresh_token: session.refresh_token,
+            expires_at: session.expires_at,
+        }
+    }
 }
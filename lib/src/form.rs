@@ -0,0 +1,165 @@
+//! Requêtes envoyées à l'API
+//!
+//! Ces formulaires sont envoyés par le front en `application/x-www-form-urlencoded` (ou dans
+//! la query string des routes `GET`). Avec la feature `rocket`, ils dérivent aussi `FromForm`
+//! pour être lus directement par les routes de l'API.
+//!
+//! L'utilisateur n'y figure pas : il est authentifié par le jeton de sa session, envoyé dans
+//! l'en-tête `Authorization: Bearer <token>` (voir `Session`).
+
+use serde::{Deserialize, Serialize};
+
+/// Identifiant et mot de passe, pour créer un utilisateur ou se connecter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormAddUser {
+    pub username: String,
+    pub password: String,
+}
+
+/// Ancien et nouveau mot de passe, pour changer de mot de passe
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormChangePassword {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+/// Jeton de réinitialisation donné par un administrateur et nouveau mot de passe
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormResetPassword {
+    pub token: String,
+    pub new_password: String,
+}
+
+/// Jeton de rafraîchissement, à échanger contre de nouveaux jetons de session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormRefresh {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormMessage {
+    pub room_id: i64,
+    pub text: String,
+    /// Message auquel on répond (fil de discussion)
+    pub parent_id: Option<i64>,
+    /// Pièces jointes envoyées au préalable dans le salon (route `/room/<id>/attachment`)
+    pub attachment_ids: Vec<i64>,
+    /// Date d'envoi (timestamp) d'un message programmé
+    pub send_at: Option<i64>,
+    /// Durée de vie en secondes d'un message éphémère, supprimé ensuite
+    pub ttl: Option<i64>,
+}
+
+impl FormMessage {
+    /// Champs du formulaire, avec un champ `attachment_ids` par pièce jointe
+    ///
+    /// `serde_urlencoded` (utilisé par `reqwest`) ne sait pas sérialiser une liste.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("room_id", self.room_id.to_string()),
+            ("text", self.text.clone()),
+        ];
+        if let Some(parent_id) = self.parent_id {
+            fields.push(("parent_id", parent_id.to_string()));
+        }
+        for attachment_id in &self.attachment_ids {
+            fields.push(("attachment_ids", attachment_id.to_string()));
+        }
+        if let Some(send_at) = self.send_at {
+            fields.push(("send_at", send_at.to_string()));
+        }
+        if let Some(ttl) = self.ttl {
+            fields.push(("ttl", ttl.to_string()));
+        }
+        fields
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormEditMessage {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormReaction {
+    pub emoji: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormAddRoom {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormAddUserRoom {
+    pub other_user_username: String,
+    pub room_id: i64,
+}
+
+/// Utilisateur avec qui ouvrir une conversation privée
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormDirectRoom {
+    pub other_user_username: String,
+}
+
+/// Nouveau nom, sujet et (ou) durée de conservation des messages d'un salon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormEditRoom {
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    /// Durée de conservation des messages en secondes (`0` pour les garder indéfiniment)
+    pub retention: Option<i64>,
+}
+
+/// Membre d'un salon à expulser ou bannir
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormMember {
+    pub other_user_id: i64,
+}
+
+/// Nouveau rôle (`admin` ou `member`) d'un membre d'un salon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormRole {
+    pub other_user_id: i64,
+    pub role: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormRead {
+    pub message_id: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormEditScheduled {
+    pub text: Option<String>,
+    pub send_at: Option<i64>,
+}
+
+/// Termes recherchés et filtres d'une recherche
+///
+/// `after` et `before` sont des timestamps (en secondes).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "rocket", derive(rocket::FromForm, rocket::UriDisplayQuery))]
+pub struct FormSearch {
+    pub q: String,
+    pub room_id: Option<i64>,
+    pub author_id: Option<i64>,
+    pub after: Option<i64>,
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
@@ -2,71 +2,509 @@
 //!
 //! Ce module implémente des fonctions de sérialisation et désérialisation pour différentes données
 //! structurées et définit des structures pour représenter les salles (rooms) et les messages dans une application.
+//!
+//! Tout passe par `serde` : les textes des utilisateurs (guillemets, antislashs, retours
+//! à la ligne...) sont échappés correctement. Les requêtes sont dans `form`, les réponses
+//! dans `response` et les événements de l'Event Stream sont décodés par `EventMessage::parse`.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use std::collections::HashMap;
+mod form;
+mod response;
+#[cfg(test)]
+mod tests;
 
-use chrono::{DateTime, TimeZone, Utc};
-use json::JsonValue;
+pub use form::{
+    FormAddRoom, FormAddUser, FormAddUserRoom, FormChangePassword, FormDirectRoom, FormEditMessage,
+    FormEditRoom, FormEditScheduled, FormMember, FormMessage, FormReaction, FormRead, FormRefresh,
+    FormResetPassword, FormRole, FormSearch,
+};
+pub use response::{
+    ApiResponse, CreatedAttachment, CreatedMessage, CreatedRoom, CreatedScheduled, EditedScheduled,
+    MessagePage, Pins, Replies, ScheduledMessages, SearchResults, Session, SessionInfo, Sessions,
+    UserProfile,
+};
 
-pub fn serialize_login(username: String, password: String) -> HashMap<&'static str, String> {
-    HashMap::<&'static str, String>::from([("username", username), ("password", password)])
+/// Sérialise en JSON
+///
+/// Les structures de ce module n'ont que des clés textuelles : la sérialisation ne peut pas échouer.
+pub fn to_json<T: Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string(value).expect("Sérialisation JSON")
 }
 
-pub fn serialize_message(
-    room: i64,
-    user_id: i64,
-    api_key: String,
-    text: String,
-) -> HashMap<&'static str, String> {
-    HashMap::<&'static str, String>::from([
-        ("room_id", room.to_string()),
-        ("user_id", user_id.to_string()),
-        ("api_key", api_key),
-        ("text", text),
-    ])
+/// Découpe le texte d'un message en morceaux, avec `true` pour les mentions (`@username`)
+///
+/// Un nom mentionné contient seulement des lettres, des chiffres, `_` et `-`.
+pub fn split_mentions(text: &str) -> Vec<(&str, bool)> {
+    let est_nom = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    let mut morceaux = Vec::new();
+    let mut debut = 0;
+    let mut precedent: Option<char> = None;
+    let mut caracteres = text.char_indices().peekable();
+    while let Some((i, c)) = caracteres.next() {
+        // Un `@` au milieu d'un mot (une adresse courriel) n'est pas une mention
+        if c == '@' && !precedent.is_some_and(char::is_alphanumeric) {
+            let mut fin = i + 1;
+            while let Some(&(j, suivant)) = caracteres.peek() {
+                if !est_nom(suivant) {
+                    break;
+                }
+                fin = j + suivant.len_utf8();
+                caracteres.next();
+            }
+            if fin > i + 1 {
+                if i > debut {
+                    morceaux.push((&text[debut..i], false));
+                }
+                morceaux.push((&text[i..fin], true));
+                debut = fin;
+            }
+            precedent = text[..fin].chars().last();
+        } else {
+            precedent = Some(c);
+        }
+    }
+    if debut < text.len() {
+        morceaux.push((&text[debut..], false));
+    }
+    morceaux
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Room {
     pub id: i64,
     pub name: String,
+    /// Sujet du salon (vide s'il n'a pas été défini)
+    pub topic: String,
+    pub kind: RoomKind,
+    /// Durée de conservation des messages en secondes (`None` s'ils sont gardés indéfiniment)
+    pub retention: Option<i64>,
+    /// Nombre de messages non lus par le destinataire (hors réponses et hors ses propres messages)
+    pub unread_count: i64,
+    /// Dernier message (hors réponses) du salon au moment du calcul de `unread_count`
+    pub last_message_id: Option<i64>,
+    /// Membres du salon et leur rôle
+    pub members: Vec<RoomMember>,
 }
 
 impl Room {
     pub fn serialize(&self) -> String {
-        format!(
-            "{{ \"objectId\": {}, \"id\": {}, \"name\": \"{}\" }}",
-            EventMessageId::Room.as_u8(),
-            self.id,
-            self.name.clone(),
-        )
+        serialize_event(EventMessageId::Room, self)
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Type d'un salon
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomKind {
+    /// Salon créé avec un nom, où ses membres peuvent en inviter d'autres
+    Group,
+    /// Conversation privée entre deux utilisateurs (unique pour chaque paire), sans nom
+    Direct,
+}
+
+impl RoomKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoomKind::Group => "group",
+            RoomKind::Direct => "direct",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<RoomKind> {
+        match value {
+            "group" => Some(RoomKind::Group),
+            "direct" => Some(RoomKind::Direct),
+            _ => None,
+        }
+    }
+}
+
+/// Nouveau nom, sujet et durée de conservation des messages d'un salon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomSettings {
+    pub room_id: i64,
+    pub name: String,
+    pub topic: String,
+    /// Durée de conservation des messages en secondes (voir `Room::retention`)
+    pub retention: Option<i64>,
+}
+
+impl RoomSettings {
+    pub fn serialize(&self) -> String {
+        serialize_event(EventMessageId::RoomUpdate, self)
+    }
+}
+
+/// Rôle d'un membre dans un salon, du moins au plus privilégié
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomRole {
+    Member,
+    /// Peut expulser, bannir et changer le rôle des membres
+    Admin,
+    /// Créateur du salon, peut aussi gérer les administrateurs
+    Owner,
+}
+
+impl RoomRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoomRole::Member => "member",
+            RoomRole::Admin => "admin",
+            RoomRole::Owner => "owner",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<RoomRole> {
+        match value {
+            "member" => Some(RoomRole::Member),
+            "admin" => Some(RoomRole::Admin),
+            "owner" => Some(RoomRole::Owner),
+            _ => None,
+        }
+    }
+}
+
+/// Membre d'un salon (envoyé quand il y est ajouté ou que son rôle change)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomMember {
+    pub room_id: i64,
+    pub user_id: i64,
+    pub role: RoomRole,
+}
+
+impl RoomMember {
+    pub fn serialize(&self) -> String {
+        serialize_event(EventMessageId::Member, self)
+    }
+}
+
+/// Membre retiré d'un salon (expulsé ou banni)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemovedMember {
+    pub room_id: i64,
+    pub user_id: i64,
+    /// Le membre ne peut plus être invité dans le salon
+    pub banned: bool,
+}
+
+impl RemovedMember {
+    pub fn serialize(&self) -> String {
+        serialize_event(EventMessageId::MemberRemove, self)
+    }
+
+    /// Sérialise le retrait pour le membre retiré, qui doit oublier le salon
+    pub fn serialize_room_remove(&self) -> String {
+        serialize_event(EventMessageId::RoomRemove, self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Message {
+    pub id: i64,
+    #[serde(with = "chrono::serde::ts_seconds")]
     pub date: DateTime<Utc>,
     pub room_id: i64,
     pub user_id: i64,
     pub text: String,
+    /// Message auquel celui-ci répond (fil de discussion)
+    pub parent_id: Option<i64>,
+    /// Nombre de réponses à ce message
+    pub reply_count: i64,
+    /// Réactions à ce message, regroupées par emoji
+    pub reactions: Vec<Reaction>,
+    /// Pièces jointes de ce message
+    pub attachments: Vec<Attachment>,
+    /// Date à laquelle ce message éphémère sera supprimé
+    #[serde(with = "chrono::serde::ts_seconds_option", default)]
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 impl Message {
     pub fn serialize(&self) -> String {
-        format!(
-            "{{ \"objectId\": {}, \"date\": {}, \"room_id\": {}, \"user_id\": {}, \"text\": \"{}\" }}",
-            EventMessageId::Message.as_u8(),
-            self.date.timestamp(),
-            self.room_id,
-            self.user_id,
-            self.text.clone(),
-        )
+        serialize_event(EventMessageId::Message, self)
+    }
+
+    /// Sérialise le message comme une modification d'un message existant
+    pub fn serialize_edit(&self) -> String {
+        serialize_event(EventMessageId::MessageEdit, self)
+    }
+
+    /// Sérialise le message comme une mention, envoyée seulement aux utilisateurs mentionnés
+    pub fn serialize_mention(&self) -> String {
+        serialize_event(EventMessageId::Mention, self)
+    }
+}
+
+/// Description d'une pièce jointe (le fichier se télécharge avec la route `/attachment/<id>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: i64,
+    pub name: String,
+    pub content_type: String,
+    /// Taille du fichier en octets
+    pub size: i64,
+}
+
+/// Types des images affichées dans le navigateur : les autres, comme le SVG qui peut contenir
+/// des scripts, sont seulement téléchargés
+const IMAGES_AFFICHEES: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+impl Attachment {
+    /// Indique si la pièce jointe peut être affichée comme une image
+    pub fn is_image(&self) -> bool {
+        let content_type = self.content_type.split(';').next().unwrap_or_default();
+        IMAGES_AFFICHEES.contains(&content_type.trim().to_lowercase().as_str())
+    }
+}
+
+/// Référence vers un message supprimé
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeletedMessage {
+    pub id: i64,
+    pub room_id: i64,
+}
+
+impl DeletedMessage {
+    pub fn serialize(&self) -> String {
+        serialize_event(EventMessageId::MessageDelete, self)
+    }
+}
+
+/// Nouvel état d'un fil de discussion (après l'ajout ou la suppression d'une réponse)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Thread {
+    pub parent_id: i64,
+    pub room_id: i64,
+    pub reply_count: i64,
+}
+
+impl Thread {
+    pub fn serialize(&self) -> String {
+        serialize_event(EventMessageId::Thread, self)
+    }
+}
+
+/// Utilisateurs ayant réagi à un message avec un même emoji
+///
+/// Envoyé après chaque ajout ou retrait d'une réaction, avec `user_ids` vide s'il n'en reste plus.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reaction {
+    pub message_id: i64,
+    pub room_id: i64,
+    pub emoji: String,
+    pub user_ids: Vec<i64>,
+}
+
+impl Reaction {
+    pub fn serialize(&self) -> String {
+        serialize_event(EventMessageId::Reaction, self)
+    }
+}
+
+/// Dernier message lu par un utilisateur dans un salon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReadReceipt {
+    pub room_id: i64,
+    pub user_id: i64,
+    pub message_id: i64,
+}
+
+impl ReadReceipt {
+    pub fn serialize(&self) -> String {
+        serialize_event(EventMessageId::ReadReceipt, self)
+    }
+}
+
+/// Utilisateur en train d'écrire (ou qui a arrêté d'écrire) dans un salon
+///
+/// Cet événement n'est pas journalisé : il n'est pas renvoyé à la reconnexion.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Typing {
+    pub room_id: i64,
+    pub user_id: i64,
+    pub typing: bool,
+}
+
+impl Typing {
+    pub fn serialize(&self) -> String {
+        serialize_event(EventMessageId::Typing, self)
+    }
+}
+
+/// État de connexion d'un utilisateur
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    /// Au moins un Event Stream ouvert
+    Online,
+    /// Aucun Event Stream ouvert, mais vu récemment
+    Away,
+    Offline,
+}
+
+impl PresenceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PresenceStatus::Online => "online",
+            PresenceStatus::Away => "away",
+            PresenceStatus::Offline => "offline",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<PresenceStatus> {
+        match value {
+            "online" => Some(PresenceStatus::Online),
+            "away" => Some(PresenceStatus::Away),
+            "offline" => Some(PresenceStatus::Offline),
+            _ => None,
+        }
+    }
+}
+
+/// Présence d'un utilisateur qui partage un salon avec le destinataire
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserPresence {
+    pub user_id: i64,
+    pub username: String,
+    pub status: PresenceStatus,
+    #[serde(with = "chrono::serde::ts_seconds_option", default)]
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+impl UserPresence {
+    pub fn serialize(&self) -> String {
+        serialize_event(EventMessageId::Presence, self)
     }
 }
 
+/// Message trouvé par une recherche, avec un extrait où les termes trouvés sont entourés
+/// de `<mark>` et `</mark>`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub message: Message,
+    pub snippet: String,
+}
+
+/// Message épinglé dans un salon, avec l'utilisateur qui l'a épinglé et quand
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pin {
+    pub room_id: i64,
+    pub user_id: i64,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub date: DateTime<Utc>,
+    pub message: Message,
+}
+
+impl Pin {
+    pub fn serialize(&self) -> String {
+        serialize_event(EventMessageId::Pin, self)
+    }
+}
+
+/// Référence vers un message qui n'est plus épinglé
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Unpin {
+    pub room_id: i64,
+    pub message_id: i64,
+}
+
+impl Unpin {
+    pub fn serialize(&self) -> String {
+        serialize_event(EventMessageId::Unpin, self)
+    }
+}
+
+/// Message en attente d'envoi à la date `send_at` (visible seulement par son auteur)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledMessage {
+    pub id: i64,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub send_at: DateTime<Utc>,
+    pub room_id: i64,
+    pub user_id: i64,
+    pub text: String,
+    /// Message auquel celui-ci répondra (fil de discussion)
+    pub parent_id: Option<i64>,
+}
+
+/// Code d'une erreur de l'API, qui détermine le statut HTTP de sa réponse
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Requête invalide (400)
+    BadRequest,
+    /// Mauvais identifiant, mot de passe ou jeton de session (401)
+    Unauthorized,
+    /// Action interdite à l'utilisateur, par exemple dans un salon dont il ne fait pas partie (403)
+    Forbidden,
+    /// Ressource introuvable (404)
+    NotFound,
+    /// Ressource qui existe déjà (409)
+    Conflict,
+    /// Erreur du serveur (500)
+    Internal,
+    /// Nom d'utilisateur déjà pris (409)
+    UsernameTaken,
+    /// L'utilisateur ne fait pas partie du salon (403)
+    NotRoomMember,
+    /// Message de quelqu'un d'autre (403)
+    NotAuthor,
+    /// Jeton d'accès expiré d'une session encore valide, à rafraîchir (401)
+    TokenExpired,
+}
+
+/// Erreur renvoyée par l'API, avec la raison à afficher à l'utilisateur
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub reason: String,
+}
+
+impl ApiError {
+    pub fn serialize(&self) -> String {
+        to_json(self)
+    }
+
+    pub fn parse(body: &str) -> Result<ApiError, String> {
+        serde_json::from_str(body).map_err(|e| format!("ApiError {}", e))
+    }
+
+    /// Erreur d'une réponse, ou une erreur générique si elle n'en est pas une
+    pub fn from_response(body: &str) -> ApiError {
+        ApiError::parse(body).unwrap_or_else(|_| ApiError {
+            code: ErrorCode::Internal,
+            reason: String::from("Réponse inattendue du serveur"),
+        })
+    }
+
+    /// Raison d'une réponse d'erreur, ou un message générique si elle n'en est pas une
+    pub fn reason_of(body: &str) -> String {
+        ApiError::from_response(body).reason
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 enum EventMessageId {
     Room,
     Message,
+    MessageEdit,
+    MessageDelete,
+    Thread,
+    Reaction,
+    ReadReceipt,
+    Typing,
+    Presence,
+    Member,
+    MemberRemove,
+    RoomRemove,
+    RoomUpdate,
+    Mention,
+    Pin,
+    Unpin,
 }
 
 impl EventMessageId {
@@ -74,6 +512,20 @@ impl EventMessageId {
         match value {
             0 => Some(EventMessageId::Room),
             1 => Some(EventMessageId::Message),
+            2 => Some(EventMessageId::MessageEdit),
+            3 => Some(EventMessageId::MessageDelete),
+            4 => Some(EventMessageId::Thread),
+            5 => Some(EventMessageId::Reaction),
+            6 => Some(EventMessageId::ReadReceipt),
+            7 => Some(EventMessageId::Typing),
+            8 => Some(EventMessageId::Presence),
+            9 => Some(EventMessageId::Member),
+            10 => Some(EventMessageId::MemberRemove),
+            11 => Some(EventMessageId::RoomRemove),
+            12 => Some(EventMessageId::RoomUpdate),
+            13 => Some(EventMessageId::Mention),
+            14 => Some(EventMessageId::Pin),
+            15 => Some(EventMessageId::Unpin),
             _ => None,
         }
     }
@@ -81,59 +533,130 @@ impl EventMessageId {
         match self {
             EventMessageId::Room => 0,
             EventMessageId::Message => 1,
+            EventMessageId::MessageEdit => 2,
+            EventMessageId::MessageDelete => 3,
+            EventMessageId::Thread => 4,
+            EventMessageId::Reaction => 5,
+            EventMessageId::ReadReceipt => 6,
+            EventMessageId::Typing => 7,
+            EventMessageId::Presence => 8,
+            EventMessageId::Member => 9,
+            EventMessageId::MemberRemove => 10,
+            EventMessageId::RoomRemove => 11,
+            EventMessageId::RoomUpdate => 12,
+            EventMessageId::Mention => 13,
+            EventMessageId::Pin => 14,
+            EventMessageId::Unpin => 15,
         }
     }
 }
 
-#[derive(Debug)]
+/// Données d'un événement, précédées de son type (`objectId`)
+#[derive(Serialize)]
+struct Event<'a, T> {
+    #[serde(rename = "objectId")]
+    object_id: u8,
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+fn serialize_event<T: Serialize>(object_id: EventMessageId, data: &T) -> String {
+    to_json(&Event {
+        object_id: object_id.as_u8(),
+        data,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum EventMessage {
     Room(Room),
     Message(Message),
+    MessageEdit(Message),
+    MessageDelete(DeletedMessage),
+    Thread(Thread),
+    Reaction(Reaction),
+    ReadReceipt(ReadReceipt),
+    Typing(Typing),
+    Presence(UserPresence),
+    Member(RoomMember),
+    MemberRemove(RemovedMember),
+    /// Le destinataire a été retiré du salon
+    RoomRemove(RemovedMember),
+    RoomUpdate(RoomSettings),
+    /// Message où le destinataire est mentionné (`@username`)
+    Mention(Message),
+    Pin(Pin),
+    Unpin(Unpin),
 }
 
 impl EventMessage {
-    pub fn parse<'a>(message: &'a JsonValue) -> Result<EventMessage, &'a str> {
-        match message["objectId"]
-            .as_u8()
-            .map(|id| EventMessageId::parse(id))
-        {
-            Some(Some(EventMessageId::Room)) => Ok(EventMessage::Room(Room {
-                id: message["id"]
-                    .as_i64()
-                    .ok_or("EventMessage Room.id Not found")?,
-                name: message["name"]
-                    .as_str()
-                    .ok_or("EventMessage Room.name Not found")?
-                    .to_string(),
-            })),
-            Some(Some(EventMessageId::Message)) => Ok(EventMessage::Message(Message {
-                date: match Utc.timestamp_opt(
-                    message["date"]
-                        .as_i64()
-                        .ok_or("EventMessage Message.date Not found")?,
-                    0,
-                ) {
-                    chrono::LocalResult::Single(d) => Ok(d),
-                    chrono::LocalResult::None => {
-                        Err("EventMessage Message.date Error parsing date (None)")
-                    }
-                    chrono::LocalResult::Ambiguous(_, _) => {
-                        Err("EventMessage Message.date Error parsing date (Ambiguous)")
-                    }
-                }?,
-                room_id: message["room_id"]
-                    .as_i64()
-                    .ok_or("EventMessage Message.room_id Not found")?,
-                user_id: message["user_id"]
-                    .as_i64()
-                    .ok_or("EventMessage Message.user_id Not found")?,
-                text: message["text"]
-                    .as_str()
-                    .ok_or("EventMessage Message.text Not found")?
-                    .to_string(),
-            })),
-            Some(None) => Err("EventMessage Object ID Not Supported"),
-            None => Err("EventMessage Object ID Not Found"),
+    pub fn serialize(&self) -> String {
+        match self {
+            EventMessage::Room(room) => room.serialize(),
+            EventMessage::Message(message) => message.serialize(),
+            EventMessage::MessageEdit(message) => message.serialize_edit(),
+            EventMessage::MessageDelete(deleted) => deleted.serialize(),
+            EventMessage::Thread(thread) => thread.serialize(),
+            EventMessage::Reaction(reaction) => reaction.serialize(),
+            EventMessage::ReadReceipt(receipt) => receipt.serialize(),
+            EventMessage::Typing(typing) => typing.serialize(),
+            EventMessage::Presence(presence) => presence.serialize(),
+            EventMessage::Member(member) => member.serialize(),
+            EventMessage::MemberRemove(removed) => removed.serialize(),
+            EventMessage::RoomRemove(removed) => removed.serialize_room_remove(),
+            EventMessage::RoomUpdate(settings) => settings.serialize(),
+            EventMessage::Mention(message) => message.serialize_mention(),
+            EventMessage::Pin(pin) => pin.serialize(),
+            EventMessage::Unpin(unpin) => unpin.serialize(),
         }
     }
+
+    /// Désérialise les données d'un événement de l'Event Stream
+    pub fn parse(data: &str) -> Result<EventMessage, String> {
+        let value: Value = serde_json::from_str(data).map_err(|e| format!("EventMessage {}", e))?;
+        let object_id = match value["objectId"].as_u64() {
+            Some(object_id) => object_id,
+            None => return Err(String::from("EventMessage Object ID Not Found")),
+        };
+        let event = match u8::try_from(object_id).ok().and_then(EventMessageId::parse) {
+            Some(EventMessageId::Room) => serde_json::from_value(value).map(EventMessage::Room),
+            Some(EventMessageId::Message) => {
+                serde_json::from_value(value).map(EventMessage::Message)
+            }
+            Some(EventMessageId::MessageEdit) => {
+                serde_json::from_value(value).map(EventMessage::MessageEdit)
+            }
+            Some(EventMessageId::MessageDelete) => {
+                serde_json::from_value(value).map(EventMessage::MessageDelete)
+            }
+            Some(EventMessageId::Thread) => serde_json::from_value(value).map(EventMessage::Thread),
+            Some(EventMessageId::Reaction) => {
+                serde_json::from_value(value).map(EventMessage::Reaction)
+            }
+            Some(EventMessageId::ReadReceipt) => {
+                serde_json::from_value(value).map(EventMessage::ReadReceipt)
+            }
+            Some(EventMessageId::Typing) => serde_json::from_value(value).map(EventMessage::Typing),
+            Some(EventMessageId::Presence) => {
+                serde_json::from_value(value).map(EventMessage::Presence)
+            }
+            Some(EventMessageId::Member) => serde_json::from_value(value).map(EventMessage::Member),
+            Some(EventMessageId::MemberRemove) => {
+                serde_json::from_value(value).map(EventMessage::MemberRemove)
+            }
+            Some(EventMessageId::RoomRemove) => {
+                serde_json::from_value(value).map(EventMessage::RoomRemove)
+            }
+            Some(EventMessageId::RoomUpdate) => {
+                serde_json::from_value(value).map(EventMessage::RoomUpdate)
+            }
+            Some(EventMessageId::Mention) => {
+                serde_json::from_value(value).map(EventMessage::Mention)
+            }
+            Some(EventMessageId::Pin) => serde_json::from_value(value).map(EventMessage::Pin),
+            Some(EventMessageId::Unpin) => serde_json::from_value(value).map(EventMessage::Unpin),
+            None => return Err(String::from("EventMessage Object ID Not Supported")),
+        };
+        event.map_err(|e| format!("EventMessage {}", e))
+    }
 }
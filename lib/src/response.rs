@@ -0,0 +1,154 @@
+//! Réponses de l'API
+//!
+//! Chaque route répond avec une de ces structures en JSON, ou avec une `ApiError`.
+//! Les routes qui ne renvoient rien d'autre que leur succès répondent `{}`.
+
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{ApiError, Attachment, Message, Pin, ScheduledMessage, SearchResult};
+
+/// Réponse de l'API, sérialisée en JSON
+///
+/// `to_json` plutôt que `serialize`, qui serait ambigu avec `serde::Serialize::serialize`.
+pub trait ApiResponse: Serialize + DeserializeOwned {
+    fn to_json(&self) -> String {
+        crate::to_json(self)
+    }
+
+    /// Désérialise une réponse, ou l'erreur que l'API a renvoyée à la place
+    fn parse(body: &str) -> Result<Self, ApiError> {
+        serde_json::from_str(body).map_err(|_| ApiError::from_response(body))
+    }
+}
+
+/// Session ouverte à la création d'un utilisateur ou à la connexion, ou rafraîchie
+///
+/// Le jeton d'accès s'envoie dans l'en-tête `Authorization: Bearer <token>` de chaque requête,
+/// jusqu'à `expires_at`. Avant, le jeton de rafraîchissement s'échange (`POST /refresh`) contre
+/// de nouveaux jetons, tant que la session n'a pas expiré.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub user_id: i64,
+    pub token: String,
+    /// Expiration du jeton d'accès
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub expires_at: DateTime<Utc>,
+    /// Jeton à usage unique pour rafraîchir la session
+    pub refresh_token: String,
+}
+
+impl ApiResponse for Session {}
+
+/// Session ouverte sur un appareil (sans son jeton)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: i64,
+    /// Navigateur ou application qui a ouvert la session (en-tête `User-Agent`)
+    pub user_agent: String,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub last_used: DateTime<Utc>,
+    /// Session de la requête qui a demandé la liste
+    pub current: bool,
+}
+
+/// Sessions ouvertes de l'utilisateur, de la dernière utilisée à la plus ancienne
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sessions {
+    pub sessions: Vec<SessionInfo>,
+}
+
+impl ApiResponse for Sessions {}
+
+/// Nom d'un utilisateur
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub user_id: i64,
+    pub username: String,
+}
+
+impl ApiResponse for UserProfile {}
+
+/// Salon créé, ou conversation privée ouverte
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreatedRoom {
+    pub room_id: i64,
+}
+
+impl ApiResponse for CreatedRoom {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreatedMessage {
+    pub message_id: i64,
+}
+
+impl ApiResponse for CreatedMessage {}
+
+/// Message programmé à la place d'être envoyé (`send_at`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreatedScheduled {
+    pub scheduled_id: i64,
+}
+
+impl ApiResponse for CreatedScheduled {}
+
+/// Pièce jointe envoyée, à lier ensuite à un message avec `attachment_ids`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreatedAttachment {
+    pub attachment: Attachment,
+}
+
+impl ApiResponse for CreatedAttachment {}
+
+/// Message programmé après sa modification
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EditedScheduled {
+    pub scheduled: ScheduledMessage,
+}
+
+impl ApiResponse for EditedScheduled {}
+
+/// Page de l'historique d'un salon ou de la boîte de mentions
+///
+/// `has_more` indique s'il reste des messages plus anciens que ceux de la page.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessagePage {
+    pub messages: Vec<Message>,
+    pub has_more: bool,
+}
+
+impl ApiResponse for MessagePage {}
+
+/// Réponses à un message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replies {
+    pub messages: Vec<Message>,
+}
+
+impl ApiResponse for Replies {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchResults {
+    pub results: Vec<SearchResult>,
+}
+
+impl ApiResponse for SearchResults {}
+
+/// Messages épinglés d'un salon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pins {
+    pub pins: Vec<Pin>,
+}
+
+impl ApiResponse for Pins {}
+
+/// Messages programmés de l'utilisateur
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledMessages {
+    pub scheduled: Vec<ScheduledMessage>,
+}
+
+impl ApiResponse for ScheduledMessages {}
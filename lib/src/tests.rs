@@ -0,0 +1,360 @@
+//! Tests du format d'échange
+//!
+//! Les structures sont sérialisées puis désérialisées avec des textes hostiles (guillemets,
+//! antislashs, retours à la ligne, caractères de contrôle, unicode) qui doivent revenir intacts.
+
+use chrono::{DateTime, TimeZone, Utc};
+use proptest::prelude::*;
+
+use super::*;
+
+/// Texte quelconque, ou fait surtout de caractères à échapper en JSON
+fn texte() -> impl Strategy<Value = String> {
+    prop_oneof![
+        any::<String>(),
+        "[\"\\\\/\n\r\t\u{0}\u{1f}\u{7f}{}\\[\\]:,é€😀 a-z]*",
+    ]
+}
+
+fn date() -> impl Strategy<Value = DateTime<Utc>> {
+    (0i64..4_000_000_000).prop_map(|secondes| Utc.timestamp_opt(secondes, 0).unwrap())
+}
+
+fn code_erreur() -> impl Strategy<Value = ErrorCode> {
+    prop::sample::select(vec![
+        ErrorCode::BadRequest,
+        ErrorCode::Unauthorized,
+        ErrorCode::Forbidden,
+        ErrorCode::NotFound,
+        ErrorCode::Conflict,
+        ErrorCode::Internal,
+        ErrorCode::UsernameTaken,
+        ErrorCode::NotRoomMember,
+        ErrorCode::NotAuthor,
+        ErrorCode::TokenExpired,
+    ])
+}
+
+fn reaction() -> impl Strategy<Value = Reaction> {
+    (
+        any::<i64>(),
+        any::<i64>(),
+        texte(),
+        prop::collection::vec(any::<i64>(), 0..4),
+    )
+        .prop_map(|(message_id, room_id, emoji, user_ids)| Reaction {
+            message_id,
+            room_id,
+            emoji,
+            user_ids,
+        })
+}
+
+fn attachment() -> impl Strategy<Value = Attachment> {
+    (any::<i64>(), texte(), texte(), any::<i64>()).prop_map(|(id, name, content_type, size)| {
+        Attachment {
+            id,
+            name,
+            content_type,
+            size,
+        }
+    })
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    (
+        (any::<i64>(), date(), any::<i64>(), any::<i64>(), texte()),
+        (
+            any::<Option<i64>>(),
+            any::<i64>(),
+            prop::collection::vec(reaction(), 0..3),
+            prop::collection::vec(attachment(), 0..3),
+            prop::option::of(date()),
+        ),
+    )
+        .prop_map(
+            |(
+                (id, date, room_id, user_id, text),
+                (parent_id, reply_count, reactions, attachments, expires_at),
+            )| Message {
+                id,
+                date,
+                room_id,
+                user_id,
+                text,
+                parent_id,
+                reply_count,
+                reactions,
+                attachments,
+                expires_at,
+            },
+        )
+}
+
+fn member() -> impl Strategy<Value = RoomMember> {
+    (any::<i64>(), any::<i64>(), 0..3usize).prop_map(|(room_id, user_id, role)| RoomMember {
+        room_id,
+        user_id,
+        role: [RoomRole::Member, RoomRole::Admin, RoomRole::Owner][role],
+    })
+}
+
+fn removed() -> impl Strategy<Value = RemovedMember> {
+    (any::<i64>(), any::<i64>(), any::<bool>()).prop_map(|(room_id, user_id, banned)| {
+        RemovedMember {
+            room_id,
+            user_id,
+            banned,
+        }
+    })
+}
+
+fn room() -> impl Strategy<Value = Room> {
+    (
+        (any::<i64>(), texte(), texte(), any::<bool>()),
+        (
+            any::<Option<i64>>(),
+            any::<i64>(),
+            any::<Option<i64>>(),
+            prop::collection::vec(member(), 0..4),
+        ),
+    )
+        .prop_map(
+            |((id, name, topic, direct), (retention, unread_count, last_message_id, members))| {
+                Room {
+                    id,
+                    name,
+                    topic,
+                    kind: match direct {
+                        true => RoomKind::Direct,
+                        false => RoomKind::Group,
+                    },
+                    retention,
+                    unread_count,
+                    last_message_id,
+                    members,
+                }
+            },
+        )
+}
+
+fn presence() -> impl Strategy<Value = UserPresence> {
+    (any::<i64>(), texte(), 0..3usize, prop::option::of(date())).prop_map(
+        |(user_id, username, status, last_seen)| UserPresence {
+            user_id,
+            username,
+            status: [
+                PresenceStatus::Online,
+                PresenceStatus::Away,
+                PresenceStatus::Offline,
+            ][status],
+            last_seen,
+        },
+    )
+}
+
+fn event() -> impl Strategy<Value = EventMessage> {
+    prop_oneof![
+        room().prop_map(EventMessage::Room),
+        message().prop_map(EventMessage::Message),
+        message().prop_map(EventMessage::MessageEdit),
+        (any::<i64>(), any::<i64>())
+            .prop_map(|(id, room_id)| EventMessage::MessageDelete(DeletedMessage { id, room_id })),
+        (any::<i64>(), any::<i64>(), any::<i64>()).prop_map(|(parent_id, room_id, reply_count)| {
+            EventMessage::Thread(Thread {
+                parent_id,
+                room_id,
+                reply_count,
+            })
+        }),
+        reaction().prop_map(EventMessage::Reaction),
+        (any::<i64>(), any::<i64>(), any::<i64>()).prop_map(|(room_id, user_id, message_id)| {
+            EventMessage::ReadReceipt(ReadReceipt {
+                room_id,
+                user_id,
+                message_id,
+            })
+        }),
+        (any::<i64>(), any::<i64>(), any::<bool>()).prop_map(|(room_id, user_id, typing)| {
+            EventMessage::Typing(Typing {
+                room_id,
+                user_id,
+                typing,
+            })
+        }),
+        presence().prop_map(EventMessage::Presence),
+        member().prop_map(EventMessage::Member),
+        removed().prop_map(EventMessage::MemberRemove),
+        removed().prop_map(EventMessage::RoomRemove),
+        (any::<i64>(), texte(), texte(), any::<Option<i64>>()).prop_map(
+            |(room_id, name, topic, retention)| EventMessage::RoomUpdate(RoomSettings {
+                room_id,
+                name,
+                topic,
+                retention,
+            })
+        ),
+        message().prop_map(EventMessage::Mention),
+        (any::<i64>(), any::<i64>(), date(), message()).prop_map(
+            |(room_id, user_id, date, message)| EventMessage::Pin(Pin {
+                room_id,
+                user_id,
+                date,
+                message,
+            })
+        ),
+        (any::<i64>(), any::<i64>()).prop_map(|(room_id, message_id)| EventMessage::Unpin(Unpin {
+            room_id,
+            message_id
+        })),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn test_event_round_trip(event in event()) {
+        let data = event.serialize();
+        // Un retour à la ligne couperait l'événement en plusieurs lignes `data:` de l'Event Stream
+        prop_assert!(!data.contains('\n'));
+        prop_assert_eq!(EventMessage::parse(data.as_str()), Ok(event));
+    }
+
+    #[test]
+    fn test_response_round_trip(
+        messages in prop::collection::vec(message(), 0..3),
+        has_more in any::<bool>(),
+        snippet in texte(),
+        token in texte(),
+        expires_at in date(),
+        user_agent in texte(),
+    ) {
+        let page = MessagePage { messages: messages.clone(), has_more };
+        prop_assert_eq!(MessagePage::parse(page.to_json().as_str()), Ok(page));
+
+        let results = SearchResults {
+            results: messages
+                .into_iter()
+                .map(|message| SearchResult { message, snippet: snippet.clone() })
+                .collect(),
+        };
+        prop_assert_eq!(SearchResults::parse(results.to_json().as_str()), Ok(results));
+
+        let session = Session {
+            user_id: 1,
+            token: token.clone(),
+            expires_at,
+            refresh_token: token,
+        };
+        prop_assert_eq!(Session::parse(session.to_json().as_str()), Ok(session));
+
+        let sessions = Sessions {
+            sessions: vec![SessionInfo {
+                id: 1,
+                user_agent,
+                created_at: expires_at,
+                last_used: expires_at,
+                current: has_more,
+            }],
+        };
+        prop_assert_eq!(Sessions::parse(sessions.to_json().as_str()), Ok(sessions));
+    }
+
+    #[test]
+    fn test_error_round_trip(code in code_erreur(), reason in texte()) {
+        let error = ApiError { code, reason };
+        let body = error.serialize();
+        prop_assert_eq!(ApiError::parse(body.as_str()), Ok(error.clone()));
+        prop_assert_eq!(Replies::parse(body.as_str()), Err(error));
+    }
+
+    #[test]
+    fn test_event_parse_garbage(data in texte()) {
+        // Ne doit jamais paniquer
+        let _ = EventMessage::parse(data.as_str());
+        prop_assert_eq!(
+            ApiError::reason_of(data.as_str()),
+            "Réponse inattendue du serveur"
+        );
+    }
+}
+
+#[test]
+fn test_event_format() {
+    let message = Message {
+        id: 3,
+        date: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        room_id: 1,
+        user_id: 2,
+        text: String::from("Il a dit \"salut\\\"\net il est parti"),
+        parent_id: None,
+        reply_count: 0,
+        reactions: Vec::new(),
+        attachments: Vec::new(),
+        expires_at: None,
+    };
+    assert_eq!(
+        message.serialize_edit(),
+        "{\"objectId\":2,\"id\":3,\"date\":1700000000,\"room_id\":1,\"user_id\":2,\
+        \"text\":\"Il a dit \\\"salut\\\\\\\"\\net il est parti\",\"parent_id\":null,\
+        \"reply_count\":0,\"reactions\":[],\"attachments\":[],\"expires_at\":null}"
+    );
+
+    assert_eq!(
+        EventMessage::parse("{ \"objectId\": 42 }"),
+        Err(String::from("EventMessage Object ID Not Supported"))
+    );
+    assert_eq!(
+        EventMessage::parse("{ \"id\": 1 }"),
+        Err(String::from("EventMessage Object ID Not Found"))
+    );
+    assert!(EventMessage::parse("{ \"objectId\": 1, \"id\": 1 }").is_err());
+}
+
+#[test]
+fn test_form_message_fields() {
+    let form = FormMessage {
+        room_id: 2,
+        text: String::from("a&b=c"),
+        parent_id: None,
+        attachment_ids: vec![4, 5],
+        send_at: None,
+        ttl: Some(60),
+    };
+    assert_eq!(
+        form.fields(),
+        vec![
+            ("room_id", String::from("2")),
+            ("text", String::from("a&b=c")),
+            ("attachment_ids", String::from("4")),
+            ("attachment_ids", String::from("5")),
+            ("ttl", String::from("60")),
+        ]
+    );
+}
+
+#[test]
+fn test_attachment_is_image() {
+    let attachment = |content_type: &str| Attachment {
+        id: 1,
+        name: String::from("image"),
+        content_type: content_type.to_string(),
+        size: 0,
+    };
+    assert!(attachment("image/png").is_image());
+    assert!(attachment("image/JPEG").is_image());
+    assert!(attachment("image/webp; q=1").is_image());
+    assert!(!attachment("image/svg+xml").is_image());
+    assert!(!attachment("text/html").is_image());
+}
+
+#[test]
+fn test_error_format() {
+    let error = ApiError {
+        code: ErrorCode::TokenExpired,
+        reason: String::from("Jeton d'accès expiré"),
+    };
+    assert_eq!(
+        error.serialize(),
+        "{\"code\":\"token_expired\",\"reason\":\"Jeton d'accès expiré\"}"
+    );
+}